@@ -16,7 +16,6 @@ fn generate_compatibility_tests() {
 
     let paths = fs::read_dir("./test_files/")
         .unwrap()
-        .into_iter()
         .map(|pth| pth.unwrap().path().canonicalize().unwrap())
         .filter(|pth| pth.is_file())
         .filter(|pth| pth.to_string_lossy().contains("original_v"))
@@ -37,7 +36,7 @@ fn generate_compatibility_tests() {
         use crate::decrypt;
         use crate::header::strategy::Verbosity;
         use crate::key::key::Key;
-        use crate::config::typ::{InputAction, OnFileExist};
+        use crate::config::typ::OnFileExist;
 
         lazy_static! {
             static ref COMPAT_KEY: Key = Key::new(\" LP0y#shbogtwhGjM=*jFFZPmNd&qBO+ \");
@@ -47,15 +46,14 @@ fn generate_compatibility_tests() {
             let out_dir = TempDir::new().unwrap();
             let mut original_pth = enc_pth.clone();
             original_pth.pop();
-            original_pth.push(\"original.png\".to_owned());
-            let conf = DecryptConfig::new(
-                vec![enc_pth.to_owned()],
-                COMPAT_KEY.clone(),
-                Verbosity::Debug,
-                OnFileExist::Overwrite,
-                InputAction::Keep,
-                Some(out_dir.path().to_owned()),
-            );
+            original_pth.push(\"original.png\");
+            let conf = DecryptConfig::builder(COMPAT_KEY.clone())
+                .with_file(enc_pth)
+                .with_verbosity(Verbosity::Debug)
+                .with_overwrite(OnFileExist::Overwrite)
+                .with_output_dir(out_dir.path().to_owned())
+                .build()
+                .unwrap();
             let dec_pths = decrypt(&conf).unwrap();
             assert_eq!(dec_pths.len(), 1);
             let dec_pth = dec_pths.first().unwrap();
@@ -65,12 +63,12 @@ fn generate_compatibility_tests() {
                 .read_to_end(&mut original_data)
                 .unwrap();
             let mut dec_data = vec![];
-            File::open(&dec_pth)
+            File::open(dec_pth)
                 .unwrap()
                 .read_to_end(&mut dec_data)
                 .unwrap();
             assert_eq!(&original_data, &dec_data);
-            fs::remove_file(&dec_pth).unwrap();
+            fs::remove_file(dec_pth).unwrap();
         }
         "
     )
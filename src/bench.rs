@@ -1,5 +1,7 @@
 use ::criterion::criterion_group;
 use ::criterion::criterion_main;
+#[cfg(not(feature = "expose"))]
+use ::criterion::Criterion;
 
 #[cfg(all(test, feature = "expose"))]
 mod hash {
@@ -65,10 +67,11 @@ mod hash {
                     stretch_key(
                         &Key::new(&"MY secret p@ssw0rd"),
                         &Salt::fixed_for_test(123_456_789),
-                        strat.stretch_count,
+                        &strat.kdf_cost,
                         &strat.key_hash_algorithms,
                         &mut |_| (),
                     )
+                    .unwrap()
                 })
             })
             .sample_size(10),
@@ -153,11 +156,17 @@ mod back_and_forth {
 
     use ::file_endec::decrypt;
     use ::file_endec::encrypt;
+    use ::file_endec::ArchiveAction;
     use ::file_endec::DecryptConfig;
     use ::file_endec::EncOption;
     use ::file_endec::EncOptionSet;
     use ::file_endec::EncryptConfig;
+    use ::file_endec::FileSelection;
+    use ::file_endec::InputAction;
     use ::file_endec::Key;
+    use ::file_endec::MetaAction;
+    use ::file_endec::OnFileExist;
+    use ::file_endec::RunMode;
     use ::file_endec::Verbosity;
 
     fn create_test_file() -> PathBuf {
@@ -179,15 +188,30 @@ mod back_and_forth {
             key.clone(),
             options,
             Verbosity::Quiet,
-            true,
-            true,
+            OnFileExist::Overwrite,
+            InputAction::Delete,
             None,
             ".enc".to_string(),
-            false,
+            RunMode::IsReal,
+            FileSelection::default(),
+            None,
+            1,
+            vec![],
         );
         let enc_files = encrypt(&conf).unwrap();
 
-        let conf = DecryptConfig::new(enc_files, key, Verbosity::Quiet, false, false, None);
+        let conf = DecryptConfig::new(
+            enc_files,
+            key,
+            Verbosity::Quiet,
+            OnFileExist::Overwrite,
+            InputAction::Delete,
+            None,
+            MetaAction::Restore,
+            FileSelection::default(),
+            ArchiveAction::ExtractAll,
+            1,
+        );
         decrypt(&conf).unwrap();
     }
 
@@ -1,47 +1,250 @@
 use ::std::path::Path;
 use ::std::path::PathBuf;
 
-use crate::config::typ::{EndecConfig, InputAction, OnFileExist};
+use crate::config::typ::{
+    ArchiveAction, EndecConfig, FileSelection, InputAction, MetaAction, OnFileExist,
+};
 use crate::header::strategy::Verbosity;
-use crate::key::Key;
+use crate::key::agent::AgentClient;
+use crate::key::{DeferredKey, Key};
+use crate::util::jobs::resolve_job_count;
+use crate::util::{FedError, FedResult};
 
 #[derive(Debug)]
 pub struct DecryptConfig {
     files: Vec<PathBuf>,
-    raw_key: Key,
+    raw_key: DeferredKey,
     verbosity: Verbosity,
     overwrite: OnFileExist,
     delete_input: InputAction,
     output_dir: Option<PathBuf>,
+    restore_meta: MetaAction,
+    selection: FileSelection,
+    archive_action: ArchiveAction,
+    jobs: usize,
+    agent: Option<AgentClient>,
+    input_extension: String,
 }
 
 impl DecryptConfig {
+    /// Start building a configuration with the same defaults as `filedec`.
+    pub fn builder(raw_key: impl Into<DeferredKey>) -> DecryptConfigBuilder {
+        DecryptConfigBuilder::new(raw_key)
+    }
+
+    /// Panics if there are no files or no jobs; use `builder` to get an error instead.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         files: Vec<PathBuf>,
-        raw_key: Key,
+        raw_key: impl Into<DeferredKey>,
         verbosity: Verbosity,
-        overwrite: OnFileExist,
-        delete_input: InputAction,
+        mut overwrite: OnFileExist,
+        mut delete_input: InputAction,
         output_dir: Option<PathBuf>,
+        restore_meta: MetaAction,
+        selection: FileSelection,
+        archive_action: ArchiveAction,
+        jobs: usize,
     ) -> Self {
         assert!(!files.is_empty());
+        assert!(jobs >= 1);
+        // Input is only deleted if everything was decrypted.
+        if archive_action != ArchiveAction::ExtractAll {
+            delete_input = InputAction::Keep;
+        }
+        // Nothing is written when listing or verifying, so existing output files do not matter.
+        if archive_action == ArchiveAction::List || archive_action == ArchiveAction::Verify {
+            overwrite = OnFileExist::Overwrite;
+        }
         DecryptConfig {
             files,
-            raw_key,
+            raw_key: raw_key.into(),
             verbosity,
             overwrite,
             delete_input,
             output_dir,
+            restore_meta,
+            selection,
+            archive_action,
+            jobs,
+            agent: None,
+            input_extension: ".enc".to_owned(),
         }
     }
 
+    /// Extension of the encrypted files to decrypt when walking directories, including the dot.
+    pub fn with_input_extension(mut self, extension: String) -> Self {
+        self.input_extension = extension;
+        self
+    }
+
+    pub fn input_extension(&self) -> &str {
+        &self.input_extension
+    }
+
+    /// Get stretched keys from the agent if it has them, and give it the ones it does not have.
+    /// With a `DeferredKey`, the key is then only obtained if the agent is missing one.
+    pub fn with_agent(mut self, agent: AgentClient) -> Self {
+        self.agent = Some(agent);
+        self
+    }
+
+    pub fn agent(&self) -> Option<&AgentClient> {
+        self.agent.as_ref()
+    }
+
     pub fn output_dir(&self) -> Option<&Path> {
         match &self.output_dir {
             Some(dir) => Some(dir.as_path()),
             None => None,
         }
     }
+
+    pub fn restore_meta(&self) -> bool {
+        self.restore_meta == MetaAction::Restore
+    }
+
+    pub fn archive_action(&self) -> &ArchiveAction {
+        &self.archive_action
+    }
+}
+
+/// Builds a `DecryptConfig`, checking the settings when done instead of panicking.
+#[derive(Debug)]
+pub struct DecryptConfigBuilder {
+    files: Vec<PathBuf>,
+    raw_key: DeferredKey,
+    verbosity: Verbosity,
+    overwrite: OnFileExist,
+    delete_input: InputAction,
+    output_dir: Option<PathBuf>,
+    restore_meta: MetaAction,
+    selection: FileSelection,
+    archive_action: ArchiveAction,
+    jobs: usize,
+    agent: Option<AgentClient>,
+    input_extension: String,
+}
+
+impl DecryptConfigBuilder {
+    pub fn new(raw_key: impl Into<DeferredKey>) -> Self {
+        DecryptConfigBuilder {
+            files: vec![],
+            raw_key: raw_key.into(),
+            verbosity: Verbosity::Normal,
+            overwrite: OnFileExist::Fail,
+            delete_input: InputAction::Keep,
+            output_dir: None,
+            restore_meta: MetaAction::Restore,
+            selection: FileSelection::default(),
+            archive_action: ArchiveAction::ExtractAll,
+            jobs: 1,
+            agent: None,
+            input_extension: ".enc".to_owned(),
+        }
+    }
+
+    pub fn with_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.files.push(file.into());
+        self
+    }
+
+    pub fn with_files(mut self, files: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.files.extend(files);
+        self
+    }
+
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn with_overwrite(mut self, overwrite: OnFileExist) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn with_delete_input(mut self, delete_input: InputAction) -> Self {
+        self.delete_input = delete_input;
+        self
+    }
+
+    pub fn with_output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    pub fn with_restore_meta(mut self, restore_meta: MetaAction) -> Self {
+        self.restore_meta = restore_meta;
+        self
+    }
+
+    pub fn with_selection(mut self, selection: FileSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    pub fn with_archive_action(mut self, archive_action: ArchiveAction) -> Self {
+        self.archive_action = archive_action;
+        self
+    }
+
+    /// Number of files to process at the same time; 0 means one per CPU core.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn with_agent(mut self, agent: AgentClient) -> Self {
+        self.agent = Some(agent);
+        self
+    }
+
+    /// The extension of encrypted files in directories; a leading dot is added if there is none.
+    pub fn with_input_extension(mut self, extension: &str) -> Self {
+        self.input_extension = if extension.starts_with('.') {
+            extension.to_owned()
+        } else {
+            format!(".{}", extension)
+        };
+        self
+    }
+
+    pub fn build(self) -> FedResult<DecryptConfig> {
+        if self.files.is_empty() {
+            return Err(FedError::Usage("no files to decrypt".to_owned()));
+        }
+        if let ArchiveAction::Extract(members) = &self.archive_action {
+            if members.is_empty() {
+                return Err(FedError::Usage(
+                    "no archive members given to extract".to_owned(),
+                ));
+            }
+        }
+        if self.input_extension.len() <= 1 || self.input_extension.contains('/') {
+            return Err(FedError::Usage(format!(
+                "invalid input extension '{}'",
+                self.input_extension
+            )));
+        }
+        let config = DecryptConfig::new(
+            self.files,
+            self.raw_key,
+            self.verbosity,
+            self.overwrite,
+            self.delete_input,
+            self.output_dir,
+            self.restore_meta,
+            self.selection,
+            self.archive_action,
+            resolve_job_count(self.jobs),
+        )
+        .with_input_extension(self.input_extension);
+        Ok(match self.agent {
+            Some(agent) => config.with_agent(agent),
+            None => config,
+        })
+    }
 }
 
 impl EndecConfig for DecryptConfig {
@@ -49,8 +252,8 @@ impl EndecConfig for DecryptConfig {
         &self.files
     }
 
-    fn raw_key(&self) -> &Key {
-        &self.raw_key
+    fn raw_key(&self) -> FedResult<Key> {
+        self.raw_key.obtain()
     }
 
     fn verbosity(&self) -> Verbosity {
@@ -71,4 +274,44 @@ impl EndecConfig for DecryptConfig {
             None => None,
         }
     }
+
+    fn selection(&self) -> &FileSelection {
+        &self.selection
+    }
+
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_defaults() {
+        let config = DecryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt.enc")
+            .with_archive_action(ArchiveAction::List)
+            .build()
+            .unwrap();
+        assert_eq!(config.files(), &[PathBuf::from("a.txt.enc")]);
+        assert_eq!(config.verbosity(), Verbosity::Normal);
+        assert_eq!(config.jobs(), 1);
+        assert!(config.restore_meta());
+        assert!(!config.delete_input());
+        // Listing does not write files, so existing ones are not a problem.
+        assert!(config.overwrite());
+    }
+
+    #[test]
+    fn builder_errors() {
+        let no_files = DecryptConfig::builder(Key::new("secret")).build();
+        assert_eq!(no_files.unwrap_err().code(), "usage");
+        let no_members = DecryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt.enc")
+            .with_archive_action(ArchiveAction::Extract(vec![]))
+            .build();
+        assert_eq!(no_members.unwrap_err().code(), "usage");
+    }
 }
@@ -1,10 +1,14 @@
 use ::std::path::Path;
 use ::std::path::PathBuf;
 
-use crate::config::typ::{EndecConfig, InputAction, OnFileExist};
+use crate::config::typ::{EndecConfig, FileSelection, InputAction, OnFileExist};
 use crate::header::strategy::Verbosity;
-use crate::key::Key;
-use crate::util::option::EncOptionSet;
+use crate::key::cost::KdfCost;
+use crate::key::recipient::Recipient;
+use crate::key::{Key, Salt};
+use crate::util::jobs::resolve_job_count;
+use crate::util::option::{EncOption, EncOptionSet};
+use crate::util::{FedError, FedResult};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RunMode {
@@ -23,25 +27,48 @@ pub struct EncryptConfig {
     output_dir: Option<PathBuf>,
     output_extension: String,
     dry_run: RunMode,
+    selection: FileSelection,
+    archive: Option<PathBuf>,
+    jobs: usize,
+    recipients: Vec<Recipient>,
+    kdf_cost: Option<KdfCost>,
+    fixed_salts: Option<(Salt, Salt)>,
 }
 
 impl EncryptConfig {
+    /// Start building a configuration with the same defaults as `fileenc`.
+    pub fn builder(raw_key: Key) -> EncryptConfigBuilder {
+        EncryptConfigBuilder::new(raw_key)
+    }
+
+    /// Panics if there are no files or no jobs; use `builder` to get an error instead.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         files: Vec<PathBuf>,
         raw_key: Key,
-        options: EncOptionSet,
+        mut options: EncOptionSet,
         verbosity: Verbosity,
         overwrite: OnFileExist,
         mut delete_input: InputAction,
         output_dir: Option<PathBuf>,
         output_extension: String,
         dry_run: RunMode,
+        selection: FileSelection,
+        archive: Option<PathBuf>,
+        jobs: usize,
+        recipients: Vec<Recipient>,
     ) -> Self {
         assert!(!files.is_empty());
+        assert!(jobs >= 1);
         if dry_run == RunMode::IsDryRun {
             delete_input = InputAction::Keep;
         }
+        if archive.is_some() {
+            options.add(EncOption::Archive);
+        }
+        if !recipients.is_empty() {
+            options.add(EncOption::Recipients);
+        }
         EncryptConfig {
             files,
             raw_key,
@@ -52,9 +79,24 @@ impl EncryptConfig {
             output_dir,
             output_extension,
             dry_run,
+            selection,
+            archive,
+            jobs,
+            recipients,
+            kdf_cost: None,
+            fixed_salts: None,
         }
     }
 
+    /// Use a different key stretching cost than the default of the strategy.
+    pub fn with_kdf_cost(mut self, kdf_cost: KdfCost) -> FedResult<Self> {
+        kdf_cost
+            .check()
+            .map_err(|msg| FedError::Usage(format!("invalid key stretching cost: {}", msg)))?;
+        self.kdf_cost = Some(kdf_cost);
+        Ok(self)
+    }
+
     pub fn options(&self) -> &EncOptionSet {
         &self.options
     }
@@ -66,6 +108,210 @@ impl EncryptConfig {
     pub fn dry_run(&self) -> bool {
         self.dry_run == RunMode::IsDryRun
     }
+
+    /// Path of the archive, if all files should be packed into one.
+    pub fn archive(&self) -> Option<&Path> {
+        self.archive.as_deref()
+    }
+
+    /// Public keys to encrypt the key for. If there are any, the key should be random,
+    /// since it is stored (encrypted) in every file.
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    pub fn kdf_cost(&self) -> Option<&KdfCost> {
+        self.kdf_cost.as_ref()
+    }
+
+    /// Use the given public salt and secret pepper instead of random ones, so that encrypting
+    /// the same data with the same key always gives the same output. This is recorded in the header.
+    /// Identical files can be recognized this way, so only use it for tests or deduplication.
+    /// The access time is not stored, and key slots, recipients and streams cannot be used.
+    pub fn with_deterministic(mut self, salt: Salt, pepper: Salt) -> Self {
+        self.options.add(EncOption::Deterministic);
+        self.fixed_salts = Some((salt, pepper));
+        self
+    }
+
+    /// The salt and pepper chosen by the caller, if encryption is deterministic.
+    pub fn fixed_salts(&self) -> Option<&(Salt, Salt)> {
+        self.fixed_salts.as_ref()
+    }
+}
+
+/// Builds an `EncryptConfig`, checking the settings when done instead of panicking.
+#[derive(Debug)]
+pub struct EncryptConfigBuilder {
+    files: Vec<PathBuf>,
+    raw_key: Key,
+    options: EncOptionSet,
+    verbosity: Verbosity,
+    overwrite: OnFileExist,
+    delete_input: InputAction,
+    output_dir: Option<PathBuf>,
+    output_extension: String,
+    dry_run: RunMode,
+    selection: FileSelection,
+    archive: Option<PathBuf>,
+    jobs: usize,
+    recipients: Vec<Recipient>,
+    kdf_cost: Option<KdfCost>,
+    fixed_salts: Option<(Salt, Salt)>,
+}
+
+impl EncryptConfigBuilder {
+    pub fn new(raw_key: Key) -> Self {
+        EncryptConfigBuilder {
+            files: vec![],
+            raw_key,
+            options: EncOptionSet::empty(),
+            verbosity: Verbosity::Normal,
+            overwrite: OnFileExist::Fail,
+            delete_input: InputAction::Keep,
+            output_dir: None,
+            output_extension: ".enc".to_owned(),
+            dry_run: RunMode::IsReal,
+            selection: FileSelection::default(),
+            archive: None,
+            jobs: 1,
+            recipients: vec![],
+            kdf_cost: None,
+            fixed_salts: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.files.push(file.into());
+        self
+    }
+
+    pub fn with_files(mut self, files: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.files.extend(files);
+        self
+    }
+
+    pub fn with_option(mut self, option: EncOption) -> Self {
+        self.options.add(option);
+        self
+    }
+
+    pub fn with_options(mut self, options: EncOptionSet) -> Self {
+        for option in options.iter() {
+            self.options.add(option.clone());
+        }
+        self
+    }
+
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn with_overwrite(mut self, overwrite: OnFileExist) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn with_delete_input(mut self, delete_input: InputAction) -> Self {
+        self.delete_input = delete_input;
+        self
+    }
+
+    pub fn with_output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// The extension of encrypted files; a leading dot is added if there is none.
+    pub fn with_output_extension(mut self, extension: &str) -> Self {
+        self.output_extension = if extension.starts_with('.') {
+            extension.to_owned()
+        } else {
+            format!(".{}", extension)
+        };
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: RunMode) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_selection(mut self, selection: FileSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    pub fn with_archive(mut self, archive: impl Into<PathBuf>) -> Self {
+        self.archive = Some(archive.into());
+        self
+    }
+
+    /// Number of files to process at the same time; 0 means one per CPU core.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn with_recipient(mut self, recipient: Recipient) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    pub fn with_kdf_cost(mut self, kdf_cost: KdfCost) -> Self {
+        self.kdf_cost = Some(kdf_cost);
+        self
+    }
+
+    /// See `EncryptConfig::with_deterministic`.
+    pub fn with_deterministic(mut self, salt: Salt, pepper: Salt) -> Self {
+        self.fixed_salts = Some((salt, pepper));
+        self
+    }
+
+    pub fn build(self) -> FedResult<EncryptConfig> {
+        if self.files.is_empty() {
+            return Err(FedError::Usage("no files to encrypt".to_owned()));
+        }
+        if self.output_extension.len() <= 1 || self.output_extension.contains('/') {
+            return Err(FedError::Usage(format!(
+                "invalid output extension '{}'",
+                self.output_extension
+            )));
+        }
+        let compression_count = [EncOption::Zstd, EncOption::Xz, EncOption::NoCompression]
+            .iter()
+            .filter(|&option| self.options.has(option.clone()))
+            .count();
+        if compression_count > 1 {
+            return Err(FedError::Usage(
+                "choose at most one compression option".to_owned(),
+            ));
+        }
+        let mut config = EncryptConfig::new(
+            self.files,
+            self.raw_key,
+            self.options,
+            self.verbosity,
+            self.overwrite,
+            self.delete_input,
+            self.output_dir,
+            self.output_extension,
+            self.dry_run,
+            self.selection,
+            self.archive,
+            resolve_job_count(self.jobs),
+            self.recipients,
+        );
+        if let Some(kdf_cost) = self.kdf_cost {
+            config = config.with_kdf_cost(kdf_cost)?;
+        }
+        if let Some((salt, pepper)) = self.fixed_salts {
+            config = config.with_deterministic(salt, pepper);
+        }
+        Ok(config)
+    }
 }
 
 impl EndecConfig for EncryptConfig {
@@ -73,8 +319,8 @@ impl EndecConfig for EncryptConfig {
         &self.files
     }
 
-    fn raw_key(&self) -> &Key {
-        &self.raw_key
+    fn raw_key(&self) -> FedResult<Key> {
+        Ok(self.raw_key.clone())
     }
 
     fn verbosity(&self) -> Verbosity {
@@ -95,4 +341,56 @@ impl EndecConfig for EncryptConfig {
             None => None,
         }
     }
+
+    fn selection(&self) -> &FileSelection {
+        &self.selection
+    }
+
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_defaults() {
+        let config = EncryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt")
+            .with_output_extension("secret")
+            .build()
+            .unwrap();
+        assert_eq!(config.files(), &[PathBuf::from("a.txt")]);
+        assert_eq!(config.output_extension(), ".secret");
+        assert_eq!(config.verbosity(), Verbosity::Normal);
+        assert_eq!(config.jobs(), 1);
+        assert!(!config.overwrite());
+        assert!(!config.delete_input());
+        assert!(!config.dry_run());
+        assert!(config.archive().is_none());
+    }
+
+    #[test]
+    fn builder_errors() {
+        let no_files = EncryptConfig::builder(Key::new("secret")).build();
+        assert_eq!(no_files.unwrap_err().code(), "usage");
+        let no_extension = EncryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt")
+            .with_output_extension(".")
+            .build();
+        assert_eq!(no_extension.unwrap_err().code(), "usage");
+        let weak_cost = EncryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt")
+            .with_kdf_cost(KdfCost::interactive().with_argon2(Some(1), Some(0)))
+            .build();
+        assert_eq!(weak_cost.unwrap_err().code(), "usage");
+        let two_compressions = EncryptConfig::builder(Key::new("secret"))
+            .with_file("a.txt")
+            .with_option(EncOption::Zstd)
+            .with_option(EncOption::Xz)
+            .build();
+        assert_eq!(two_compressions.unwrap_err().code(), "usage");
+    }
 }
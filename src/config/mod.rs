@@ -1,7 +1,8 @@
 pub use self::dec::DecryptConfig;
+pub use self::dec::DecryptConfigBuilder;
 pub use self::enc::EncryptConfig;
+pub use self::enc::EncryptConfigBuilder;
 pub use self::typ::EndecConfig;
-pub use self::typ::Extension;
 
 pub mod dec;
 pub mod enc;
@@ -2,12 +2,17 @@ use ::std::fmt::Debug;
 use ::std::path::Path;
 use ::std::path::PathBuf;
 
+use ::glob::Pattern;
+
 use crate::header::strategy::Verbosity;
 use crate::key::Key;
+use crate::util::{FedError, FedResult};
 
 #[derive(Debug, Clone, Copy)]
 pub enum Extension<'a> {
     Add(&'a str),
+    /// Replace the name by a random one, and add the extension.
+    Obfuscate(&'a str),
     Strip,
 }
 
@@ -23,10 +28,74 @@ pub enum InputAction {
     Delete,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaAction {
+    Restore,
+    Skip,
+}
+
+/// What to do with archives, which are encrypted files that contain multiple files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveAction {
+    ExtractAll,
+    /// Only extract the members with these names.
+    Extract(Vec<String>),
+    /// Show the names and sizes of the files, without decrypting any data.
+    List,
+    /// Decrypt everything and check the checksums, without writing any files.
+    Verify,
+}
+
+/// Which files to use when directories are given as input.
+#[derive(Debug, Clone, Default)]
+pub struct FileSelection {
+    recursive: bool,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileSelection {
+    pub fn new(recursive: bool, include: &[String], exclude: &[String]) -> FedResult<Self> {
+        let parse = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|glob| {
+                    Pattern::new(glob)
+                        .map_err(|err| FedError::Usage(format!("invalid glob '{}': {}", glob, err)))
+                })
+                .collect::<FedResult<Vec<_>>>()
+        };
+        Ok(FileSelection {
+            recursive,
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+        })
+    }
+
+    pub fn recursive(&self) -> bool {
+        self.recursive
+    }
+
+    /// Whether a file, with path relative to the directory being walked, should be used.
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        let is_included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path));
+        is_included
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path))
+    }
+}
+
 pub trait EndecConfig: Debug {
     fn files(&self) -> &[PathBuf];
 
-    fn raw_key(&self) -> &Key;
+    /// The key, which might only be obtained now, for example by asking for it.
+    fn raw_key(&self) -> FedResult<Key>;
 
     fn verbosity(&self) -> Verbosity;
 
@@ -43,45 +112,9 @@ pub trait EndecConfig: Debug {
     fn delete_input(&self) -> bool;
 
     fn output_dir(&self) -> Option<&Path>;
-}
-
-#[cfg(test)]
-#[derive(Debug)]
-pub struct MockEndecConfig {
-    pub files: Vec<PathBuf>,
-    pub raw_key: Key,
-    pub verbosity: Verbosity,
-    pub overwrite: bool,
-    pub delete_input: bool,
-    pub output_dir: Option<PathBuf>,
-}
-
-#[cfg(test)]
-impl EndecConfig for MockEndecConfig {
-    fn files(&self) -> &[PathBuf] {
-        &self.files
-    }
-
-    fn raw_key(&self) -> &Key {
-        &self.raw_key
-    }
-
-    fn verbosity(&self) -> Verbosity {
-        self.verbosity
-    }
 
-    fn overwrite(&self) -> bool {
-        self.overwrite
-    }
+    fn selection(&self) -> &FileSelection;
 
-    fn delete_input(&self) -> bool {
-        self.delete_input
-    }
-
-    fn output_dir(&self) -> Option<&Path> {
-        match &self.output_dir {
-            Some(dir) => Some(dir.as_path()),
-            None => None,
-        }
-    }
+    /// Number of files to process concurrently.
+    fn jobs(&self) -> usize;
 }
@@ -1,17 +1,34 @@
 use ::std::fmt;
+use ::std::fs::File;
+use ::std::io;
 use ::std::io::stderr;
-use ::std::io::Write;
-use ::std::path::PathBuf;
+use ::std::io::{BufWriter, Read, Write};
+use ::std::path::{Path, PathBuf};
 use ::std::process::exit;
 
+use ::serde_json::to_string_pretty;
 use ::structopt::StructOpt;
 
-use ::file_endec::decrypt;
+use ::file_endec::checksum_error;
+use ::file_endec::decrypt_report;
+use ::file_endec::decrypt_stream;
+use ::file_endec::default_agent_socket;
+use ::file_endec::failure_error;
+use ::file_endec::reports_to_json;
+use ::file_endec::AgentClient;
+use ::file_endec::ArchiveAction;
 use ::file_endec::DecryptConfig;
+use ::file_endec::DeferredKey;
+use ::file_endec::FedError;
 use ::file_endec::FedResult;
-use ::file_endec::Key;
+use ::file_endec::FileReport;
+use ::file_endec::FileSelection;
+use ::file_endec::InputAction;
 use ::file_endec::KeySource;
+use ::file_endec::MetaAction;
+use ::file_endec::OnFileExist;
 use ::file_endec::Verbosity;
+use ::file_endec::STREAM_PATH;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -25,7 +42,7 @@ pub struct DecryptArguments {
         parse(from_os_str),
         required = true,
         min_values = 1,
-        help = "One or more paths to encrypted input files (absolute or relative)"
+        help = "One or more paths to encrypted input files (absolute or relative), or '-' for stdin with --stdout"
     )]
     files: Vec<PathBuf>,
 
@@ -33,10 +50,17 @@ pub struct DecryptArguments {
         short = "k",
         long = "key",
         default_value = "ask",
-        help = "Where to get the key; one of 'pass:$password', 'env:$var_name', 'file:$path', 'ask', 'ask-once', 'pipe'"
+        help = "Where to get the key; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'identity:$path', 'ask', 'ask-once', 'pipe', 'agent'"
     )]
     key_source: KeySource,
 
+    #[structopt(
+        short = "K",
+        long,
+        help = "Second key that is mixed into --key, so that both are needed to decrypt; usually 'keyfile:$path', which can be any file."
+    )]
+    keyfile: Option<KeySource>,
+
     #[structopt(
         short = "v",
         long,
@@ -52,6 +76,20 @@ pub struct DecryptArguments {
     )]
     quiet: bool,
 
+    #[structopt(
+        conflicts_with_all = &["debug", "list"],
+        long,
+        help = "Print the result for each file as json, instead of progress (implies --quiet)."
+    )]
+    json: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["debug", "json", "output-dir", "list", "verify", "member", "delete-input", "recursive"],
+        help = "Write the decrypted data to stdout instead of to a file. There should be one input, which can be '-' for stdin."
+    )]
+    stdout: bool,
+
     #[structopt(short = "f", long, help = "Overwrite output files if they exist.")]
     overwrite: bool,
 
@@ -69,6 +107,70 @@ pub struct DecryptArguments {
         help = "Alternative output directory. If not given, output is saved alongside input."
     )]
     output_dir: Option<PathBuf>,
+
+    #[structopt(
+        short = "r",
+        long,
+        help = "Also decrypt files in directories, recursively. Output mirrors the directory layout."
+    )]
+    recursive: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "With --recursive, only include files whose relative path matches this glob (can be repeated)."
+    )]
+    include: Vec<String>,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "With --recursive, skip files whose relative path matches this glob (can be repeated)."
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long,
+        default_value = ".enc",
+        help = "With --recursive, the extension of the encrypted files to decrypt."
+    )]
+    input_extension: String,
+
+    #[structopt(
+        short = "l",
+        long,
+        help = "Only show the names and sizes of the encrypted files, or of the files in archives."
+    )]
+    list: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["list", "delete-input"],
+        help = "Decrypt in memory and check the checksums, without writing anything. Fails if any file does not match."
+    )]
+    verify: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["list", "verify", "delete-input"],
+        help = "Only extract the file with this name from an archive (can be repeated)."
+    )]
+    member: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Do not restore the original permissions and timestamps of decrypted files."
+    )]
+    no_restore_meta: bool,
+
+    #[structopt(
+        short = "j",
+        long,
+        default_value = "1",
+        help = "Number of files to process at the same time. Use 0 for one per CPU core."
+    )]
+    jobs: usize,
 }
 
 impl fmt::Display for DecryptArguments {
@@ -88,11 +190,36 @@ impl fmt::Display for DecryptArguments {
             )?,
         }
 
+        writeln!(
+            f,
+            "* recursive: {}",
+            if self.recursive { "yes" } else { "no" }
+        )?;
+        for pattern in &self.include {
+            writeln!(f, "  - include: {}", pattern)?;
+        }
+        for pattern in &self.exclude {
+            writeln!(f, "  - exclude: {}", pattern)?;
+        }
+        writeln!(f, "  - extension: {}", &self.input_extension)?;
+
+        writeln!(
+            f,
+            "* jobs: {}",
+            if self.jobs == 0 {
+                "one per CPU core".to_owned()
+            } else {
+                self.jobs.to_string()
+            }
+        )?;
+
         writeln!(
             f,
             "* logging: {}",
             if self.debug {
                 "verbose"
+            } else if self.json {
+                "json"
             } else if self.quiet {
                 "quiet"
             } else {
@@ -112,6 +239,25 @@ impl fmt::Display for DecryptArguments {
             if self.delete_input { "yes" } else { "no" }
         )?;
 
+        if self.list {
+            writeln!(f, "* list contents: yes (nothing is decrypted)")?;
+        } else if self.verify {
+            writeln!(f, "* verify: yes (nothing is written)")?;
+        } else if self.member.is_empty() {
+            writeln!(f, "* archive members: all")?;
+        } else {
+            writeln!(f, "* archive members:")?;
+            for member in &self.member {
+                writeln!(f, "  - {}", member)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "* restore metadata: {}",
+            if self.no_restore_meta { "no" } else { "yes" }
+        )?;
+
         Ok(())
     }
 }
@@ -119,61 +265,192 @@ impl fmt::Display for DecryptArguments {
 pub fn main() {
     let args = DecryptArguments::from_args();
     if let Err(err) = go_decrypt(args) {
-        stderr().write_all(err.as_bytes()).unwrap();
+        stderr().write_all(err.message().as_bytes()).unwrap();
         stderr().write_all(b"\n").unwrap();
-        exit(1);
+        exit(err.exit_code());
     }
 }
 
 impl DecryptArguments {
-    fn convert(self, key: Key) -> FedResult<DecryptConfig> {
-        let verbosity = match (self.debug, self.quiet) {
-            (true, true) => return Err("cannot use quiet mode and debug mode together".to_owned()),
+    fn convert(self, key: impl Into<DeferredKey>) -> FedResult<DecryptConfig> {
+        let verbosity = match (self.debug, self.quiet || self.json || self.stdout) {
+            (true, true) => {
+                return Err(FedError::Usage(
+                    "cannot use quiet mode and debug mode together".to_owned(),
+                ))
+            }
             (true, false) => Verbosity::Debug,
             (false, true) => Verbosity::Quiet,
             (false, false) => Verbosity::Normal,
         };
-        Ok(DecryptConfig::new(
-            self.files,
-            key,
-            verbosity,
-            self.overwrite,
-            self.delete_input,
-            self.output_dir,
-        ))
+        let selection = FileSelection::new(self.recursive, &self.include, &self.exclude)?;
+        let mut builder = DecryptConfig::builder(key)
+            .with_files(self.files)
+            .with_verbosity(verbosity)
+            .with_overwrite(if self.overwrite {
+                OnFileExist::Overwrite
+            } else {
+                OnFileExist::Fail
+            })
+            .with_delete_input(if self.delete_input {
+                InputAction::Delete
+            } else {
+                InputAction::Keep
+            })
+            .with_restore_meta(if self.no_restore_meta {
+                MetaAction::Skip
+            } else {
+                MetaAction::Restore
+            })
+            .with_selection(selection)
+            .with_archive_action(if self.list {
+                ArchiveAction::List
+            } else if self.verify {
+                ArchiveAction::Verify
+            } else if !self.member.is_empty() {
+                ArchiveAction::Extract(self.member)
+            } else {
+                ArchiveAction::ExtractAll
+            })
+            .with_input_extension(&self.input_extension)
+            .with_jobs(self.jobs);
+        if let Some(output_dir) = self.output_dir {
+            builder = builder.with_output_dir(output_dir);
+        }
+        if self.key_source == KeySource::Agent {
+            builder = builder.with_agent(AgentClient::new(default_agent_socket()?));
+        }
+        builder.build()
     }
 }
 
-//TODO: if wildcards or directories are ever supported, then skip files that have the encrypted extension (i.e. .enc)
-
+/// With --json, the result is printed even if there was an error, so that scripts can always parse it.
+/// Files whose checksum did not match are reported, and also make the run fail.
 fn go_decrypt(args: DecryptArguments) -> FedResult<()> {
+    let json = args.json;
+    let result = decrypt_files(args);
+    let error = match &result {
+        Ok(reports) => failure_error(reports).or_else(|| checksum_error(reports)),
+        Err(err) => Some(err.clone()),
+    };
+    if json {
+        let reports = result.as_deref().unwrap_or(&[]);
+        let json = reports_to_json(reports, error.as_ref());
+        println!("{}", to_string_pretty(&json).unwrap());
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn decrypt_files(args: DecryptArguments) -> FedResult<Vec<FileReport>> {
+    check_stream_args(
+        &args.files,
+        &args.key_source,
+        args.keyfile.as_ref(),
+        args.stdout,
+    )?;
+    if args.stdout {
+        decrypt_to_stdout(args)?;
+        return Ok(vec![]);
+    }
     if args.debug {
         println!("arguments provided:\n{}", args);
     }
-    let key = args.key_source.obtain_key()?;
+    let key = obtain_decryption_key(&args)?;
+    let config = args.convert(key)?;
+    decrypt_report(&config)
+}
+
+/// With the agent, the key is only asked for if the agent does not have all the stretched keys.
+fn obtain_decryption_key(args: &DecryptArguments) -> FedResult<DeferredKey> {
+    if args.key_source == KeySource::Agent {
+        return Ok(DeferredKey::new(KeySource::Agent, args.keyfile.clone()));
+    }
+    let key = args.key_source.obtain_combined_key(args.keyfile.as_ref())?;
     if args.debug {
         println!("approximate time to crack key: {}", key.time_to_crack());
     }
-    let config = args.convert(key)?;
-    decrypt(&config)?;
+    Ok(key.into())
+}
+
+/// Stdin can only be used as a stream, and then it cannot also provide the key.
+fn check_stream_args(
+    files: &[PathBuf],
+    key_source: &KeySource,
+    keyfile: Option<&KeySource>,
+    stdout: bool,
+) -> FedResult<()> {
+    let is_stdin = files.iter().any(|pth| pth == Path::new(STREAM_PATH));
+    if is_stdin && !stdout {
+        return Err(FedError::Usage(format!(
+            "input '{}' (stdin) can only be used together with --stdout",
+            STREAM_PATH
+        )));
+    }
+    if stdout && files.len() != 1 {
+        return Err(FedError::Usage(
+            "with --stdout, there should be exactly one input".to_owned(),
+        ));
+    }
+    if is_stdin && (key_source == &KeySource::Pipe || keyfile == Some(&KeySource::Pipe)) {
+        return Err(FedError::Usage(
+            "the key cannot be piped when the data is read from stdin; \
+            use for example 'env:$var_name' or 'file:$path'"
+                .to_owned(),
+        ));
+    }
     Ok(())
 }
 
+/// Open the input for --stdout, which is either stdin or a file.
+fn open_stream_input(pth: &Path) -> FedResult<Box<dyn Read>> {
+    if pth == Path::new(STREAM_PATH) {
+        return Ok(Box::new(io::stdin()));
+    }
+    match File::open(pth) {
+        Ok(file) => Ok(Box::new(file)),
+        Err(err) => Err(FedError::NotFound(format!(
+            "could not open input file {}: {}",
+            pth.to_string_lossy(),
+            err
+        ))),
+    }
+}
+
+fn decrypt_to_stdout(args: DecryptArguments) -> FedResult<()> {
+    let input = open_stream_input(&args.files[0])?;
+    let key = obtain_decryption_key(&args)?;
+    let config = args.convert(key)?;
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    decrypt_stream(input, &mut output, &config)?;
+    output
+        .flush()
+        .map_err(|err| FedError::Io(format!("could not write to stdout: {}", err)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ::file_endec::EndecConfig;
+    use ::file_endec::Key;
 
     #[test]
     fn parse_args_minimal() {
         let args = DecryptArguments::from_iter(&["fileenc", "file.txt"]);
         let config = args.convert(Key::new("abcdef123!")).unwrap();
         assert!(config.files().contains(&PathBuf::from("file.txt")));
-        assert_eq!(config.raw_key().key_data.unsecure(), "abcdef123!");
+        assert_eq!(config.raw_key().unwrap().key_data.unsecure(), "abcdef123!");
         assert_eq!(config.verbosity(), Verbosity::Normal);
-        assert_eq!(config.overwrite(), false);
-        assert_eq!(config.delete_input(), false);
+        assert!(!config.overwrite());
+        assert!(!config.delete_input());
         assert_eq!(config.output_dir(), None);
+        assert!(config.restore_meta());
+        assert!(!config.selection().recursive());
+        assert_eq!(config.archive_action(), &ArchiveAction::ExtractAll);
+        assert_eq!(config.jobs(), 1);
     }
 
     #[test]
@@ -186,16 +463,126 @@ mod tests {
             "-f",
             "-o",
             "/tmp/hello",
+            "--no-restore-meta",
+            "--recursive",
+            "--exclude",
+            "*.png.enc",
+            "--jobs",
+            "0",
         ]);
         let config = args.convert(Key::new("abcdef123!")).unwrap();
         assert!(config.files().contains(&PathBuf::from("file.txt")));
-        assert_eq!(config.raw_key().key_data.unsecure(), "abcdef123!");
+        assert_eq!(config.raw_key().unwrap().key_data.unsecure(), "abcdef123!");
         assert_eq!(config.verbosity(), Verbosity::Quiet);
-        assert_eq!(config.overwrite(), true);
-        assert_eq!(config.delete_input(), true);
+        assert!(config.overwrite());
+        assert!(config.delete_input());
         assert_eq!(
             config.output_dir(),
             Some(PathBuf::from("/tmp/hello").as_path())
         );
+        assert!(!config.restore_meta());
+        assert!(config.selection().recursive());
+        assert!(!config.selection().matches(Path::new("image.png.enc")));
+        assert_eq!(config.archive_action(), &ArchiveAction::ExtractAll);
+        assert!(config.jobs() >= 1);
+    }
+
+    #[test]
+    fn parse_args_list() {
+        let args = DecryptArguments::from_iter(&["fileenc", "archive.enc", "-l", "-d"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.archive_action(), &ArchiveAction::List);
+        assert!(!config.delete_input());
+    }
+
+    #[test]
+    fn parse_args_verify() {
+        let args = DecryptArguments::from_iter(&["fileenc", "archive.enc", "--verify"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.archive_action(), &ArchiveAction::Verify);
+        assert!(!config.delete_input());
+        assert!(DecryptArguments::from_iter_safe(&["fileenc", "a.enc", "--verify", "-l"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_json() {
+        let args = DecryptArguments::from_iter(&["fileenc", "a.enc", "--json", "--verify"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.verbosity(), Verbosity::Quiet);
+        assert_eq!(config.archive_action(), &ArchiveAction::Verify);
+        assert!(DecryptArguments::from_iter_safe(&["fileenc", "a.enc", "--json", "-l"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_keyfile() {
+        let args = DecryptArguments::from_iter(&[
+            "filedec",
+            "file.txt.enc",
+            "-k",
+            "env:KEY",
+            "--keyfile",
+            "keyfile:/media/key.bin",
+        ]);
+        assert_eq!(KeySource::EnvVar("KEY".to_owned()), args.key_source);
+        assert_eq!(
+            Some(KeySource::KeyFile(PathBuf::from("/media/key.bin"))),
+            args.keyfile
+        );
+    }
+
+    #[test]
+    fn parse_args_stdout() {
+        let args = DecryptArguments::from_iter(&["filedec", "-", "--stdout", "-k", "env:KEY"]);
+        check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout,
+        )
+        .unwrap();
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.verbosity(), Verbosity::Quiet);
+        let args = DecryptArguments::from_iter(&["filedec", "-", "--stdout", "-k", "pipe"]);
+        assert!(check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout
+        )
+        .is_err());
+        let args = DecryptArguments::from_iter(&["filedec", "-"]);
+        assert!(check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout
+        )
+        .is_err());
+        assert!(DecryptArguments::from_iter_safe(&["filedec", "-", "--stdout", "-l"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_members() {
+        let args = DecryptArguments::from_iter(&[
+            "fileenc",
+            "archive.enc",
+            "--member",
+            "a.txt",
+            "--member",
+            "sub/b.txt",
+        ]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(
+            config.archive_action(),
+            &ArchiveAction::Extract(vec!["a.txt".to_owned(), "sub/b.txt".to_owned()])
+        );
+        assert!(DecryptArguments::from_iter_safe(&[
+            "fileenc",
+            "archive.enc",
+            "--member",
+            "a",
+            "-d"
+        ])
+        .is_err());
     }
 }
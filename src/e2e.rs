@@ -9,6 +9,7 @@ use ::std::path::PathBuf;
 use ::tempfile::{NamedTempFile, TempDir};
 
 use crate::files::mockfile::write_test_file;
+use crate::files::scan::get_enc_files_direct;
 use crate::util::test_cmd::filename_append_enc;
 use crate::util::test_cmd::test_decrypt;
 use crate::util::test_cmd::test_encrypt;
@@ -17,15 +18,6 @@ mod compatibility {
     use std::path::PathBuf;
     // These tests are generated by `build.rs`.
     include!(concat!(env!("OUT_DIR"), "/compatibility_tests.rs"));
-
-    fn tmp() {
-        //TODO @mark: TEMPORARY! REMOVE THIS!
-        let enc_pth = PathBuf::from("/home/mark/file_endec/test_files/original_v1.0.0.png.enc"); //TODO @mark: TEMPORARY! REMOVE THIS!
-        let mut original_pth = enc_pth.clone();
-        original_pth.pop();
-        original_pth.push("original.png".to_owned());
-        panic!();
-    }
 }
 
 /// This test is always enabled as a fast encrypy/decrypt cycle (using -s mode, a small file and -q).
@@ -33,7 +25,6 @@ mod compatibility {
 fn fast() {
     let key = "3Q#J3RwOIns@MK9TQDwZkpUK-EmH7T07";
     let (tmp, raw_pth, data) = write_test_file(1024);
-    let enc_pth = filename_append_enc(raw_pth.as_path());
     test_encrypt(
         &[raw_pth.as_path()],
         &[
@@ -46,17 +37,19 @@ fn fast() {
         ],
         None,
     );
-    assert!(enc_pth.as_path().exists());
+    // The metadata is hidden, so the encrypted file has a random name.
+    let enc_pth = get_enc_files_direct(tmp.path()).unwrap().remove(0);
+    assert_ne!(enc_pth, filename_append_enc(raw_pth.as_path()));
     assert!(!raw_pth.as_path().exists());
     test_decrypt(
-        &[raw_pth.as_path()],
+        &[enc_pth.as_path()],
         &["-k", &format!("pass:{}", key), "-d", "-q"],
         None,
-        true,
+        false,
     );
     assert!(!enc_pth.as_path().exists());
-    assert!(raw_pth.as_path().exists());
-    assert_eq!(fs::read(raw_pth.as_path()).unwrap(), data);
+    let dec_pth = enc_pth.with_extension("");
+    assert_eq!(fs::read(dec_pth.as_path()).unwrap(), data);
     tmp.close().unwrap();
 }
 
@@ -184,9 +177,9 @@ fn many_files() {
         true,
     );
     for (_, path, data) in &files {
-        assert!(filename_append_enc(&path).exists());
+        assert!(filename_append_enc(path).exists());
         assert!(path.exists());
-        assert_eq!(&fs::read(&path).unwrap(), data);
+        assert_eq!(&fs::read(path).unwrap(), data);
     }
     files.into_iter().for_each(|f| f.0.close().unwrap());
 }
@@ -267,7 +260,7 @@ fn output_dir_multi_salt() {
         let enc_pth = {
             let mut p = path.2.clone();
             let name = p.file_name().unwrap().to_str().unwrap().to_owned();
-            p.set_file_name(&name[..name.len() - 4].to_owned());
+            p.set_file_name(&name[..name.len() - 4]);
             p
         };
         assert!(enc_pth.exists());
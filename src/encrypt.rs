@@ -1,30 +1,85 @@
 use ::std::fmt;
-use ::std::path::PathBuf;
+use ::std::fs::File;
+use ::std::io;
+use ::std::io::{BufReader, BufWriter, Read, Write};
+use ::std::path::{Path, PathBuf};
 use ::std::process::exit;
+use ::std::time::Duration;
 
+use ::serde_json::{to_string_pretty, Value};
+use ::structopt::clap::AppSettings;
 use ::structopt::StructOpt;
 
-use ::file_endec::encrypt;
+use ::file_endec::add_key;
+use ::file_endec::default_agent_socket;
+use ::file_endec::encrypt_report;
+use ::file_endec::encrypt_stream;
+use ::file_endec::failure_error;
+use ::file_endec::get_current_version_strategy;
+use ::file_endec::inspect_headers;
+use ::file_endec::rekey;
+use ::file_endec::remove_key;
+use ::file_endec::reports_to_json;
+use ::file_endec::run_agent;
+use ::file_endec::write_identity_file;
+use ::file_endec::AgentClient;
 use ::file_endec::EncOption;
+use ::file_endec::EncOptionSet;
 use ::file_endec::EncryptConfig;
+use ::file_endec::FedError;
 use ::file_endec::FedResult;
+use ::file_endec::FileReport;
+use ::file_endec::FileSelection;
+use ::file_endec::InputAction;
+use ::file_endec::KdfCost;
 use ::file_endec::Key;
 use ::file_endec::KeySource;
+use ::file_endec::OnFileExist;
+use ::file_endec::Recipient;
+use ::file_endec::RunMode;
 use ::file_endec::Verbosity;
+use ::file_endec::STREAM_PATH;
 
+/// Encrypts files, unless the first argument is one of the commands. A file with the name
+/// of a command can be encrypted as './info' or after '--'. External subcommands are allowed
+/// only so that file names that look like a command are not rejected; files take precedence.
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "FileEnc",
     author = "github.com/mverleg/file_endec",
-    about = "Securely encrypt one or more files using the given key."
+    about = "Securely encrypt one or more files using the given key. Use 'fileenc info' to show the headers of encrypted files, 'fileenc keygen' to create a key pair for --recipient, 'fileenc rekey' to change the key of encrypted files, and 'fileenc add-key' or 'fileenc remove-key' to change the keys of files with --key-slots. Use 'fileenc agent' to remember stretched keys between runs of 'filedec --key agent'. To encrypt a file that has the name of a command, use for example './info' or put it after '--'.",
+    setting = AppSettings::SubcommandsNegateReqs,
+    setting = AppSettings::ArgsNegateSubcommands,
+    setting = AppSettings::DisableHelpSubcommand,
+    setting = AppSettings::AllowExternalSubcommands
 )]
+pub struct FileEncArguments {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    #[structopt(flatten)]
+    encrypt: EncryptArguments,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    Keygen(KeygenArguments),
+    AddKey(AddKeyArguments),
+    RemoveKey(RemoveKeyArguments),
+    Rekey(RekeyArguments),
+    Info(InfoArguments),
+    Agent(AgentArguments),
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "FileEnc", author = "github.com/mverleg/file_endec")]
 pub struct EncryptArguments {
     #[structopt(
         name = "FILES",
         parse(from_os_str),
         required = true,
         min_values = 1,
-        help = "One or more paths to input files (absolute or relative)"
+        help = "One or more paths to input files (absolute or relative), or '-' for stdin with --stdout"
     )]
     files: Vec<PathBuf>,
 
@@ -32,10 +87,17 @@ pub struct EncryptArguments {
         short = "k",
         long = "key",
         default_value = "ask",
-        help = "Where to get the key; one of 'pass:$password', 'env:$var_name', 'file:$path', 'ask', 'ask-once', 'pipe'"
+        help = "Where to get the key; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'ask', 'ask-once', 'pipe'"
     )]
     key_source: KeySource,
 
+    #[structopt(
+        short = "K",
+        long,
+        help = "Second key that is mixed into --key, so that both are needed to decrypt; usually 'keyfile:$path', which can be any file."
+    )]
+    keyfile: Option<KeySource>,
+
     #[structopt(
         short = "v",
         long,
@@ -51,6 +113,20 @@ pub struct EncryptArguments {
     )]
     quiet: bool,
 
+    #[structopt(
+        conflicts_with = "debug",
+        long,
+        help = "Print the result for each file as json, instead of progress (implies --quiet)."
+    )]
+    json: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["debug", "json", "output-dir", "archive", "dry-run", "delete-input", "hide-size", "recursive"],
+        help = "Write the encrypted data to stdout instead of to a file. There should be one input, which can be '-' for stdin."
+    )]
+    stdout: bool,
+
     #[structopt(short = "f", long, help = "Overwrite output files if they exist.")]
     overwrite: bool,
 
@@ -61,7 +137,10 @@ pub struct EncryptArguments {
     )]
     delete_input: bool,
 
-    #[structopt(long, help = "Hide name, timestamp and permissions.")]
+    #[structopt(
+        long,
+        help = "Hide name, timestamp and permissions. With --output-dir, directories are not mirrored."
+    )]
     hide_meta: bool,
 
     #[structopt(
@@ -70,6 +149,12 @@ pub struct EncryptArguments {
     )]
     hide_size: bool,
 
+    #[structopt(
+        long,
+        help = "Encrypt with a random key, stored in a key slot that the key unlocks. Keys can then be added or removed later with 'fileenc add-key' and 'fileenc remove-key'."
+    )]
+    key_slots: bool,
+
     #[structopt(
         short = "s",
         long,
@@ -77,6 +162,33 @@ pub struct EncryptArguments {
     )]
     fast: bool,
 
+    #[structopt(
+        long,
+        default_value = "brotli",
+        possible_values = &["brotli", "zstd", "xz", "none"],
+        help = "Compression algorithm: 'zstd' is fast, 'xz' is dense, 'brotli' is in between."
+    )]
+    compression: String,
+
+    #[structopt(
+        long,
+        possible_values = &["interactive", "moderate", "paranoid"],
+        help = "How hard to make guessing the key. 'interactive' is quick, 'paranoid' can take minutes and a gigabyte of memory. The cost is stored in the file, so decryption uses the same."
+    )]
+    kdf_cost: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Memory in MiB for key stretching with argon2, instead of the amount for --kdf-cost."
+    )]
+    kdf_memory: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Number of argon2 passes for key stretching, instead of the number for --kdf-cost."
+    )]
+    kdf_iterations: Option<u32>,
+
     #[structopt(
         parse(from_os_str),
         short = "o",
@@ -85,6 +197,41 @@ pub struct EncryptArguments {
     )]
     output_dir: Option<PathBuf>,
 
+    #[structopt(
+        short = "r",
+        long,
+        help = "Also encrypt files in directories, recursively. Output mirrors the directory layout."
+    )]
+    recursive: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "With --recursive, only include files whose relative path matches this glob (can be repeated)."
+    )]
+    include: Vec<String>,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "With --recursive, skip files whose relative path matches this glob (can be repeated)."
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        parse(from_os_str),
+        long,
+        help = "Pack all files into a single encrypted archive at this path, which hides how many files there are, and their names and sizes."
+    )]
+    archive: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Encrypt for this public key ('fedpub:...', or a file that contains it) instead of with a password (can be repeated)."
+    )]
+    recipient: Vec<String>,
+
     #[structopt(
         long,
         default_value = ".enc",
@@ -100,6 +247,14 @@ pub struct EncryptArguments {
 
     #[structopt(long, help = "Suppress warning if the encryption key is not strong.")]
     accept_weak_key: bool,
+
+    #[structopt(
+        short = "j",
+        long,
+        default_value = "1",
+        help = "Number of files to process at the same time. Use 0 for one per CPU core."
+    )]
+    jobs: usize,
 }
 
 impl fmt::Display for EncryptArguments {
@@ -121,6 +276,26 @@ impl fmt::Display for EncryptArguments {
 
         writeln!(f, "* extension: {}", &self.output_extension)?;
 
+        match &self.archive {
+            Some(archive) => writeln!(f, "* archive: {}", archive.to_string_lossy().as_ref())?,
+            None => writeln!(f, "* archive: no (one output file per input file)")?,
+        }
+
+        writeln!(
+            f,
+            "* key slots: {}",
+            if self.key_slots { "yes" } else { "no" }
+        )?;
+
+        if self.recipient.is_empty() {
+            writeln!(f, "* recipients: none (encrypt with key)")?;
+        } else {
+            writeln!(f, "* recipients:")?;
+            for recipient in &self.recipient {
+                writeln!(f, "  - {}", recipient)?;
+            }
+        }
+
         writeln!(
             f,
             "* hide metadata: {}",
@@ -134,11 +309,49 @@ impl fmt::Display for EncryptArguments {
 
         writeln!(f, "* fast mode: {}", if self.fast { "YES" } else { "no" })?;
 
+        writeln!(f, "* compression: {}", self.compression)?;
+
+        writeln!(
+            f,
+            "* key stretching cost: {}",
+            self.kdf_cost.as_deref().unwrap_or("default")
+        )?;
+        if let Some(memory) = self.kdf_memory {
+            writeln!(f, "  - argon2 memory: {} MiB", memory)?;
+        }
+        if let Some(iterations) = self.kdf_iterations {
+            writeln!(f, "  - argon2 passes: {}", iterations)?;
+        }
+
+        writeln!(
+            f,
+            "* recursive: {}",
+            if self.recursive { "yes" } else { "no" }
+        )?;
+        for pattern in &self.include {
+            writeln!(f, "  - include: {}", pattern)?;
+        }
+        for pattern in &self.exclude {
+            writeln!(f, "  - exclude: {}", pattern)?;
+        }
+
+        writeln!(
+            f,
+            "* jobs: {}",
+            if self.jobs == 0 {
+                "one per CPU core".to_owned()
+            } else {
+                self.jobs.to_string()
+            }
+        )?;
+
         writeln!(
             f,
             "* logging: {}",
             if self.debug {
                 "verbose"
+            } else if self.json {
+                "json"
             } else if self.quiet {
                 "quiet"
             } else {
@@ -188,18 +401,294 @@ impl fmt::Display for EncryptArguments {
     }
 }
 
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc agent",
+    author = "github.com/mverleg/file_endec",
+    about = "Remember stretched keys for a while, so that 'filedec --key agent' does not ask for the key of files that were decrypted before, and decrypts them faster. The key itself is not remembered, so it only helps decryption. The socket is in XDG_RUNTIME_DIR, or at FILE_ENDEC_AGENT in a private directory. It runs until it is stopped; start it in the background with 'fileenc agent &'."
+)]
+pub struct AgentArguments {
+    #[structopt(
+        short = "t",
+        long,
+        default_value = "15",
+        help = "Minutes after which keys are forgotten."
+    )]
+    timeout: u64,
+
+    #[structopt(long, help = "Stop the agent that is running.")]
+    stop: bool,
+
+    #[structopt(
+        long,
+        conflicts_with = "stop",
+        help = "Make the running agent forget all stretched keys, without stopping it."
+    )]
+    forget: bool,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc keygen",
+    author = "github.com/mverleg/file_endec",
+    about = "Create an identity (private key) file, and show the public key to encrypt for it."
+)]
+pub struct KeygenArguments {
+    #[structopt(
+        name = "IDENTITY_FILE",
+        parse(from_os_str),
+        help = "Where to store the private key. Decrypt using '--key identity:$path'."
+    )]
+    output: PathBuf,
+
+    #[structopt(short = "f", long, help = "Overwrite the identity file if it exists.")]
+    overwrite: bool,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc add-key",
+    author = "github.com/mverleg/file_endec",
+    about = "Add a key to files that were encrypted with --key-slots. Only the header is changed."
+)]
+pub struct AddKeyArguments {
+    #[structopt(
+        name = "FILES",
+        parse(from_os_str),
+        required = true,
+        min_values = 1,
+        help = "One or more encrypted files"
+    )]
+    files: Vec<PathBuf>,
+
+    #[structopt(
+        short = "k",
+        long = "key",
+        default_value = "ask-once",
+        help = "One of the current keys of the files; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'ask', 'ask-once', 'pipe'"
+    )]
+    key_source: KeySource,
+
+    #[structopt(
+        short = "K",
+        long,
+        help = "Second key that belongs to --key, if there is one; usually 'keyfile:$path'."
+    )]
+    keyfile: Option<KeySource>,
+
+    #[structopt(
+        short = "n",
+        long = "new-key",
+        default_value = "ask",
+        help = "The key to add, in the same format as --key"
+    )]
+    new_key_source: KeySource,
+
+    #[structopt(
+        short = "N",
+        long,
+        help = "Second key that is mixed into --new-key, so that both are needed to decrypt; usually 'keyfile:$path', which can be any file."
+    )]
+    new_keyfile: Option<KeySource>,
+
+    #[structopt(short = "v", long, help = "Show debug information.")]
+    debug: bool,
+
+    #[structopt(
+        conflicts_with = "debug",
+        short = "q",
+        long = "quiet",
+        help = "Do not show non-critical output."
+    )]
+    quiet: bool,
+
+    #[structopt(long, help = "Suppress warning if the new key is not strong.")]
+    accept_weak_key: bool,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc remove-key",
+    author = "github.com/mverleg/file_endec",
+    about = "Remove a key from files that were encrypted with --key-slots. Only the header is changed."
+)]
+pub struct RemoveKeyArguments {
+    #[structopt(
+        name = "FILES",
+        parse(from_os_str),
+        required = true,
+        min_values = 1,
+        help = "One or more encrypted files"
+    )]
+    files: Vec<PathBuf>,
+
+    #[structopt(
+        short = "k",
+        long = "key",
+        default_value = "ask-once",
+        help = "The key to remove; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'ask', 'ask-once', 'pipe'"
+    )]
+    key_source: KeySource,
+
+    #[structopt(
+        short = "K",
+        long,
+        help = "Second key that belongs to --key, if there is one; usually 'keyfile:$path'."
+    )]
+    keyfile: Option<KeySource>,
+
+    #[structopt(short = "v", long, help = "Show debug information.")]
+    debug: bool,
+
+    #[structopt(
+        conflicts_with = "debug",
+        short = "q",
+        long = "quiet",
+        help = "Do not show non-critical output."
+    )]
+    quiet: bool,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc rekey",
+    author = "github.com/mverleg/file_endec",
+    about = "Change the key of encrypted files in place, without writing the decrypted data anywhere. Files with --key-slots only get a new header."
+)]
+pub struct RekeyArguments {
+    #[structopt(
+        name = "FILES",
+        parse(from_os_str),
+        required = true,
+        min_values = 1,
+        help = "One or more encrypted files"
+    )]
+    files: Vec<PathBuf>,
+
+    #[structopt(
+        short = "k",
+        long = "key",
+        default_value = "ask-once",
+        help = "The current key of the files; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'ask', 'ask-once', 'pipe'"
+    )]
+    key_source: KeySource,
+
+    #[structopt(
+        short = "K",
+        long,
+        help = "Second current key of the files, if they were encrypted with one; usually 'keyfile:$path'."
+    )]
+    keyfile: Option<KeySource>,
+
+    #[structopt(
+        short = "n",
+        long = "new-key",
+        default_value = "ask",
+        help = "The new key, in the same format as --key"
+    )]
+    new_key_source: KeySource,
+
+    #[structopt(
+        short = "N",
+        long,
+        help = "Second key that is mixed into --new-key, so that both are needed to decrypt; usually 'keyfile:$path', which can be any file."
+    )]
+    new_keyfile: Option<KeySource>,
+
+    #[structopt(short = "v", long, help = "Show debug information.")]
+    debug: bool,
+
+    #[structopt(
+        conflicts_with = "debug",
+        short = "q",
+        long = "quiet",
+        help = "Do not show non-critical output."
+    )]
+    quiet: bool,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "FileEnc info",
+    author = "github.com/mverleg/file_endec",
+    about = "Show the headers of encrypted files, without decrypting the data. The public header needs no key; with --key, the private header is shown too."
+)]
+pub struct InfoArguments {
+    #[structopt(
+        name = "FILES",
+        parse(from_os_str),
+        required = true,
+        min_values = 1,
+        help = "One or more encrypted files"
+    )]
+    files: Vec<PathBuf>,
+
+    #[structopt(
+        short = "k",
+        long = "key",
+        help = "Key to also show the private header; one of 'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'identity:$path', 'ask', 'ask-once', 'pipe'"
+    )]
+    key_source: Option<KeySource>,
+
+    #[structopt(
+        short = "K",
+        long,
+        requires = "key-source",
+        help = "Second key that belongs to --key, if there is one; usually 'keyfile:$path'."
+    )]
+    keyfile: Option<KeySource>,
+
+    #[structopt(long, help = "Print the information as json.")]
+    json: bool,
+
+    #[structopt(short = "v", long, help = "Show debug information.")]
+    debug: bool,
+}
+
+fn verbosity(debug: bool, quiet: bool) -> Verbosity {
+    if debug {
+        Verbosity::Debug
+    } else if quiet {
+        Verbosity::Quiet
+    } else {
+        Verbosity::Normal
+    }
+}
+
+/// Show what the key is for, if it is asked for interactively.
+fn obtain_key_for(source: &KeySource, second: Option<&KeySource>, purpose: &str) -> FedResult<Key> {
+    let asks = |source: &KeySource| matches!(source, KeySource::AskOnce | KeySource::AskTwice);
+    if asks(source) || second.is_some_and(asks) {
+        println!("{}", purpose);
+    }
+    source.obtain_combined_key(second)
+}
+
 pub fn main() {
-    let args = EncryptArguments::from_args();
-    if let Err(err) = go_encrypt(args) {
+    let args = FileEncArguments::from_args();
+    let result = match args.command {
+        Some(Command::Keygen(args)) => go_keygen(args),
+        Some(Command::AddKey(args)) => go_add_key(args),
+        Some(Command::RemoveKey(args)) => go_remove_key(args),
+        Some(Command::Rekey(args)) => go_rekey(args),
+        Some(Command::Info(args)) => go_info(args),
+        Some(Command::Agent(args)) => go_agent(args),
+        None => go_encrypt(args.encrypt),
+    };
+    if let Err(err) = result {
         eprintln!("{}", err);
-        exit(1);
+        exit(err.exit_code());
     }
 }
 
 impl EncryptArguments {
     fn convert(self, key: Key) -> FedResult<EncryptConfig> {
-        let verbosity = match (self.debug, self.quiet) {
-            (true, true) => return Err("cannot use quiet mode and debug mode together".to_owned()),
+        let verbosity = match (self.debug, self.quiet || self.json || self.stdout) {
+            (true, true) => {
+                return Err(FedError::Usage(
+                    "cannot use quiet mode and debug mode together".to_owned(),
+                ))
+            }
             (true, false) => Verbosity::Debug,
             (false, true) => Verbosity::Quiet,
             (false, false) => Verbosity::Normal,
@@ -214,49 +703,299 @@ impl EncryptArguments {
         if self.hide_size {
             options.push(EncOption::PadSize);
         }
-        let extension = if self.output_extension.starts_with('.') {
-            self.output_extension
-        } else {
-            format!(".{}", self.output_extension)
-        };
-        Ok(EncryptConfig::new(
-            self.files,
-            key,
-            options.into(),
-            verbosity,
-            self.overwrite,
-            self.delete_input,
-            self.output_dir,
-            extension,
-            self.dry_run,
-        ))
+        if self.key_slots {
+            options.push(EncOption::KeySlots);
+        }
+        match self.compression.as_str() {
+            "zstd" => options.push(EncOption::Zstd),
+            "xz" => options.push(EncOption::Xz),
+            "none" => options.push(EncOption::NoCompression),
+            _ => {}
+        }
+        let options: EncOptionSet = options.into();
+        let kdf_cost = self.kdf_cost_for(&options)?;
+        let selection = FileSelection::new(self.recursive, &self.include, &self.exclude)?;
+        let recipients = self
+            .recipient
+            .iter()
+            .map(|recipient| Recipient::load(recipient))
+            .collect::<FedResult<Vec<_>>>()?;
+        let mut builder = EncryptConfig::builder(key)
+            .with_files(self.files)
+            .with_options(options)
+            .with_verbosity(verbosity)
+            .with_overwrite(if self.overwrite {
+                OnFileExist::Overwrite
+            } else {
+                OnFileExist::Fail
+            })
+            .with_delete_input(if self.delete_input {
+                InputAction::Delete
+            } else {
+                InputAction::Keep
+            })
+            .with_output_extension(&self.output_extension)
+            .with_dry_run(if self.dry_run {
+                RunMode::IsDryRun
+            } else {
+                RunMode::IsReal
+            })
+            .with_selection(selection)
+            .with_jobs(self.jobs);
+        if let Some(output_dir) = self.output_dir {
+            builder = builder.with_output_dir(output_dir);
+        }
+        if let Some(archive) = self.archive {
+            builder = builder.with_archive(archive);
+        }
+        for recipient in recipients {
+            builder = builder.with_recipient(recipient);
+        }
+        if let Some(kdf_cost) = kdf_cost {
+            builder = builder.with_kdf_cost(kdf_cost);
+        }
+        builder.build()
+    }
+
+    /// The preset cost, or the default of the strategy, with any explicit argon2 settings applied.
+    fn kdf_cost_for(&self, options: &EncOptionSet) -> FedResult<Option<KdfCost>> {
+        if self.kdf_cost.is_none() && self.kdf_memory.is_none() && self.kdf_iterations.is_none() {
+            return Ok(None);
+        }
+        let cost = self
+            .kdf_cost
+            .as_deref()
+            .and_then(KdfCost::preset)
+            .unwrap_or_else(|| {
+                get_current_version_strategy(options, false)
+                    .kdf_cost
+                    .clone()
+            })
+            .with_argon2(
+                self.kdf_memory.map(|mib| mib.saturating_mul(1024)),
+                self.kdf_iterations,
+            );
+        cost.check()
+            .map_err(|msg| FedError::Usage(format!("invalid key stretching cost: {}", msg)))?;
+        Ok(Some(cost))
     }
 }
 
-//TODO: if wildcards or directories are ever supported, then skip files that have the encrypted extension (i.e. .enc)
+/// With --json, the result is printed even if there was an error, so that scripts can always parse it.
+fn print_json(reports: &[FileReport], error: Option<&FedError>) {
+    let json = reports_to_json(reports, error);
+    println!("{}", to_string_pretty(&json).unwrap());
+}
 
 fn go_encrypt(args: EncryptArguments) -> FedResult<()> {
+    let json = args.json;
+    let result = encrypt_files(args);
+    let error = match &result {
+        Ok(reports) => failure_error(reports),
+        Err(err) => Some(err.clone()),
+    };
+    if json {
+        print_json(result.as_deref().unwrap_or(&[]), error.as_ref());
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn encrypt_files(args: EncryptArguments) -> FedResult<Vec<FileReport>> {
+    if args.key_source == KeySource::Agent {
+        return Err(FedError::Usage(
+            "the agent only remembers stretched keys of existing files, so it cannot be used \
+            for encryption; use 'filedec --key agent' when decrypting"
+                .to_owned(),
+        ));
+    }
+    check_stream_args(
+        &args.files,
+        &args.key_source,
+        args.keyfile.as_ref(),
+        args.stdout,
+    )?;
+    if args.stdout {
+        encrypt_to_stdout(args)?;
+        return Ok(vec![]);
+    }
     if args.debug {
         println!("arguments provided:\n{}", args);
     }
-    let key = args.key_source.obtain_key()?;
-    if args.debug {
-        println!("approximate time to crack key: {}", key.time_to_crack());
+    let key = obtain_encryption_key(&args)?;
+    let config = args.convert(key)?;
+    encrypt_report(&config)
+}
+
+/// Stdin can only be used as a stream, and then it cannot also provide the key.
+fn check_stream_args(
+    files: &[PathBuf],
+    key_source: &KeySource,
+    keyfile: Option<&KeySource>,
+    stdout: bool,
+) -> FedResult<()> {
+    let is_stdin = files.iter().any(|pth| pth == Path::new(STREAM_PATH));
+    if is_stdin && !stdout {
+        return Err(FedError::Usage(format!(
+            "input '{}' (stdin) can only be used together with --stdout",
+            STREAM_PATH
+        )));
+    }
+    if stdout && files.len() != 1 {
+        return Err(FedError::Usage(
+            "with --stdout, there should be exactly one input".to_owned(),
+        ));
     }
-    if !args.accept_weak_key && !key.is_strong() {
+    if is_stdin && (key_source == &KeySource::Pipe || keyfile == Some(&KeySource::Pipe)) {
+        return Err(FedError::Usage(
+            "the key cannot be piped when the data is read from stdin; \
+            use for example 'env:$var_name' or 'file:$path'"
+                .to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Open the input for --stdout, which is either stdin or a file.
+fn open_stream_input(pth: &Path) -> FedResult<Box<dyn Read>> {
+    if pth == Path::new(STREAM_PATH) {
+        return Ok(Box::new(io::stdin()));
+    }
+    match File::open(pth) {
+        Ok(file) => Ok(Box::new(file)),
+        Err(err) => Err(FedError::NotFound(format!(
+            "could not open input file {}: {}",
+            pth.to_string_lossy(),
+            err
+        ))),
+    }
+}
+
+fn encrypt_to_stdout(args: EncryptArguments) -> FedResult<()> {
+    let mut input = BufReader::new(open_stream_input(&args.files[0])?);
+    let key = obtain_encryption_key(&args)?;
+    let config = args.convert(key)?;
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    encrypt_stream(&mut input, &mut output, &config)?;
+    output
+        .flush()
+        .map_err(|err| FedError::Io(format!("could not write to stdout: {}", err)))
+}
+
+fn obtain_encryption_key(args: &EncryptArguments) -> FedResult<Key> {
+    Ok(if args.recipient.is_empty() || args.key_slots {
+        let key = args.key_source.obtain_combined_key(args.keyfile.as_ref())?;
+        if args.debug {
+            println!("approximate time to crack key: {}", key.time_to_crack());
+        }
+        if !args.accept_weak_key && !key.is_strong() {
+            eprintln!(
+                "warning: the encryption key is not strong (it might be cracked in {})",
+                key.time_to_crack()
+            );
+        }
+        key
+    } else if args.keyfile.is_some() {
+        return Err(FedError::Usage(
+            "--keyfile has no effect when encrypting only for recipients; \
+            use it together with --key-slots"
+                .to_owned(),
+        ));
+    } else {
+        // The recipients can unlock this random key, which is stored in the files.
+        Key::generate_random()
+    })
+}
+
+fn go_agent(args: AgentArguments) -> FedResult<()> {
+    let socket = default_agent_socket()?;
+    let client = AgentClient::new(socket.clone());
+    if args.stop {
+        return client.stop();
+    }
+    if args.forget {
+        return client.forget();
+    }
+    if args.timeout == 0 {
+        return Err(FedError::Usage(
+            "the timeout should be at least one minute".to_owned(),
+        ));
+    }
+    eprintln!("starting the agent at '{}'", socket.to_string_lossy());
+    run_agent(&socket, Duration::from_secs(args.timeout * 60))
+}
+
+fn go_keygen(args: KeygenArguments) -> FedResult<()> {
+    let identity = write_identity_file(&args.output, args.overwrite)?;
+    println!("{}", identity.recipient());
+    Ok(())
+}
+
+fn go_add_key(args: AddKeyArguments) -> FedResult<()> {
+    let current_key = obtain_key_for(
+        &args.key_source,
+        args.keyfile.as_ref(),
+        "current key of the files",
+    )?;
+    let new_key = obtain_key_for(
+        &args.new_key_source,
+        args.new_keyfile.as_ref(),
+        "key to add",
+    )?;
+    if !args.accept_weak_key && !new_key.is_strong() {
         eprintln!(
-            "warning: the encryption key is not strong (it might be cracked in {})",
-            key.time_to_crack()
+            "warning: the new key is not strong (it might be cracked in {})",
+            new_key.time_to_crack()
         );
     }
-    let config = args.convert(key)?;
-    encrypt(&config)?;
+    add_key(
+        &args.files,
+        &current_key,
+        &new_key,
+        verbosity(args.debug, args.quiet),
+    )
+}
+
+fn go_remove_key(args: RemoveKeyArguments) -> FedResult<()> {
+    let key = obtain_key_for(&args.key_source, args.keyfile.as_ref(), "key to remove")?;
+    remove_key(&args.files, &key, verbosity(args.debug, args.quiet))
+}
+
+fn go_info(args: InfoArguments) -> FedResult<()> {
+    let key = match &args.key_source {
+        Some(source) => Some(source.obtain_combined_key(args.keyfile.as_ref())?),
+        None => None,
+    };
+    let details = inspect_headers(&args.files, key.as_ref(), verbosity(args.debug, true))?;
+    if args.json {
+        let json = details.iter().map(|info| info.to_json()).collect();
+        println!("{}", to_string_pretty(&Value::Array(json)).unwrap());
+    } else {
+        for info in &details {
+            print!("{}", info);
+        }
+    }
     Ok(())
 }
 
+fn go_rekey(args: RekeyArguments) -> FedResult<()> {
+    rekey(
+        &args.files,
+        &args.key_source,
+        args.keyfile.as_ref(),
+        &args.new_key_source,
+        args.new_keyfile.as_ref(),
+        verbosity(args.debug, args.quiet),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use ::file_endec::EndecConfig;
+    use ::file_endec::Identity;
 
     use super::*;
 
@@ -266,13 +1005,17 @@ mod tests {
         let config = args.convert(Key::new("abcdef123!")).unwrap();
         assert!(config.files().contains(&PathBuf::from("file.txt")));
         assert_eq!(1, config.files().len());
-        assert_eq!(config.raw_key().key_data.unsecure(), "abcdef123!");
+        assert_eq!(config.raw_key().unwrap().key_data.unsecure(), "abcdef123!");
         assert_eq!(config.verbosity(), Verbosity::Normal);
-        assert_eq!(config.overwrite(), false);
-        assert_eq!(config.delete_input(), false);
+        assert!(!config.overwrite());
+        assert!(!config.delete_input());
         assert_eq!(config.output_dir(), None);
         assert_eq!(config.output_extension(), ".enc");
-        assert_eq!(config.dry_run(), false);
+        assert!(!config.dry_run());
+        assert!(!config.selection().recursive());
+        assert_eq!(config.archive(), None);
+        assert!(!config.options().has(EncOption::Archive));
+        assert_eq!(config.jobs(), 1);
     }
 
     #[test]
@@ -290,6 +1033,17 @@ mod tests {
             "/tmp/hello",
             "--output-extension",
             "secret",
+            "-r",
+            "--include",
+            "*.txt",
+            "--exclude",
+            "tmp/*",
+            "--archive",
+            "all.enc",
+            "-j",
+            "4",
+            "--compression",
+            "zstd",
             "another_file.txt",
             "there_are_three_files",
         ]);
@@ -300,15 +1054,272 @@ mod tests {
             .files()
             .contains(&PathBuf::from("there_are_three_files")));
         assert_eq!(3, config.files().len());
-        assert_eq!(config.raw_key().key_data.unsecure(), "abcdef123!");
+        assert_eq!(config.raw_key().unwrap().key_data.unsecure(), "abcdef123!");
         assert_eq!(config.verbosity(), Verbosity::Quiet);
-        assert_eq!(config.overwrite(), true);
-        assert_eq!(config.delete_input(), true);
+        assert!(config.overwrite());
+        assert!(config.delete_input());
         assert_eq!(
             config.output_dir(),
             Some(PathBuf::from("/tmp/hello").as_path())
         );
         assert_eq!(config.output_extension(), ".secret");
-        assert_eq!(config.dry_run(), false);
+        assert!(!config.dry_run());
+        assert!(config.selection().recursive());
+        assert!(config.selection().matches(Path::new("notes.txt")));
+        assert!(!config.selection().matches(Path::new("tmp/notes.txt")));
+        assert!(!config.selection().matches(Path::new("image.png")));
+        assert_eq!(config.archive(), Some(Path::new("all.enc")));
+        assert!(config.options().has(EncOption::Archive));
+        assert!(config.options().has(EncOption::Zstd));
+        assert_eq!(config.jobs(), 4);
+    }
+
+    #[test]
+    fn parse_args_recipients() {
+        let public = Identity::generate().recipient().to_string();
+        let args = EncryptArguments::from_iter(&[
+            "fileenc",
+            "file.txt",
+            "--recipient",
+            &public,
+            "--recipient",
+            &public,
+        ]);
+        let config = args.convert(Key::generate_random()).unwrap();
+        assert_eq!(2, config.recipients().len());
+        assert_eq!(public, config.recipients()[0].to_string());
+        assert!(config.options().has(EncOption::Recipients));
+    }
+
+    #[test]
+    fn parse_args_key_slots() {
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt", "--key-slots"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert!(config.options().has(EncOption::KeySlots));
+    }
+
+    #[test]
+    fn parse_args_kdf_cost() {
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(None, config.kdf_cost());
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt", "--kdf-cost", "paranoid"]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(Some(&KdfCost::paranoid()), config.kdf_cost());
+        let args = EncryptArguments::from_iter(&[
+            "fileenc",
+            "file.txt",
+            "--kdf-cost",
+            "interactive",
+            "--kdf-memory",
+            "16",
+            "--kdf-iterations",
+            "2",
+        ]);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(
+            Some(&KdfCost::interactive().with_argon2(Some(16 * 1024), Some(2))),
+            config.kdf_cost()
+        );
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt", "--kdf-iterations", "0"]);
+        let err = args.convert(Key::new("abcdef123!")).unwrap_err();
+        assert_eq!("usage", err.code());
+    }
+
+    #[test]
+    fn parse_args_keyfile() {
+        let args =
+            EncryptArguments::from_iter(&["fileenc", "file.txt", "-K", "keyfile:/media/key.bin"]);
+        assert_eq!(
+            Some(KeySource::KeyFile(PathBuf::from("/media/key.bin"))),
+            args.keyfile
+        );
+        let args = EncryptArguments::from_iter(&["fileenc", "-", "--stdout", "-K", "pipe"]);
+        assert!(check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout
+        )
+        .is_err());
+        let public = Identity::generate().recipient().to_string();
+        let args = EncryptArguments::from_iter(&[
+            "fileenc",
+            "file.txt",
+            "--recipient",
+            &public,
+            "-K",
+            "pass:second",
+        ]);
+        assert_eq!("usage", obtain_encryption_key(&args).unwrap_err().code());
+    }
+
+    #[test]
+    fn parse_args_stdout() {
+        let args = EncryptArguments::from_iter(&["fileenc", "-", "--stdout", "-k", "env:KEY"]);
+        check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout,
+        )
+        .unwrap();
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.verbosity(), Verbosity::Quiet);
+        let args = EncryptArguments::from_iter(&["fileenc", "-", "--stdout", "-k", "pipe"]);
+        let err = check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout,
+        )
+        .unwrap_err();
+        assert_eq!("usage", err.code());
+        let args = EncryptArguments::from_iter(&["fileenc", "-", "file.txt"]);
+        assert!(check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout
+        )
+        .is_err());
+        let args = EncryptArguments::from_iter(&["fileenc", "a.txt", "b.txt", "--stdout"]);
+        assert!(check_stream_args(
+            &args.files,
+            &args.key_source,
+            args.keyfile.as_ref(),
+            args.stdout
+        )
+        .is_err());
+        assert!(EncryptArguments::from_iter_safe(&[
+            "fileenc",
+            "-",
+            "--stdout",
+            "--archive",
+            "a.enc"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parse_args_json() {
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt", "--json"]);
+        assert!(args.json);
+        let config = args.convert(Key::new("abcdef123!")).unwrap();
+        assert_eq!(config.verbosity(), Verbosity::Quiet);
+        let args = EncryptArguments::from_iter_safe(&["fileenc", "file.txt", "--json", "-v"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn parse_args_add_key() {
+        let args = AddKeyArguments::from_iter(&[
+            "add-key",
+            "a.enc",
+            "b.enc",
+            "-k",
+            "pass:old",
+            "--new-key",
+            "env:NEW_KEY",
+        ]);
+        assert_eq!(2, args.files.len());
+        assert_eq!(KeySource::CliArg(Key::new("old")), args.key_source);
+        assert_eq!(KeySource::EnvVar("NEW_KEY".to_owned()), args.new_key_source);
+        assert_eq!(None, args.keyfile);
+        let args = AddKeyArguments::from_iter(&[
+            "add-key",
+            "a.enc",
+            "-K",
+            "keyfile:old.bin",
+            "-N",
+            "keyfile:new.bin",
+        ]);
+        assert_eq!(Some(KeySource::KeyFile("old.bin".into())), args.keyfile);
+        assert_eq!(Some(KeySource::KeyFile("new.bin".into())), args.new_keyfile);
+        let args = RemoveKeyArguments::from_iter(&["remove-key", "a.enc"]);
+        assert_eq!(KeySource::AskOnce, args.key_source);
+        assert_eq!(None, args.keyfile);
+        let args = RemoveKeyArguments::from_iter(&["remove-key", "a.enc", "-K", "keyfile:k.bin"]);
+        assert_eq!(Some(KeySource::KeyFile("k.bin".into())), args.keyfile);
+    }
+
+    #[test]
+    fn parse_args_rekey() {
+        let args = RekeyArguments::from_iter(&["rekey", "a.enc", "-n", "pass:new", "-q"]);
+        assert_eq!(vec![PathBuf::from("a.enc")], args.files);
+        assert_eq!(KeySource::AskOnce, args.key_source);
+        assert_eq!(KeySource::CliArg(Key::new("new")), args.new_key_source);
+        assert_eq!(None, args.keyfile);
+        assert!(args.quiet);
+        let args = RekeyArguments::from_iter(&[
+            "rekey",
+            "a.enc",
+            "-K",
+            "keyfile:old.bin",
+            "-N",
+            "keyfile:new.bin",
+        ]);
+        assert_eq!(Some(KeySource::KeyFile("old.bin".into())), args.keyfile);
+        assert_eq!(Some(KeySource::KeyFile("new.bin".into())), args.new_keyfile);
+    }
+
+    #[test]
+    fn parse_args_info() {
+        let args = InfoArguments::from_iter(&["info", "a.enc", "b.enc", "--json"]);
+        assert_eq!(2, args.files.len());
+        assert_eq!(None, args.key_source);
+        assert!(args.json);
+        let args = InfoArguments::from_iter(&["info", "a.enc", "-k", "pass:secret"]);
+        assert_eq!(Some(KeySource::CliArg(Key::new("secret"))), args.key_source);
+        assert_eq!(None, args.keyfile);
+        assert!(!args.json);
+        let args = InfoArguments::from_iter(&["info", "a.enc", "-k", "ask", "-K", "keyfile:k.bin"]);
+        assert_eq!(Some(KeySource::KeyFile("k.bin".into())), args.keyfile);
+        assert!(InfoArguments::from_iter_safe(&["info", "a.enc", "-K", "keyfile:k.bin"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_commands() {
+        let args = FileEncArguments::from_iter(&["fileenc", "info", "a.enc", "-k", "ask"]);
+        assert!(matches!(args.command, Some(Command::Info(_))));
+        let args = FileEncArguments::from_iter(&["fileenc", "rekey", "a.enc"]);
+        assert!(matches!(args.command, Some(Command::Rekey(_))));
+        let args = FileEncArguments::from_iter(&["fileenc", "file.txt", "-q"]);
+        assert!(args.command.is_none());
+        assert_eq!(vec![PathBuf::from("file.txt")], args.encrypt.files);
+        assert!(args.encrypt.quiet);
+        // Files with the name of a command can still be encrypted.
+        let args = FileEncArguments::from_iter(&["fileenc", "./info"]);
+        assert!(args.command.is_none());
+        assert_eq!(vec![PathBuf::from("./info")], args.encrypt.files);
+        let args = FileEncArguments::from_iter(&["fileenc", "--", "info"]);
+        assert!(args.command.is_none());
+        assert_eq!(vec![PathBuf::from("info")], args.encrypt.files);
+        let args = FileEncArguments::from_iter(&["fileenc", "a.txt", "info"]);
+        assert!(args.command.is_none());
+        assert_eq!(2, args.encrypt.files.len());
+        let args = FileEncArguments::from_iter(&["fileenc", "-q", "--", "info"]);
+        assert!(args.command.is_none());
+        assert_eq!(vec![PathBuf::from("info")], args.encrypt.files);
+        assert!(FileEncArguments::from_iter_safe(&["fileenc"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_agent() {
+        let args = AgentArguments::from_iter(&["agent", "-t", "60"]);
+        assert_eq!(60, args.timeout);
+        assert!(!args.stop);
+        let args = AgentArguments::from_iter(&["agent", "--stop"]);
+        assert!(args.stop);
+        assert!(AgentArguments::from_iter_safe(&["agent", "--stop", "--forget"]).is_err());
+        let args = EncryptArguments::from_iter(&["fileenc", "file.txt", "-k", "agent"]);
+        assert_eq!("usage", encrypt_files(args).unwrap_err().code());
+    }
+
+    #[test]
+    fn parse_args_keygen() {
+        let args = KeygenArguments::from_iter(&["keygen", "me.fedsec", "-f"]);
+        assert_eq!(PathBuf::from("me.fedsec"), args.output);
+        assert!(args.overwrite);
     }
 }
@@ -0,0 +1,195 @@
+use ::std::collections::HashSet;
+use ::std::fs::File;
+use ::std::io;
+use ::std::io::{Cursor, Read, Take};
+use ::std::path::{Component, Path, PathBuf};
+
+use crate::files::file_meta::FileInfo;
+use crate::files::scan::InputFile;
+use crate::util::{FedError, FedResult};
+
+/// The name of a file inside an archive: its path relative to the directory it was found in,
+/// with '/' as separator on all platforms.
+pub fn member_name(input: &InputFile) -> String {
+    input
+        .sub_dir
+        .join(input.path.file_name().unwrap())
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Names of all members, which must be unique.
+pub fn member_names(inputs: &[InputFile]) -> FedResult<Vec<String>> {
+    let mut seen = HashSet::with_capacity(inputs.len());
+    let mut names = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let name = member_name(input);
+        if name.contains('\n') {
+            return Err(FedError::Usage(format!(
+                "cannot add '{}' to an archive because the name contains a newline",
+                input.path.to_string_lossy()
+            )));
+        }
+        if !seen.insert(name.clone()) {
+            return Err(FedError::Usage(format!(
+                "cannot add '{}' to the archive because there is already a file named '{}'",
+                input.path.to_string_lossy(),
+                name
+            )));
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Where to extract a member. Names that could escape the output directory are rejected,
+/// even though they are authenticated.
+pub fn member_output_path(out_dir: &Path, name: &str) -> FedResult<PathBuf> {
+    let relative = Path::new(name);
+    let is_safe = !name.is_empty()
+        && relative
+            .components()
+            .all(|part| matches!(part, Component::Normal(_)));
+    if !is_safe {
+        return Err(FedError::Corrupted(format!(
+            "refusing to extract archive member '{}' because it is not a relative path",
+            name
+        )));
+    }
+    Ok(out_dir.join(relative))
+}
+
+/// Reads the archive index followed by the data of every member, opening one file at a time.
+pub struct ArchiveReader<'a> {
+    index: Cursor<Vec<u8>>,
+    files: &'a [FileInfo<'a>],
+    next_file: usize,
+    current: Option<Take<File>>,
+}
+
+impl<'a> ArchiveReader<'a> {
+    pub fn new(index: Vec<u8>, files: &'a [FileInfo<'a>]) -> Self {
+        ArchiveReader {
+            index: Cursor::new(index),
+            files,
+            next_file: 0,
+            current: None,
+        }
+    }
+}
+
+impl<'a> Read for ArchiveReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.index.read(buf)?;
+        if count > 0 {
+            return Ok(count);
+        }
+        loop {
+            if let Some(current) = &mut self.current {
+                let count = current.read(buf)?;
+                if count > 0 || buf.is_empty() {
+                    return Ok(count);
+                }
+                if current.limit() > 0 {
+                    let file = &self.files[self.next_file - 1];
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("file '{}' became shorter while archiving", file.path_str()),
+                    ));
+                }
+            }
+            if self.next_file >= self.files.len() {
+                return Ok(0);
+            }
+            let file = &self.files[self.next_file];
+            self.current = Some(File::open(file.in_path)?.take(file.size_b));
+            self.next_file += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::fs;
+
+    use ::tempfile::tempdir;
+
+    use crate::config::typ::{Extension, FileSelection};
+    use crate::files::file_meta::inspect_files;
+    use crate::files::scan::collect_input_files;
+    use crate::header::strategy::Verbosity;
+
+    use super::*;
+
+    #[test]
+    fn names_are_relative() {
+        let inputs = vec![
+            InputFile::direct(PathBuf::from("/alpha/beta.txt")),
+            InputFile {
+                path: PathBuf::from("/alpha/gamma/delta/epsilon.txt"),
+                sub_dir: PathBuf::from("gamma/delta"),
+            },
+        ];
+        assert_eq!(
+            vec!["beta.txt", "gamma/delta/epsilon.txt"],
+            member_names(&inputs).unwrap()
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let inputs = vec![
+            InputFile::direct(PathBuf::from("/alpha/beta.txt")),
+            InputFile::direct(PathBuf::from("/gamma/beta.txt")),
+        ];
+        assert!(member_names(&inputs)
+            .unwrap_err()
+            .message()
+            .contains("already a file"));
+    }
+
+    #[test]
+    fn output_path_stays_inside() {
+        let out_dir = PathBuf::from("/out");
+        assert_eq!(
+            PathBuf::from("/out/sub/file.txt"),
+            member_output_path(&out_dir, "sub/file.txt").unwrap()
+        );
+        for name in &[
+            "../file.txt",
+            "/etc/passwd",
+            "sub/../../file.txt",
+            "./file",
+            "",
+        ] {
+            assert!(member_output_path(&out_dir, name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn read_index_and_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"first").unwrap();
+        fs::write(dir.path().join("b.txt"), b"second").unwrap();
+        let inputs = collect_input_files(
+            &[dir.path().join("a.txt"), dir.path().join("b.txt")],
+            &FileSelection::default(),
+            |_| true,
+        )
+        .unwrap();
+        let files = inspect_files(
+            &inputs,
+            Verbosity::Quiet,
+            true,
+            Extension::Add(".enc"),
+            None,
+        )
+        .unwrap();
+        let mut reader = ArchiveReader::new(b"index;".to_vec(), &files);
+        let mut content = vec![];
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(b"index;firstsecond", content.as_slice());
+    }
+}
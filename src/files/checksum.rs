@@ -2,6 +2,8 @@ use ::std::fmt::Display;
 use ::std::fmt::Error;
 use ::std::fmt::Formatter;
 use ::std::hash::Hasher;
+use ::std::io;
+use ::std::io::Read;
 use ::std::num::NonZeroU32;
 
 use ::ring::pbkdf2::{derive, PBKDF2_HMAC_SHA512};
@@ -9,7 +11,7 @@ use ::twox_hash::XxHash64;
 
 use crate::util::base::base64str_to_u8s;
 use crate::util::base::u8s_to_base64str;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ChecksumType {
@@ -24,7 +26,10 @@ impl ChecksumType {
         if "xx_sha256" == input {
             Ok(ChecksumType::Xxhash_Sha256_b64)
         } else {
-            Err(format!("unrecognized checksum type: '{}'", input))
+            Err(FedError::Corrupted(format!(
+                "unrecognized checksum type: '{}'",
+                input
+            )))
         }
     }
 }
@@ -60,10 +65,10 @@ impl Checksum {
                 value: base64str_to_u8s(&input[10..])?,
             });
         }
-        Err(format!(
+        Err(FedError::Corrupted(format!(
             "failed to parse checksum format: {}",
             input.split(' ').next().unwrap()
-        ))
+        )))
     }
 }
 
@@ -76,27 +81,73 @@ impl Display for Checksum {
     }
 }
 
-pub fn calculate_checksum(data: &[u8], start_progress: &mut impl FnMut()) -> Checksum {
-    start_progress();
-    let mut hasher = XxHash64::with_seed(5_771_919_056_451_745_621);
-    for b in data {
-        hasher.write_u8(*b);
+/// Calculates the checksum incrementally, for data that is not all in memory at once.
+pub struct ChecksumCalculator {
+    hasher: XxHash64,
+}
+
+impl ChecksumCalculator {
+    pub fn new() -> Self {
+        ChecksumCalculator {
+            hasher: XxHash64::with_seed(5_771_919_056_451_745_621),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.write(data);
+    }
+
+    pub fn finish(self) -> Checksum {
+        let xxhash = self.hasher.finish().to_le_bytes();
+        let mut shahash = vec![0; 16];
+        derive(
+            PBKDF2_HMAC_SHA512,
+            NonZeroU32::new(1).unwrap(),
+            &[],
+            &xxhash,
+            &mut shahash,
+        );
+        Checksum {
+            typ: ChecksumType::Xxhash_Sha256_b64,
+            value: shahash,
+        }
+    }
+}
+
+/// Calculates the checksum of all the data that is read through it.
+pub struct ChecksumReader<R: Read> {
+    reader: R,
+    calculator: ChecksumCalculator,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(reader: R) -> Self {
+        ChecksumReader {
+            reader,
+            calculator: ChecksumCalculator::new(),
+        }
+    }
+
+    pub fn finish(self) -> Checksum {
+        self.calculator.finish()
     }
-    let xxhash = hasher.finish().to_le_bytes();
-    let mut shahash = vec![0; 16];
-    derive(
-        PBKDF2_HMAC_SHA512,
-        NonZeroU32::new(1).unwrap(),
-        &[],
-        &xxhash,
-        &mut shahash,
-    );
-    Checksum {
-        typ: ChecksumType::Xxhash_Sha256_b64,
-        value: shahash,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        self.calculator.update(&buf[..count]);
+        Ok(count)
     }
 }
 
+pub fn calculate_checksum(data: &[u8], start_progress: &mut impl FnMut()) -> Checksum {
+    start_progress();
+    let mut calculator = ChecksumCalculator::new();
+    calculator.update(data);
+    calculator.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::files::mockfile::generate_test_file_content_for_test;
@@ -120,4 +171,24 @@ mod tests {
             checksum.value,
         );
     }
+
+    #[test]
+    fn calculate_in_chunks() {
+        let data = generate_test_file_content_for_test(15_001);
+        let mut calculator = ChecksumCalculator::new();
+        for chunk in data.chunks(1000) {
+            calculator.update(chunk);
+        }
+        assert_eq!(calculate_checksum(&data, &mut || {}), calculator.finish());
+    }
+
+    #[test]
+    fn calculate_while_reading() {
+        let data = generate_test_file_content_for_test(15_001);
+        let mut reader = ChecksumReader::new(data.as_slice());
+        let mut copy = vec![];
+        reader.read_to_end(&mut copy).unwrap();
+        assert_eq!(data, copy);
+        assert_eq!(calculate_checksum(&data, &mut || {}), reader.finish());
+    }
 }
@@ -1,49 +1,167 @@
+use ::std::io;
 use ::std::io::Read;
+use ::std::io::Write;
 
-use ::brotli::enc::BrotliEncoderParams;
-use ::lazy_static::lazy_static;
-
+use crate::files::reading::CHUNK_SIZE;
 use crate::header::CompressionAlg;
-use crate::util::FedResult;
+use crate::util::errors::wrap_io;
+use crate::util::{FedError, FedResult};
 
-lazy_static! {
-    static ref BROTLI_CONFIG: BrotliEncoderParams = BrotliEncoderParams {
-        quality: 6,
-        ..Default::default()
-    };
-}
-
-pub fn compress_file(
-    data: Vec<u8>,
-    alg: &Option<CompressionAlg>,
-    start_progress: &mut impl FnMut(&CompressionAlg),
-) -> FedResult<Vec<u8>> {
-    match alg {
-        Some(alg) => {
-            start_progress(&alg);
-            match alg {
-                CompressionAlg::Brotli => brotli_compress(&data),
-            }
-        }
-        None => Ok(data),
-    }
-}
+const BROTLI_BUFFER: usize = 4096;
+const BROTLI_QUALITY: u32 = 6;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+const XZ_PRESET: u32 = 6;
+/// Samples smaller than this are always compressed, because that is cheap anyway.
+const MIN_SAMPLE_SIZE: usize = 4 * 1024;
 
+#[cfg(test)]
 pub fn brotli_compress(data: &[u8]) -> FedResult<Vec<u8>> {
-    let mut compress = brotli::CompressorReader::new(data, 4096, 6, 22);
+    let mut compress =
+        brotli::CompressorReader::new(data, BROTLI_BUFFER, BROTLI_QUALITY, BROTLI_WINDOW);
     let mut output = Vec::with_capacity(data.len());
     match compress.read_to_end(&mut output) {
         Ok(len) => {
             if len > 0 {
                 Ok(output)
             } else {
-                Err("No data was read during compression".to_owned())
+                Err(FedError::Other(
+                    "No data was read during compression".to_owned(),
+                ))
+            }
+        }
+        Err(err) => Err(FedError::Other(format!("Brotli compress error: {}", err))),
+    }
+}
+
+/// Compresses everything written to it and passes it on to the inner writer.
+/// Use `finish` after writing all data, to complete the compressed stream.
+pub enum CompressWriter<W: Write> {
+    Brotli(Box<brotli::CompressorWriter<W>>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Xz(xz2::write::XzEncoder<W>),
+    Uncompressed(W),
+}
+
+impl<W: Write> CompressWriter<W> {
+    pub fn new(
+        writer: W,
+        alg: &Option<CompressionAlg>,
+        start_progress: &mut impl FnMut(&CompressionAlg),
+    ) -> FedResult<Self> {
+        Ok(match alg {
+            Some(alg) => {
+                start_progress(alg);
+                match alg {
+                    CompressionAlg::Brotli => {
+                        CompressWriter::Brotli(Box::new(brotli::CompressorWriter::new(
+                            writer,
+                            BROTLI_BUFFER,
+                            BROTLI_QUALITY,
+                            BROTLI_WINDOW,
+                        )))
+                    }
+                    CompressionAlg::Zstd => CompressWriter::Zstd(wrap_io(
+                        || "could not start zstd compression",
+                        zstd::stream::write::Encoder::new(writer, ZSTD_LEVEL),
+                    )?),
+                    CompressionAlg::Xz => {
+                        CompressWriter::Xz(xz2::write::XzEncoder::new(writer, XZ_PRESET))
+                    }
+                }
+            }
+            None => CompressWriter::Uncompressed(writer),
+        })
+    }
+
+    pub fn finish(self) -> FedResult<W> {
+        match self {
+            CompressWriter::Brotli(mut compress) => {
+                // Flush first, because `into_inner` does not report errors.
+                compress
+                    .flush()
+                    .map_err(|err| FedError::Other(format!("Brotli compress error: {}", err)))?;
+                Ok(compress.into_inner())
             }
+            CompressWriter::Zstd(compress) => compress
+                .finish()
+                .map_err(|err| FedError::Other(format!("Zstd compress error: {}", err))),
+            CompressWriter::Xz(compress) => compress
+                .finish()
+                .map_err(|err| FedError::Other(format!("Xz compress error: {}", err))),
+            CompressWriter::Uncompressed(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressWriter::Brotli(compress) => compress.write(buf),
+            CompressWriter::Zstd(compress) => compress.write(buf),
+            CompressWriter::Xz(compress) => compress.write(buf),
+            CompressWriter::Uncompressed(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressWriter::Brotli(compress) => compress.flush(),
+            CompressWriter::Zstd(compress) => compress.flush(),
+            CompressWriter::Xz(compress) => compress.flush(),
+            CompressWriter::Uncompressed(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Counts the bytes written to it, and then discards them.
+struct CountingSink(u64);
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Determine the compressed size of the data, without keeping the compressed data.
+pub fn compressed_size(reader: &mut impl Read, alg: &Option<CompressionAlg>) -> FedResult<u64> {
+    let mut compress = CompressWriter::new(CountingSink(0), alg, &mut |_| {})?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let count = wrap_io(|| "could not read input file", reader.read(&mut buffer))?;
+        if count == 0 {
+            break;
         }
-        Err(err) => Err(format!("Brotli compress error: {}", err)),
+        wrap_io(
+            || "failed to compress file data",
+            compress.write_all(&buffer[..count]),
+        )?;
+    }
+    Ok(compress.finish()?.0)
+}
+
+/// Whether data is worth compressing, judged by compressing a sample from its start. Data that is
+/// already compressed or encrypted, like images, videos or archives, usually is not.
+pub fn is_compressible(sample: &[u8], alg: &CompressionAlg) -> FedResult<bool> {
+    if sample.len() < MIN_SAMPLE_SIZE {
+        return Ok(true);
     }
+    let mut compress = CompressWriter::new(CountingSink(0), &Some(alg.clone()), &mut |_| {})?;
+    wrap_io(
+        || "failed to compress file data",
+        compress.write_all(sample),
+    )?;
+    let compressed_len = compress.finish()?.0;
+    // Saving only a few percent is not worth the time it takes.
+    Ok(compressed_len * 20 < sample.len() as u64 * 19)
 }
 
+#[cfg(test)]
 pub fn decompress_file(
     data: Vec<u8>,
     alg: &Option<CompressionAlg>,
@@ -54,23 +172,99 @@ pub fn decompress_file(
             start_progress(alg);
             match alg {
                 CompressionAlg::Brotli => brotli_decompress(&data),
+                CompressionAlg::Zstd => zstd::decode_all(data.as_slice())
+                    .map_err(|err| FedError::Corrupted(format!("Zstd decompress error: {}", err))),
+                CompressionAlg::Xz => {
+                    let mut output = Vec::with_capacity(data.len());
+                    xz2::read::XzDecoder::new(data.as_slice())
+                        .read_to_end(&mut output)
+                        .map_err(|err| {
+                            FedError::Corrupted(format!("Xz decompress error: {}", err))
+                        })?;
+                    Ok(output)
+                }
             }
         }
         None => Ok(data),
     }
 }
 
+#[cfg(test)]
 pub fn brotli_decompress(data: &[u8]) -> FedResult<Vec<u8>> {
-    let mut decompress = brotli::Decompressor::new(data, 4096);
+    let mut decompress = brotli::Decompressor::new(data, BROTLI_BUFFER);
     let mut output = Vec::with_capacity(data.len());
     match decompress.read_to_end(&mut output) {
         Ok(_) => Ok(output),
-        Err(err) => Err(format!("Brotli decompress error: {}", err)),
+        Err(err) => Err(FedError::Corrupted(format!(
+            "Brotli decompress error: {}",
+            err
+        ))),
+    }
+}
+
+/// Decompresses the data read from the inner reader.
+pub enum DecompressReader<R: Read> {
+    Brotli(Box<brotli::Decompressor<R>>),
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<R>>),
+    Xz(xz2::read::XzDecoder<R>),
+    Uncompressed(R),
+}
+
+impl<R: Read> DecompressReader<R> {
+    pub fn new(
+        reader: R,
+        alg: &Option<CompressionAlg>,
+        start_progress: &mut impl FnMut(&CompressionAlg),
+    ) -> FedResult<Self> {
+        Ok(match alg {
+            Some(alg) => {
+                start_progress(alg);
+                match alg {
+                    CompressionAlg::Brotli => DecompressReader::Brotli(Box::new(
+                        brotli::Decompressor::new(reader, BROTLI_BUFFER),
+                    )),
+                    CompressionAlg::Zstd => DecompressReader::Zstd(wrap_io(
+                        || "could not start zstd decompression",
+                        zstd::stream::read::Decoder::new(reader),
+                    )?),
+                    // Keep reading after the end of the xz stream, so that all the data is authenticated.
+                    CompressionAlg::Xz => {
+                        DecompressReader::Xz(xz2::read::XzDecoder::new_multi_decoder(reader))
+                    }
+                }
+            }
+            None => DecompressReader::Uncompressed(reader),
+        })
+    }
+
+    /// The inner reader, e.g. to read what follows the compressed data.
+    pub fn get_mut(&mut self) -> &mut R {
+        match self {
+            DecompressReader::Brotli(decompress) => decompress.get_mut(),
+            DecompressReader::Zstd(decompress) => decompress.get_mut().get_mut(),
+            DecompressReader::Xz(decompress) => decompress.get_mut(),
+            DecompressReader::Uncompressed(reader) => reader,
+        }
+    }
+}
+
+impl<R: Read> Read for DecompressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DecompressReader::Brotli(decompress) => decompress.read(buf),
+            DecompressReader::Zstd(decompress) => decompress.read(buf),
+            DecompressReader::Xz(decompress) => decompress.read(buf),
+            DecompressReader::Uncompressed(reader) => reader.read(buf),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ::rand::RngCore;
+
+    use crate::files::mockfile::generate_test_file_content_for_test;
+
     use super::*;
 
     #[test]
@@ -118,4 +312,98 @@ mod tests {
         let actual = brotli_decompress(&input).unwrap();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn brotli_stream_back_and_forth() {
+        let input = generate_test_file_content_for_test(100_000);
+        let alg = Some(CompressionAlg::Brotli);
+        let mut compress = CompressWriter::new(vec![], &alg, &mut |_| {}).unwrap();
+        for chunk in input.chunks(3_000) {
+            compress.write_all(chunk).unwrap();
+        }
+        let small = compress.finish().unwrap();
+        assert!(small.len() < input.len());
+        assert_eq!(input, brotli_decompress(&small).unwrap());
+        let mut decompress = DecompressReader::new(small.as_slice(), &alg, &mut |_| {}).unwrap();
+        let mut actual = vec![];
+        decompress.read_to_end(&mut actual).unwrap();
+        assert_eq!(input, actual);
+    }
+
+    #[test]
+    fn all_algorithms_back_and_forth() {
+        let input = generate_test_file_content_for_test(100_000);
+        for alg in &[
+            Some(CompressionAlg::Brotli),
+            Some(CompressionAlg::Zstd),
+            Some(CompressionAlg::Xz),
+            None,
+        ] {
+            let mut compress = CompressWriter::new(vec![], alg, &mut |_| {}).unwrap();
+            for chunk in input.chunks(3_000) {
+                compress.write_all(chunk).unwrap();
+            }
+            let small = compress.finish().unwrap();
+            if alg.is_some() {
+                assert!(small.len() < input.len(), "{:?} did not compress", alg);
+            }
+            assert_eq!(
+                input,
+                decompress_file(small.clone(), alg, &mut |_| {}).unwrap()
+            );
+            let mut decompress = DecompressReader::new(small.as_slice(), alg, &mut |_| {}).unwrap();
+            let mut actual = vec![];
+            decompress.read_to_end(&mut actual).unwrap();
+            assert_eq!(input, actual);
+        }
+    }
+
+    #[test]
+    fn all_algorithms_reject_garbage() {
+        let garbage = generate_test_file_content_for_test(10_000);
+        for alg in &[
+            Some(CompressionAlg::Brotli),
+            Some(CompressionAlg::Zstd),
+            Some(CompressionAlg::Xz),
+        ] {
+            assert!(decompress_file(garbage.clone(), alg, &mut |_| {}).is_err());
+            let mut decompress =
+                DecompressReader::new(garbage.as_slice(), alg, &mut |_| {}).unwrap();
+            assert!(
+                decompress.read_to_end(&mut vec![]).is_err(),
+                "{:?} accepted invalid data",
+                alg
+            );
+        }
+    }
+
+    #[test]
+    fn random_data_is_incompressible() {
+        let mut random = vec![0; 50_000];
+        rand::thread_rng().fill_bytes(&mut random);
+        let text = generate_test_file_content_for_test(50_000);
+        for alg in &[
+            CompressionAlg::Brotli,
+            CompressionAlg::Zstd,
+            CompressionAlg::Xz,
+        ] {
+            assert!(!is_compressible(&random, alg).unwrap());
+            assert!(is_compressible(&text, alg).unwrap());
+            assert!(is_compressible(&random[..100], alg).unwrap());
+        }
+    }
+
+    #[test]
+    fn compressed_size_matches() {
+        let input = generate_test_file_content_for_test(100_000);
+        let alg = Some(CompressionAlg::Brotli);
+        let size = compressed_size(&mut input.as_slice(), &alg).unwrap();
+        let mut compress = CompressWriter::new(vec![], &alg, &mut |_| {}).unwrap();
+        compress.write_all(&input).unwrap();
+        assert_eq!(compress.finish().unwrap().len() as u64, size);
+        assert_eq!(
+            100_000,
+            compressed_size(&mut input.as_slice(), &None).unwrap()
+        );
+    }
 }
@@ -1,12 +1,16 @@
+use ::std::path::Path;
+
 use ::file_shred::shred_file;
 
 use crate::files::file_meta::FileInfo;
-use crate::{FedResult, Verbosity};
+use crate::{FedError, FedResult, Verbosity};
 
-pub fn delete_existing_file_in_output_location(file: &FileInfo) -> FedResult<()> {
-    assert!(file.out_pth.is_file());
-    shred_file(&file.out_pth).map_err(|_| {
-        "Failed to remove previously-existing file that exists in output location".to_string()
+pub fn delete_existing_file_in_output_location(out_pth: &Path) -> FedResult<()> {
+    assert!(out_pth.is_file());
+    shred_file(out_pth).map_err(|_| {
+        FedError::Io(
+            "Failed to remove previously-existing file that exists in output location".to_string(),
+        )
     })
 }
 
@@ -18,7 +22,13 @@ pub fn delete_input_file(
 ) -> FedResult<()> {
     if delete_input {
         start_progress();
-        shred_file(&file.in_path)?;
+        shred_file(file.in_path).map_err(|err| {
+            FedError::Io(format!(
+                "could not delete input file '{}': {}",
+                file.path_str(),
+                err
+            ))
+        })?;
         if verbosity.debug() {
             println!("deleted {}", &file.file_name());
         }
@@ -1,18 +1,22 @@
 use ::std::fs;
 use ::std::hash;
 use ::std::io;
-#[cfg(any(target_os = "linux", target_os = "l4re"))]
+#[cfg(unix)]
 use ::std::os::unix::fs::PermissionsExt;
 use ::std::path::Path;
 use ::std::path::PathBuf;
 use ::std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::config::typ::Extension;
+use crate::files::scan::InputFile;
 use crate::header::strategy::Verbosity;
 use crate::util::pth::determine_output_path;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
 use std::fs::Metadata;
 
+/// The path that means stdin or stdout on the command line, also used as the name of streams.
+pub const STREAM_PATH: &str = "-";
+
 #[derive(Debug)]
 pub struct FileInfo<'a> {
     pub in_path: &'a Path,
@@ -37,6 +41,19 @@ impl<'a> FileInfo<'a> {
     pub fn size_kb(&self) -> u64 {
         self.size_b / 1024
     }
+
+    /// Stand-in for data that is not in a file, like stdin. The size is unknown, so it is zero.
+    pub fn stream() -> Self {
+        FileInfo {
+            in_path: Path::new(STREAM_PATH),
+            size_b: 0,
+            permissions: None,
+            created_ns: None,
+            changed_ns: None,
+            accessed_ns: None,
+            out_pth: PathBuf::from(STREAM_PATH),
+        }
+    }
 }
 
 // Only relies on in_path, which should be uniquely identifying
@@ -71,19 +88,18 @@ fn to_ns(sys_time: io::Result<SystemTime>) -> Option<u128> {
     }
 }
 
-#[cfg(any(target_os = "linux", target_os = "l4re"))]
+#[cfg(unix)]
 fn get_perms(meta: &Metadata) -> Option<u32> {
     Some(meta.permissions().mode())
 }
 
-//TODO: maybe support more operating systems
-#[cfg(not(any(target_os = "linux", target_os = "l4re")))]
-fn get_perms() -> Option<u32> {
+#[cfg(not(unix))]
+fn get_perms(_meta: &Metadata) -> Option<u32> {
     None
 }
 
 pub fn inspect_files<'a>(
-    files: &'a [PathBuf],
+    files: &'a [InputFile],
     verbosity: Verbosity,
     overwrite: bool,
     extension: Extension,
@@ -92,7 +108,8 @@ pub fn inspect_files<'a>(
     let mut not_found_cnt: u32 = 0;
     let mut output_exists_cnt: u32 = 0;
     let mut infos = Vec::with_capacity(files.len());
-    for file in files {
+    for input in files {
+        let file = &input.path;
         // Input file
         let meta = match fs::metadata(file) {
             Ok(meta) => meta,
@@ -116,8 +133,13 @@ pub fn inspect_files<'a>(
             continue;
         }
 
-        // Output file
-        let output_file = determine_output_path(file.as_path(), extension, output_dir);
+        // Output file, which mirrors the directory layout under the output directory,
+        // unless the names are obfuscated, in which case the layout should not show either.
+        let output_dir = match extension {
+            Extension::Obfuscate(_) => output_dir.map(Path::to_owned),
+            _ => output_dir.map(|dir| dir.join(&input.sub_dir)),
+        };
+        let output_file = determine_output_path(file.as_path(), extension, output_dir.as_deref());
         if !overwrite && output_file.exists() {
             eprintln!(
                 "output path '{}' already exists",
@@ -137,13 +159,13 @@ pub fn inspect_files<'a>(
         });
     }
     if not_found_cnt > 0 {
-        return Err(format!(
+        return Err(FedError::NotFound(format!(
             "aborting because {} input file{} not found",
             not_found_cnt,
             if not_found_cnt > 1 { "s were" } else { " was" }
-        ));
+        )));
     } else if output_exists_cnt > 0 {
-        return Err(format!(
+        return Err(FedError::OutputExists(format!(
             "aborting because {} output file{} already exist (use --overwrite to overwrite, \
             or --output-dir {}to control output location)",
             output_exists_cnt,
@@ -153,7 +175,7 @@ pub fn inspect_files<'a>(
             } else {
                 ""
             },
-        ));
+        )));
     }
     Ok(infos)
 }
@@ -164,33 +186,31 @@ mod tests {
     use ::tempfile::TempDir;
 
     use crate::config::typ::Extension;
-    use crate::config::typ::{EndecConfig, InputAction, OnFileExist};
+    use crate::config::typ::{EndecConfig, FileSelection, InputAction, OnFileExist};
     use crate::config::EncryptConfig;
+    use crate::files::scan::collect_input_files;
     use crate::header::strategy::Verbosity;
     use crate::key::Key;
-    use crate::util::option::EncOptionSet;
 
     use super::*;
-    use crate::config::enc::RunMode;
 
     #[test]
     fn output_path() {
         let pth = TempDir::new().unwrap();
         let in_file_1 = NamedTempFile::new_in(pth.path()).unwrap();
         let in_file_2 = NamedTempFile::new_in(pth.path()).unwrap();
-        let config = EncryptConfig::new(
-            vec![in_file_1.path().to_owned(), in_file_2.path().to_owned()],
-            Key::new("secret"),
-            EncOptionSet::empty(),
-            Verbosity::Debug,
-            OnFileExist::Overwrite,
-            InputAction::Delete,
-            None,
-            ".enc".to_owned(),
-            RunMode::IsReal,
-        );
+        let config = EncryptConfig::builder(Key::new("secret"))
+            .with_file(in_file_1.path().to_owned())
+            .with_file(in_file_2.path().to_owned())
+            .with_verbosity(Verbosity::Debug)
+            .with_overwrite(OnFileExist::Overwrite)
+            .with_delete_input(InputAction::Delete)
+            .build()
+            .unwrap();
+        let input_files =
+            collect_input_files(config.files(), config.selection(), |_| true).unwrap();
         let out_files = inspect_files(
-            config.files(),
+            &input_files,
             config.verbosity(),
             config.overwrite(),
             Extension::Add(".enc"),
@@ -203,4 +223,30 @@ mod tests {
         assert_eq!(out_files[0].out_pth.to_string_lossy(), expected_out_pth_1);
         assert_eq!(out_files[1].out_pth.to_string_lossy(), expected_out_pth_2);
     }
+
+    #[test]
+    fn output_path_mirrors_directories() {
+        let in_dir = TempDir::new().unwrap();
+        fs::create_dir_all(in_dir.path().join("sub/deeper")).unwrap();
+        fs::write(in_dir.path().join("top.txt"), b"top").unwrap();
+        fs::write(in_dir.path().join("sub/deeper/low.txt"), b"low").unwrap();
+        let out_dir = TempDir::new().unwrap();
+        let selection = FileSelection::new(true, &[], &[]).unwrap();
+        let input_files =
+            collect_input_files(&[in_dir.path().to_owned()], &selection, |_| true).unwrap();
+        let out_files = inspect_files(
+            &input_files,
+            Verbosity::Quiet,
+            false,
+            Extension::Add(".enc"),
+            Some(out_dir.path()),
+        )
+        .unwrap();
+        assert_eq!(2, out_files.len());
+        assert_eq!(
+            out_dir.path().join("sub/deeper/low.txt.enc"),
+            out_files[0].out_pth
+        );
+        assert_eq!(out_dir.path().join("top.txt.enc"), out_files[1].out_pth);
+    }
 }
@@ -1,13 +1,14 @@
 pub use checksum::Checksum;
 
+pub mod archive;
 pub mod checksum;
 pub mod compress;
 pub mod delete;
 pub mod file_meta;
 pub mod mockfile;
+pub mod padding;
 pub mod read_headers;
 pub mod reading;
-pub mod write_output;
-
-#[cfg(test)]
+pub mod replace_header;
 pub mod scan;
+pub mod write_output;
@@ -0,0 +1,143 @@
+use ::std::io::Read;
+use ::std::io::Write;
+
+use ::ring::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+
+use crate::key::Salt;
+use crate::util::errors::wrap_io;
+use crate::util::rounding::round_up_to_power_of_two;
+use crate::util::{FedError, FedResult};
+
+/// Number of padding bytes needed to round the compressed data up to a power of two.
+pub fn data_padding_len(compressed_len: u64) -> u64 {
+    round_up_to_power_of_two(compressed_len) - compressed_len
+}
+
+/// Generates padding from the pepper. It is deterministic, so that it can be verified
+/// when decrypting, but it looks random, so that it does not compress.
+struct PaddingGenerator<'a> {
+    pepper: &'a Salt,
+    counter: u64,
+}
+
+impl<'a> PaddingGenerator<'a> {
+    fn new(pepper: &'a Salt) -> Self {
+        PaddingGenerator { pepper, counter: 0 }
+    }
+
+    fn next_block(&mut self) -> [u8; SHA256_OUTPUT_LEN] {
+        let mut input = self.pepper.salt.to_vec();
+        input.extend_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        let mut block = [0u8; SHA256_OUTPUT_LEN];
+        block.copy_from_slice(digest(&SHA256, &input).as_ref());
+        block
+    }
+}
+
+/// Write `len` bytes of padding.
+pub fn write_padding(writer: &mut impl Write, pepper: &Salt, len: u64) -> FedResult<()> {
+    let mut generator = PaddingGenerator::new(pepper);
+    let mut remaining = len as usize;
+    while remaining > 0 {
+        let block = generator.next_block();
+        let count = remaining.min(block.len());
+        wrap_io(
+            || "failed to write padding",
+            writer.write_all(&block[..count]),
+        )?;
+        remaining -= count;
+    }
+    Ok(())
+}
+
+/// Padding for the private header, which has to be printable. It is also derived from
+/// the pepper, so that the header is reproducible if the pepper is.
+pub fn printable_padding(buffer: &mut String, pepper: &Salt, len: usize) {
+    buffer.clear();
+    let mut generator = PaddingGenerator::new(pepper);
+    while buffer.len() < len {
+        for byte in generator.next_block().iter().take(len - buffer.len()) {
+            // Characters in the non-whitespace, printable ascii range, except the colon,
+            // because a line ending in a colon would look like the end of the header.
+            let chr = b'!' + byte % 93;
+            buffer.push(if chr >= b':' { chr + 1 } else { chr } as char);
+        }
+    }
+}
+
+/// Read `len` bytes of padding, and check that they are the expected padding.
+pub fn verify_padding(reader: &mut impl Read, pepper: &Salt, len: u64) -> FedResult<()> {
+    let mut generator = PaddingGenerator::new(pepper);
+    let mut remaining = len as usize;
+    let mut buffer = [0u8; SHA256_OUTPUT_LEN];
+    while remaining > 0 {
+        let block = generator.next_block();
+        let count = remaining.min(block.len());
+        wrap_io(
+            || "could not read the padding; the file may be corrupted",
+            reader.read_exact(&mut buffer[..count]),
+        )?;
+        if buffer[..count] != block[..count] {
+            return Err(FedError::Corrupted(
+                "the padding did not match; the file may be corrupted".to_owned(),
+            ));
+        }
+        remaining -= count;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::files::compress::brotli_compress;
+
+    use super::*;
+
+    #[test]
+    fn padding_len() {
+        assert_eq!(0, data_padding_len(0));
+        assert_eq!(0, data_padding_len(1024));
+        assert_eq!(1023, data_padding_len(1025));
+        assert_eq!(3, data_padding_len(13));
+    }
+
+    #[test]
+    fn deterministic() {
+        let pepper = Salt::fixed_for_test(123_456);
+        let mut first = vec![];
+        write_padding(&mut first, &pepper, 100).unwrap();
+        let mut second = vec![];
+        write_padding(&mut second, &pepper, 50).unwrap();
+        assert_eq!(100, first.len());
+        assert_eq!(&first[..50], second.as_slice());
+        verify_padding(&mut first.as_slice(), &pepper, 100).unwrap();
+    }
+
+    #[test]
+    fn printable() {
+        let mut padding = String::new();
+        printable_padding(&mut padding, &Salt::fixed_for_test(123_456), 100);
+        assert_eq!(100, padding.len());
+        assert!(padding.chars().all(|c| c.is_ascii_graphic() && c != ':'));
+        let mut other = String::new();
+        printable_padding(&mut other, &Salt::fixed_for_test(123_456), 100);
+        assert_eq!(padding, other);
+    }
+
+    #[test]
+    fn incompressible() {
+        let mut padding = vec![];
+        write_padding(&mut padding, &Salt::fixed_for_test(123_456), 10_000).unwrap();
+        assert!(brotli_compress(&padding).unwrap().len() >= padding.len());
+    }
+
+    #[test]
+    fn detect_wrong_padding() {
+        let mut padding = vec![];
+        write_padding(&mut padding, &Salt::fixed_for_test(123_456), 100).unwrap();
+        let other_pepper = Salt::fixed_for_test(654_321);
+        assert!(verify_padding(&mut padding.as_slice(), &other_pepper, 100).is_err());
+        assert!(verify_padding(&mut &padding[..99], &Salt::fixed_for_test(123_456), 100).is_err());
+    }
+}
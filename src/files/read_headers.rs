@@ -1,6 +1,7 @@
 use crate::files::file_meta::FileInfo;
 use crate::files::reading::open_reader;
 use crate::header::{get_version_strategy, parse_public_header, PublicHeader, Strategy};
+use crate::key::cost::KdfCost;
 use crate::{FedResult, Verbosity};
 
 #[derive(Debug)]
@@ -8,7 +9,9 @@ pub struct FileHeaderStrategy<'a> {
     pub file: &'a FileInfo<'a>,
     pub pub_header: PublicHeader,
     pub pub_header_len: usize,
-    pub strategy: &'a Strategy,
+    pub strategy: Strategy,
+    /// The cost from the header, or the default of the strategy for older files.
+    pub kdf_cost: KdfCost,
 }
 
 impl<'a> FileHeaderStrategy<'a> {
@@ -19,11 +22,16 @@ impl<'a> FileHeaderStrategy<'a> {
         verbosity: Verbosity,
     ) -> FedResult<Self> {
         let strategy = get_version_strategy(header.version(), header.options(), verbosity.debug())?;
+        let kdf_cost = header
+            .kdf_cost()
+            .cloned()
+            .unwrap_or_else(|| strategy.kdf_cost.clone());
         Ok(FileHeaderStrategy {
             file,
             pub_header: header,
             pub_header_len: header_len,
             strategy,
+            kdf_cost,
         })
     }
 }
@@ -34,7 +42,7 @@ pub fn read_file_strategies<'a>(
 ) -> FedResult<Vec<FileHeaderStrategy<'a>>> {
     files
         .iter()
-        .map(|fi| (fi, open_reader(&fi, verbosity)))
+        .map(|fi| (fi, open_reader(fi, verbosity)))
         .map(|(fi, reader)| {
             (
                 fi,
@@ -48,16 +56,16 @@ pub fn read_file_strategies<'a>(
 }
 
 pub trait FileStrategy {
-    fn file(&self) -> &FileInfo;
+    fn file(&self) -> &FileInfo<'_>;
     fn strategy(&self) -> &Strategy;
 }
 
 impl<'a> FileStrategy for FileHeaderStrategy<'a> {
-    fn file(&self) -> &FileInfo {
+    fn file(&self) -> &FileInfo<'_> {
         self.file
     }
 
     fn strategy(&self) -> &Strategy {
-        self.strategy
+        &self.strategy
     }
 }
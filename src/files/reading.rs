@@ -1,28 +1,32 @@
 use ::std::fs::File;
-use ::std::io::{BufReader, Read};
+use ::std::io;
+use ::std::io::{BufRead, BufReader, Read};
 
 use crate::files::file_meta::FileInfo;
 use crate::header::strategy::Verbosity;
 use crate::header::PUB_HEADER_MARKER;
 use crate::util::errors::wrap_io;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
+
+/// Files are read and processed in chunks of this size, so they never need to fit in memory.
+pub const CHUNK_SIZE: usize = 64 * 1024;
 
 pub fn open_reader(file: &FileInfo, verbosity: Verbosity) -> FedResult<BufReader<File>> {
     match File::open(file.in_path) {
-        Ok(file) => Ok(BufReader::new(file)),
-        Err(err) => Err(if verbosity.debug() {
+        Ok(file) => Ok(BufReader::with_capacity(CHUNK_SIZE, file)),
+        Err(err) => Err(FedError::NotFound(if verbosity.debug() {
             format!("could not open input file {}: {}", file.path_str(), err)
         } else {
             format!("could not open input file {}", file.path_str())
-        }),
+        })),
     }
 }
 
-pub fn read_file(
-    data: &mut Vec<u8>,
+/// Prepare to read the file, warning if it looks like it was already encrypted.
+/// This only peeks at the start of the file; nothing is consumed from the reader.
+pub fn start_reading_file(
     reader: &mut BufReader<File>,
     path_str: &str,
-    size_kb: u64,
     verbosity: Verbosity,
     start_progress: &mut impl FnMut(),
 ) -> FedResult<()> {
@@ -30,18 +34,74 @@ pub fn read_file(
     if verbosity.debug() {
         println!("reading {}", path_str);
     }
-    if !verbosity.quiet() && size_kb > 1024 * 1024 {
-        eprintln!(
-            "warning: reading {} Mb file '{}' into RAM",
-            size_kb / 1024,
-            path_str
-        );
-    }
-    wrap_io(|| "could not read input file", reader.read_to_end(data))?;
-
-    if !verbosity.quiet() && data.starts_with(PUB_HEADER_MARKER.as_bytes()) {
-        //TODO @mark: this is called during decrypt, how does this not happen too often?
+    let start = wrap_io(|| "could not read input file", reader.fill_buf())?;
+    if !verbosity.quiet() && start.starts_with(PUB_HEADER_MARKER.as_bytes()) {
         eprintln!("warning: file '{}' seems to already be encrypted", path_str);
     }
     Ok(())
 }
+
+/// Reads all but the last bytes of a stream, which are kept as the trailer. For files the
+/// trailer can be read by seeking to the end, but that is not possible for streams like stdin.
+pub struct TrailerReader<R: Read> {
+    reader: R,
+    trailer_len: usize,
+    pending: Vec<u8>,
+    is_done: bool,
+}
+
+impl<R: Read> TrailerReader<R> {
+    pub fn new(reader: R, trailer_len: usize) -> Self {
+        TrailerReader {
+            reader,
+            trailer_len,
+            pending: Vec::with_capacity(CHUNK_SIZE + trailer_len),
+            is_done: false,
+        }
+    }
+
+    /// Skip any remaining data, and return the trailer. It is shorter than expected
+    /// if the stream was too short.
+    pub fn read_trailer(&mut self) -> io::Result<Vec<u8>> {
+        io::copy(self, &mut io::sink())?;
+        Ok(self.pending.clone())
+    }
+}
+
+impl<R: Read> Read for TrailerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; 8 * 1024];
+        while self.pending.len() <= self.trailer_len && !self.is_done {
+            let count = self.reader.read(&mut chunk)?;
+            self.is_done = count == 0;
+            self.pending.extend_from_slice(&chunk[..count]);
+        }
+        let count = buf
+            .len()
+            .min(self.pending.len().saturating_sub(self.trailer_len));
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailer_is_held_back() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let mut reader = TrailerReader::new(data.as_slice(), 10);
+        let mut start = vec![0; 50];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(&data[..50], start.as_slice());
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(&data[50..90], rest.as_slice());
+        assert_eq!(&data[90..], reader.read_trailer().unwrap().as_slice());
+
+        let mut short = TrailerReader::new(&data[..4], 10);
+        assert_eq!(&data[..4], short.read_trailer().unwrap().as_slice());
+    }
+}
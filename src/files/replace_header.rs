@@ -0,0 +1,164 @@
+use ::std::fs;
+use ::std::fs::File;
+use ::std::io;
+use ::std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use ::std::path::{Path, PathBuf};
+
+use crate::files::reading::CHUNK_SIZE;
+use crate::header::{parse_public_header, write_public_header, PublicHeader};
+use crate::util::errors::wrap_io;
+use crate::util::FedResult;
+
+/// Read the public header of an encrypted file, and the number of bytes it takes.
+pub fn read_public_header(pth: &Path, verbose: bool) -> FedResult<(usize, PublicHeader)> {
+    let file = wrap_io(
+        || format!("could not open encrypted file '{}'", pth.to_string_lossy()),
+        File::open(pth),
+    )?;
+    parse_public_header(&mut BufReader::new(file), verbose)
+        .map_err(|err| err.map_message(|msg| format!("{} (file '{}')", msg, pth.to_string_lossy())))
+}
+
+fn temporary_path(pth: &Path) -> PathBuf {
+    let mut name = pth.file_name().unwrap().to_owned();
+    name.push(".fed-tmp");
+    pth.with_file_name(name)
+}
+
+/// A new version of a file that has been written next to it, but has not replaced it yet.
+#[derive(Debug)]
+pub struct PendingRewrite {
+    pth: PathBuf,
+    tmp_pth: PathBuf,
+}
+
+impl PendingRewrite {
+    /// Replace the old file by the new version.
+    pub fn commit(self) -> FedResult<()> {
+        wrap_io(
+            || format!("could not update '{}'", self.pth.to_string_lossy()),
+            fs::rename(&self.tmp_pth, &self.pth),
+        )
+    }
+
+    /// Remove the new version, leaving the old file as it is.
+    pub fn discard(self) {
+        let _ = fs::remove_file(&self.tmp_pth);
+    }
+}
+
+fn write_replacement(
+    pth: &Path,
+    tmp_pth: &Path,
+    write: impl FnOnce(&mut File, &mut BufWriter<File>) -> FedResult<()>,
+) -> FedResult<()> {
+    let mut input = wrap_io(
+        || format!("could not open encrypted file '{}'", pth.to_string_lossy()),
+        File::open(pth),
+    )?;
+    let output = wrap_io(
+        || {
+            format!(
+                "could not create temporary file '{}'",
+                tmp_pth.to_string_lossy()
+            )
+        },
+        File::create(tmp_pth),
+    )?;
+    let mut output = BufWriter::with_capacity(CHUNK_SIZE, output);
+    write(&mut input, &mut output)?;
+    let err = || format!("could not update '{}'", pth.to_string_lossy());
+    let output = wrap_io(err, output.into_inner().map_err(|err| err.into_error()))?;
+    wrap_io(
+        err,
+        input
+            .metadata()
+            .and_then(|meta| output.set_permissions(meta.permissions())),
+    )?;
+    wrap_io(err, output.sync_all())
+}
+
+/// Write a new version of a file next to it, based on the current one. It only replaces the
+/// old file when committed, by renaming it over it, so the file is never left half-written.
+/// Several files can be changed together this way.
+pub fn prepare_rewrite(
+    pth: &Path,
+    write: impl FnOnce(&mut File, &mut BufWriter<File>) -> FedResult<()>,
+) -> FedResult<PendingRewrite> {
+    let tmp_pth = temporary_path(pth);
+    if let Err(err) = write_replacement(pth, &tmp_pth, write) {
+        let _ = fs::remove_file(&tmp_pth);
+        return Err(err);
+    }
+    Ok(PendingRewrite {
+        pth: pth.to_owned(),
+        tmp_pth,
+    })
+}
+
+/// Replace the public header of an encrypted file, and copy the rest of the file unchanged.
+pub fn replace_public_header(
+    pth: &Path,
+    header: &PublicHeader,
+    old_header_len: usize,
+    verbose: bool,
+) -> FedResult<()> {
+    prepare_public_header(pth, header, old_header_len, verbose)?.commit()
+}
+
+/// Like `replace_public_header`, but the file is only replaced when the result is committed.
+pub fn prepare_public_header(
+    pth: &Path,
+    header: &PublicHeader,
+    old_header_len: usize,
+    verbose: bool,
+) -> FedResult<PendingRewrite> {
+    prepare_rewrite(pth, |input, output| {
+        write_public_header(output, header, verbose)?;
+        wrap_io(
+            || format!("could not update the header of '{}'", pth.to_string_lossy()),
+            input
+                .seek(SeekFrom::Start(old_header_len as u64))
+                .and_then(|_| io::copy(input, output)),
+        )?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ::semver::Version;
+    use ::tempfile::tempdir;
+
+    use crate::files::Checksum;
+    use crate::key::Salt;
+    use crate::util::option::EncOptionSet;
+
+    use super::*;
+
+    #[test]
+    fn replace_keeps_data() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("file.enc");
+        let header = |salt: u64| {
+            PublicHeader::new(
+                Version::parse("1.2.0").unwrap(),
+                Salt::fixed_for_test(salt),
+                EncOptionSet::empty(),
+                (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+            )
+        };
+        let mut content = vec![];
+        write_public_header(&mut content, &header(1), false).unwrap();
+        content.extend_from_slice(b"the encrypted data");
+        fs::write(&pth, &content).unwrap();
+
+        let (header_len, original) = read_public_header(&pth, false).unwrap();
+        assert_eq!(header(1), original);
+        replace_public_header(&pth, &header(2), header_len, false).unwrap();
+        let (_, replaced) = read_public_header(&pth, false).unwrap();
+        assert_eq!(header(2), replaced);
+        assert!(fs::read(&pth).unwrap().ends_with(b"the encrypted data"));
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
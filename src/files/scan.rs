@@ -1,12 +1,114 @@
+#[cfg(test)]
 use ::std::env;
+use ::std::ffi::OsStr;
+use ::std::fs;
+use ::std::path::{Path, PathBuf};
 
-use crate::FedResult;
+#[cfg(test)]
 use ::lazy_static::lazy_static;
-use std::ffi::OsStr;
-use std::fs;
-use std::path::{Path, PathBuf};
 
-/// Recursively get all the files in a directory that have '.enc' extension.
+use crate::config::typ::FileSelection;
+use crate::{FedError, FedResult};
+
+/// A file to use as input, with the directory relative to the directory in which it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub sub_dir: PathBuf,
+}
+
+impl InputFile {
+    pub fn direct(path: PathBuf) -> Self {
+        InputFile {
+            path,
+            sub_dir: PathBuf::new(),
+        }
+    }
+}
+
+/// Whether the path ends with the extension of encrypted files, which includes the dot.
+pub fn has_enc_extension(path: &Path, extension: &str) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .map(|name| name.len() > extension.len() && name.ends_with(extension))
+        .unwrap_or(false)
+}
+
+fn read_dir_sorted(dir: &Path) -> FedResult<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|err| {
+            FedError::Io(format!(
+                "Failed to read directory '{}' because '{}'",
+                dir.to_string_lossy(),
+                err
+            ))
+        })?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn walk_dir(
+    root: &Path,
+    dir: &Path,
+    selection: &FileSelection,
+    is_candidate: &impl Fn(&Path) -> bool,
+    found: &mut Vec<InputFile>,
+) -> FedResult<()> {
+    for path in read_dir_sorted(dir)? {
+        // Symlinks to directories are not followed, to prevent loops.
+        let is_dir = fs::symlink_metadata(&path)
+            .map(|meta| meta.is_dir())
+            .unwrap_or(false);
+        if is_dir {
+            walk_dir(root, &path, selection, is_candidate, found)?;
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap();
+        if is_candidate(&path) && selection.matches(relative) {
+            found.push(InputFile {
+                sub_dir: relative.parent().unwrap_or(Path::new("")).to_owned(),
+                path,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Find the input files. Directories are walked if the selection is recursive, in which case
+/// only files that are candidates and match the selection are used. The order is deterministic.
+pub fn collect_input_files(
+    paths: &[PathBuf],
+    selection: &FileSelection,
+    is_candidate: impl Fn(&Path) -> bool,
+) -> FedResult<Vec<InputFile>> {
+    let mut found = vec![];
+    for path in paths {
+        if selection.recursive() && path.is_dir() {
+            let count = found.len();
+            walk_dir(path, path, selection, &is_candidate, &mut found)?;
+            if found.len() == count {
+                eprintln!(
+                    "warning: no matching files found in directory '{}'",
+                    path.to_string_lossy()
+                );
+            }
+        } else {
+            found.push(InputFile::direct(path.to_owned()));
+        }
+    }
+    if found.is_empty() {
+        return Err(FedError::NotFound("no input files were found".to_owned()));
+    }
+    Ok(found)
+}
+
+/// Get all the files directly in a directory that have '.enc' extension.
+#[cfg(test)]
 pub fn get_enc_files_direct(dir: &Path) -> FedResult<Vec<PathBuf>> {
     let mut matches = vec![];
     match fs::read_dir(dir) {
@@ -18,26 +120,26 @@ pub fn get_enc_files_direct(dir: &Path) -> FedResult<Vec<PathBuf>> {
                         if !path.is_file() {
                             continue;
                         }
-                        if let Some("enc") = path.extension().and_then(OsStr::to_str) {
+                        if has_enc_extension(&path, ".enc") {
                             matches.push(path.to_owned());
                         }
                     }
                     Err(err) => {
-                        return Err(format!(
+                        return Err(FedError::Io(format!(
                             "Failed on entry in directory '{}' because '{}'",
                             dir.to_string_lossy(),
                             err
-                        ))
+                        )))
                     }
                 }
             }
         }
         Err(err) => {
-            return Err(format!(
+            return Err(FedError::Io(format!(
                 "Failed to read directory '{}' because '{}'",
                 dir.to_string_lossy(),
                 err
-            ))
+            )))
         }
     }
     Ok(matches)
@@ -63,10 +165,10 @@ lazy_static! {
                     original_file = test_files_dir.clone();
                     original_file.push("original.png");
                     if !original_file.is_file() {
-                        panic!(format!("Expected test files at '{}' based on environment variable 'ENDEC_TEST_FILE_DIR', but the files were not found.", test_files_dir.to_string_lossy()));
+                        panic!("Expected test files at '{}' based on environment variable 'ENDEC_TEST_FILE_DIR', but the files were not found.", test_files_dir.to_string_lossy());
                     }
                 },
-                Err(_) => panic!(format!("Expected test files at '{}' but they were not found; set the environment variable 'ENDEC_TEST_FILE_DIR' to the test file location.", test_files_dir.to_string_lossy())),
+                Err(_) => panic!("Expected test files at '{}' but they were not found; set the environment variable 'ENDEC_TEST_FILE_DIR' to the test file location.", test_files_dir.to_string_lossy()),
             }
         }
         test_files_dir
@@ -75,11 +177,101 @@ lazy_static! {
 
 #[cfg(test)]
 mod tests {
+    use ::tempfile::tempdir;
+
     use super::*;
 
     #[test]
     fn find_files() {
-        let files = get_enc_files_direct(&*TEST_FILE_DIR).unwrap();
+        let files = get_enc_files_direct(&TEST_FILE_DIR).unwrap();
         assert!(!files.is_empty(), "no .enc files found");
     }
+
+    fn create_tree() -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        for name in &[
+            "b.txt",
+            "a.txt",
+            "a.txt.enc",
+            "sub/c.txt",
+            "sub/deeper/d.png",
+        ] {
+            let pth = dir.path().join(name);
+            fs::create_dir_all(pth.parent().unwrap()).unwrap();
+            fs::write(pth, name.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    fn relative_paths(root: &Path, files: &[InputFile]) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| {
+                assert_eq!(
+                    file.path.parent().unwrap(),
+                    root.join(&file.sub_dir).as_path()
+                );
+                file.path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recursive_sorted() {
+        let dir = create_tree();
+        let selection = FileSelection::new(true, &[], &[]).unwrap();
+        let files = collect_input_files(&[dir.path().to_owned()], &selection, |pth| {
+            !has_enc_extension(pth, ".enc")
+        })
+        .unwrap();
+        assert_eq!(
+            vec!["a.txt", "b.txt", "sub/c.txt", "sub/deeper/d.png"],
+            relative_paths(dir.path(), &files)
+        );
+    }
+
+    #[test]
+    fn recursive_enc_only() {
+        let dir = create_tree();
+        let selection = FileSelection::new(true, &[], &[]).unwrap();
+        let files = collect_input_files(&[dir.path().to_owned()], &selection, |pth| {
+            has_enc_extension(pth, ".enc")
+        })
+        .unwrap();
+        assert_eq!(vec!["a.txt.enc"], relative_paths(dir.path(), &files));
+        let files = collect_input_files(&[dir.path().to_owned()], &selection, |pth| {
+            has_enc_extension(pth, ".png")
+        })
+        .unwrap();
+        assert_eq!(vec!["sub/deeper/d.png"], relative_paths(dir.path(), &files));
+    }
+
+    #[test]
+    fn include_exclude() {
+        let dir = create_tree();
+        let selection =
+            FileSelection::new(true, &["*.txt".to_owned()], &["sub/*".to_owned()]).unwrap();
+        let files = collect_input_files(&[dir.path().to_owned()], &selection, |_| true).unwrap();
+        assert_eq!(vec!["a.txt", "b.txt"], relative_paths(dir.path(), &files));
+    }
+
+    #[test]
+    fn directory_not_recursive() {
+        let dir = create_tree();
+        let files =
+            collect_input_files(&[dir.path().to_owned()], &FileSelection::default(), |_| {
+                true
+            })
+            .unwrap();
+        assert_eq!(vec![InputFile::direct(dir.path().to_owned())], files);
+    }
+
+    #[test]
+    fn invalid_glob() {
+        assert!(FileSelection::new(true, &["[".to_owned()], &[]).is_err());
+    }
 }
@@ -1,55 +1,166 @@
+use ::std::fs;
 use ::std::fs::File;
-use ::std::io::Write;
+use ::std::fs::FileTimes;
+#[cfg(unix)]
+use ::std::fs::Permissions;
+use ::std::io::BufWriter;
+#[cfg(unix)]
+use ::std::os::unix::fs::PermissionsExt;
+use ::std::path::Path;
+use ::std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::config::typ::EndecConfig;
 use crate::files::delete::delete_existing_file_in_output_location;
-use crate::files::file_meta::FileInfo;
-use crate::header::write_public_header;
-use crate::header::PublicHeader;
+use crate::files::reading::CHUNK_SIZE;
+use crate::header::private_header_type::PrivateHeader;
 use crate::util::errors::wrap_io;
-use crate::util::FedResult;
-
-pub fn write_output_file(
-    config: &impl EndecConfig,
-    file: &FileInfo,
-    data: &[u8],
-    header: Option<&PublicHeader>,
-    start_progress: &mut impl FnMut(),
-) -> FedResult<()> {
-    start_progress();
-    if file.out_pth.exists() {
+use crate::util::{FedError, FedResult};
+
+/// Create the output file, so that data can be streamed into it.
+pub fn open_output_file(config: &impl EndecConfig, out_pth: &Path) -> FedResult<BufWriter<File>> {
+    if out_pth.exists() {
         if config.overwrite() {
-            delete_existing_file_in_output_location(&file)?;
+            delete_existing_file_in_output_location(out_pth)?;
         } else {
-            return Err(format!(
-                "While encrypting, a file appeared in previously empty output location '{}'",
-                &file.out_pth.to_string_lossy()
-            ));
+            return Err(FedError::OutputExists(format!(
+                "output path '{}' already exists (use --overwrite to overwrite, \
+                or --output-dir to control output location)",
+                out_pth.to_string_lossy()
+            )));
         }
     }
-    let mut out_file = wrap_io(
+    if let Some(parent) = out_pth.parent() {
+        // Recursive mode mirrors the input directories, which may not exist yet.
+        wrap_io(
+            || {
+                format!(
+                    "Could not create output directory '{}'",
+                    parent.to_string_lossy()
+                )
+            },
+            fs::create_dir_all(parent),
+        )?;
+    }
+    let out_file = wrap_io(
         || {
             format!(
                 "Could not create output file for '{}'",
-                &file.out_pth.to_string_lossy()
+                out_pth.to_string_lossy()
             )
         },
-        File::create(&file.out_pth),
+        File::create(out_pth),
     )?;
-    if let Some(header) = header {
-        write_public_header(&mut out_file, header, config.debug())?;
+    Ok(BufWriter::with_capacity(CHUNK_SIZE, out_file))
+}
+
+fn from_ns(time_ns: u128) -> SystemTime {
+    let nanos_per_sec = 1_000_000_000;
+    UNIX_EPOCH
+        + Duration::new(
+            (time_ns / nanos_per_sec) as u64,
+            (time_ns % nanos_per_sec) as u32,
+        )
+}
+
+#[cfg(unix)]
+fn set_perms(file: &File, permissions: u32) -> ::std::io::Result<()> {
+    // Setuid, setgid and sticky bits are not restored, since the header could be crafted.
+    file.set_permissions(Permissions::from_mode(permissions & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_perms(_file: &File, _permissions: u32) -> ::std::io::Result<()> {
+    Ok(())
+}
+
+/// Apply the permissions and modification and access times from the private header
+/// to the output file. The creation time cannot be changed, so it is not restored.
+pub fn restore_file_meta(file: &File, out_pth: &Path, header: &PrivateHeader) -> FedResult<()> {
+    let mut times = FileTimes::new();
+    if let Some(time_ns) = header.changed_ns() {
+        times = times.set_modified(from_ns(time_ns));
+    }
+    if let Some(time_ns) = header.accessed_ns() {
+        times = times.set_accessed(from_ns(time_ns));
     }
     wrap_io(
         || {
             format!(
-                "Failed to write encrypted output data for '{}'",
-                &file.out_pth.to_string_lossy()
+                "Could not restore the timestamps of '{}'",
+                out_pth.to_string_lossy()
             )
         },
-        out_file.write_all(&data),
+        file.set_times(times),
     )?;
-    if config.debug() {
-        println!("encrypted {}", &file.file_name());
+    if let Some(permissions) = header.permissions() {
+        wrap_io(
+            || {
+                format!(
+                    "Could not restore the permissions of '{}'",
+                    out_pth.to_string_lossy()
+                )
+            },
+            set_perms(file, permissions),
+        )?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use ::std::fs;
+
+    use ::tempfile::tempdir;
+
+    use crate::key::Salt;
+
+    use super::*;
+
+    #[test]
+    fn restore_meta() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("restored.txt");
+        fs::write(&pth, b"hello").unwrap();
+        let header = PrivateHeader::new(
+            Some("restored.txt".to_owned()),
+            Some(0o100_640),
+            Some(1_000_000_000_000_000_000),
+            Some(1_200_000_000_123_456_789),
+            Some(1_300_000_000_000_000_000),
+            Some(5),
+            Salt::fixed_for_test(1),
+            0,
+            0,
+        );
+        let file = File::open(&pth).unwrap();
+        restore_file_meta(&file, &pth, &header).unwrap();
+        let meta = fs::metadata(&pth).unwrap();
+        assert_eq!(from_ns(1_200_000_000_123_456_789), meta.modified().unwrap());
+        assert_eq!(from_ns(1_300_000_000_000_000_000), meta.accessed().unwrap());
+        #[cfg(unix)]
+        assert_eq!(0o640, meta.permissions().mode() & 0o7777);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_special_bits() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("restored.sh");
+        fs::write(&pth, b"echo hello").unwrap();
+        let header = PrivateHeader::new(
+            Some("restored.sh".to_owned()),
+            Some(0o106_755),
+            None,
+            None,
+            None,
+            Some(10),
+            Salt::fixed_for_test(1),
+            0,
+            0,
+        );
+        let file = File::open(&pth).unwrap();
+        restore_file_meta(&file, &pth, &header).unwrap();
+        let meta = fs::metadata(&pth).unwrap();
+        assert_eq!(0o755, meta.permissions().mode() & 0o7777);
+    }
+}
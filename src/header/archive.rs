@@ -0,0 +1,149 @@
+use ::std::io::BufRead;
+use ::std::io::Write;
+
+use crate::header::decode_util::{read_header_keys, HeaderErr};
+use crate::header::encode_util::write_line;
+use crate::header::private_decode::parse_private_header;
+use crate::header::private_encode::write_private_header;
+use crate::header::private_header_type::PrivateHeader;
+use crate::util::base::{small_str_to_u64, u64_to_small_str};
+use crate::util::{FedError, FedResult};
+
+pub const ARCHIVE_MEMBER_COUNT: &str = "mbrs";
+pub const ARCHIVE_INDEX_END: &str = "idx:";
+
+/// The index at the start of an archive's data. It has a private header for each member,
+/// with the name relative to the archive root. The members' data follows in the same order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    members: Vec<PrivateHeader>,
+}
+
+impl ArchiveIndex {
+    pub fn new(members: Vec<PrivateHeader>) -> Self {
+        debug_assert!(members
+            .iter()
+            .all(|member| member.filename().is_some() && member.size().is_some()));
+        ArchiveIndex { members }
+    }
+
+    pub fn members(&self) -> &[PrivateHeader] {
+        &self.members
+    }
+
+    /// Total size of the data of all members, excluding the index.
+    pub fn data_size(&self) -> u64 {
+        self.members
+            .iter()
+            .map(|member| member.size().unwrap())
+            .sum()
+    }
+}
+
+pub fn write_archive_index(
+    writer: &mut impl Write,
+    index: &ArchiveIndex,
+    verbose: bool,
+) -> FedResult<()> {
+    write_line(
+        writer,
+        ARCHIVE_MEMBER_COUNT,
+        Some(&u64_to_small_str(index.members.len() as u64)),
+        verbose,
+    )?;
+    write_line(writer, ARCHIVE_INDEX_END, None, verbose)?;
+    for member in &index.members {
+        write_private_header(writer, member, verbose)?;
+    }
+    Ok(())
+}
+
+/// Parse the index, leaving the reader at the start of the first member's data.
+pub fn parse_archive_index<R: BufRead>(reader: &mut R) -> FedResult<ArchiveIndex> {
+    let (_, mut header_data) =
+        read_header_keys(reader, None, &[ARCHIVE_INDEX_END]).map_err(|err| {
+            FedError::Corrupted(match err {
+                HeaderErr::NoEndMarker => {
+                    "could not find the end of the archive index; has the file been corrupted?"
+                        .to_owned()
+                }
+                _ => "the archive index could not be read; has the file been corrupted?".to_owned(),
+            })
+        })?;
+    let count = header_data
+        .remove(ARCHIVE_MEMBER_COUNT)
+        .and_then(|count| small_str_to_u64(&count))
+        .ok_or_else(|| {
+            FedError::Corrupted(
+                "could not find the number of files in the archive index".to_owned(),
+            )
+        })?;
+    let mut members = Vec::with_capacity((count as usize).min(1024));
+    for _ in 0..count {
+        let (_, member) = parse_private_header(reader)?;
+        if member.filename().is_none() || member.size().is_none() {
+            return Err(FedError::Corrupted(
+                "a file in the archive index does not have a name or size".to_owned(),
+            ));
+        }
+        members.push(member);
+    }
+    Ok(ArchiveIndex::new(members))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Read;
+
+    use crate::key::Salt;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let pepper = Salt::fixed_for_test(123_456);
+        let index = ArchiveIndex::new(vec![
+            PrivateHeader::new(
+                Some("alpha.txt".to_owned()),
+                Some(0o644),
+                None,
+                Some(987_654_321_000),
+                None,
+                Some(1_000),
+                pepper.clone(),
+                0,
+                0,
+            ),
+            PrivateHeader::new(
+                Some("sub/beta.png".to_owned()),
+                None,
+                None,
+                None,
+                None,
+                Some(0),
+                pepper,
+                0,
+                0,
+            ),
+        ]);
+        let mut buf = vec![];
+        write_archive_index(&mut buf, &index, true).unwrap();
+        let expected_start = format!("mbrs {}\nidx:\nname alpha.txt\n", u64_to_small_str(2));
+        assert!(buf.starts_with(expected_start.as_bytes()));
+        buf.extend_from_slice(b"data");
+        let mut reader = buf.as_slice();
+        let parsed = parse_archive_index(&mut reader).unwrap();
+        assert_eq!(index, parsed);
+        assert_eq!(1_000, parsed.data_size());
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(b"data", rest.as_slice());
+    }
+
+    #[test]
+    fn truncated() {
+        let txt = format!("mbrs {}\nidx:\nname alpha.txt\n", u64_to_small_str(1));
+        let mut reader = txt.as_bytes();
+        assert!(parse_archive_index(&mut reader).is_err());
+    }
+}
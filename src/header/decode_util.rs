@@ -29,7 +29,7 @@ fn read_line(
             return Err(HeaderErr::ReadError);
         }
     }
-    *index = *index + line.len();
+    *index += line.len();
     line.pop();
     dbg!(&line); //TODO @mark: TEMPORARY! REMOVE THIS!
     Ok(())
@@ -101,8 +101,7 @@ mod tests {
         fn empty() {
             let input = "hello\0\nworld:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let (index, map) =
-                read_header_keys(&mut reader, Some("hello\0"), &vec!["world:"]).unwrap();
+            let (index, map) = read_header_keys(&mut reader, Some("hello\0"), &["world:"]).unwrap();
             assert_eq!(index, 14);
             assert!(map.is_empty());
         }
@@ -111,7 +110,7 @@ mod tests {
         fn no_start_empty() {
             let input = "end:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let (index, map) = read_header_keys(&mut reader, None, &vec!["end:"]).unwrap();
+            let (index, map) = read_header_keys(&mut reader, None, &["end:"]).unwrap();
             assert_eq!(index, 5);
             assert!(map.is_empty());
         }
@@ -120,7 +119,7 @@ mod tests {
         fn no_start_single_end() {
             let input = "key value\nend:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let (index, map) = read_header_keys(&mut reader, None, &vec!["end:"]).unwrap();
+            let (index, map) = read_header_keys(&mut reader, None, &["end:"]).unwrap();
             assert_eq!(index, 15);
             assert!(!map.is_empty());
             assert_eq!(map.get("key").map(|v| v.as_str()), Some("value"));
@@ -132,7 +131,7 @@ mod tests {
             let input = "start\0\nkey value\nletters alpha beta gamma\nend2:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
             let (index, map) =
-                read_header_keys(&mut reader, Some("start\0"), &vec!["end1:", "end2:"]).unwrap();
+                read_header_keys(&mut reader, Some("start\0"), &["end1:", "end2:"]).unwrap();
             assert_eq!(index, 48);
             assert!(!map.is_empty());
             assert_eq!(map.get("key").map(|v| v.as_str()), Some("value"));
@@ -148,7 +147,7 @@ mod tests {
             let input = "start\0\n  \nkey value\n\nletters alpha beta gamma\n\nend2:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
             let (index, map) =
-                read_header_keys(&mut reader, Some("start\0"), &vec!["end1:", "end2:"]).unwrap();
+                read_header_keys(&mut reader, Some("start\0"), &["end1:", "end2:"]).unwrap();
             assert_eq!(index, 53);
             assert!(!map.is_empty());
             assert_eq!(map.get("key").map(|v| v.as_str()), Some("value"));
@@ -163,7 +162,7 @@ mod tests {
         fn error_no_start_marker() {
             let input = "not_start\nkey value\nend:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let err = read_header_keys(&mut reader, Some("start"), &vec!["end:"]);
+            let err = read_header_keys(&mut reader, Some("start"), &["end:"]);
             assert!(err.is_err());
             assert_eq!(err.unwrap_err(), HeaderErr::NoStartMarker);
         }
@@ -172,7 +171,7 @@ mod tests {
         fn error_no_end_marker() {
             let input = "start\nkey value\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let err = read_header_keys(&mut reader, Some("start"), &vec!["end:"]);
+            let err = read_header_keys(&mut reader, Some("start"), &["end:"]);
             assert!(err.is_err());
             assert_eq!(err.unwrap_err(), HeaderErr::NoEndMarker);
         }
@@ -181,7 +180,7 @@ mod tests {
         fn error_header_syntax() {
             let input = "start\nkey_without_value\nend:\nignore this";
             let mut reader = BufReader::new(input.as_bytes());
-            let err = read_header_keys(&mut reader, Some("start"), &vec!["end:"]);
+            let err = read_header_keys(&mut reader, Some("start"), &["end:"]);
             assert!(err.is_err());
             assert_eq!(
                 err.unwrap_err(),
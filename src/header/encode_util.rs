@@ -2,7 +2,7 @@ use ::std::error::Error;
 use ::std::io::Write;
 
 use crate::util::errors::add_err;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
 
 /// Use a space for separating key and value.
 const KEY_VALUE_DELIMITER_CHARS: [u8; 1] = [b' '];
@@ -12,7 +12,11 @@ const END_LINE_CHARS: [u8; 1] = [b'\n'];
 
 fn wrap_err(res: Result<usize, impl Error>, verbose: bool) -> FedResult<()> {
     if let Err(err) = res {
-        Err(add_err("failed to write encryption header", verbose, err))
+        Err(FedError::Io(add_err(
+            "failed to write encryption header",
+            verbose,
+            err,
+        )))
     } else {
         Ok(())
     }
@@ -1,12 +1,16 @@
 pub use self::public_decode::parse_public_header;
+pub use self::public_decode::parse_public_trailer;
 pub use self::public_encode::write_public_header;
+pub use self::public_encode::write_public_trailer;
 pub use self::public_header_type::*;
 pub use self::strategy::get_version_strategy;
 pub use self::strategy::CompressionAlg;
+pub use self::strategy::DataLayout;
 pub use self::strategy::KeyHashAlg;
 pub use self::strategy::Strategy;
 pub use self::strategy::SymmetricEncryptionAlg;
 
+pub mod archive;
 pub mod decode_util;
 pub mod encode_util;
 pub mod private_decode;
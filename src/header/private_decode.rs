@@ -5,19 +5,18 @@ use crate::header::decode_util::read_header_keys;
 use crate::header::decode_util::HeaderErr;
 use crate::header::private_header_type::PrivateHeader;
 use crate::header::private_header_type::{
-    PRIV_HEADER_ACCESSED, PRIV_HEADER_CREATED, PRIV_HEADER_DATA, PRIV_HEADER_FILENAME,
-    PRIV_HEADER_MODIFIED, PRIV_HEADER_PADDING, PRIV_HEADER_PEPPER, PRIV_HEADER_PERMISSIONS,
-    PRIV_HEADER_SIZE,
+    PRIV_HEADER_ACCESSED, PRIV_HEADER_CREATED, PRIV_HEADER_DATA, PRIV_HEADER_DATA_PADDING,
+    PRIV_HEADER_FILENAME, PRIV_HEADER_MODIFIED, PRIV_HEADER_PADDING, PRIV_HEADER_PEPPER,
+    PRIV_HEADER_PERMISSIONS, PRIV_HEADER_SIZE,
 };
 use crate::key::Salt;
 use crate::util::base::small_str_to_u128;
 use crate::util::base::small_str_to_u64;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
 
-fn parse_filename(header_data: &mut HashMap<String, String>) -> FedResult<String> {
-    header_data
-        .remove(PRIV_HEADER_FILENAME)
-        .ok_or("could not find the original filename in the private file header".to_owned())
+/// The filename is absent if the file was encrypted with hidden metadata.
+fn parse_filename(header_data: &mut HashMap<String, String>) -> FedResult<Option<String>> {
+    Ok(header_data.remove(PRIV_HEADER_FILENAME))
 }
 
 fn parse_permissions(header_data: &mut HashMap<String, String>) -> FedResult<Option<u32>> {
@@ -43,11 +42,30 @@ fn parse_sizes(
     ))
 }
 
-fn parse_size(header_data: &mut HashMap<String, String>) -> FedResult<u64> {
+/// The data padding is absent if the size is not hidden.
+fn parse_data_padding(header_data: &mut HashMap<String, String>) -> FedResult<u64> {
+    match header_data.remove(PRIV_HEADER_DATA_PADDING) {
+        Some(pad) => small_str_to_u64(&pad).ok_or_else(|| {
+            FedError::Corrupted(
+                "could not parse the data padding length in the private file header".to_owned(),
+            )
+        }),
+        None => Ok(0),
+    }
+}
+
+/// The size is absent if the data was streamed.
+fn parse_size(header_data: &mut HashMap<String, String>) -> FedResult<Option<u64>> {
     header_data
         .remove(PRIV_HEADER_SIZE)
-        .map(|sz| small_str_to_u64(&sz).unwrap())
-        .ok_or("could not find the original file size in the private file header".to_owned())
+        .map(|sz| {
+            small_str_to_u64(&sz).ok_or_else(|| {
+                FedError::Corrupted(
+                    "could not parse the original file size in the private file header".to_owned(),
+                )
+            })
+        })
+        .transpose()
 }
 
 /// Pepper and padding are included to obfuscate metadata.
@@ -55,41 +73,44 @@ fn parse_obfuscation(header_data: &mut HashMap<String, String>) -> FedResult<(Sa
     let pepper = header_data
         .remove(PRIV_HEADER_PEPPER)
         .map(|pepr| Salt::parse_base64(&pepr, false))
-        .ok_or(
-            "could not find the pepper (private salt) in the private file header".to_owned(),
-        )??;
+        .ok_or_else(|| {
+            FedError::Corrupted(
+                "could not find the pepper (private salt) in the private file header".to_owned(),
+            )
+        })??;
     let padding_len = header_data
         .remove(PRIV_HEADER_PADDING)
         .map(|pad| pad.len() as u16)
-        .ok_or("could not find the padding in the private file header".to_owned())?;
+        .ok_or_else(|| {
+            FedError::Corrupted("could not find the padding in the private file header".to_owned())
+        })?;
     Ok((pepper, padding_len))
 }
 
 //TODO @mark: include filename in error at caller?
 /// Parses the data in the private header and returns it, along with the index of the first byte after the header.
 pub fn parse_private_header<R: BufRead>(reader: &mut R) -> FedResult<(usize, PrivateHeader)> {
-    eprintln!("START parse_private_header"); //TODO @mark: TEMPORARY! REMOVE THIS!
     let (index, mut header_data) = match read_header_keys(reader, None, &[PRIV_HEADER_DATA]) {
         Ok(map) => map,
-        Err(err) => return Err(match err {
+        Err(err) => return Err(FedError::Corrupted(match err {
             HeaderErr::NoStartMarker => unreachable!(),
-            HeaderErr::NoEndMarker => format!("could not find the end of the private file header; has the file been corrupted?"),
-            HeaderErr::HeaderSyntax(_) => format!("part of the private file header could not be parsed because it did not have the expected format"),
-            HeaderErr::ReadError => format!("the private file header could not be read; perhaps the file was not accessible, or the file header has been corrupted"),
-        }),
+            HeaderErr::NoEndMarker => "could not find the end of the private file header; has the file been corrupted?".to_string(),
+            HeaderErr::HeaderSyntax(_) => "part of the private file header could not be parsed because it did not have the expected format".to_string(),
+            HeaderErr::ReadError => "the private file header could not be read; perhaps the file was not accessible, or the file header has been corrupted".to_string(),
+        })),
     };
-    eprintln!("END parse_private_header"); //TODO @mark: TEMPORARY! REMOVE THIS!
 
     let filename = parse_filename(&mut header_data)?;
     let permissions = parse_permissions(&mut header_data)?;
     let (created, changed, accessed) = parse_sizes(&mut header_data)?;
     let size = parse_size(&mut header_data)?;
+    let data_padding_len = parse_data_padding(&mut header_data)?;
     let (pepper, padding_len) = parse_obfuscation(&mut header_data)?;
 
     if !header_data.is_empty() {
         let key_names = header_data
-            .iter()
-            .map(|(key, _)| key.as_str())
+            .keys()
+            .map(|key| key.as_str())
             .collect::<Vec<_>>()
             .join("', '");
         eprintln!("encountered unknown private header keys '{}'; this may happen if the file is encrypted using a newer version of file_endec, or if the file is corrupt; ignoring this problem", key_names);
@@ -106,6 +127,7 @@ pub fn parse_private_header<R: BufRead>(reader: &mut R) -> FedResult<(usize, Pri
             size,
             pepper,
             padding_len,
+            data_padding_len,
         ),
     ))
 }
@@ -132,13 +154,14 @@ mod tests {
         let mut txt = "name my_filename.ext\nsz C4_A\npepr EiGaAAAAAAASIZoAAAAAABIhmgAAAAAAEiGaAAAAAAASIZoAAAAAABIhmgAAAAAAEiGaAAAAAAASIZoAAAAAAA\npad \nenc:\n".as_bytes();
         let (length, actual) = parse_private_header(&mut txt).unwrap();
         let expected = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             None,
             None,
             None,
             None,
-            1024_000,
-            Salt::fixed_for_test(010_101_010),
+            Some(1_024_000),
+            Salt::fixed_for_test(10_101_010),
+            0,
             0,
         );
         assert_eq!(length, 131);
@@ -150,14 +173,15 @@ mod tests {
         let mut txt = "name my_filename.ext\nperm 754\ncrt Ax9lQnI\ncng NWzxOMo\nacs NiToP-_\nsz C4_A\npepr zeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAA\npad I(R#:Y:r$F\nenc:\n".as_bytes();
         let (length, actual) = parse_private_header(&mut txt).unwrap();
         let expected = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             Some(0o754),
             Some(123_456_789_000),
             Some(987_654_321_000),
             Some(999_999_999_999),
-            1024_000,
+            Some(1_024_000),
             Salt::fixed_for_test(246_801_357),
             10,
+            0,
         );
         assert_eq!(length, 186);
         assert_eq!(actual, expected);
@@ -168,16 +192,36 @@ mod tests {
         let mut txt = "name my_filename.ext\ncrt Ax9lQnI\ncng NWzxOMo\nsz C4_A\npepr zeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAA\npad 'Z\";&(-GRt~rTrp>_nb[2Lc*13!Bn)\nenc:\n".as_bytes();
         let (length, actual) = parse_private_header(&mut txt).unwrap();
         let expected = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             None,
             Some(123_456_789_000),
             Some(987_654_321_000),
             None,
-            1024_000,
+            Some(1_024_000),
             Salt::fixed_for_test(246_801_357),
             30,
+            0,
         );
         assert_eq!(length, 185);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn read_hidden_meta() {
+        let mut txt = "sz C4_A\npepr zeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAA\npad I(R#:Y:r$F\nenc:\n".as_bytes();
+        let (length, actual) = parse_private_header(&mut txt).unwrap();
+        let expected = PrivateHeader::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(1_024_000),
+            Salt::fixed_for_test(246_801_357),
+            10,
+            0,
+        );
+        assert_eq!(length, 120);
+        assert_eq!(actual, expected);
+    }
 }
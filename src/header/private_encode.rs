@@ -2,92 +2,100 @@ use ::std::borrow::BorrowMut;
 use ::std::cell::RefCell;
 use ::std::io::Write;
 
+use crate::files::padding::printable_padding;
 use crate::header::encode_util::write_line;
 use crate::header::private_header_type::{
     PrivateHeader, PRIV_HEADER_ACCESSED, PRIV_HEADER_CREATED, PRIV_HEADER_DATA,
-    PRIV_HEADER_FILENAME, PRIV_HEADER_MODIFIED, PRIV_HEADER_PADDING, PRIV_HEADER_PEPPER,
-    PRIV_HEADER_PERMISSIONS, PRIV_HEADER_SIZE,
+    PRIV_HEADER_DATA_PADDING, PRIV_HEADER_FILENAME, PRIV_HEADER_MODIFIED, PRIV_HEADER_PADDING,
+    PRIV_HEADER_PEPPER, PRIV_HEADER_PERMISSIONS, PRIV_HEADER_SIZE,
 };
-use crate::key::random::generate_secure_pseudo_random_printable;
+use crate::key::Salt;
 use crate::util::base::u128_to_small_str;
 use crate::util::base::u64_to_small_str;
 use crate::util::base::u8s_to_base64str;
 use crate::util::FedResult;
-use crate::{EncOption, EncOptionSet};
 
 thread_local! {
     static BUFFER: RefCell<String> = RefCell::new(String::with_capacity(256));
 }
 
-fn write_padding(length: u16, write: impl FnOnce(&str) -> FedResult<()>) -> FedResult<()> {
+fn write_padding(
+    length: u16,
+    pepper: &Salt,
+    write: impl FnOnce(&str) -> FedResult<()>,
+) -> FedResult<()> {
     // Use a per-thread shared buffer to prevent allocations.
     BUFFER.with(|buf| {
         let mut padding = buf.borrow_mut();
-        generate_secure_pseudo_random_printable(padding.borrow_mut(), length as usize);
+        printable_padding(padding.borrow_mut(), pepper, length as usize);
         write(&padding)
     })
 }
 
+/// Write the private header. Metadata that is absent, e.g. because it is hidden, is left out.
 pub fn write_private_header(
     writer: &mut impl Write,
     header: &PrivateHeader,
-    options: &EncOptionSet,
     verbose: bool,
 ) -> FedResult<()> {
-    write_line(
-        writer,
-        PRIV_HEADER_FILENAME,
-        Some(header.filename()),
-        verbose,
-    )?;
-    if options.has(EncOption::HideMeta) {
-        if let Some(perms) = header.permissions() {
-            write_line(
-                writer,
-                PRIV_HEADER_PERMISSIONS,
-                Some(&format!("{:o}", perms)),
-                verbose,
-            )?;
-        }
-        if let Some(time_ns) = header.created_ns() {
-            write_line(
-                writer,
-                PRIV_HEADER_CREATED,
-                Some(&u128_to_small_str(time_ns)),
-                verbose,
-            )?;
-        }
-        if let Some(time_ns) = header.changed_ns() {
-            write_line(
-                writer,
-                PRIV_HEADER_MODIFIED,
-                Some(&u128_to_small_str(time_ns)),
-                verbose,
-            )?;
-        }
-        if let Some(time_ns) = header.accessed_ns() {
-            write_line(
-                writer,
-                PRIV_HEADER_ACCESSED,
-                Some(&u128_to_small_str(time_ns)),
-                verbose,
-            )?;
-        }
+    if let Some(filename) = header.filename() {
+        write_line(writer, PRIV_HEADER_FILENAME, Some(filename), verbose)?;
+    }
+    if let Some(perms) = header.permissions() {
+        write_line(
+            writer,
+            PRIV_HEADER_PERMISSIONS,
+            Some(&format!("{:o}", perms)),
+            verbose,
+        )?;
+    }
+    if let Some(time_ns) = header.created_ns() {
+        write_line(
+            writer,
+            PRIV_HEADER_CREATED,
+            Some(&u128_to_small_str(time_ns)),
+            verbose,
+        )?;
+    }
+    if let Some(time_ns) = header.changed_ns() {
+        write_line(
+            writer,
+            PRIV_HEADER_MODIFIED,
+            Some(&u128_to_small_str(time_ns)),
+            verbose,
+        )?;
+    }
+    if let Some(time_ns) = header.accessed_ns() {
+        write_line(
+            writer,
+            PRIV_HEADER_ACCESSED,
+            Some(&u128_to_small_str(time_ns)),
+            verbose,
+        )?;
+    }
+    if let Some(size) = header.size() {
+        write_line(
+            writer,
+            PRIV_HEADER_SIZE,
+            Some(&u64_to_small_str(size)),
+            verbose,
+        )?;
+    }
+    if header.data_padding_len() > 0 {
+        write_line(
+            writer,
+            PRIV_HEADER_DATA_PADDING,
+            Some(&u64_to_small_str(header.data_padding_len())),
+            verbose,
+        )?;
     }
-    //if options.has(EncOption::PadSize) {  //TODO @mark: keep it required? even if not used?
-    write_line(
-        writer,
-        PRIV_HEADER_SIZE,
-        Some(&u64_to_small_str(header.size())),
-        verbose,
-    )?;
     write_line(
         writer,
         PRIV_HEADER_PEPPER,
         Some(&u8s_to_base64str(&header.pepper().salt)),
         verbose,
     )?;
-    write_padding(header.padding_len(), |pad| {
+    write_padding(header.padding_len(), header.pepper(), |pad| {
         write_line(writer, PRIV_HEADER_PADDING, Some(pad), verbose)
     })?;
     write_line(writer, PRIV_HEADER_DATA, None, verbose)?;
@@ -98,6 +106,7 @@ pub fn write_private_header(
 mod tests {
     use ::std::str::from_utf8;
 
+    use crate::header::private_decode::parse_private_header;
     use crate::key::Salt;
 
     use super::*;
@@ -105,36 +114,38 @@ mod tests {
     #[test]
     fn write_vanilla() {
         let header = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             None,
             None,
             None,
             None,
-            1024_000,
-            Salt::fixed_for_test(010_101_010),
+            Some(1_024_000),
+            Salt::fixed_for_test(10_101_010),
+            0,
             0,
         );
         let mut buf: Vec<u8> = Vec::new();
-        write_private_header(&mut buf, &header, &EncOptionSet::empty(), true).unwrap();
+        write_private_header(&mut buf, &header, true).unwrap();
         let expected =
             "name my_filename.ext\nsz C4_A\npepr EiGaAAAAAAASIZoAAAAAABIhmgAAAAAAEiGaAAAAAAASIZoAAAAAABIhmgAAAAAAEiGaAAAAAAASIZoAAAAAAA\npad \nenc:\n";
         assert_eq!(expected, from_utf8(&buf).unwrap());
     }
 
     #[test]
-    fn write_hide_meta_size() {
+    fn write_all_meta() {
         let header = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             Some(0o754),
             Some(123_456_789_000),
             Some(987_654_321_000),
             Some(999_999_999_999),
-            1024_000,
+            Some(1_024_000),
             Salt::fixed_for_test(246_801_357),
             10,
+            0,
         );
         let mut buf: Vec<u8> = Vec::new();
-        write_private_header(&mut buf, &header, &EncOptionSet::all_for_test(), true).unwrap();
+        write_private_header(&mut buf, &header, true).unwrap();
         let txt = from_utf8(&buf).unwrap();
         let expected_prefix =
             "name my_filename.ext\nperm 754\ncrt Ax9lQnI\ncng NWzxOMo\nacs NiToP-_\nsz C4_A\npepr zeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAA\npad ";
@@ -148,19 +159,20 @@ mod tests {
     }
 
     #[test]
-    fn write_hide_unsupported() {
+    fn write_partial_meta() {
         let header = PrivateHeader::new(
-            "my_filename.ext".to_owned(),
+            Some("my_filename.ext".to_owned()),
             None,
             Some(123_456_789_000),
             Some(987_654_321_000),
             None,
-            1024_000,
+            Some(1_024_000),
             Salt::fixed_for_test(246_801_357),
             30,
+            0,
         );
         let mut buf: Vec<u8> = Vec::new();
-        write_private_header(&mut buf, &header, &EncOptionSet::all_for_test(), true).unwrap();
+        write_private_header(&mut buf, &header, true).unwrap();
         let txt = from_utf8(&buf).unwrap();
         let expected_prefix =
             "name my_filename.ext\ncrt Ax9lQnI\ncng NWzxOMo\nsz C4_A\npepr zeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAM3jtQ4AAAAAzeO1DgAAAADN47UOAAAAAA\npad ";
@@ -172,4 +184,26 @@ mod tests {
             expected_prefix.len() + expected_postfix.len() + 30
         );
     }
+
+    #[test]
+    fn write_hidden_meta() {
+        let header = PrivateHeader::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(1_024_000),
+            Salt::fixed_for_test(246_801_357),
+            10,
+            5000,
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        write_private_header(&mut buf, &header, true).unwrap();
+        let txt = from_utf8(&buf).unwrap();
+        assert!(txt.starts_with("sz C4_A\ndpad "));
+        assert!(txt.ends_with("\nenc:\n"));
+        let (_, parsed) = parse_private_header(&mut buf.as_slice()).unwrap();
+        assert_eq!(header, parsed);
+    }
 }
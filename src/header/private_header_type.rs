@@ -2,38 +2,44 @@ use crate::key::Salt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PrivateHeader {
-    // The original filename without directory path, with extension.
-    filename: String,
+    // The original filename without directory path, with extension. Absent if metadata is hidden.
+    filename: Option<String>,
     // Linux-like permissions in octal, e.g. "754" for "rwxrw.r..".
     permissions: Option<u32>,
     // Created and modified timestamps in nanoseconds.
     created_ns: Option<u128>,
     changed_ns: Option<u128>,
     accessed_ns: Option<u128>,
-    // Original filesize in bytes.
-    size: u64,
+    // Original filesize in bytes. Absent for streams, because it is not known when the header is written.
+    size: Option<u64>,
     // Secret seed for values like checksum. This prevents an attacker from verifying whether
     // an encrypted file contains a specific file that the attacker has access to.
     //TODO @mark: make sure pepper influences the checksum
     pepper: Salt,
-    // Padding bytes length to obfuscate header size.
-    //TODO @mark: padding data must not be very compressible, but should be deterministic
+    // Padding bytes length to obfuscate header size. The content is derived from the pepper.
     padding_len: u16,
+    // Padding bytes before the compressed data, to obfuscate the file size.
+    data_padding_len: u64,
 }
 
 impl PrivateHeader {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        filename: String,
+        filename: Option<String>,
         permissions: Option<u32>,
         created_ns: Option<u128>,
         changed_ns: Option<u128>,
         accessed_ns: Option<u128>,
-        size: u64,
+        size: Option<u64>,
         pepper: Salt,
         padding_len: u16,
+        data_padding_len: u64,
     ) -> Self {
         debug_assert!(padding_len <= 1024); // implementation detail in padding data generation
-        assert!(!filename.contains('\n'));
+        assert!(!filename
+            .as_ref()
+            .map(|name| name.contains('\n'))
+            .unwrap_or(false));
         PrivateHeader {
             filename,
             permissions,
@@ -43,11 +49,12 @@ impl PrivateHeader {
             size,
             pepper,
             padding_len,
+            data_padding_len,
         }
     }
 
-    pub fn filename(&self) -> &str {
-        &self.filename
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
     }
 
     pub fn permissions(&self) -> Option<u32> {
@@ -66,7 +73,7 @@ impl PrivateHeader {
         self.accessed_ns
     }
 
-    pub fn size(&self) -> u64 {
+    pub fn size(&self) -> Option<u64> {
         self.size
     }
 
@@ -77,6 +84,10 @@ impl PrivateHeader {
     pub fn padding_len(&self) -> u16 {
         self.padding_len
     }
+
+    pub fn data_padding_len(&self) -> u64 {
+        self.data_padding_len
+    }
 }
 
 pub const PRIV_HEADER_FILENAME: &str = "name";
@@ -87,4 +98,5 @@ pub const PRIV_HEADER_ACCESSED: &str = "acs";
 pub const PRIV_HEADER_SIZE: &str = "sz";
 pub const PRIV_HEADER_PEPPER: &str = "pepr";
 pub const PRIV_HEADER_PADDING: &str = "pad";
+pub const PRIV_HEADER_DATA_PADDING: &str = "dpad";
 pub const PRIV_HEADER_DATA: &str = "enc:";
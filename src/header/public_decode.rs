@@ -1,5 +1,6 @@
 use ::std::collections::HashMap;
 use ::std::io::BufRead;
+use ::std::str::from_utf8;
 use ::std::str::FromStr;
 
 use ::semver::Version;
@@ -8,32 +9,43 @@ use crate::files::Checksum;
 use crate::header::decode_util::{read_header_keys, HeaderErr};
 use crate::header::PublicHeader;
 use crate::header::PUB_HEADER_CHECKSUM_MARKER;
+use crate::header::PUB_HEADER_KDF_COST_MARKER;
+use crate::header::PUB_HEADER_KEY_SLOTS_MARKER;
 use crate::header::PUB_HEADER_MARKER;
 use crate::header::PUB_HEADER_META_DATA_MARKER;
 use crate::header::PUB_HEADER_OPTION_MARKER;
 use crate::header::PUB_HEADER_PRIVATE_HEADER_META_MARKER;
 use crate::header::PUB_HEADER_PURE_DATA_MARKER;
+use crate::header::PUB_HEADER_RECIPIENTS_MARKER;
 use crate::header::PUB_HEADER_SALT_MARKER;
 use crate::header::PUB_HEADER_VERSION_MARKER;
+use crate::header::PUB_TRAILER_LEN;
+use crate::key::cost::KdfCost;
+use crate::key::recipient::WrappedKey;
 use crate::key::salt::Salt;
+use crate::key::slot::KeySlot;
 use crate::util::base::small_str_to_u64;
 use crate::util::errors::add_err;
 use crate::util::option::{EncOption, EncOptionSet};
+use crate::util::version::version_has_kdf_cost;
 use crate::util::version::version_has_options_meta;
-use crate::util::FedResult;
+use crate::util::version::version_has_stream_layout;
+use crate::util::{FedError, FedResult};
 
 fn parse_version(header_data: &mut HashMap<String, String>, verbose: bool) -> FedResult<Version> {
     //TODO @mark: do these ok_or cause too many allocations? use ok_or_else?
     let version_str = header_data
         .remove(PUB_HEADER_VERSION_MARKER)
-        .ok_or("could not find the version in the file header".to_owned())?;
+        .ok_or_else(|| {
+            FedError::Corrupted("could not find the version in the file header".to_owned())
+        })?;
     match Version::parse(&version_str) {
         Ok(version) => Ok(version),
-        Err(err) => Err(add_err(
+        Err(err) => Err(FedError::Corrupted(add_err(
             format!("could not determine the version of fileenc that encrypted this file; got {} which is invalid", version_str),
             verbose,
             err,
-        )),
+        ))),
     }
 }
 
@@ -49,36 +61,69 @@ fn parse_options(
     for option_str in options_str.split_whitespace() {
         match EncOption::from_str(option_str) {
             Ok(option) => option_vec.push(option),
-            Err(err) => return Err(add_err(
+            Err(err) => return Err(FedError::Corrupted(add_err(
                 format!("could not determine the options of fileenc that encrypted this file (got {} which is unknown); maybe it was encrypted with a newer version?", option_str),
                 verbose,
                 err,
-            )),
+            ))),
         }
     }
     let option_count = option_vec.len();
     let options: EncOptionSet = option_vec.into();
     if options.len() != option_count {
-        return Err(add_err(
-            format!("there were duplicate encryption options in the file header; it is possible the header has been meddled with"),
+        return Err(FedError::Corrupted(add_err(
+            "there were duplicate encryption options in the file header; it is possible the header has been meddled with".to_string(),
             verbose,
             format!("found {}", options_str),
-        ));
+        )));
     }
     Ok(options)
 }
 
 fn parse_salt(header_data: &mut HashMap<String, String>, verbose: bool) -> FedResult<Salt> {
-    let salt_str = header_data
-        .remove(PUB_HEADER_SALT_MARKER)
-        .ok_or("could not find the salt in the file header".to_owned())?;
+    let salt_str = header_data.remove(PUB_HEADER_SALT_MARKER).ok_or_else(|| {
+        FedError::Corrupted("could not find the salt in the file header".to_owned())
+    })?;
     Salt::parse_base64(&salt_str, verbose)
 }
 
+fn parse_recipients(header_data: &mut HashMap<String, String>) -> FedResult<Vec<WrappedKey>> {
+    match header_data.remove(PUB_HEADER_RECIPIENTS_MARKER) {
+        Some(recipients_str) => recipients_str
+            .split_whitespace()
+            .map(WrappedKey::parse)
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+fn parse_key_slots(header_data: &mut HashMap<String, String>) -> FedResult<Vec<KeySlot>> {
+    match header_data.remove(PUB_HEADER_KEY_SLOTS_MARKER) {
+        Some(key_slots_str) => key_slots_str
+            .split_whitespace()
+            .map(KeySlot::parse)
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+fn parse_kdf_cost(header_data: &mut HashMap<String, String>) -> FedResult<KdfCost> {
+    let kdf_cost_str = header_data
+        .remove(PUB_HEADER_KDF_COST_MARKER)
+        .ok_or_else(|| {
+            FedError::Corrupted(
+                "could not find the key stretching cost in the file header".to_owned(),
+            )
+        })?;
+    KdfCost::parse(&kdf_cost_str)
+}
+
 fn parse_checksum(header_data: &mut HashMap<String, String>) -> FedResult<Checksum> {
     let checksum_str = header_data
         .remove(PUB_HEADER_CHECKSUM_MARKER)
-        .ok_or("could not find the checksum in the file header".to_owned())?;
+        .ok_or_else(|| {
+            FedError::Corrupted("could not find the checksum in the file header".to_owned())
+        })?;
     Checksum::parse(&checksum_str)
 }
 
@@ -87,17 +132,27 @@ fn parse_private_header_meta(
 ) -> FedResult<(u64, Checksum)> {
     let priv_meta = header_data
         .remove(PUB_HEADER_PRIVATE_HEADER_META_MARKER)
-        .ok_or("could not find the private header metadata in the public file header".to_owned())?;
+        .ok_or_else(|| {
+            FedError::Corrupted(
+                "could not find the private header metadata in the public file header".to_owned(),
+            )
+        })?;
     let mut parts = priv_meta.splitn(2, ' ');
 
-    let length = small_str_to_u64(parts.next().unwrap())
-        .ok_or("metadata about private header contained an incorrectly formatted length")?;
+    let length = small_str_to_u64(parts.next().unwrap()).ok_or_else(|| {
+        FedError::Corrupted(
+            "metadata about private header contained an incorrectly formatted length".to_owned(),
+        )
+    })?;
 
-    let checksum_str = parts
-        .next()
-        .ok_or("metadata about private header has a missing separator")?;
-    let checksum = Checksum::parse(checksum_str)
-        .map_err(|_| "metadata about private header contained an incorrectly formatted checksum")?;
+    let checksum_str = parts.next().ok_or_else(|| {
+        FedError::Corrupted("metadata about private header has a missing separator".to_owned())
+    })?;
+    let checksum = Checksum::parse(checksum_str).map_err(|_| {
+        FedError::Corrupted(
+            "metadata about private header contained an incorrectly formatted checksum".to_owned(),
+        )
+    })?;
 
     Ok((length, checksum))
 }
@@ -114,28 +169,39 @@ pub fn parse_public_header<R: BufRead>(
     ) {
         Ok(map) => map,
         Err(err) => {
-            return Err(if verbose {
+            return Err(FedError::Corrupted(if verbose {
                 match err {
                 HeaderErr::NoStartMarker => format!("did not recognize encryption header (expected '{}'); was this file really encrypted with fileenc?", PUB_HEADER_MARKER),
                 HeaderErr::NoEndMarker => format!("could not find the end of the file header ('{}' or '{}'); has the file header been corrupted?", PUB_HEADER_PURE_DATA_MARKER, PUB_HEADER_META_DATA_MARKER),
                 HeaderErr::HeaderSyntax(line) => format!("part of the file header could not be parsed because it did not have the expected format (found '{}')", &line),
-                HeaderErr::ReadError => format!("the file header could not be read; perhaps the file was not accessible, or the file header has been corrupted"),
+                HeaderErr::ReadError => "the file header could not be read; perhaps the file was not accessible, or the file header has been corrupted".to_string(),
             }
             } else {
                 match err {
-                HeaderErr::NoStartMarker => format!("did not recognize encryption header; was this file really encrypted with fileenc?"),
-                HeaderErr::NoEndMarker => format!("could not find the end of the file header; has the file header been corrupted?"),
-                HeaderErr::HeaderSyntax(_) => format!("part of the file header could not be parsed because it did not have the expected format"),
-                HeaderErr::ReadError => format!("the file header could not be read; perhaps the file was not accessible, or the file header has been corrupted"),
+                HeaderErr::NoStartMarker => "did not recognize encryption header; was this file really encrypted with fileenc?".to_string(),
+                HeaderErr::NoEndMarker => "could not find the end of the file header; has the file header been corrupted?".to_string(),
+                HeaderErr::HeaderSyntax(_) => "part of the file header could not be parsed because it did not have the expected format".to_string(),
+                HeaderErr::ReadError => "the file header could not be read; perhaps the file was not accessible, or the file header has been corrupted".to_string(),
             }
-            })
+            }))
         }
     };
 
     let version = parse_version(&mut header_data, verbose)?;
     let options = parse_options(&mut header_data, verbose)?;
     let salt = parse_salt(&mut header_data, verbose)?;
-    let checksum = parse_checksum(&mut header_data)?;
+    let kdf_cost = if version_has_kdf_cost(&version) {
+        Some(parse_kdf_cost(&mut header_data)?)
+    } else {
+        None
+    };
+    let recipients = parse_recipients(&mut header_data)?;
+    let key_slots = parse_key_slots(&mut header_data)?;
+    let checksum = if version_has_stream_layout(&version) {
+        None
+    } else {
+        Some(parse_checksum(&mut header_data)?)
+    };
     let private_header = if version_has_options_meta(&version) {
         Some(parse_private_header_meta(&mut header_data)?)
     } else {
@@ -144,8 +210,8 @@ pub fn parse_public_header<R: BufRead>(
 
     if !header_data.is_empty() {
         let key_names = header_data
-            .iter()
-            .map(|(key, _)| key.as_str())
+            .keys()
+            .map(|key| key.as_str())
             .collect::<Vec<_>>()
             .join("', '");
         eprintln!("encountered unknown header keys '{}'; this may happen if the file is encrypted using a newer version of file_endec, or if the file is corrupt; ignoring this problem", key_names);
@@ -153,23 +219,46 @@ pub fn parse_public_header<R: BufRead>(
 
     Ok((
         index,
-        PublicHeader::legacy(version, salt, checksum, options, private_header),
+        PublicHeader::legacy(version, salt, checksum, options, private_header)
+            .with_recipients(recipients)
+            .with_key_slots(key_slots)
+            .with_kdf_cost(kdf_cost),
     ))
 }
 
+/// Parse the trailer, which comes after the data, for versions that have the stream layout.
+pub fn parse_public_trailer(trailer: &[u8]) -> FedResult<Checksum> {
+    let err_msg = "could not find the checksum at the end of the file; has the file been truncated or corrupted?";
+    if trailer.len() != PUB_TRAILER_LEN || trailer.last() != Some(&b'\n') {
+        return Err(FedError::Corrupted(err_msg.to_owned()));
+    }
+    let line = from_utf8(&trailer[..trailer.len() - 1])
+        .map_err(|_| FedError::Corrupted(err_msg.to_owned()))?;
+    match line.split_once(' ') {
+        Some((PUB_HEADER_CHECKSUM_MARKER, checksum_str)) => Checksum::parse(checksum_str),
+        _ => Err(FedError::Corrupted(err_msg.to_owned())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ::std::io::BufReader;
     use ::std::io::Read;
+    use ::std::str::from_utf8;
 
     use ::semver::Version;
 
     use crate::files::Checksum;
+    use crate::header::write_public_header;
     use crate::header::PublicHeader;
+    use crate::key::cost::KdfCost;
+    use crate::key::recipient::{wrap_key, Identity};
     use crate::key::salt::Salt;
-    use crate::util::option::EncOptionSet;
+    use crate::key::Key;
+    use crate::util::option::{EncOption, EncOptionSet};
 
     use super::parse_public_header;
+    use super::parse_public_trailer;
 
     #[test]
     fn stop_read_after_header() {
@@ -194,7 +283,7 @@ mod tests {
         let expected = PublicHeader::legacy(
             Version::parse("1.0.0").unwrap(),
             Salt::fixed_for_test(1),
-            Checksum::fixed_for_test(vec![2]),
+            Some(Checksum::fixed_for_test(vec![2])),
             EncOptionSet::empty(), // always empty for v1.0
             None,
         );
@@ -208,12 +297,12 @@ mod tests {
     fn read_v1_1_0_one() {
         let input =
             "github.com/mverleg/file_endec\0\nv 1.1.0\nsalt AQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAA\ncheck xx_sha256 Ag\nprv U xx_sha256 ChQe\nmeta1+data:\n";
-        let expected = PublicHeader::new(
+        let expected = PublicHeader::legacy(
             Version::parse("1.1.0").unwrap(),
             Salt::fixed_for_test(1),
-            Checksum::fixed_for_test(vec![2]),
+            Some(Checksum::fixed_for_test(vec![2])),
             EncOptionSet::empty(), // always empty for v1.0
-            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+            Some((20, Checksum::fixed_for_test(vec![10, 20, 30]))),
         );
         let mut buf = input.as_bytes();
         let (length, header) = parse_public_header(&mut buf, false).unwrap();
@@ -224,16 +313,91 @@ mod tests {
     #[test]
     fn read_v1_1_0_two() {
         let input = "github.com/mverleg/file_endec\0\nv 1.1.0\nopts fast hide-meta pad-size\nsalt FV_QrEubtgEVX9CsS5u2ARVf0KxLm7YBFV_QrEubtgEVX9CsS5u2ARVf0KxLm7YBFV_QrEubtgEVX9CsS5u2AQ\ncheck xx_sha256 AAUABQAFAAUABQAF\nprv U xx_sha256 CmQ\nmeta1+data:\n";
-        let expected = PublicHeader::new(
+        let expected = PublicHeader::legacy(
             Version::parse("1.1.0").unwrap(),
             Salt::fixed_for_test(123_456_789_123_456_789),
-            Checksum::fixed_for_test(vec![0, 5, 0, 5, 0, 5, 0, 5, 0, 5, 0, 5]),
+            Some(Checksum::fixed_for_test(vec![
+                0, 5, 0, 5, 0, 5, 0, 5, 0, 5, 0, 5,
+            ])),
             EncOptionSet::all_for_test(),
-            (20, Checksum::fixed_for_test(vec![10, 100])),
+            Some((20, Checksum::fixed_for_test(vec![10, 100]))),
         );
         let mut buf = input.as_bytes();
         let (length, header) = parse_public_header(&mut buf, true).unwrap();
         assert_eq!(length, 225);
         assert_eq!(expected, header);
     }
+
+    #[test]
+    fn read_v1_2_0_stream() {
+        let input =
+            "github.com/mverleg/file_endec\0\nv 1.2.0\nsalt AQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAA\nprv U xx_sha256 ChQe\nmeta1+data:\n";
+        let expected = PublicHeader::new(
+            Version::parse("1.2.0").unwrap(),
+            Salt::fixed_for_test(1),
+            EncOptionSet::empty(),
+            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+        );
+        let mut buf = input.as_bytes();
+        let (length, header) = parse_public_header(&mut buf, false).unwrap();
+        assert_eq!(length, input.len());
+        assert_eq!(expected, header);
+    }
+
+    #[test]
+    fn read_recipients() {
+        let key = Key::new("file key");
+        let recipients = vec![
+            wrap_key(&key, &Identity::generate().recipient()),
+            wrap_key(&key, &Identity::generate().recipient()),
+        ];
+        let header = PublicHeader::new(
+            Version::parse("1.2.0").unwrap(),
+            Salt::fixed_for_test(1),
+            vec![EncOption::Recipients].into(),
+            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+        )
+        .with_recipients(recipients);
+        let mut buf = vec![];
+        write_public_header(&mut buf, &header, false).unwrap();
+        let (_, parsed) = parse_public_header(&mut buf.as_slice(), false).unwrap();
+        assert_eq!(header, parsed);
+    }
+
+    #[test]
+    fn read_kdf_cost() {
+        let header = PublicHeader::new(
+            Version::parse("1.5.0").unwrap(),
+            Salt::fixed_for_test(1),
+            vec![EncOption::Fast].into(),
+            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+        )
+        .with_kdf_cost(Some(KdfCost::moderate()));
+        let mut buf = vec![];
+        write_public_header(&mut buf, &header, false).unwrap();
+        assert!(from_utf8(&buf)
+            .unwrap()
+            .contains("\nkdf rounds=2 bcrypt=12 argon2=4:262144 sha=300000\n"));
+        let (_, parsed) = parse_public_header(&mut buf.as_slice(), false).unwrap();
+        assert_eq!(header, parsed);
+        let without_cost = header.with_kdf_cost(None);
+        let mut buf = vec![];
+        write_public_header(&mut buf, &without_cost, false).unwrap();
+        let err = parse_public_header(&mut buf.as_slice(), false).unwrap_err();
+        assert_eq!("corrupted", err.code());
+    }
+
+    #[test]
+    fn read_trailer() {
+        let input = "check xx_sha256 AQIDBAUGBwgJCgsMDQ4PEA\n";
+        let expected =
+            Checksum::fixed_for_test(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(expected, parse_public_trailer(input.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn read_trailer_truncated() {
+        let input = "check xx_sha256 AQIDBAUGBwgJCgsMDQ4P\n";
+        assert!(parse_public_trailer(input.as_bytes()).is_err());
+    }
 }
@@ -7,11 +7,17 @@ use crate::header::encode_util::write_line;
 use crate::header::public_header_type::PUB_HEADER_META_DATA_MARKER;
 use crate::header::public_header_type::PUB_HEADER_OPTION_MARKER;
 use crate::header::PublicHeader;
+use crate::header::PUB_HEADER_KDF_COST_MARKER;
+use crate::header::PUB_HEADER_KEY_SLOTS_MARKER;
 use crate::header::PUB_HEADER_MARKER;
+use crate::header::PUB_HEADER_RECIPIENTS_MARKER;
 use crate::header::PUB_HEADER_SALT_MARKER;
 use crate::header::PUB_HEADER_VERSION_MARKER;
 use crate::header::{PUB_HEADER_CHECKSUM_MARKER, PUB_HEADER_PRIVATE_HEADER_META_MARKER};
+use crate::key::cost::KdfCost;
+use crate::key::recipient::WrappedKey;
 use crate::key::salt::Salt;
+use crate::key::slot::KeySlot;
 use crate::util::base::u64_to_small_str;
 use crate::util::version::version_has_options_meta;
 use crate::util::FedResult;
@@ -32,7 +38,7 @@ fn write_version(writer: &mut impl Write, version: &Version, verbose: bool) -> F
 }
 
 fn write_options(writer: &mut impl Write, options: &EncOptionSet, verbose: bool) -> FedResult<()> {
-    if options.len() == 0 {
+    if options.is_empty() {
         return Ok(());
     }
     let options_txt = options
@@ -53,6 +59,53 @@ fn write_salt(writer: &mut impl Write, salt: &Salt, verbose: bool) -> FedResult<
     write_line(writer, PUB_HEADER_SALT_MARKER, Some(&salt_str), verbose)
 }
 
+fn write_recipients(
+    writer: &mut impl Write,
+    recipients: &[WrappedKey],
+    verbose: bool,
+) -> FedResult<()> {
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    let recipients_txt = recipients
+        .iter()
+        .map(|recipient| recipient.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_line(
+        writer,
+        PUB_HEADER_RECIPIENTS_MARKER,
+        Some(&recipients_txt),
+        verbose,
+    )
+}
+
+fn write_key_slots(writer: &mut impl Write, key_slots: &[KeySlot], verbose: bool) -> FedResult<()> {
+    if key_slots.is_empty() {
+        return Ok(());
+    }
+    let key_slots_txt = key_slots
+        .iter()
+        .map(|slot| slot.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_line(
+        writer,
+        PUB_HEADER_KEY_SLOTS_MARKER,
+        Some(&key_slots_txt),
+        verbose,
+    )
+}
+
+fn write_kdf_cost(writer: &mut impl Write, kdf_cost: &KdfCost, verbose: bool) -> FedResult<()> {
+    write_line(
+        writer,
+        PUB_HEADER_KDF_COST_MARKER,
+        Some(&kdf_cost.to_string()),
+        verbose,
+    )
+}
+
 fn write_checksum(writer: &mut impl Write, checksum: &Checksum, verbose: bool) -> FedResult<()> {
     write_line(
         writer,
@@ -88,7 +141,14 @@ pub fn write_public_header(
         write_options(writer, header.options(), verbose)?;
     }
     write_salt(writer, header.salt(), verbose)?;
-    write_checksum(writer, header.checksum(), verbose)?;
+    if let Some(kdf_cost) = header.kdf_cost() {
+        write_kdf_cost(writer, kdf_cost, verbose)?;
+    }
+    write_recipients(writer, header.recipients(), verbose)?;
+    write_key_slots(writer, header.key_slots(), verbose)?;
+    if let Some(checksum) = header.checksum() {
+        write_checksum(writer, checksum, verbose)?;
+    }
     if let Some((length, checksum)) = header.private_header() {
         write_private_header_meta(writer, *length, checksum, verbose)?;
     }
@@ -96,6 +156,15 @@ pub fn write_public_header(
     Ok(())
 }
 
+/// Write the trailer, which comes after the data, for versions that have the stream layout.
+pub fn write_public_trailer(
+    writer: &mut impl Write,
+    checksum: &Checksum,
+    verbose: bool,
+) -> FedResult<()> {
+    write_checksum(writer, checksum, verbose)
+}
+
 #[cfg(test)]
 mod tests {
     use ::std::str::from_utf8;
@@ -108,16 +177,18 @@ mod tests {
     use crate::util::option::EncOptionSet;
 
     use super::write_public_header;
+    use super::write_public_trailer;
+    use crate::header::PUB_TRAILER_LEN;
 
     #[test]
     fn write_vanilla() {
         let version = Version::parse("1.1.0").unwrap();
-        let header = PublicHeader::new(
+        let header = PublicHeader::legacy(
             version,
             Salt::fixed_for_test(1),
-            Checksum::fixed_for_test(vec![2]),
+            Some(Checksum::fixed_for_test(vec![2])),
             EncOptionSet::empty(),
-            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+            Some((20, Checksum::fixed_for_test(vec![10, 20, 30]))),
         );
         let mut buf: Vec<u8> = Vec::new();
         write_public_header(&mut buf, &header, true).unwrap();
@@ -129,16 +200,45 @@ mod tests {
     #[test]
     fn write_options() {
         let version = Version::parse("1.1.0").unwrap();
-        let header = PublicHeader::new(
+        let header = PublicHeader::legacy(
             version,
             Salt::fixed_for_test(123_456_789_123_456_789),
-            Checksum::fixed_for_test(vec![0, 5, 0, 5, 0, 5, 0, 5, 0, 5, 0, 5]),
+            Some(Checksum::fixed_for_test(vec![
+                0, 5, 0, 5, 0, 5, 0, 5, 0, 5, 0, 5,
+            ])),
             EncOptionSet::all_for_test(),
-            (20, Checksum::fixed_for_test(vec![10, 100])),
+            Some((20, Checksum::fixed_for_test(vec![10, 100]))),
         );
         let mut buf: Vec<u8> = Vec::new();
         write_public_header(&mut buf, &header, true).unwrap();
         let expected = "github.com/mverleg/file_endec\0\nv 1.1.0\nopts fast hide-meta pad-size\nsalt FV_QrEubtgEVX9CsS5u2ARVf0KxLm7YBFV_QrEubtgEVX9CsS5u2ARVf0KxLm7YBFV_QrEubtgEVX9CsS5u2AQ\ncheck xx_sha256 AAUABQAFAAUABQAF\nprv U xx_sha256 CmQ\nmeta1+data:\n";
         assert_eq!(expected, from_utf8(&buf).unwrap());
     }
+
+    #[test]
+    fn write_stream() {
+        let version = Version::parse("1.2.0").unwrap();
+        let header = PublicHeader::new(
+            version,
+            Salt::fixed_for_test(1),
+            EncOptionSet::empty(),
+            (20, Checksum::fixed_for_test(vec![10, 20, 30])),
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        write_public_header(&mut buf, &header, true).unwrap();
+        let expected =
+            "github.com/mverleg/file_endec\0\nv 1.2.0\nsalt AQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAAEAAAAAAAAAAQAAAAAAAAABAAAAAAAAAA\nprv U xx_sha256 ChQe\nmeta1+data:\n";
+        assert_eq!(expected, from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn write_trailer() {
+        let checksum =
+            Checksum::fixed_for_test(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        let mut buf: Vec<u8> = Vec::new();
+        write_public_trailer(&mut buf, &checksum, true).unwrap();
+        let expected = "check xx_sha256 AQIDBAUGBwgJCgsMDQ4PEA\n";
+        assert_eq!(expected, from_utf8(&buf).unwrap());
+        assert_eq!(PUB_TRAILER_LEN, buf.len());
+    }
 }
@@ -1,6 +1,9 @@
 use ::semver::Version;
 
 use crate::files::Checksum;
+use crate::key::cost::KdfCost;
+use crate::key::recipient::WrappedKey;
+use crate::key::slot::KeySlot;
 use crate::key::Salt;
 use crate::util::option::EncOptionSet;
 
@@ -9,28 +12,36 @@ pub struct PublicHeader {
     version: Version,
     salt: Salt,
     //TODO: move data_checksum to private header?
-    data_checksum: Checksum,
+    // Only in the header before v1.2; after that it is in the trailer.
+    data_checksum: Option<Checksum>,
     options: EncOptionSet,
     // Length and checksum; required from v1.1
     private_header: Option<(u64, Checksum)>,
+    // The key encrypted for each recipient, if the file was encrypted for public keys.
+    recipients: Vec<WrappedKey>,
+    // The key encrypted with each password, if the file uses key slots.
+    key_slots: Vec<KeySlot>,
+    // Cost of key stretching; required from v1.5, before that it followed from the strategy.
+    kdf_cost: Option<KdfCost>,
 }
 
 impl PublicHeader {
+    /// Current version, which does not contain the data checksum (it is in the trailer).
     pub fn new(
         version: Version,
         salt: Salt,
-        checksum: Checksum,
         options: EncOptionSet,
         private_header: (u64, Checksum),
     ) -> Self {
-        Self::legacy(version, salt, checksum, options, Some(private_header))
+        Self::legacy(version, salt, None, options, Some(private_header))
     }
 
-    /// Legacy version (which may not have private headers if it was before v1.1)
+    /// Legacy version (which may not have private headers if it was before v1.1,
+    /// and has the data checksum in the header if it was before v1.2)
     pub fn legacy(
         version: Version,
         salt: Salt,
-        data_checksum: Checksum,
+        data_checksum: Option<Checksum>,
         options: EncOptionSet,
         private_header: Option<(u64, Checksum)>,
     ) -> Self {
@@ -40,9 +51,27 @@ impl PublicHeader {
             data_checksum,
             options,
             private_header,
+            recipients: vec![],
+            key_slots: vec![],
+            kdf_cost: None,
         }
     }
 
+    pub fn with_recipients(mut self, recipients: Vec<WrappedKey>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    pub fn with_key_slots(mut self, key_slots: Vec<KeySlot>) -> Self {
+        self.key_slots = key_slots;
+        self
+    }
+
+    pub fn with_kdf_cost(mut self, kdf_cost: Option<KdfCost>) -> Self {
+        self.kdf_cost = kdf_cost;
+        self
+    }
+
     pub fn version(&self) -> &Version {
         &self.version
     }
@@ -51,8 +80,8 @@ impl PublicHeader {
         &self.salt
     }
 
-    pub fn checksum(&self) -> &Checksum {
-        &self.data_checksum
+    pub fn checksum(&self) -> Option<&Checksum> {
+        self.data_checksum.as_ref()
     }
 
     pub fn options(&self) -> &EncOptionSet {
@@ -63,6 +92,18 @@ impl PublicHeader {
     pub fn private_header(&self) -> &Option<(u64, Checksum)> {
         &self.private_header
     }
+
+    pub fn recipients(&self) -> &[WrappedKey] {
+        &self.recipients
+    }
+
+    pub fn key_slots(&self) -> &[KeySlot] {
+        &self.key_slots
+    }
+
+    pub fn kdf_cost(&self) -> Option<&KdfCost> {
+        self.kdf_cost.as_ref()
+    }
 }
 
 pub const PUB_HEADER_MARKER: &str = "github.com/mverleg/file_endec\0";
@@ -71,5 +112,10 @@ pub const PUB_HEADER_SALT_MARKER: &str = "salt";
 pub const PUB_HEADER_CHECKSUM_MARKER: &str = "check";
 pub const PUB_HEADER_OPTION_MARKER: &str = "opts";
 pub const PUB_HEADER_PRIVATE_HEADER_META_MARKER: &str = "prv";
+pub const PUB_HEADER_RECIPIENTS_MARKER: &str = "rcpt";
+pub const PUB_HEADER_KEY_SLOTS_MARKER: &str = "slots";
+pub const PUB_HEADER_KDF_COST_MARKER: &str = "kdf";
 pub const PUB_HEADER_PURE_DATA_MARKER: &str = "data:";
 pub const PUB_HEADER_META_DATA_MARKER: &str = "meta1+data:";
+/// The trailer is a single checksum line, like "check xx_sha256 [22 characters]\n".
+pub const PUB_TRAILER_LEN: usize = 39;
@@ -4,23 +4,23 @@ use ::std::fmt::Formatter;
 use ::lazy_static::lazy_static;
 use ::semver::Version;
 
+use crate::key::cost::KdfCost;
 use crate::util::option::{EncOption, EncOptionSet};
 use crate::util::version::get_current_version;
-use crate::util::FedResult;
+use crate::util::version::version_has_argon2id;
+use crate::util::version::version_has_authenticated_encryption;
+use crate::util::version::version_has_compression_choice;
+use crate::util::version::version_has_stream_layout;
+use crate::util::{FedError, FedResult};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Verbosity {
     Quiet,
+    #[default]
     Normal,
     Debug,
 }
 
-impl Default for Verbosity {
-    fn default() -> Self {
-        Verbosity::Normal
-    }
-}
-
 impl Verbosity {
     pub fn debug(self) -> bool {
         Verbosity::Debug == self
@@ -34,12 +34,18 @@ impl Verbosity {
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum CompressionAlg {
     Brotli,
+    // Fast compression, at the cost of a somewhat larger size
+    Zstd,
+    // Dense compression, at the cost of speed and memory
+    Xz,
 }
 
 impl fmt::Display for CompressionAlg {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
         f.write_str(match self {
             CompressionAlg::Brotli => "brotli",
+            CompressionAlg::Zstd => "zstd",
+            CompressionAlg::Xz => "xz",
         })
     }
 }
@@ -47,8 +53,11 @@ impl fmt::Display for CompressionAlg {
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum KeyHashAlg {
     BCrypt,
+    // Only for older files; new files use argon2id
     Argon2i,
     Sha512,
+    // Resists both side-channel and GPU attacks
+    Argon2id,
 }
 
 impl fmt::Display for KeyHashAlg {
@@ -57,6 +66,7 @@ impl fmt::Display for KeyHashAlg {
             KeyHashAlg::BCrypt => "bcrypt",
             KeyHashAlg::Argon2i => "argon2i",
             KeyHashAlg::Sha512 => "sha512",
+            KeyHashAlg::Argon2id => "argon2id",
         })
     }
 }
@@ -67,6 +77,8 @@ pub enum SymmetricEncryptionAlg {
     Aes256,
     // Twofish with Iso7816 padding and cipher block chaining
     Twofish,
+    // Aes 256 in Galois/counter mode, with an authentication tag for every chunk
+    Aes256Gcm,
 }
 
 impl fmt::Display for SymmetricEncryptionAlg {
@@ -74,34 +86,118 @@ impl fmt::Display for SymmetricEncryptionAlg {
         f.write_str(match self {
             SymmetricEncryptionAlg::Aes256 => "aes256",
             SymmetricEncryptionAlg::Twofish => "twofish",
+            SymmetricEncryptionAlg::Aes256Gcm => "aes256gcm",
         })
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum DataLayout {
+    // All the data is read into memory, and the checksum is in the public header.
+    Buffered,
+    // The data is processed in chunks, and the checksum is in a trailer after the data.
+    Stream,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Strategy {
-    pub stretch_count: u64,
+    /// The key stretching cost for files that do not store it in the header.
+    pub kdf_cost: KdfCost,
     pub compression_algorithm: Option<CompressionAlg>,
     pub key_hash_algorithms: Vec<KeyHashAlg>,
     pub symmetric_algorithms: Vec<SymmetricEncryptionAlg>,
+    pub layout: DataLayout,
 }
 
 lazy_static! {
     static ref STRATEGY_1_0: Strategy = Strategy {
-        stretch_count: 5,
+        kdf_cost: KdfCost::legacy(5),
         compression_algorithm: Some(CompressionAlg::Brotli),
         key_hash_algorithms: vec![KeyHashAlg::BCrypt, KeyHashAlg::Argon2i, KeyHashAlg::Sha512],
         symmetric_algorithms: vec![
             SymmetricEncryptionAlg::Aes256,
             SymmetricEncryptionAlg::Twofish
         ],
+        layout: DataLayout::Buffered,
     };
     static ref STRATEGY_1_1_FAST: Strategy = Strategy {
-        stretch_count: 0,
+        kdf_cost: KdfCost::legacy(0),
         compression_algorithm: Some(CompressionAlg::Brotli),
         key_hash_algorithms: vec![KeyHashAlg::Argon2i],
         symmetric_algorithms: vec![SymmetricEncryptionAlg::Aes256,],
+        layout: DataLayout::Buffered,
+    };
+    static ref STRATEGY_1_2: Strategy = Strategy {
+        kdf_cost: KdfCost::legacy(5),
+        compression_algorithm: Some(CompressionAlg::Brotli),
+        key_hash_algorithms: vec![KeyHashAlg::BCrypt, KeyHashAlg::Argon2i, KeyHashAlg::Sha512],
+        symmetric_algorithms: vec![
+            SymmetricEncryptionAlg::Aes256,
+            SymmetricEncryptionAlg::Twofish
+        ],
+        layout: DataLayout::Stream,
+    };
+    static ref STRATEGY_1_2_FAST: Strategy = Strategy {
+        kdf_cost: KdfCost::legacy(0),
+        compression_algorithm: Some(CompressionAlg::Brotli),
+        key_hash_algorithms: vec![KeyHashAlg::Argon2i],
+        symmetric_algorithms: vec![SymmetricEncryptionAlg::Aes256,],
+        layout: DataLayout::Stream,
+    };
+    static ref STRATEGY_1_3: Strategy = Strategy {
+        kdf_cost: KdfCost::legacy(5),
+        compression_algorithm: Some(CompressionAlg::Brotli),
+        key_hash_algorithms: vec![KeyHashAlg::BCrypt, KeyHashAlg::Argon2i, KeyHashAlg::Sha512],
+        symmetric_algorithms: vec![
+            SymmetricEncryptionAlg::Twofish,
+            SymmetricEncryptionAlg::Aes256Gcm
+        ],
+        layout: DataLayout::Stream,
     };
+    static ref STRATEGY_1_3_FAST: Strategy = Strategy {
+        kdf_cost: KdfCost::legacy(0),
+        compression_algorithm: Some(CompressionAlg::Brotli),
+        key_hash_algorithms: vec![KeyHashAlg::Argon2i],
+        symmetric_algorithms: vec![SymmetricEncryptionAlg::Aes256Gcm,],
+        layout: DataLayout::Stream,
+    };
+}
+
+/// The compression is chosen with options since 1.4; without any of them, it is brotli.
+fn compression_from_options(options: &EncOptionSet) -> Option<CompressionAlg> {
+    if options.has(EncOption::NoCompression) {
+        None
+    } else if options.has(EncOption::Zstd) {
+        Some(CompressionAlg::Zstd)
+    } else if options.has(EncOption::Xz) {
+        Some(CompressionAlg::Xz)
+    } else {
+        Some(CompressionAlg::Brotli)
+    }
+}
+
+/// Since 1.4, the strategy is that of 1.3 with the compression from the options. Since 1.6,
+/// the key is stretched with argon2id, with more memory and fewer passes, which is harder
+/// to speed up with GPUs.
+fn strategy_1_4(options: &EncOptionSet, argon2id: bool) -> Strategy {
+    let base = if options.has(EncOption::Fast) {
+        &*STRATEGY_1_3_FAST
+    } else {
+        &*STRATEGY_1_3
+    };
+    let mut strategy = Strategy {
+        compression_algorithm: compression_from_options(options),
+        ..base.clone()
+    };
+    if argon2id {
+        for alg in &mut strategy.key_hash_algorithms {
+            if *alg == KeyHashAlg::Argon2i {
+                *alg = KeyHashAlg::Argon2id;
+            }
+        }
+        strategy.kdf_cost = strategy.kdf_cost.with_argon2(Some(64 * 1024), Some(3));
+    }
+    strategy
 }
 
 /// Get the encryption strategy used for a specific code version.
@@ -109,23 +205,38 @@ pub fn get_version_strategy(
     version: &Version,
     options: &EncOptionSet,
     verbose: bool,
-) -> FedResult<&'static Strategy> {
+) -> FedResult<Strategy> {
     // This should return the strategy for all old versions - don't delete any, just add new ones!
     if version < &Version::parse("1.0.0").unwrap() {
-        return Err(if verbose {
+        return Err(FedError::Corrupted(if verbose {
             "non-existent version".to_owned()
         } else {
             format!("non-existent version {} (minimum is 1.0.0)", version)
-        });
+        }));
+    }
+    if version_has_compression_choice(version) {
+        return Ok(strategy_1_4(options, version_has_argon2id(version)));
+    }
+    if version_has_authenticated_encryption(version) {
+        if options.has(EncOption::Fast) {
+            return Ok(STRATEGY_1_3_FAST.clone());
+        }
+        return Ok(STRATEGY_1_3.clone());
+    }
+    if version_has_stream_layout(version) {
+        if options.has(EncOption::Fast) {
+            return Ok(STRATEGY_1_2_FAST.clone());
+        }
+        return Ok(STRATEGY_1_2.clone());
     }
     if options.has(EncOption::Fast) {
-        Ok(&*STRATEGY_1_1_FAST)
+        Ok(STRATEGY_1_1_FAST.clone())
     } else {
-        Ok(&*STRATEGY_1_0)
+        Ok(STRATEGY_1_0.clone())
     }
 }
 
-pub fn get_current_version_strategy(options: &EncOptionSet, verbose: bool) -> &'static Strategy {
+pub fn get_current_version_strategy(options: &EncOptionSet, verbose: bool) -> Strategy {
     get_version_strategy(&get_current_version(), options, verbose).unwrap()
 }
 
@@ -136,7 +247,7 @@ mod tests {
     #[test]
     fn determine_strategy_1_0() {
         assert_eq!(
-            &*STRATEGY_1_0,
+            *STRATEGY_1_0,
             get_version_strategy(
                 &Version::parse("1.0.0").unwrap(),
                 &EncOptionSet::empty(),
@@ -145,7 +256,7 @@ mod tests {
             .unwrap()
         );
         assert_eq!(
-            &*STRATEGY_1_0,
+            *STRATEGY_1_0,
             get_version_strategy(
                 &Version::parse("1.0.0").unwrap(),
                 &EncOptionSet::empty(),
@@ -158,7 +269,7 @@ mod tests {
     #[test]
     fn determine_strategy_1_1() {
         assert_eq!(
-            &*STRATEGY_1_0,
+            *STRATEGY_1_0,
             get_version_strategy(
                 &Version::parse("1.1.0").unwrap(),
                 &EncOptionSet::empty(),
@@ -167,7 +278,7 @@ mod tests {
             .unwrap()
         );
         assert_eq!(
-            &*STRATEGY_1_0,
+            *STRATEGY_1_0,
             get_version_strategy(
                 &Version::parse("1.1.0").unwrap(),
                 &EncOptionSet::empty(),
@@ -176,7 +287,7 @@ mod tests {
             .unwrap()
         );
         assert_eq!(
-            &*STRATEGY_1_1_FAST,
+            *STRATEGY_1_1_FAST,
             get_version_strategy(
                 &Version::parse("1.1.0").unwrap(),
                 &EncOptionSet::all_for_test(),
@@ -185,7 +296,7 @@ mod tests {
             .unwrap()
         );
         assert_eq!(
-            &*STRATEGY_1_1_FAST,
+            *STRATEGY_1_1_FAST,
             get_version_strategy(
                 &Version::parse("1.1.0").unwrap(),
                 &EncOptionSet::all_for_test(),
@@ -194,4 +305,88 @@ mod tests {
             .unwrap()
         );
     }
+
+    #[test]
+    fn determine_strategy_1_2() {
+        assert_eq!(
+            *STRATEGY_1_2,
+            get_version_strategy(
+                &Version::parse("1.2.0").unwrap(),
+                &EncOptionSet::empty(),
+                true
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            *STRATEGY_1_2_FAST,
+            get_version_strategy(
+                &Version::parse("1.2.0").unwrap(),
+                &EncOptionSet::all_for_test(),
+                false
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn determine_strategy_1_3() {
+        assert_eq!(
+            *STRATEGY_1_3,
+            get_version_strategy(
+                &Version::parse("1.3.0").unwrap(),
+                &EncOptionSet::empty(),
+                true
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            *STRATEGY_1_3_FAST,
+            get_version_strategy(
+                &Version::parse("1.3.0").unwrap(),
+                &EncOptionSet::all_for_test(),
+                false
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn determine_strategy_1_4() {
+        let version = Version::parse("1.4.0").unwrap();
+        let default = get_version_strategy(&version, &EncOptionSet::empty(), true).unwrap();
+        assert_eq!(Some(CompressionAlg::Brotli), default.compression_algorithm);
+        assert_eq!(
+            STRATEGY_1_3.symmetric_algorithms,
+            default.symmetric_algorithms
+        );
+        let options = vec![EncOption::Fast, EncOption::Zstd].into();
+        let fast_zstd = get_version_strategy(&version, &options, true).unwrap();
+        assert_eq!(Some(CompressionAlg::Zstd), fast_zstd.compression_algorithm);
+        assert_eq!(0, fast_zstd.kdf_cost.rounds);
+        let options = vec![EncOption::NoCompression].into();
+        let uncompressed = get_version_strategy(&version, &options, true).unwrap();
+        assert_eq!(None, uncompressed.compression_algorithm);
+        // Older versions always used brotli.
+        let options = vec![EncOption::Xz].into();
+        let old = get_version_strategy(&Version::parse("1.3.0").unwrap(), &options, true).unwrap();
+        assert_eq!(*STRATEGY_1_3, old);
+    }
+
+    #[test]
+    fn determine_strategy_1_6() {
+        let version = Version::parse("1.6.0").unwrap();
+        let default = get_version_strategy(&version, &EncOptionSet::empty(), true).unwrap();
+        assert_eq!(
+            vec![KeyHashAlg::BCrypt, KeyHashAlg::Argon2id, KeyHashAlg::Sha512],
+            default.key_hash_algorithms
+        );
+        assert_eq!(64 * 1024, default.kdf_cost.argon2_memory_kib);
+        let options = vec![EncOption::Fast, EncOption::Xz].into();
+        let fast_xz = get_version_strategy(&version, &options, true).unwrap();
+        assert_eq!(vec![KeyHashAlg::Argon2id], fast_xz.key_hash_algorithms);
+        assert_eq!(Some(CompressionAlg::Xz), fast_xz.compression_algorithm);
+        assert_eq!(0, fast_xz.kdf_cost.rounds);
+        let old = get_version_strategy(&Version::parse("1.5.0").unwrap(), &options, true).unwrap();
+        assert_eq!(vec![KeyHashAlg::Argon2i], old.key_hash_algorithms);
+    }
 }
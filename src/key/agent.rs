@@ -0,0 +1,432 @@
+use ::std::collections::HashMap;
+use ::std::env;
+use ::std::fs;
+use ::std::io::{self, BufRead, BufReader, Write};
+use ::std::mem;
+use ::std::net::Shutdown;
+use ::std::os::unix::fs::MetadataExt;
+use ::std::os::unix::io::AsRawFd;
+use ::std::os::unix::net::{UnixListener, UnixStream};
+use ::std::path::{Path, PathBuf};
+use ::std::time::{Duration, Instant};
+
+use ::ring::digest::{Context, SHA256};
+
+use crate::header::KeyHashAlg;
+use crate::key::cost::KdfCost;
+use crate::key::key::StretchKey;
+use crate::key::stretch::stretch_key;
+use crate::key::{Key, Salt};
+use crate::util::base::{base64str_to_u8s, u8s_to_base64str};
+use crate::util::errors::wrap_io;
+use crate::util::{FedError, FedResult};
+
+/// Environment variable with the socket of the agent, if it is not at the default location.
+pub const AGENT_SOCKET_ENV: &str = "FILE_ENDEC_AGENT";
+
+/// In the runtime directory of the user. There is no fallback to a shared directory like /tmp,
+/// because another user could create the socket there first.
+pub fn default_agent_socket() -> FedResult<PathBuf> {
+    if let Some(pth) = env::var_os(AGENT_SOCKET_ENV) {
+        return Ok(PathBuf::from(pth));
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir).join("file_endec-agent.sock")),
+        None => Err(FedError::Usage(format!(
+            "the agent needs a private directory for its socket, but XDG_RUNTIME_DIR is not set; \
+            set {} to a path in a directory that only you can access",
+            AGENT_SOCKET_ENV
+        ))),
+    }
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// The user on the other end of the socket, according to the operating system.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let status = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if status != 0 || len as usize != mem::size_of::<libc::ucred>() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Only the current user should be able to reach the socket, so the directory must be theirs
+/// and not accessible to anyone else.
+fn check_private_dir(socket: &Path) -> FedResult<()> {
+    let dir = match socket.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let meta = wrap_io(
+        || {
+            format!(
+                "could not access the directory of the agent socket '{}'",
+                dir.to_string_lossy()
+            )
+        },
+        fs::metadata(dir),
+    )?;
+    if meta.uid() != current_uid() || meta.mode() & 0o077 != 0 {
+        return Err(FedError::Usage(format!(
+            "the directory of the agent socket '{}' should belong to you and \
+            not be accessible to others (mode 700)",
+            dir.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+/// Identifies a stretched key by everything it depends on except the key, so that it can
+/// be found without knowing the key. The salt is random, so it belongs to one key.
+pub fn stretch_cache_id(salt: &Salt, cost: &KdfCost, key_hash_algorithms: &[KeyHashAlg]) -> String {
+    let mut context = Context::new(&SHA256);
+    context.update(salt.as_base64().as_bytes());
+    context.update(cost.to_string().as_bytes());
+    for alg in key_hash_algorithms {
+        context.update(alg.to_string().as_bytes());
+    }
+    u8s_to_base64str(context.finish().as_ref())
+}
+
+/// The stretched key for this salt, if the agent has it.
+/// The agent only speeds things up, so if it is not reachable, the key is just stretched.
+pub fn cached_stretched_key(
+    agent: Option<&AgentClient>,
+    salt: &Salt,
+    cost: &KdfCost,
+    key_hash_algorithms: &[KeyHashAlg],
+) -> Option<StretchKey> {
+    let id = stretch_cache_id(salt, cost, key_hash_algorithms);
+    agent?.stretched_key(&id).ok().flatten()
+}
+
+/// Stretch the key, and give it to the agent, so that the key is not needed next time.
+pub fn stretch_key_with_agent(
+    agent: Option<&AgentClient>,
+    raw_key: &Key,
+    salt: &Salt,
+    cost: &KdfCost,
+    key_hash_algorithms: &[KeyHashAlg],
+    start_progress: &mut impl FnMut(&KeyHashAlg),
+) -> FedResult<StretchKey> {
+    let stretched = stretch_key(raw_key, salt, cost, key_hash_algorithms, start_progress)?;
+    if let Some(agent) = agent {
+        let id = stretch_cache_id(salt, cost, key_hash_algorithms);
+        let _ = agent.store_stretched_key(&id, &stretched);
+    }
+    Ok(stretched)
+}
+
+struct Entry {
+    key: StretchKey,
+    expires: Instant,
+}
+
+/// What the agent remembers. It only ever has stretched keys, which are each useful for just one
+/// salt, and never the key itself. Everything is forgotten after the timeout.
+pub struct AgentCache {
+    timeout: Duration,
+    stretched: HashMap<String, Entry>,
+}
+
+impl AgentCache {
+    pub fn new(timeout: Duration) -> Self {
+        AgentCache {
+            timeout,
+            stretched: HashMap::new(),
+        }
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.stretched.retain(|_, entry| entry.expires > now);
+    }
+
+    /// Answer a request, which is one line of text. The second value is whether to stop.
+    pub fn handle(&mut self, request: &str) -> (String, bool) {
+        self.expire();
+        let mut parts = request.trim().split(' ');
+        let response = match (parts.next(), parts.next(), parts.next()) {
+            (Some("stretched"), Some(id), None) => match self.stretched.get(id) {
+                Some(entry) => format!(
+                    "ok {}",
+                    u8s_to_base64str(entry.key.unsecure_slice(entry.key.len()))
+                ),
+                None => "none".to_owned(),
+            },
+            (Some("set-stretched"), Some(id), Some(key)) => match base64str_to_u8s(key) {
+                Ok(data) if data.len() >= 32 => {
+                    let entry = Entry {
+                        key: StretchKey::new(&data),
+                        expires: Instant::now() + self.timeout,
+                    };
+                    self.stretched.insert(id.to_owned(), entry);
+                    "ok".to_owned()
+                }
+                _ => "error invalid stretched key".to_owned(),
+            },
+            (Some("forget"), None, None) => {
+                self.stretched.clear();
+                "ok".to_owned()
+            }
+            (Some("stop"), None, None) => return ("ok".to_owned(), true),
+            _ => "error unknown request".to_owned(),
+        };
+        (response, false)
+    }
+}
+
+fn serve_one(stream: UnixStream, cache: &mut AgentCache) -> FedResult<bool> {
+    let err = || "agent could not communicate with a client";
+    // The directory should already keep others out, but this does not depend on that.
+    if wrap_io(err, peer_uid(&stream))? != current_uid() {
+        return Ok(false);
+    }
+    wrap_io(err, stream.set_read_timeout(Some(Duration::from_secs(5))))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    wrap_io(err, reader.read_line(&mut request))?;
+    let (response, stop) = cache.handle(&request);
+    wrap_io(
+        err,
+        (&stream).write_all(format!("{}\n", response).as_bytes()),
+    )?;
+    Ok(stop)
+}
+
+/// Bind with a umask, so that the socket is private from the moment it is created.
+fn bind_private(socket: &Path) -> io::Result<UnixListener> {
+    let previous = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    unsafe { libc::umask(previous) };
+    listener
+}
+
+/// Run the agent in the foreground, until it is stopped with 'fileenc agent --stop'.
+pub fn run_agent(socket: &Path, timeout: Duration) -> FedResult<()> {
+    check_private_dir(socket)?;
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(FedError::Usage(format!(
+                "an agent is already running at '{}'",
+                socket.to_string_lossy()
+            )));
+        }
+        // Left behind by an agent that did not stop cleanly.
+        wrap_io(
+            || "could not remove old agent socket",
+            fs::remove_file(socket),
+        )?;
+    }
+    let listener = wrap_io(
+        || {
+            format!(
+                "could not start the agent at '{}'",
+                socket.to_string_lossy()
+            )
+        },
+        bind_private(socket),
+    )?;
+    let mut cache = AgentCache::new(timeout);
+    for stream in listener.incoming() {
+        // A failing client should not stop the agent.
+        let stop = match stream {
+            Ok(stream) => serve_one(stream, &mut cache).unwrap_or(false),
+            Err(_) => false,
+        };
+        if stop {
+            break;
+        }
+    }
+    wrap_io(
+        || "could not remove the agent socket",
+        fs::remove_file(socket),
+    )
+}
+
+/// Talks to a running agent. Every request uses a new connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentClient {
+    socket: PathBuf,
+}
+
+impl AgentClient {
+    pub fn new(socket: PathBuf) -> Self {
+        AgentClient { socket }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Connect only to an agent of the current user, so that keys are not given to anyone else.
+    fn connect(&self) -> FedResult<UnixStream> {
+        check_private_dir(&self.socket)?;
+        let not_running = || {
+            FedError::Usage(format!(
+                "no agent is running at '{}'; start one with 'fileenc agent'",
+                self.socket.to_string_lossy()
+            ))
+        };
+        let meta = fs::symlink_metadata(&self.socket).map_err(|_| not_running())?;
+        let stream = UnixStream::connect(&self.socket).map_err(|_| not_running())?;
+        let peer = wrap_io(
+            || "could not check the owner of the agent",
+            peer_uid(&stream),
+        )?;
+        if meta.uid() != current_uid() || peer != current_uid() {
+            return Err(FedError::Usage(format!(
+                "the agent at '{}' belongs to another user",
+                self.socket.to_string_lossy()
+            )));
+        }
+        Ok(stream)
+    }
+
+    /// The value of the response, or None if the agent does not have it.
+    fn request(&self, request: &str) -> FedResult<Option<String>> {
+        let mut stream = self.connect()?;
+        let err = || "could not communicate with the agent";
+        wrap_io(err, stream.write_all(format!("{}\n", request).as_bytes()))?;
+        wrap_io(err, stream.shutdown(Shutdown::Write))?;
+        let mut response = String::new();
+        wrap_io(err, BufReader::new(&stream).read_line(&mut response))?;
+        let response = response.trim();
+        if response == "none" {
+            return Ok(None);
+        }
+        match response.strip_prefix("ok") {
+            Some(value) => Ok(Some(value.trim_start().to_owned())),
+            None => Err(FedError::Other(format!(
+                "the agent did not accept the request: {}",
+                response
+            ))),
+        }
+    }
+
+    pub fn stretched_key(&self, id: &str) -> FedResult<Option<StretchKey>> {
+        match self.request(&format!("stretched {}", id))? {
+            Some(value) => {
+                let data = base64str_to_u8s(&value).map_err(|_| {
+                    FedError::Other("the agent returned an invalid stretched key".to_owned())
+                })?;
+                Ok(Some(StretchKey::new(&data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn store_stretched_key(&self, id: &str, key: &StretchKey) -> FedResult<()> {
+        let data = u8s_to_base64str(key.unsecure_slice(key.len()));
+        self.request(&format!("set-stretched {} {}", id, data))
+            .map(|_| ())
+    }
+
+    /// Forget all stretched keys, but keep running.
+    pub fn forget(&self) -> FedResult<()> {
+        self.request("forget").map(|_| ())
+    }
+
+    pub fn stop(&self) -> FedResult<()> {
+        self.request("stop").map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::os::unix::fs::PermissionsExt;
+    use ::std::thread;
+
+    use ::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn cache_expires() {
+        let mut cache = AgentCache::new(Duration::from_secs(0));
+        let key = u8s_to_base64str(&[7; 32]);
+        assert_eq!("ok", cache.handle(&format!("set-stretched abc {}", key)).0);
+        assert_eq!("none", cache.handle("stretched abc").0);
+        assert_eq!("error unknown request", cache.handle("key").0);
+        assert_eq!(
+            "error unknown request",
+            cache.handle(&format!("set-key {}", key)).0
+        );
+        assert_eq!(("ok".to_owned(), true), cache.handle("stop"));
+    }
+
+    #[test]
+    fn socket_round_trip() {
+        let dir = tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        let socket = dir.path().join("agent.sock");
+        let agent_socket = socket.clone();
+        let agent = thread::spawn(move || run_agent(&agent_socket, Duration::from_secs(60)));
+        let client = AgentClient::new(socket.clone());
+        while client.stretched_key("none").is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(0o600, fs::metadata(&socket).unwrap().mode() & 0o777);
+
+        let key = Key::new("correct horse: battery staple");
+        let salt = Salt::fixed_for_test(1);
+        let cost = KdfCost::interactive().with_argon2(Some(64), Some(1));
+        let algs = vec![KeyHashAlg::Sha512];
+        assert_ne!(
+            stretch_cache_id(&salt, &cost, &algs),
+            stretch_cache_id(&Salt::fixed_for_test(2), &cost, &algs)
+        );
+        assert!(cached_stretched_key(Some(&client), &salt, &cost, &algs).is_none());
+        let stretched =
+            stretch_key_with_agent(Some(&client), &key, &salt, &cost, &algs, &mut |_| {}).unwrap();
+        let cached = cached_stretched_key(Some(&client), &salt, &cost, &algs).unwrap();
+        assert_eq!(stretched.unsecure_slice(32), cached.unsecure_slice(32));
+
+        client.forget().unwrap();
+        assert!(cached_stretched_key(Some(&client), &salt, &cost, &algs).is_none());
+        client.stop().unwrap();
+        agent.join().unwrap().unwrap();
+        assert!(!socket.exists());
+        assert!(client.stretched_key("none").is_err());
+    }
+
+    #[test]
+    fn refuse_shared_dir() {
+        let dir = tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        let socket = dir.path().join("agent.sock");
+        let err = run_agent(&socket, Duration::from_secs(60)).unwrap_err();
+        assert_eq!("usage", err.code());
+        assert!(!socket.exists());
+        let err = AgentClient::new(socket).stretched_key("none").unwrap_err();
+        assert_eq!("usage", err.code());
+    }
+}
@@ -0,0 +1,73 @@
+use ::std::path::{Path, PathBuf};
+use ::std::time::Duration;
+
+use crate::header::KeyHashAlg;
+use crate::key::cost::KdfCost;
+use crate::key::key::StretchKey;
+use crate::key::stretch::stretch_key;
+use crate::key::{Key, Salt};
+use crate::util::{FedError, FedResult};
+
+fn unsupported() -> FedError {
+    FedError::Usage("the agent needs unix sockets, which this platform does not have".to_owned())
+}
+
+pub fn default_agent_socket() -> FedResult<PathBuf> {
+    Err(unsupported())
+}
+
+pub fn cached_stretched_key(
+    _agent: Option<&AgentClient>,
+    _salt: &Salt,
+    _cost: &KdfCost,
+    _key_hash_algorithms: &[KeyHashAlg],
+) -> Option<StretchKey> {
+    None
+}
+
+pub fn stretch_key_with_agent(
+    _agent: Option<&AgentClient>,
+    raw_key: &Key,
+    salt: &Salt,
+    cost: &KdfCost,
+    key_hash_algorithms: &[KeyHashAlg],
+    start_progress: &mut impl FnMut(&KeyHashAlg),
+) -> FedResult<StretchKey> {
+    stretch_key(raw_key, salt, cost, key_hash_algorithms, start_progress)
+}
+
+pub fn run_agent(_socket: &Path, _timeout: Duration) -> FedResult<()> {
+    Err(unsupported())
+}
+
+/// Can be created, but every request fails, since there cannot be an agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentClient {
+    socket: PathBuf,
+}
+
+impl AgentClient {
+    pub fn new(socket: PathBuf) -> Self {
+        AgentClient { socket }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    pub fn stretched_key(&self, _id: &str) -> FedResult<Option<StretchKey>> {
+        Err(unsupported())
+    }
+
+    pub fn store_stretched_key(&self, _id: &str, _key: &StretchKey) -> FedResult<()> {
+        Err(unsupported())
+    }
+
+    pub fn forget(&self) -> FedResult<()> {
+        Err(unsupported())
+    }
+
+    pub fn stop(&self) -> FedResult<()> {
+        Err(unsupported())
+    }
+}
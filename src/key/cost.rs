@@ -0,0 +1,228 @@
+use ::std::fmt;
+
+use crate::util::{FedError, FedResult};
+
+/// Lanes that argon2 uses; each needs at least 8 KiB of memory.
+pub const ARGON2_LANES: u32 = 8;
+// Costs are read from headers, so these limits prevent a crafted file from taking
+// hours or all memory to decrypt. The paranoid preset fits well within them.
+const MAX_ROUNDS: u64 = 10;
+const MAX_BCRYPT_COST: u32 = 18;
+const MAX_ARGON2_PASSES: u32 = 64;
+const MAX_ARGON2_MEMORY_KIB: u32 = 2 * 1024 * 1024;
+const MAX_SHA_ITERATIONS: u32 = 10_000_000;
+
+/// How much work it takes to stretch the key, which is what makes guessing passwords slow.
+/// It is stored in the public header, so that decryption uses the same cost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfCost {
+    /// How many extra times each hash algorithm is applied.
+    pub rounds: u64,
+    pub bcrypt_cost: u32,
+    pub argon2_passes: u32,
+    pub argon2_memory_kib: u32,
+    pub sha_iterations: u32,
+}
+
+impl KdfCost {
+    /// The cost that was used for every file before it could be chosen.
+    pub fn legacy(rounds: u64) -> Self {
+        KdfCost {
+            rounds,
+            bcrypt_cost: 10,
+            argon2_passes: 30,
+            argon2_memory_kib: 4096,
+            sha_iterations: 70_000,
+        }
+    }
+
+    /// Takes around a second, short enough to wait for after typing a password.
+    pub fn interactive() -> Self {
+        KdfCost {
+            rounds: 0,
+            bcrypt_cost: 10,
+            argon2_passes: 3,
+            argon2_memory_kib: 64 * 1024,
+            sha_iterations: 100_000,
+        }
+    }
+
+    pub fn moderate() -> Self {
+        KdfCost {
+            rounds: 2,
+            bcrypt_cost: 12,
+            argon2_passes: 4,
+            argon2_memory_kib: 256 * 1024,
+            sha_iterations: 300_000,
+        }
+    }
+
+    /// Can take minutes and needs a gigabyte of memory, in return for much slower guessing.
+    pub fn paranoid() -> Self {
+        KdfCost {
+            rounds: 5,
+            bcrypt_cost: 14,
+            argon2_passes: 8,
+            argon2_memory_kib: 1024 * 1024,
+            sha_iterations: 1_000_000,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "interactive" => Some(KdfCost::interactive()),
+            "moderate" => Some(KdfCost::moderate()),
+            "paranoid" => Some(KdfCost::paranoid()),
+            _ => None,
+        }
+    }
+
+    /// Change the memory (in KiB) or the number of passes of argon2, the memory-hard algorithm.
+    pub fn with_argon2(mut self, memory_kib: Option<u32>, passes: Option<u32>) -> Self {
+        if let Some(memory_kib) = memory_kib {
+            self.argon2_memory_kib = memory_kib;
+        }
+        if let Some(passes) = passes {
+            self.argon2_passes = passes;
+        }
+        self
+    }
+
+    /// Reject costs that the algorithms do not support, or that are unreasonably high.
+    pub fn check(&self) -> Result<(), String> {
+        if self.rounds > MAX_ROUNDS {
+            return Err(format!(
+                "{} rounds is too many (max {})",
+                self.rounds, MAX_ROUNDS
+            ));
+        }
+        if !(4..=MAX_BCRYPT_COST).contains(&self.bcrypt_cost) {
+            return Err(format!(
+                "bcrypt cost {} is not supported (4 to {})",
+                self.bcrypt_cost, MAX_BCRYPT_COST
+            ));
+        }
+        if self.argon2_passes == 0 || self.argon2_passes > MAX_ARGON2_PASSES {
+            return Err(format!(
+                "argon2 needs 1 to {} passes, not {}",
+                MAX_ARGON2_PASSES, self.argon2_passes
+            ));
+        }
+        if self.argon2_memory_kib < 8 * ARGON2_LANES
+            || self.argon2_memory_kib > MAX_ARGON2_MEMORY_KIB
+        {
+            return Err(format!(
+                "argon2 needs {} to {} KiB of memory, not {}",
+                8 * ARGON2_LANES,
+                MAX_ARGON2_MEMORY_KIB,
+                self.argon2_memory_kib
+            ));
+        }
+        if self.sha_iterations == 0 || self.sha_iterations > MAX_SHA_ITERATIONS {
+            return Err(format!(
+                "sha512 needs 1 to {} iterations, not {}",
+                MAX_SHA_ITERATIONS, self.sha_iterations
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> FedResult<Self> {
+        let err = |reason: String| {
+            FedError::Corrupted(format!(
+                "key stretching cost '{}' in the header is not valid: {}",
+                text, reason
+            ))
+        };
+        let mut cost = KdfCost::legacy(0);
+        let mut seen = vec![];
+        for part in text.split_whitespace() {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| err(format!("'{}' is not a name=value pair", part)))?;
+            let number = |txt: &str| {
+                txt.parse::<u32>()
+                    .map_err(|_| err(format!("'{}' is not a number", txt)))
+            };
+            match name {
+                "rounds" => cost.rounds = number(value)? as u64,
+                "bcrypt" => cost.bcrypt_cost = number(value)?,
+                "argon2" => {
+                    let (passes, memory) = value.split_once(':').ok_or_else(|| {
+                        err(format!("argon2 should be passes:memory, not '{}'", value))
+                    })?;
+                    cost.argon2_passes = number(passes)?;
+                    cost.argon2_memory_kib = number(memory)?;
+                }
+                "sha" => cost.sha_iterations = number(value)?,
+                _ => return Err(err(format!("unknown part '{}'", name))),
+            }
+            seen.push(name);
+        }
+        seen.sort_unstable();
+        if seen != ["argon2", "bcrypt", "rounds", "sha"] {
+            return Err(err(
+                "it should have each of rounds, bcrypt, argon2 and sha once".to_owned(),
+            ));
+        }
+        cost.check().map_err(err)?;
+        Ok(cost)
+    }
+}
+
+impl fmt::Display for KdfCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rounds={} bcrypt={} argon2={}:{} sha={}",
+            self.rounds,
+            self.bcrypt_cost,
+            self.argon2_passes,
+            self.argon2_memory_kib,
+            self.sha_iterations
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        for cost in [
+            KdfCost::legacy(5),
+            KdfCost::interactive(),
+            KdfCost::moderate(),
+            KdfCost::paranoid(),
+        ] {
+            assert_eq!(cost, KdfCost::parse(&cost.to_string()).unwrap());
+        }
+        assert_eq!(
+            "rounds=0 bcrypt=10 argon2=2:1024 sha=100000",
+            KdfCost::interactive()
+                .with_argon2(Some(1024), Some(2))
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let invalid = [
+            "rounds=0 bcrypt=10 argon2=2:1024",
+            "rounds=0 bcrypt=10 argon2=2:1024 sha=1 sha=1",
+            "rounds=0 bcrypt=3 argon2=2:1024 sha=1",
+            "rounds=0 bcrypt=10 argon2=2:9999999 sha=1",
+            "rounds=0 bcrypt=10 argon2=2 sha=1",
+            "rounds=0 bcrypt=10 argon2=2:1024 sha=many",
+            "rounds=1000 bcrypt=10 argon2=2:1024 sha=1",
+            "rounds=0 bcrypt=31 argon2=2:1024 sha=1",
+            "rounds=0 bcrypt=10 argon2=10000:1024 sha=1",
+            "rounds=0 bcrypt=10 argon2=2:4194304 sha=1",
+            "rounds=0 bcrypt=10 argon2=2:1024 sha=4000000000",
+        ];
+        for text in invalid.iter() {
+            assert_eq!("corrupted", KdfCost::parse(text).unwrap_err().code());
+        }
+    }
+}
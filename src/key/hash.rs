@@ -1,43 +1,51 @@
+use ::std::convert::TryFrom;
+use ::std::fmt;
 use ::std::num::NonZeroU32;
 
+use ::argon2::Algorithm;
+use ::argon2::Params;
 use ::argon2rs::Argon2;
 use ::argon2rs::Variant;
 use ::bcrypt;
-use ::lazy_static::lazy_static;
 use ::ring::pbkdf2::derive;
 use ::ring::pbkdf2::PBKDF2_HMAC_SHA512;
 
 use crate::header::KeyHashAlg;
+use crate::key::cost::{KdfCost, ARGON2_LANES};
+use crate::util::{FedError, FedResult};
 
+fn cost_error(alg: &str, err: impl fmt::Display) -> FedError {
+    FedError::Usage(format!("invalid key stretching cost for {}: {}", alg, err))
+}
+
+/// Fails if the cost is not supported by the algorithm; use `KdfCost::check` to find out earlier.
 #[inline]
-pub fn hash(data: &[u8], salt: &[u8], algorithm: &KeyHashAlg) -> Vec<u8> {
+pub fn hash(
+    data: &[u8],
+    salt: &[u8],
+    algorithm: &KeyHashAlg,
+    cost: &KdfCost,
+) -> FedResult<Vec<u8>> {
     match algorithm {
-        KeyHashAlg::BCrypt => hash_bcrypt(data, salt),
-        KeyHashAlg::Argon2i => hash_argon2i(data, salt),
-        KeyHashAlg::Sha512 => hash_sha256(data, salt),
+        KeyHashAlg::BCrypt => hash_bcrypt_cost(data, salt, cost.bcrypt_cost),
+        KeyHashAlg::Argon2i => {
+            hash_argon2i_cost(data, salt, cost.argon2_passes, cost.argon2_memory_kib)
+        }
+        KeyHashAlg::Sha512 => hash_sha256_cost(data, salt, cost.sha_iterations),
+        KeyHashAlg::Argon2id => {
+            hash_argon2id_cost(data, salt, cost.argon2_passes, cost.argon2_memory_kib)
+        }
     }
 }
 
-lazy_static! {
-    // NOTE: if the hash parameters ever change, then KeyHashAlg must be extended to reflect that,
-    // to make sure that anything encrypted before can still be decrypted.
-    static ref BCRYPT_COST: u32 = 10;
-}
-
-lazy_static! {
-    // NOTE: if the hash parameters ever change, then KeyHashAlg must be extended to reflect that,
-    // to make sure that anything encrypted before can still be decrypted.
-    static ref ARGON_CONFIG: Argon2 = Argon2::new(30, 8, 4096, Variant::Argon2i).unwrap();
-}
-
-lazy_static! {
-    // NOTE: if the hash parameters ever change, then KeyHashAlg must be extended to reflect that,
-    // to make sure that anything encrypted before can still be decrypted.
-    static ref SHA_REPS: NonZeroU32 = NonZeroU32::new(70_000).unwrap();
+#[cfg(any(test, feature = "expose"))]
+#[inline]
+pub fn hash_bcrypt(data: &[u8], salt: &[u8]) -> Vec<u8> {
+    hash_bcrypt_cost(data, salt, KdfCost::legacy(0).bcrypt_cost).unwrap()
 }
 
 #[inline]
-pub fn hash_bcrypt(data: &[u8], salt: &[u8]) -> Vec<u8> {
+pub fn hash_bcrypt_cost(data: &[u8], salt: &[u8], bcrypt_cost: u32) -> FedResult<Vec<u8>> {
     // Note that this returns a string, which is a combination of the base64 key, and metadata like salt.
     // Also note that 0-bytes are now allowed in the input.
     let mut nonzero = data.to_vec();
@@ -47,24 +55,66 @@ pub fn hash_bcrypt(data: &[u8], salt: &[u8]) -> Vec<u8> {
         .enumerate()
         .filter(|(_, v)| **v == 0u8)
         .for_each(|(i, v)| *v = 1 + (i % 255) as u8);
-    bcrypt::hash_with_salt(&nonzero, *BCRYPT_COST, &salt[..16])
-        .unwrap()
-        .to_string()
-        .into_bytes()
+    bcrypt::hash_with_salt(
+        &nonzero,
+        bcrypt_cost,
+        <[u8; 16]>::try_from(&salt[..16]).unwrap(),
+    )
+    .map(|hash| hash.to_string().into_bytes())
+    .map_err(|err| cost_error("bcrypt", err))
 }
 
+#[cfg(any(test, feature = "expose"))]
 #[inline]
 pub fn hash_argon2i(data: &[u8], salt: &[u8]) -> Vec<u8> {
+    let cost = KdfCost::legacy(0);
+    hash_argon2i_cost(data, salt, cost.argon2_passes, cost.argon2_memory_kib).unwrap()
+}
+
+#[inline]
+pub fn hash_argon2i_cost(
+    data: &[u8],
+    salt: &[u8],
+    passes: u32,
+    memory_kib: u32,
+) -> FedResult<Vec<u8>> {
     let mut output = vec![0; 32];
-    (*ARGON_CONFIG).hash(&mut output, data, salt, &[], &[]);
-    output
+    Argon2::new(passes, ARGON2_LANES, memory_kib, Variant::Argon2i)
+        .map_err(|err| cost_error("argon2i", format!("{:?}", err)))?
+        .hash(&mut output, data, salt, &[], &[]);
+    Ok(output)
+}
+
+/// Argon2id from the maintained RustCrypto implementation, used since v1.6.
+#[inline]
+pub fn hash_argon2id_cost(
+    data: &[u8],
+    salt: &[u8],
+    passes: u32,
+    memory_kib: u32,
+) -> FedResult<Vec<u8>> {
+    let mut output = vec![0; 32];
+    let params = Params::new(memory_kib, passes, ARGON2_LANES, Some(output.len()))
+        .map_err(|err| cost_error("argon2id", err))?;
+    ::argon2::Argon2::new(Algorithm::Argon2id, ::argon2::Version::V0x13, params)
+        .hash_password_into(data, salt, &mut output)
+        .map_err(|err| cost_error("argon2id", err))?;
+    Ok(output)
 }
 
+#[cfg(any(test, feature = "expose"))]
 #[inline]
 pub fn hash_sha256(data: &[u8], salt: &[u8]) -> Vec<u8> {
+    hash_sha256_cost(data, salt, KdfCost::legacy(0).sha_iterations).unwrap()
+}
+
+#[inline]
+pub fn hash_sha256_cost(data: &[u8], salt: &[u8], iterations: u32) -> FedResult<Vec<u8>> {
     let mut output = vec![0; 32];
-    derive(PBKDF2_HMAC_SHA512, *SHA_REPS, salt, data, &mut output);
-    output
+    let iterations =
+        NonZeroU32::new(iterations).ok_or_else(|| cost_error("sha512", "zero iterations"))?;
+    derive(PBKDF2_HMAC_SHA512, iterations, salt, data, &mut output);
+    Ok(output)
 }
 
 #[cfg(any(test, feature = "expose"))]
@@ -127,6 +177,16 @@ mod tests {
         assert_eq!(expected, hashed);
     }
 
+    #[test]
+    fn test_hash_argon2id() {
+        let hashed = hash_argon2id_cost(&[1; 32], &[2; 32], 3, 1024).unwrap();
+        let expected: Vec<u8> = vec![
+            195, 74, 81, 211, 19, 110, 242, 49, 156, 147, 245, 16, 5, 66, 8, 95, 40, 41, 253, 207,
+            153, 167, 126, 51, 6, 100, 215, 174, 146, 186, 28, 234,
+        ];
+        assert_eq!(expected, hashed);
+    }
+
     #[test]
     fn test_hash_sha256() {
         let hashed = hash_sha256(&[1; 32], &[2; 32]);
@@ -137,6 +197,39 @@ mod tests {
         assert_eq!(expected, hashed);
     }
 
+    #[test]
+    fn cost_changes_hash() {
+        let cheap = KdfCost::interactive().with_argon2(Some(64), Some(1));
+        let mut pricier = cheap.clone();
+        pricier.argon2_passes = 2;
+        let alg = KeyHashAlg::Argon2i;
+        assert_ne!(
+            hash(&[1; 32], &[2; 32], &alg, &cheap).unwrap(),
+            hash(&[1; 32], &[2; 32], &alg, &pricier).unwrap()
+        );
+        assert_eq!(
+            hash_argon2i(&[1; 32], &[2; 32]),
+            hash(&[1; 32], &[2; 32], &alg, &KdfCost::legacy(0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_cost_is_error() {
+        let mut cost = KdfCost::interactive();
+        cost.bcrypt_cost = 99;
+        cost.argon2_passes = 0;
+        cost.sha_iterations = 0;
+        for alg in &[
+            KeyHashAlg::BCrypt,
+            KeyHashAlg::Argon2i,
+            KeyHashAlg::Argon2id,
+            KeyHashAlg::Sha512,
+        ] {
+            let err = hash(&[1; 32], &[2; 32], alg, &cost).unwrap_err();
+            assert_eq!("usage", err.code());
+        }
+    }
+
     #[test]
     fn test_hash_sha256_0() {
         let hashed = hash_sha256(&[0; 32], &[0; 16]);
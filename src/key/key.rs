@@ -5,6 +5,8 @@ use ::zxcvbn::Entropy;
 
 #[cfg(any(test, feature = "expose"))]
 use crate::key::hash::fastish_hash;
+use crate::key::random::generate_secure_random_timed;
+use crate::util::base::u8s_to_base64str;
 
 #[derive(Debug, Clone)]
 pub struct Key {
@@ -21,6 +23,25 @@ impl Key {
         }
     }
 
+    /// A key that is not chosen by a person, for example to encrypt for recipients.
+    pub fn generate_random() -> Self {
+        let mut key_data = [0u8; 48];
+        generate_secure_random_timed(&mut key_data);
+        Key::new(&u8s_to_base64str(&key_data))
+    }
+
+    /// Mix in a second key, like a key file, so that both are needed. The length prefix
+    /// keeps different pairs of keys from combining into the same key.
+    pub fn combine(&self, other: &Key) -> Self {
+        let first = self.key_data.unsecure();
+        Key::new(&format!(
+            "{}:{}{}",
+            first.len(),
+            first,
+            other.key_data.unsecure()
+        ))
+    }
+
     pub fn is_strong(&self) -> bool {
         self.strength.score() >= 3
     }
@@ -65,6 +86,10 @@ impl StretchKey {
         self.key_data.unsecure().len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.key_data.unsecure().is_empty()
+    }
+
     pub fn unsecure_slice(&self, upto: usize) -> &[u8] {
         &self.key_data.unsecure()[..upto]
     }
@@ -1,11 +1,21 @@
 pub use key::Key;
 pub use salt::Salt;
+pub use source::DeferredKey;
 pub use source::KeySource;
 
+#[cfg(unix)]
+pub mod agent;
+/// The agent listens on a unix socket; elsewhere this has the same functions, which fail.
+#[cfg(not(unix))]
+#[path = "agent_stub.rs"]
+pub mod agent;
+pub mod cost;
 pub mod hash;
 #[allow(clippy::module_inception)]
 pub mod key;
 pub mod random;
+pub mod recipient;
 pub mod salt;
+pub mod slot;
 pub mod source;
 pub mod stretch;
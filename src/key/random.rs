@@ -1,4 +1,3 @@
-use ::std::cell::RefCell;
 use ::std::sync::atomic::AtomicBool;
 use ::std::sync::atomic::Ordering;
 use ::std::sync::Arc;
@@ -8,16 +7,7 @@ use ::std::time::Duration;
 use ::std::time::SystemTime;
 
 use ::rand::rngs::OsRng;
-use ::rand::rngs::StdRng;
-use ::rand::Rng;
 use ::rand::RngCore;
-use ::rand::SeedableRng;
-
-thread_local! {
-    // Note: implements CryptoRng  //TODO @mark: test <--
-    //TODO @mark: is seeding secure enough?
-    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
-}
 
 /// Generate a secure random series of bytes, showing a
 /// warning on stderr if it takes long.
@@ -51,29 +41,3 @@ pub fn generate_secure_random_timed(buffer: &mut [u8]) {
         );
     }
 }
-
-/// This is 'secure' in the sense that next or previous values aren't predictable; it is still pseudo-
-/// random and not 'true' random. Characters in most of the non-whitespace, printable ascii range.
-pub fn generate_secure_pseudo_random_printable(buffer: &mut String, length: usize) {
-    buffer.clear();
-    RNG.with(|rng| {
-        let mut rng = rng.borrow_mut();
-        for _ in 0..length {
-            buffer.push(rng.gen_range(33..127) as u8 as char)
-            //TODO: would this be faster in batches?
-        }
-    })
-}
-
-#[cfg(test)]
-mod tests {
-    use ::rand::CryptoRng;
-
-    use super::*;
-
-    #[allow(dead_code)]
-    fn test_is_secure() -> impl CryptoRng {
-        // This fails at compile time if not cryptographic.
-        RNG.with(|rng| rng.borrow().clone())
-    }
-}
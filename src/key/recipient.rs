@@ -0,0 +1,324 @@
+use ::std::convert::TryInto;
+use ::std::fmt;
+use ::std::fs;
+use ::std::fs::OpenOptions;
+use ::std::io;
+use ::std::io::Write;
+use ::std::path::Path;
+use ::std::str::from_utf8;
+
+use ::aes_gcm::aead::{Aead, KeyInit};
+use ::aes_gcm::{Aes256Gcm, Nonce};
+use ::ring::digest::{Context, SHA256};
+use ::x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::key::random::generate_secure_random_timed;
+use crate::key::Key;
+use crate::util::base::{base64str_to_u8s, u8s_to_base64str};
+use crate::util::{FedError, FedResult};
+
+pub const RECIPIENT_PREFIX: &str = "fedpub:";
+pub const IDENTITY_PREFIX: &str = "fedsec:";
+const WRAP_CONTEXT: &[u8] = b"github.com/mverleg/file_endec recipient";
+
+fn parse_key_bytes(text: &str, prefix: &str, what: &str) -> FedResult<[u8; 32]> {
+    let encoded = text
+        .strip_prefix(prefix)
+        .ok_or_else(|| FedError::Usage(format!("{} should start with '{}'", what, prefix)))?;
+    base64str_to_u8s(encoded)
+        .ok()
+        .and_then(|bytes| bytes.as_slice().try_into().ok())
+        .ok_or_else(|| FedError::Usage(format!("{} '{}' is not valid", what, text)))
+}
+
+/// Public key of someone who should be able to decrypt a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    public: [u8; 32],
+}
+
+impl Recipient {
+    pub fn parse(text: &str) -> FedResult<Self> {
+        Ok(Recipient {
+            public: parse_key_bytes(text.trim(), RECIPIENT_PREFIX, "public key")?,
+        })
+    }
+
+    /// Either the public key itself, or the path of a file that contains it
+    /// (like an identity file, which has the public key in a comment).
+    pub fn load(text: &str) -> FedResult<Self> {
+        if text.starts_with(RECIPIENT_PREFIX) {
+            return Recipient::parse(text);
+        }
+        let content = fs::read_to_string(text).map_err(|err| {
+            FedError::Usage(format!(
+                "recipient '{}' is not a public key, and could not be read as a file; reason: {}",
+                text, err
+            ))
+        })?;
+        let public = content
+            .split_whitespace()
+            .find(|word| word.starts_with(RECIPIENT_PREFIX))
+            .ok_or_else(|| {
+                FedError::Usage(format!("did not find a public key in file '{}'", text))
+            })?;
+        Recipient::parse(public)
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", RECIPIENT_PREFIX, u8s_to_base64str(&self.public))
+    }
+}
+
+/// Private key, which can decrypt files that were encrypted for the matching `Recipient`.
+#[derive(Clone)]
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        generate_secure_random_timed(&mut secret);
+        Identity {
+            secret: StaticSecret::from(secret),
+        }
+    }
+
+    /// Parse the content of an identity file, ignoring comment lines.
+    pub fn parse(content: &str) -> FedResult<Self> {
+        let line = content
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .ok_or_else(|| {
+                FedError::Usage("did not find a private key in the identity".to_owned())
+            })?;
+        Ok(Identity {
+            secret: StaticSecret::from(parse_key_bytes(line, IDENTITY_PREFIX, "private key")?),
+        })
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient {
+            public: PublicKey::from(&self.secret).to_bytes(),
+        }
+    }
+
+    /// The content of an identity file, which includes the public key as a comment.
+    pub fn to_file_content(&self) -> String {
+        format!(
+            "# public key: {}\n{}{}\n",
+            self.recipient(),
+            IDENTITY_PREFIX,
+            u8s_to_base64str(&self.secret.to_bytes())
+        )
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity[{}]", self.recipient())
+    }
+}
+
+/// Create a new identity file, which is only readable by the current user.
+/// Returns the identity, so the public key can be shown.
+pub fn write_identity_file(path: &Path, overwrite: bool) -> FedResult<Identity> {
+    if !overwrite && path.exists() {
+        return Err(FedError::OutputExists(format!(
+            "identity file '{}' already exists (use --overwrite to replace it)",
+            path.to_string_lossy()
+        )));
+    }
+    let identity = Identity::generate();
+    let io_err = |err: io::Error| {
+        FedError::Io(format!(
+            "could not write identity file '{}'; reason: {}",
+            path.to_string_lossy(),
+            err
+        ))
+    };
+    // The old file is removed instead of truncated, so that the new one is created private.
+    if overwrite {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(io_err(err)),
+            _ => {}
+        }
+    }
+    let mut file = private_file_options().open(path).map_err(io_err)?;
+    file.write_all(identity.to_file_content().as_bytes())
+        .map_err(io_err)?;
+    Ok(identity)
+}
+
+/// Options to create a new file that only the current user can read, from the start.
+#[cfg(unix)]
+fn private_file_options() -> OpenOptions {
+    use ::std::os::unix::fs::OpenOptionsExt;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true).mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+fn private_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    options
+}
+
+/// The file key encrypted for one recipient, with the ephemeral public key needed to decrypt it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    ephemeral: [u8; 32],
+    sealed: Vec<u8>,
+}
+
+impl WrappedKey {
+    pub fn parse(text: &str) -> FedResult<Self> {
+        let err = || {
+            FedError::Corrupted(format!(
+                "encrypted key for recipient '{}' is not valid",
+                text
+            ))
+        };
+        let (ephemeral, sealed) = text.split_once(':').ok_or_else(err)?;
+        Ok(WrappedKey {
+            ephemeral: base64str_to_u8s(ephemeral)
+                .ok()
+                .and_then(|bytes| bytes.as_slice().try_into().ok())
+                .ok_or_else(err)?,
+            sealed: base64str_to_u8s(sealed).map_err(|_| err())?,
+        })
+    }
+}
+
+impl fmt::Display for WrappedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            u8s_to_base64str(&self.ephemeral),
+            u8s_to_base64str(&self.sealed)
+        )
+    }
+}
+
+/// Every wrapping uses a new ephemeral key, so the derived key is never reused,
+/// and a fixed nonce is safe.
+fn wrapping_cipher(shared: &[u8], ephemeral: &[u8; 32], recipient: &Recipient) -> Aes256Gcm {
+    let mut context = Context::new(&SHA256);
+    context.update(WRAP_CONTEXT);
+    context.update(shared);
+    context.update(ephemeral);
+    context.update(&recipient.public);
+    Aes256Gcm::new_from_slice(context.finish().as_ref()).unwrap()
+}
+
+/// Encrypt the file key so that only the recipient can decrypt it.
+pub fn wrap_key(key: &Key, recipient: &Recipient) -> WrappedKey {
+    let ephemeral_secret = Identity::generate().secret;
+    let ephemeral = PublicKey::from(&ephemeral_secret).to_bytes();
+    let shared = ephemeral_secret.diffie_hellman(&PublicKey::from(recipient.public));
+    let sealed = wrapping_cipher(shared.as_bytes(), &ephemeral, recipient)
+        .encrypt(
+            Nonce::from_slice(&[0; 12]),
+            key.key_data.unsecure().as_bytes(),
+        )
+        .expect("failed to encrypt the key for a recipient");
+    WrappedKey { ephemeral, sealed }
+}
+
+/// Find the file key that was encrypted for this identity.
+pub fn unwrap_key(wrapped_keys: &[WrappedKey], identity: &Identity) -> FedResult<Key> {
+    let recipient = identity.recipient();
+    for wrapped in wrapped_keys {
+        let shared = identity
+            .secret
+            .diffie_hellman(&PublicKey::from(wrapped.ephemeral));
+        if !shared.was_contributory() {
+            continue;
+        }
+        let cipher = wrapping_cipher(shared.as_bytes(), &wrapped.ephemeral, &recipient);
+        if let Ok(key_data) = cipher.decrypt(Nonce::from_slice(&[0; 12]), wrapped.sealed.as_slice())
+        {
+            let key_text = from_utf8(&key_data).map_err(|_| {
+                FedError::Corrupted("the key encrypted for this identity is not valid".to_owned())
+            })?;
+            return Ok(Key::new(key_text));
+        }
+    }
+    Err(FedError::WrongKey(format!(
+        "this file was not encrypted for the identity with public key {}",
+        recipient
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn identity_file_is_private() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("identity.txt");
+        let identity = write_identity_file(&pth, false).unwrap();
+        let parsed = Identity::parse(&fs::read_to_string(&pth).unwrap()).unwrap();
+        assert_eq!(identity.recipient(), parsed.recipient());
+        #[cfg(unix)]
+        {
+            use ::std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&pth).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        assert_eq!(
+            "output-exists",
+            write_identity_file(&pth, false).unwrap_err().code()
+        );
+        let replaced = write_identity_file(&pth, true).unwrap();
+        assert_ne!(identity.recipient(), replaced.recipient());
+    }
+
+    #[test]
+    fn identity_text_round_trip() {
+        let identity = Identity::generate();
+        let content = identity.to_file_content();
+        assert!(content.starts_with("# public key: fedpub:"));
+        let parsed = Identity::parse(&content).unwrap();
+        assert_eq!(identity.recipient(), parsed.recipient());
+        let recipient = Recipient::parse(&identity.recipient().to_string()).unwrap();
+        assert_eq!(identity.recipient(), recipient);
+    }
+
+    #[test]
+    fn wrap_for_several_recipients() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let key = Key::new("random file key");
+        let wrapped = [
+            wrap_key(&key, &alice.recipient()),
+            wrap_key(&key, &bob.recipient()),
+        ];
+        let wrapped = wrapped
+            .iter()
+            .map(|wrap| WrappedKey::parse(&wrap.to_string()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(key, unwrap_key(&wrapped, &alice).unwrap());
+        assert_eq!(key, unwrap_key(&wrapped, &bob).unwrap());
+        let eve = Identity::generate();
+        assert!(unwrap_key(&wrapped, &eve).is_err());
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(Recipient::parse("fedsec:AAAA").is_err());
+        assert!(Recipient::parse("fedpub:AAAA").is_err());
+        assert!(Identity::parse("# only a comment\n").is_err());
+        assert!(WrappedKey::parse("no-separator").is_err());
+    }
+}
@@ -4,11 +4,13 @@ use ::std::fmt::Formatter;
 use ::std::hash::Hash;
 use ::std::hash::Hasher;
 
+use ::ring::digest::{digest, SHA512};
+
 use crate::key::random::generate_secure_random_timed;
 use crate::util::base::base64str_to_u8s;
 use crate::util::base::u8s_to_base64str;
 use crate::util::errors::add_err;
-use crate::util::FedResult;
+use crate::util::{FedError, FedResult};
 
 const SALT_LEN: usize = 64; // multiple of 32
 
@@ -69,6 +71,14 @@ impl Salt {
         Salt { salt: repeated }
     }
 
+    /// A fixed salt derived from any seed, for deterministic encryption. The same seed always
+    /// gives the same salt. Used as pepper, the seed should be as secret as the password.
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(digest(&SHA512, seed).as_ref());
+        Salt { salt }
+    }
+
     pub fn parse_base64(base64: &str, verbose: bool) -> FedResult<Self> {
         match base64str_to_u8s(base64) {
             Ok(salt_vec) => {
@@ -77,7 +87,7 @@ impl Salt {
                     salt.clone_from_slice(&salt_vec);
                     Ok(Salt { salt })
                 } else {
-                    Err(if verbose {
+                    Err(FedError::Corrupted(if verbose {
                         format!(
                             "could not determine the salt used by fileenc that encrypted this \
                         file; got {} which is invalid because it has the wrong length",
@@ -86,10 +96,10 @@ impl Salt {
                     } else {
                         "could not determine the salt used by fileenc to encrypt this file"
                             .to_owned()
-                    })
+                    }))
                 }
             }
-            Err(err) => Err(add_err(
+            Err(err) => Err(FedError::Corrupted(add_err(
                 format!(
                     "could not determine the salt used \
             by fileenc that encrypted this file; got {} which is invalid",
@@ -97,7 +107,7 @@ impl Salt {
                 ),
                 verbose,
                 err,
-            )),
+            ))),
         }
     }
 
@@ -117,6 +127,12 @@ mod tests {
         assert_eq!("salt[199...15]", &debug);
     }
 
+    #[test]
+    fn from_seed_is_fixed() {
+        assert_eq!(Salt::from_seed(b"seed"), Salt::from_seed(b"seed"));
+        assert_ne!(Salt::from_seed(b"seed"), Salt::from_seed(b"other seed"));
+    }
+
     #[test]
     #[cfg_attr(not(feature = "test-e2e"), ignore)]
     fn generate_salt_entropy() {
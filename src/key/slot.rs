@@ -0,0 +1,139 @@
+use ::std::fmt;
+use ::std::str::from_utf8;
+
+use ::aes_gcm::aead::{Aead, KeyInit};
+use ::aes_gcm::{Aes256Gcm, Nonce};
+
+use crate::header::{KeyHashAlg, Strategy};
+use crate::key::cost::KdfCost;
+use crate::key::stretch::stretch_key;
+use crate::key::{Key, Salt};
+use crate::util::base::{base64str_to_u8s, u8s_to_base64str};
+use crate::util::{FedError, FedResult};
+
+/// The data key, encrypted with a password. Every slot has its own salt, so the password
+/// is stretched separately, and the fixed nonce is never used twice with the same key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySlot {
+    salt: Salt,
+    sealed: Vec<u8>,
+}
+
+fn slot_cipher(
+    password: &Key,
+    salt: &Salt,
+    strategy: &Strategy,
+    cost: &KdfCost,
+    start_progress: &mut impl FnMut(&KeyHashAlg),
+) -> FedResult<Aes256Gcm> {
+    let slot_key = stretch_key(
+        password,
+        salt,
+        cost,
+        &strategy.key_hash_algorithms,
+        start_progress,
+    )?;
+    Ok(Aes256Gcm::new_from_slice(slot_key.unsecure_slice(32)).unwrap())
+}
+
+impl KeySlot {
+    pub fn seal(
+        data_key: &Key,
+        password: &Key,
+        strategy: &Strategy,
+        cost: &KdfCost,
+        start_progress: &mut impl FnMut(&KeyHashAlg),
+    ) -> FedResult<Self> {
+        let salt = Salt::generate_random()?;
+        let sealed = slot_cipher(password, &salt, strategy, cost, start_progress)?
+            .encrypt(
+                Nonce::from_slice(&[0; 12]),
+                data_key.key_data.unsecure().as_bytes(),
+            )
+            .expect("failed to encrypt the key slot");
+        Ok(KeySlot { salt, sealed })
+    }
+
+    /// The data key, if the password is the one for this slot.
+    pub fn open(
+        &self,
+        password: &Key,
+        strategy: &Strategy,
+        cost: &KdfCost,
+        start_progress: &mut impl FnMut(&KeyHashAlg),
+    ) -> FedResult<Option<Key>> {
+        let key_data = match slot_cipher(password, &self.salt, strategy, cost, start_progress)?
+            .decrypt(Nonce::from_slice(&[0; 12]), self.sealed.as_slice())
+        {
+            Ok(key_data) => key_data,
+            Err(_) => return Ok(None),
+        };
+        Ok(from_utf8(&key_data).ok().map(Key::new))
+    }
+
+    pub fn parse(text: &str) -> FedResult<Self> {
+        let err = || FedError::Corrupted(format!("key slot '{}' is not valid", text));
+        let (salt, sealed) = text.split_once(':').ok_or_else(err)?;
+        Ok(KeySlot {
+            salt: Salt::parse_base64(salt, false).map_err(|_| err())?,
+            sealed: base64str_to_u8s(sealed).map_err(|_| err())?,
+        })
+    }
+}
+
+impl fmt::Display for KeySlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.salt.as_base64(),
+            u8s_to_base64str(&self.sealed)
+        )
+    }
+}
+
+/// Find the slot that the password opens, returning its position and the data key.
+pub fn open_key_slots(
+    slots: &[KeySlot],
+    password: &Key,
+    strategy: &Strategy,
+    cost: &KdfCost,
+    start_progress: &mut impl FnMut(&KeyHashAlg),
+) -> FedResult<(usize, Key)> {
+    for (index, slot) in slots.iter().enumerate() {
+        if let Some(data_key) = slot.open(password, strategy, cost, start_progress)? {
+            return Ok((index, data_key));
+        }
+    }
+    Err(FedError::WrongKey(
+        "the key does not match any of the key slots of this file".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header::strategy::get_current_version_strategy;
+    use crate::util::option::EncOption;
+
+    use super::*;
+
+    #[test]
+    fn open_matching_slot() {
+        let strategy = &get_current_version_strategy(&vec![EncOption::Fast].into(), false);
+        let cost = &strategy.kdf_cost;
+        let data_key = Key::new("random data key");
+        let slots = [
+            KeySlot::seal(&data_key, &Key::new("first"), strategy, cost, &mut |_| {}).unwrap(),
+            KeySlot::seal(&data_key, &Key::new("second"), strategy, cost, &mut |_| {}).unwrap(),
+        ];
+        let slots = slots
+            .iter()
+            .map(|slot| KeySlot::parse(&slot.to_string()).unwrap())
+            .collect::<Vec<_>>();
+        let (index, key) =
+            open_key_slots(&slots, &Key::new("second"), strategy, cost, &mut |_| {}).unwrap();
+        assert_eq!(1, index);
+        assert_eq!(data_key, key);
+        assert!(open_key_slots(&slots, &Key::new("third"), strategy, cost, &mut |_| {}).is_err());
+    }
+}
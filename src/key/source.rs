@@ -4,18 +4,30 @@ use ::std::io::{stdin, BufRead};
 use ::std::path::Path;
 use ::std::path::PathBuf;
 use ::std::str::FromStr;
+use ::std::sync::Mutex;
 
+use ::ring::digest::{digest, SHA512};
+
+use crate::key::recipient::Identity;
 use crate::key::Key;
-use crate::util::FedResult;
+use crate::util::base::u8s_to_base64str;
+use crate::util::errors::wrap_io;
+use crate::util::{FedError, FedResult};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeySource {
     CliArg(Key),
     EnvVar(String),
     File(PathBuf),
+    /// Any file, including binary ones, of which the content is hashed into a key.
+    KeyFile(PathBuf),
+    /// Private key file, for files that were encrypted for recipients.
+    Identity(PathBuf),
     AskTwice,
     AskOnce,
     Pipe,
+    /// Asked for only if the agent does not have the stretched keys that are needed.
+    Agent,
 }
 
 impl FromStr for KeySource {
@@ -31,6 +43,12 @@ impl FromStr for KeySource {
         if let Some(stripped) = txt.strip_prefix("file:") {
             return Ok(KeySource::File(PathBuf::from(stripped.to_owned())));
         }
+        if let Some(stripped) = txt.strip_prefix("keyfile:") {
+            return Ok(KeySource::KeyFile(PathBuf::from(stripped.to_owned())));
+        }
+        if let Some(stripped) = txt.strip_prefix("identity:") {
+            return Ok(KeySource::Identity(PathBuf::from(stripped.to_owned())));
+        }
         if txt == "ask-once" || txt == "askonce" {
             return Ok(KeySource::AskOnce);
         }
@@ -40,6 +58,9 @@ impl FromStr for KeySource {
         if txt == "pipe" {
             return Ok(KeySource::Pipe);
         }
+        if txt == "agent" {
+            return Ok(KeySource::Agent);
+        }
         let txt_snip = if txt.len() > 5 {
             format!("{}...", txt[..4].to_owned())
         } else {
@@ -47,7 +68,7 @@ impl FromStr for KeySource {
         };
         Err(format!(
             "key string was not recognized; got '{}', should be one of \
-            'pass:$password', 'env:$var_name', 'file:$path', 'ask', 'ask-once', 'pipe'",
+            'pass:$password', 'env:$var_name', 'file:$path', 'keyfile:$path', 'identity:$path', 'ask', 'ask-once', 'pipe', 'agent'",
             txt_snip
         ))
     }
@@ -57,43 +78,87 @@ fn key_from_env_var(env_var_name: &str) -> FedResult<Key> {
     match env::var(env_var_name) {
         Ok(env_var_value) => Ok(Key::new(env_var_value.trim())),
         Err(err) => match err {
-            env::VarError::NotPresent => Err(format!(
+            env::VarError::NotPresent => Err(FedError::Usage(format!(
                 "could not find environment variable named '{}' (which is \
                             expected to contain the encryption key)",
                 env_var_name
-            )),
-            env::VarError::NotUnicode(_) => Err(format!(
+            ))),
+            env::VarError::NotUnicode(_) => Err(FedError::Usage(format!(
                 "environment variable named '{}' did not contain valid data (it \
                             is expected to contain the encryption key, which must be unicode)",
                 env_var_name
-            )),
+            ))),
         },
     }
 }
 
 fn key_from_file(file_path: &Path) -> FedResult<Key> {
-    match fs::read_to_string(file_path) {
-        Ok(content) => Ok(Key::new(content.trim())),
-        Err(io_err) => Err(format!(
-            "failed to read encryption key from \
-                        file '{}'; reason: {}",
-            file_path.to_string_lossy(),
-            io_err
-        )),
+    let content = wrap_io(
+        || {
+            format!(
+                "failed to read encryption key from file '{}'",
+                file_path.to_string_lossy()
+            )
+        },
+        fs::read_to_string(file_path),
+    )?;
+    Ok(Key::new(content.trim()))
+}
+
+fn key_from_keyfile(file_path: &Path) -> FedResult<Key> {
+    let content = wrap_io(
+        || format!("failed to read key file '{}'", file_path.to_string_lossy()),
+        fs::read(file_path),
+    )?;
+    if content.is_empty() {
+        return Err(FedError::Usage(format!(
+            "key file '{}' is empty",
+            file_path.to_string_lossy()
+        )));
     }
+    Ok(Key::new(&u8s_to_base64str(
+        digest(&SHA512, &content).as_ref(),
+    )))
+}
+
+fn key_from_identity_file(file_path: &Path) -> FedResult<Key> {
+    let content = wrap_io(
+        || {
+            format!(
+                "failed to read identity from file '{}'",
+                file_path.to_string_lossy()
+            )
+        },
+        fs::read_to_string(file_path),
+    )?;
+    Identity::parse(&content).map_err(|err| {
+        err.map_message(|msg| {
+            format!(
+                "file '{}' is not a valid identity; {}",
+                file_path.to_string_lossy(),
+                msg
+            )
+        })
+    })?;
+    Ok(Key::new(content.trim()))
 }
 
 fn ask_key_from_prompt(message: &str) -> FedResult<Key> {
-    println!("{}", message);
+    // Not on stdout, which may be the output data.
+    eprintln!("{}", message);
     match rpassword::read_password() {
         Ok(pw) => {
             let pw = pw.trim();
             if pw.is_empty() {
-                return Err("password from interactive console was empty".to_string());
+                return Err(FedError::Usage(
+                    "password from interactive console was empty".to_string(),
+                ));
             }
             Ok(Key::new(pw))
         }
-        Err(_) => Err("failed to get password from interactive console".to_string()),
+        Err(_) => Err(FedError::Io(
+            "failed to get password from interactive console".to_string(),
+        )),
     }
 }
 
@@ -102,7 +167,7 @@ fn key_from_prompt(ask_twice: bool) -> FedResult<Key> {
         let pw1 = ask_key_from_prompt("key: ")?;
         let pw2 = ask_key_from_prompt("repeat key: ")?;
         if pw1 != pw2 {
-            return Err("passwords did not match".to_owned());
+            return Err(FedError::Usage("passwords did not match".to_owned()));
         }
         Ok(pw2)
     } else {
@@ -117,10 +182,14 @@ fn key_from_pipe() -> FedResult<Key> {
             if count >= 1 {
                 Ok(Key::new(pw.trim()))
             } else {
-                Err("no key was piped into the program".to_owned())
+                Err(FedError::Usage(
+                    "no key was piped into the program".to_owned(),
+                ))
             }
         }
-        Err(_) => Err("failed to read data piped into the program".to_owned()),
+        Err(_) => Err(FedError::Io(
+            "failed to read data piped into the program".to_owned(),
+        )),
     }
 }
 
@@ -129,17 +198,74 @@ impl KeySource {
     pub fn obtain_key(&self) -> FedResult<Key> {
         match self {
             KeySource::CliArg(pw) => Ok(pw.to_owned()),
-            KeySource::EnvVar(env_var_name) => key_from_env_var(&env_var_name),
-            KeySource::File(file_path) => key_from_file(&file_path),
+            KeySource::EnvVar(env_var_name) => key_from_env_var(env_var_name),
+            KeySource::File(file_path) => key_from_file(file_path),
+            KeySource::KeyFile(file_path) => key_from_keyfile(file_path),
+            KeySource::Identity(file_path) => key_from_identity_file(file_path),
             KeySource::AskOnce => key_from_prompt(false),
             KeySource::AskTwice => key_from_prompt(true),
             KeySource::Pipe => key_from_pipe(),
+            // Asked twice, because the agent remembers keys stretched from a typo too.
+            KeySource::Agent => key_from_prompt(true),
+        }
+    }
+
+    /// Obtain the key, with the second key (like a key file) mixed in if there is one.
+    pub fn obtain_combined_key(&self, second: Option<&KeySource>) -> FedResult<Key> {
+        let second = match second {
+            Some(second) => second,
+            None => return self.obtain_key(),
+        };
+        if matches!(self, KeySource::Identity(_)) || matches!(second, KeySource::Identity(_)) {
+            return Err(FedError::Usage(
+                "an identity cannot be combined with another key".to_owned(),
+            ));
+        }
+        Ok(self.obtain_key()?.combine(&second.obtain_key()?))
+    }
+}
+
+/// A key that is only obtained once it is needed, which for decryption with the agent
+/// might be never. It is obtained at most once, even if it is needed by several threads.
+#[derive(Debug)]
+pub struct DeferredKey {
+    source: KeySource,
+    second: Option<KeySource>,
+    key: Mutex<Option<Key>>,
+}
+
+impl DeferredKey {
+    pub fn new(source: KeySource, second: Option<KeySource>) -> Self {
+        DeferredKey {
+            source,
+            second,
+            key: Mutex::new(None),
+        }
+    }
+
+    pub fn obtain(&self) -> FedResult<Key> {
+        let mut key = self.key.lock().unwrap();
+        if key.is_none() {
+            *key = Some(self.source.obtain_combined_key(self.second.as_ref())?);
+        }
+        Ok(key.as_ref().unwrap().clone())
+    }
+}
+
+impl From<Key> for DeferredKey {
+    fn from(key: Key) -> Self {
+        DeferredKey {
+            source: KeySource::CliArg(key.clone()),
+            second: None,
+            key: Mutex::new(Some(key)),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ::tempfile::tempdir;
+
     use super::*;
 
     #[test]
@@ -165,6 +291,56 @@ mod tests {
             KeySource::File(PathBuf::from("/my/pass.txt")),
         );
     }
+
+    #[test]
+    fn valid_keyfile() {
+        assert_eq!(
+            KeySource::from_str("keyfile:/media/usb/key.bin").unwrap(),
+            KeySource::KeyFile(PathBuf::from("/media/usb/key.bin")),
+        );
+    }
+
+    #[test]
+    fn binary_keyfile_with_password() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("key.bin");
+        fs::write(&pth, [0xff, 0, 0xfe, 10, 0]).unwrap();
+        let keyfile = KeySource::KeyFile(pth.clone());
+        let key = keyfile.obtain_key().unwrap();
+        assert_eq!(key, keyfile.obtain_key().unwrap());
+        assert!(key.is_strong());
+        let password = KeySource::CliArg(Key::new("secret"));
+        let combined = password.obtain_combined_key(Some(&keyfile)).unwrap();
+        assert_ne!(key, combined);
+        assert_ne!(Key::new("secret"), combined);
+        assert_eq!(combined, Key::new("secret").combine(&key));
+        fs::write(&pth, [0xff, 0, 0xfe, 10, 1]).unwrap();
+        assert_ne!(
+            combined,
+            password.obtain_combined_key(Some(&keyfile)).unwrap()
+        );
+        fs::write(&pth, []).unwrap();
+        assert!(keyfile.obtain_key().is_err());
+        let identity = KeySource::Identity(pth);
+        assert!(password.obtain_combined_key(Some(&identity)).is_err());
+    }
+
+    #[test]
+    fn combine_is_unambiguous() {
+        assert_ne!(
+            Key::new("ab").combine(&Key::new("c")),
+            Key::new("a").combine(&Key::new("bc"))
+        );
+    }
+
+    #[test]
+    fn valid_identity() {
+        assert_eq!(
+            KeySource::from_str("identity:/my/key.fedsec").unwrap(),
+            KeySource::Identity(PathBuf::from("/my/key.fedsec")),
+        );
+    }
+
     #[test]
     fn valid_ask_twice() {
         assert_eq!(KeySource::from_str("ask").unwrap(), KeySource::AskTwice,);
@@ -178,6 +354,23 @@ mod tests {
         assert_eq!(KeySource::from_str("pipe").unwrap(), KeySource::Pipe,);
     }
 
+    #[test]
+    fn valid_agent() {
+        assert_eq!(KeySource::from_str("agent").unwrap(), KeySource::Agent,);
+    }
+
+    #[test]
+    fn deferred_key_obtained_once() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("key.txt");
+        let deferred = DeferredKey::new(KeySource::File(pth.clone()), None);
+        assert!(deferred.obtain().is_err());
+        fs::write(&pth, "secret").unwrap();
+        assert_eq!(Key::new("secret"), deferred.obtain().unwrap());
+        fs::remove_file(&pth).unwrap();
+        assert_eq!(Key::new("secret"), deferred.obtain().unwrap());
+    }
+
     #[test]
     fn invalid_key() {
         assert!(KeySource::from_str("hi").is_err());
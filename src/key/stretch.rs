@@ -1,34 +1,39 @@
 use crate::header::KeyHashAlg;
+use crate::key::cost::KdfCost;
 use crate::key::hash::hash;
 use crate::key::key::StretchKey;
 use crate::key::Key;
 use crate::key::Salt;
+use crate::util::FedResult;
 
 pub fn stretch_key(
     raw_key: &Key,
     salt: &Salt,
-    stretch_count: u64,
+    cost: &KdfCost,
     key_hash_algorithms: &[KeyHashAlg],
     start_progress: &mut impl FnMut(&KeyHashAlg),
-) -> StretchKey {
+) -> FedResult<StretchKey> {
     assert!(!key_hash_algorithms.is_empty());
     let salt_bytes = salt.salt;
     let mut data = raw_key.key_data.clone().unsecure().as_bytes().to_owned();
     for key_hash_alg in key_hash_algorithms {
-        start_progress(&key_hash_alg);
-        data = hash(&data, &salt_bytes, key_hash_alg);
-        for i in 0..stretch_count {
+        start_progress(key_hash_alg);
+        data = hash(&data, &salt_bytes, key_hash_alg, cost)?;
+        for i in 0..cost.rounds {
             data.extend(&i.to_le_bytes());
-            data = hash(&data, &salt_bytes, key_hash_alg);
+            data = hash(&data, &salt_bytes, key_hash_alg, cost)?;
         }
     }
-    StretchKey::new(&data)
+    Ok(StretchKey::new(&data))
 }
 
 #[cfg(test)]
 mod tests {
     #[cfg(not(debug_assertions))]
-    use crate::header::strategy::get_current_version_strategy;
+    use ::semver::Version;
+
+    #[cfg(not(debug_assertions))]
+    use crate::header::strategy::get_version_strategy;
 
     #[cfg(not(debug_assertions))]
     use crate::util::option::EncOptionSet;
@@ -39,14 +44,16 @@ mod tests {
     #[cfg(not(debug_assertions))]
     #[test]
     fn stratch_test_password_v1_0() {
-        let strat = get_current_version_strategy(&EncOptionSet::empty(), true);
+        let version = Version::parse("1.0.0").unwrap();
+        let strat = get_version_strategy(&version, &EncOptionSet::empty(), true).unwrap();
         let stretched = stretch_key(
             &Key::new(&"MY secret p@ssw0rd"),
             &Salt::fixed_for_test(123_456_789),
-            strat.stretch_count,
+            &strat.kdf_cost,
             &strat.key_hash_algorithms,
             &mut |_| (),
-        );
+        )
+        .unwrap();
         assert_eq!(
             stretched.unsecure_slice(16),
             StretchKey::new(&[
@@ -59,14 +66,16 @@ mod tests {
     #[cfg(not(debug_assertions))]
     #[test]
     fn stratch_test_password_v1_1_fast() {
-        let strat = get_current_version_strategy(&EncOptionSet::all_for_test(), true);
+        let version = Version::parse("1.1.0").unwrap();
+        let strat = get_version_strategy(&version, &EncOptionSet::all_for_test(), true).unwrap();
         let stretched = stretch_key(
             &Key::new(&"MY secret p@ssw0rd"),
             &Salt::fixed_for_test(123_456_789),
-            strat.stretch_count,
+            &strat.kdf_cost,
             &strat.key_hash_algorithms,
             &mut |_| (),
-        );
+        )
+        .unwrap();
         assert_eq!(
             stretched.unsecure_slice(16),
             StretchKey::new(&[
@@ -76,6 +85,28 @@ mod tests {
         );
     }
 
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn stratch_test_password_v1_6() {
+        let version = Version::parse("1.6.0").unwrap();
+        let strat = get_version_strategy(&version, &EncOptionSet::empty(), true).unwrap();
+        let stretched = stretch_key(
+            &Key::new(&"MY secret p@ssw0rd"),
+            &Salt::fixed_for_test(123_456_789),
+            &strat.kdf_cost,
+            &strat.key_hash_algorithms,
+            &mut |_| (),
+        )
+        .unwrap();
+        assert_eq!(
+            stretched.unsecure_slice(16),
+            StretchKey::new(&[
+                20, 183, 160, 47, 186, 4, 185, 253, 114, 68, 243, 138, 124, 234, 203, 54
+            ])
+            .unsecure_slice(16)
+        );
+    }
+
     #[cfg(debug_assertions)]
     #[test]
     #[ignore]
@@ -1,10 +1,23 @@
+pub use crate::config::enc::RunMode;
+pub use crate::config::typ::ArchiveAction;
+pub use crate::config::typ::FileSelection;
+pub use crate::config::typ::InputAction;
+pub use crate::config::typ::MetaAction;
+pub use crate::config::typ::OnFileExist;
 pub use crate::config::DecryptConfig;
+pub use crate::config::DecryptConfigBuilder;
 pub use crate::config::EncryptConfig;
+pub use crate::config::EncryptConfigBuilder;
 pub use crate::config::EndecConfig;
+pub use crate::files::file_meta::STREAM_PATH;
 #[cfg(feature = "expose")]
 pub use crate::files::mockfile::generate_test_file_content_for_test;
 pub use crate::header::strategy::get_current_version_strategy;
 pub use crate::header::strategy::Verbosity;
+pub use crate::key::agent::default_agent_socket;
+pub use crate::key::agent::run_agent;
+pub use crate::key::agent::AgentClient;
+pub use crate::key::cost::KdfCost;
 #[cfg(feature = "expose")]
 pub use crate::key::hash::hash_argon2i;
 #[cfg(feature = "expose")]
@@ -13,14 +26,35 @@ pub use crate::key::hash::hash_bcrypt;
 pub use crate::key::hash::hash_sha256;
 #[cfg(feature = "expose")]
 pub use crate::key::key::StretchKey;
+pub use crate::key::recipient::write_identity_file;
+pub use crate::key::recipient::Identity;
+pub use crate::key::recipient::Recipient;
 #[cfg(feature = "expose")]
 pub use crate::key::stretch::stretch_key;
+pub use crate::key::DeferredKey;
 pub use crate::key::Key;
 pub use crate::key::KeySource;
-#[cfg(feature = "expose")]
 pub use crate::key::Salt;
 pub use crate::orchestrate::decrypt::decrypt;
+pub use crate::orchestrate::decrypt::decrypt_bytes;
+pub use crate::orchestrate::decrypt::decrypt_report;
+pub use crate::orchestrate::decrypt::decrypt_stream;
+pub use crate::orchestrate::decrypt::DecryptingReader;
 pub use crate::orchestrate::encrypt::encrypt;
+pub use crate::orchestrate::encrypt::encrypt_bytes;
+pub use crate::orchestrate::encrypt::encrypt_report;
+pub use crate::orchestrate::encrypt::encrypt_stream;
+pub use crate::orchestrate::encrypt::EncryptingWriter;
+pub use crate::orchestrate::info::inspect_headers;
+pub use crate::orchestrate::info::FileDetails;
+pub use crate::orchestrate::rekey::rekey;
+pub use crate::orchestrate::report::checksum_error;
+pub use crate::orchestrate::report::failure_error;
+pub use crate::orchestrate::report::reports_to_json;
+pub use crate::orchestrate::report::ChecksumStatus;
+pub use crate::orchestrate::report::FileReport;
+pub use crate::orchestrate::slots::add_key;
+pub use crate::orchestrate::slots::remove_key;
 #[cfg(feature = "expose")]
 pub use crate::symmetric::decrypt::decrypt_aes256;
 #[cfg(feature = "expose")]
@@ -29,12 +63,12 @@ pub use crate::symmetric::decrypt::decrypt_twofish;
 pub use crate::symmetric::encrypt::encrypt_aes256;
 #[cfg(feature = "expose")]
 pub use crate::symmetric::encrypt::encrypt_twofish;
+pub use crate::util::jobs::resolve_job_count;
 pub use crate::util::option::EncOption;
 pub use crate::util::option::EncOptionSet;
+pub use crate::util::FedError;
 pub use crate::util::FedResult;
 
-//TODO @mark: match set_file_times(path, FileTime::zero(), FileTime::zero()) {
-
 mod config;
 mod e2e;
 mod files;
@@ -48,9 +82,12 @@ mod util;
 #[cfg(test)]
 mod tests {
     use ::aes::Aes256;
+    use ::cbc::cipher::block_padding::Iso7816;
+    use ::cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
     use ::secstr::SecVec;
 
-    type Aes256Cbc = Cbc<Aes256, Iso7816>;
+    type Aes256CbcEnc = ::cbc::Encryptor<Aes256>;
+    type Aes256CbcDec = ::cbc::Decryptor<Aes256>;
 
     /// The demo used in this blog post:
     /// https://markv.nl/blog/symmetric-encryption-in-rust
@@ -75,8 +112,8 @@ mod tests {
         // Fails if the key or iv are the wrong length, so it is safe to unwrap
         // as we have the correct lengths. Key length depends on algorithm, iv length
         // depends on the block size. If it's not documented, experiment with 16 or 32.
-        let cipher = Aes256Cbc::new_var(key.unsecure(), &iv).unwrap();
-        let ciphertext = cipher.encrypt_vec(&plaintext);
+        let cipher = Aes256CbcEnc::new_from_slices(key.unsecure(), &iv).unwrap();
+        let ciphertext = cipher.encrypt_padded_vec_mut::<Iso7816>(&plaintext);
 
         // Check that it worked.
         assert_eq!(
@@ -89,8 +126,10 @@ mod tests {
         );
 
         // Decryption.
-        let cipher = Aes256Cbc::new_var(key.unsecure(), &iv).unwrap();
-        let decrypted_ciphertext = cipher.decrypt_vec(&ciphertext).unwrap();
+        let cipher = Aes256CbcDec::new_from_slices(key.unsecure(), &iv).unwrap();
+        let decrypted_ciphertext = cipher
+            .decrypt_padded_vec_mut::<Iso7816>(&ciphertext)
+            .unwrap();
 
         // Check that we got the original input back.
         assert_eq!(decrypted_ciphertext, plaintext);
@@ -1,28 +1,50 @@
 use ::std::collections::HashMap;
-use ::std::io::Seek;
+use ::std::fs;
+use ::std::io;
 use ::std::io::SeekFrom;
-use ::std::path::PathBuf;
+use ::std::io::{BufReader, Read, Seek, Write};
+use ::std::path::{Path, PathBuf};
 
-use crate::config::typ::{EndecConfig, Extension};
+use ::lazy_static::lazy_static;
+
+use crate::config::typ::{ArchiveAction, EndecConfig, Extension};
 use crate::config::DecryptConfig;
-use crate::files::checksum::calculate_checksum;
-use crate::files::compress::decompress_file;
+use crate::files::archive::member_output_path;
+use crate::files::checksum::{calculate_checksum, ChecksumCalculator, ChecksumReader};
+use crate::files::compress::DecompressReader;
 use crate::files::delete::delete_input_file;
-use crate::files::file_meta::inspect_files;
-use crate::files::read_headers::read_file_strategies;
-use crate::files::reading::{open_reader, read_file};
-use crate::files::write_output::write_output_file;
+use crate::files::file_meta::{inspect_files, FileInfo, STREAM_PATH};
+use crate::files::padding::verify_padding;
+use crate::files::read_headers::{read_file_strategies, FileHeaderStrategy};
+use crate::files::reading::{open_reader, TrailerReader, CHUNK_SIZE};
+use crate::files::scan::{collect_input_files, has_enc_extension};
+use crate::files::write_output::{open_output_file, restore_file_meta};
 use crate::files::Checksum;
+use crate::header::archive::parse_archive_index;
 use crate::header::private_decode::parse_private_header;
+use crate::header::private_header_type::PrivateHeader;
+use crate::header::{
+    parse_public_header, parse_public_trailer, DataLayout, SymmetricEncryptionAlg, PUB_TRAILER_LEN,
+};
+use crate::key::agent::{cached_stretched_key, stretch_key_with_agent};
 use crate::key::key::StretchKey;
-use crate::key::stretch::stretch_key;
-use crate::key::Salt;
+use crate::key::recipient::{unwrap_key, Identity};
+use crate::key::slot::open_key_slots;
+use crate::key::{Key, Salt};
+use crate::orchestrate::encrypt::MAX_PRIVATE_HEADER_LEN;
+use crate::orchestrate::report::{checksum_error, failure_error, ChecksumStatus, FileReport};
 use crate::progress::indicatif::IndicatifProgress;
 use crate::progress::log::LogProgress;
+use crate::progress::shared::SharedProgress;
 use crate::progress::silent::SilentProgress;
 use crate::progress::Progress;
-use crate::symmetric::decrypt::decrypt_file;
-use crate::util::version::version_has_options_meta;
+use crate::symmetric::decrypt::{decrypt_file, DecryptReader};
+use crate::util::errors::wrap_io;
+use crate::util::jobs::run_parallel;
+use crate::util::option::EncOption;
+use crate::util::pth::determine_restored_output_path;
+use crate::util::version::version_has_private_header;
+use crate::util::FedError;
 use crate::{FedResult, Verbosity};
 
 pub fn validate_checksum_matches(
@@ -52,19 +74,713 @@ pub fn validate_checksum_matches(
     false
 }
 
-/// Decrypt one or more files and return the new paths.
+/// Read the checksum from the end of the file, and return it with the length of the data.
+pub fn read_trailer(
+    reader: &mut (impl Read + Seek),
+    file_strat: &FileHeaderStrategy,
+    data_start: u64,
+) -> FedResult<(u64, Checksum)> {
+    let data_len = file_strat
+        .file
+        .size_b
+        .checked_sub(data_start + PUB_TRAILER_LEN as u64)
+        .ok_or_else(|| {
+            FedError::Corrupted(format!(
+                "encrypted file '{}' is too short; has it been truncated?",
+                &file_strat.file.path_str()
+            ))
+        })?;
+    let mut trailer = vec![0; PUB_TRAILER_LEN];
+    wrap_io(
+        || "could not read input file",
+        reader
+            .seek(SeekFrom::End(-(PUB_TRAILER_LEN as i64)))
+            .and_then(|_| reader.read_exact(&mut trailer)),
+    )?;
+    Ok((data_len, parse_public_trailer(&trailer)?))
+}
+
+/// Decrypt the private header, which comes right after the public header, and check it against
+/// the length and checksum in the public header. Returns `None` for versions without private header.
+pub fn read_private_header(
+    reader: &mut (impl Read + Seek),
+    file_strat: &FileHeaderStrategy,
+    key: &StretchKey,
+    start_progress: &mut impl FnMut(),
+) -> FedResult<Option<(u64, PrivateHeader)>> {
+    start_progress();
+    match read_private_header_data(reader, file_strat, key)? {
+        Some((priv_header_len, data)) => {
+            let (_, priv_header) = parse_private_header(&mut data.as_slice())?;
+            Ok(Some((priv_header_len, priv_header)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The length of the private header is not authenticated until it has been read, so check it
+/// before allocating anything for it.
+fn check_private_header_len(priv_header_len: u64, name: &str) -> FedResult<()> {
+    if priv_header_len > MAX_PRIVATE_HEADER_LEN {
+        return Err(FedError::Corrupted(format!(
+            "the private header of {} claims to be {} bytes, which is more than the maximum of {}; has it been corrupted?",
+            name, priv_header_len, MAX_PRIVATE_HEADER_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Like `read_private_header`, but returns the decrypted header without parsing it.
+pub fn read_private_header_data(
+    reader: &mut (impl Read + Seek),
+    file_strat: &FileHeaderStrategy,
+    key: &StretchKey,
+) -> FedResult<Option<(u64, Vec<u8>)>> {
+    if !version_has_private_header(file_strat.pub_header.version()) {
+        return Ok(None);
+    }
+    let (priv_header_len, expected_checksum) = match file_strat.pub_header.private_header() {
+        Some(priv_header) => priv_header,
+        None => {
+            return Err(FedError::Corrupted(format!(
+                "could not find the private header for '{}'; has the file been corrupted?",
+                &file_strat.file.path_str()
+            )))
+        }
+    };
+    check_private_header_len(
+        *priv_header_len,
+        &format!("'{}'", file_strat.file.path_str()),
+    )?;
+    if file_strat.pub_header_len as u64 + priv_header_len > file_strat.file.size_b {
+        return Err(FedError::Corrupted(format!(
+            "encrypted file '{}' is too short; has it been truncated?",
+            &file_strat.file.path_str()
+        )));
+    }
+    let mut secret = vec![0; *priv_header_len as usize];
+    wrap_io(
+        || "could not read input file",
+        reader
+            .seek(SeekFrom::Start(file_strat.pub_header_len as u64))
+            .and_then(|_| reader.read_exact(&mut secret)),
+    )?;
+    let data = decrypt_private_header_data(secret, expected_checksum, file_strat, key)?;
+    Ok(Some((*priv_header_len, data)))
+}
+
+/// Decrypt the private header data, and check it against the checksum from the public header.
+fn decrypt_private_header_data(
+    secret: Vec<u8>,
+    expected_checksum: &Checksum,
+    file_strat: &FileHeaderStrategy,
+    key: &StretchKey,
+) -> FedResult<Vec<u8>> {
+    let salt = file_strat.pub_header.salt();
+    let decrypted = if file_strat
+        .pub_header
+        .options()
+        .has(EncOption::Deterministic)
+    {
+        let mut data = Vec::with_capacity(secret.len());
+        open_decrypt_reader(secret.as_slice(), key, salt, file_strat, &mut |_| {})
+            .read_to_end(&mut data)
+            .map(|_| data)
+            .map_err(|_| ())
+    } else {
+        let algs = &file_strat.strategy.symmetric_algorithms;
+        decrypt_file(secret, 0, key, salt, algs, &mut |_| {}).map_err(|_| ())
+    };
+    let data = decrypted.map_err(|_| {
+        FedError::WrongKey(format!(
+            "could not decrypt the private header of '{}'; perhaps the key is incorrect, \
+            or the file has been corrupted",
+            &file_strat.file.path_str()
+        ))
+    })?;
+    if &calculate_checksum(&data, &mut || {}) != expected_checksum {
+        return Err(FedError::WrongKey(format!(
+            "the private header checksum for '{}' did not match; perhaps the key is incorrect, \
+            or the file has been corrupted",
+            &file_strat.file.path_str()
+        )));
+    }
+    Ok(data)
+}
+
+/// Decrypt while reading, expecting a synthetic IV if the file was encrypted in deterministic mode.
+pub fn open_decrypt_reader<R: Read>(
+    reader: R,
+    key: &StretchKey,
+    salt: &Salt,
+    file_strat: &FileHeaderStrategy,
+    start_progress: &mut impl FnMut(&SymmetricEncryptionAlg),
+) -> DecryptReader<R> {
+    let decrypter = DecryptReader::new(
+        reader,
+        key,
+        salt,
+        &file_strat.strategy.symmetric_algorithms,
+        start_progress,
+    );
+    if file_strat
+        .pub_header
+        .options()
+        .has(EncOption::Deterministic)
+    {
+        return decrypter.with_synthetic_iv();
+    }
+    decrypter
+}
+
+/// Decrypt and decompress the data while it is being read. Any padding is verified and skipped.
+fn open_data_reader<R: Read>(
+    reader: R,
+    key: &StretchKey,
+    salt: &Salt,
+    file_strat: &FileHeaderStrategy,
+    priv_header: Option<&PrivateHeader>,
+    progress: &mut dyn Progress,
+) -> FedResult<DecompressReader<DecryptReader<R>>> {
+    let mut decrypter = open_decrypt_reader(reader, key, salt, file_strat, &mut |alg| {
+        progress.start_sym_alg_for_file(alg, file_strat.file)
+    });
+    if let Some(hdr) = priv_header {
+        verify_padding(&mut decrypter, hdr.pepper(), hdr.data_padding_len())?;
+    }
+    DecompressReader::new(
+        decrypter,
+        &file_strat.strategy.compression_algorithm,
+        &mut |alg| progress.start_compress_alg_for_file(alg, file_strat.file),
+    )
+}
+
+/// Stream the data through decryption and decompression into `output`, in chunks, so that
+/// the whole file never has to be in memory. Returns the checksum and length of the output.
+fn decrypt_data(
+    reader: impl Read,
+    output: &mut impl Write,
+    key: &StretchKey,
+    salt: &Salt,
+    file_strat: &FileHeaderStrategy,
+    priv_header: Option<&PrivateHeader>,
+    progress: &mut dyn Progress,
+) -> FedResult<(Checksum, usize)> {
+    let mut decompressor = open_data_reader(reader, key, salt, file_strat, priv_header, progress)?;
+    // All steps happen at the same time now, so progress moves on when the reading finishes.
+    progress.start_checksum_for_file(file_strat.file);
+    let mut checksum = ChecksumCalculator::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut total_len = 0;
+    loop {
+        let count = wrap_io(
+            || "could not decrypt input file",
+            decompressor.read(&mut buffer),
+        )?;
+        if count == 0 {
+            break;
+        }
+        total_len += count;
+        checksum.update(&buffer[..count]);
+        wrap_io(
+            || {
+                format!(
+                    "Failed to write decrypted output data for '{}'",
+                    &file_strat.file.path_str()
+                )
+            },
+            output.write_all(&buffer[..count]),
+        )?;
+    }
+    progress.start_write_for_file(file_strat.file);
+    wrap_io(
+        || {
+            format!(
+                "Failed to write decrypted output data for '{}'",
+                &file_strat.file.path_str()
+            )
+        },
+        output.flush(),
+    )?;
+    Ok((checksum.finish(), total_len))
+}
+
+/// Print the name and size of the file, or of each file if it is an archive.
+fn list_contents(
+    reader: impl Read,
+    key: &StretchKey,
+    salt: &Salt,
+    file_strat: &FileHeaderStrategy,
+    priv_header: Option<&PrivateHeader>,
+    is_archive: bool,
+    progress: &mut dyn Progress,
+) -> FedResult<()> {
+    println!("{}:", &file_strat.file.path_str());
+    let priv_header = match priv_header {
+        Some(hdr) => hdr,
+        None => {
+            println!(
+                "  (files from version {} do not contain a name or size)",
+                file_strat.pub_header.version()
+            );
+            return Ok(());
+        }
+    };
+    if !is_archive {
+        let size = match priv_header.size() {
+            Some(size) => size.to_string(),
+            None => "?".to_owned(),
+        };
+        println!(
+            "{:>14}  {}",
+            size,
+            priv_header.filename().unwrap_or("(name hidden)")
+        );
+        return Ok(());
+    }
+    // Only the index is needed, so the data of the files is not decrypted.
+    let data = open_data_reader(reader, key, salt, file_strat, Some(priv_header), progress)?;
+    let index = parse_archive_index(&mut BufReader::new(data))?;
+    for member in index.members() {
+        println!(
+            "{:>14}  {}",
+            member.size().unwrap(),
+            member.filename().unwrap()
+        );
+    }
+    Ok(())
+}
+
+/// Extract the files in an archive into the output directory. Returns the checksum of
+/// the data of the whole archive, and the paths of the extracted files.
+fn extract_archive(
+    data: impl Read,
+    out_dir: &Path,
+    config: &DecryptConfig,
+    file_strat: &FileHeaderStrategy,
+) -> FedResult<(Checksum, Vec<PathBuf>)> {
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, ChecksumReader::new(data));
+    let index = parse_archive_index(&mut reader)?;
+    let selected = match config.archive_action() {
+        ArchiveAction::Extract(names) => Some(names),
+        _ => None,
+    };
+    if let Some(names) = selected {
+        for name in names {
+            if !index
+                .members()
+                .iter()
+                .any(|member| member.filename() == Some(name))
+            {
+                return Err(FedError::Usage(format!(
+                    "there is no file '{}' in archive '{}'",
+                    name,
+                    &file_strat.file.path_str()
+                )));
+            }
+        }
+    }
+    let mut out_pths = vec![];
+    for member in index.members() {
+        let name = member.filename().unwrap();
+        let member_size = member.size().unwrap();
+        let mut member_data = (&mut reader).take(member_size);
+        let is_selected = selected
+            .map(|names| names.iter().any(|selected_name| selected_name == name))
+            .unwrap_or(true);
+        if !is_selected {
+            wrap_io(
+                || "could not decrypt input file",
+                io::copy(&mut member_data, &mut io::sink()),
+            )?;
+            continue;
+        }
+        let out_pth = member_output_path(out_dir, name)?;
+        let mut output = open_output_file(config, &out_pth)?;
+        let result = io::copy(&mut member_data, &mut output)
+            .and_then(|count| output.flush().map(|_| count))
+            .map_err(|err| {
+                let msg = format!("could not extract '{}' from archive: {}", name, err);
+                match err.kind() {
+                    io::ErrorKind::InvalidData => FedError::Corrupted(msg),
+                    _ => FedError::Io(msg),
+                }
+            })
+            .and_then(|count| {
+                if count == member_size {
+                    Ok(())
+                } else {
+                    Err(FedError::Corrupted(format!(
+                        "the archive ended before the end of '{}'; the file may be corrupted",
+                        name
+                    )))
+                }
+            });
+        if let Err(err) = result {
+            // Do not leave partially decrypted (possibly tampered) data around.
+            drop(output);
+            let _ = fs::remove_file(&out_pth);
+            return Err(err);
+        }
+        if config.restore_meta() {
+            restore_file_meta(output.get_ref(), &out_pth, member)?;
+        }
+        if config.debug() {
+            println!("extracted {}", &out_pth.to_string_lossy());
+        }
+        out_pths.push(out_pth);
+    }
+    let remaining = wrap_io(
+        || "could not decrypt input file",
+        io::copy(&mut reader, &mut io::sink()),
+    )?;
+    if remaining > 0 {
+        return Err(FedError::Corrupted(format!(
+            "archive '{}' contains more data than the files in its index; \
+            the file may be corrupted",
+            &file_strat.file.path_str()
+        )));
+    }
+    Ok((reader.into_inner().finish(), out_pths))
+}
+
+/// Compare the decrypted size to the original size, if it is known.
+fn check_size(
+    file_strat: &FileHeaderStrategy,
+    priv_header: Option<&PrivateHeader>,
+    total_len: usize,
+) -> FedResult<()> {
+    match priv_header.and_then(|hdr| hdr.size()) {
+        Some(size) if size != total_len as u64 => Err(FedError::Corrupted(format!(
+            "decrypted size of '{}' was {} bytes, but the original file had {} bytes; \
+            the file may be corrupted",
+            &file_strat.file.path_str(),
+            total_len,
+            size
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Decrypt the data without writing it anywhere, and check the size and checksum.
+/// The private header has already been checked when it was read.
+fn verify_data(
+    reader: impl Read,
+    key: &StretchKey,
+    expected_checksum: &Checksum,
+    file_strat: &FileHeaderStrategy,
+    priv_header: Option<&PrivateHeader>,
+    config: &DecryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<bool> {
+    let salt = file_strat.pub_header.salt();
+    let (actual_checksum, total_len) = decrypt_data(
+        reader,
+        &mut io::sink(),
+        key,
+        salt,
+        file_strat,
+        priv_header,
+        progress,
+    )?;
+    check_size(file_strat, priv_header, total_len)?;
+    let is_checksum_ok = validate_checksum_matches(
+        &actual_checksum,
+        expected_checksum,
+        config.verbosity(),
+        &file_strat.file.path_str(),
+    );
+    if is_checksum_ok && !config.quiet() {
+        println!(
+            "verified '{}' ({} kb)",
+            &file_strat.file.path_str(),
+            total_len / 1024,
+        );
+    }
+    Ok(is_checksum_ok)
+}
+
+/// Files encrypted for recipients or with key slots store their key in the header,
+/// which is unlocked using the identity or the password.
+pub fn recover_raw_key(
+    file_strat: &FileHeaderStrategy,
+    raw_key: &Key,
+    progress: &mut dyn Progress,
+) -> FedResult<Key> {
+    let pub_header = &file_strat.pub_header;
+    let options = pub_header.options();
+    if options.has(EncOption::Recipients) {
+        if let Ok(identity) = Identity::parse(raw_key.key_data.unsecure()) {
+            return unwrap_key(pub_header.recipients(), &identity);
+        }
+    }
+    if options.has(EncOption::KeySlots) {
+        let (_, data_key) = open_key_slots(
+            pub_header.key_slots(),
+            raw_key,
+            &file_strat.strategy,
+            &file_strat.kdf_cost,
+            &mut |alg| progress.start_stretch_alg(alg, Some(file_strat.file)),
+        )?;
+        return Ok(data_key);
+    }
+    if options.has(EncOption::Recipients) {
+        return Err(FedError::Usage(
+            "this file was encrypted for recipients, so it can only be decrypted \
+            with an identity (like '--key identity:$path')"
+                .to_owned(),
+        ));
+    }
+    Ok(raw_key.clone())
+}
+
+/// Decrypt a single file, or extract the files from an archive. Reports the new paths,
+/// and whether the checksum matched.
+fn decrypt_one(
+    file_strat: &FileHeaderStrategy,
+    stretched_key: &StretchKey,
+    config: &DecryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<FileReport> {
+    let report = |outputs: Vec<PathBuf>, checksum: ChecksumStatus| {
+        FileReport::new(
+            file_strat.file.in_path,
+            outputs,
+            file_strat.file.size_b,
+            checksum,
+        )
+    };
+    let mut reader = open_reader(file_strat.file, config.verbosity())?;
+    let salt = file_strat.pub_header.salt();
+    let priv_header = read_private_header(&mut reader, file_strat, stretched_key, &mut || {
+        progress.start_private_header_for_file(file_strat.file)
+    })?;
+    let out_pth = determine_restored_output_path(
+        &file_strat.file.out_pth,
+        priv_header.as_ref().and_then(|(_, hdr)| hdr.filename()),
+    );
+    let data_start =
+        file_strat.pub_header_len as u64 + priv_header.as_ref().map(|(len, _)| *len).unwrap_or(0);
+    let (data_len, expected_checksum) = match file_strat.strategy.layout {
+        DataLayout::Buffered => (
+            file_strat.file.size_b - data_start,
+            file_strat.pub_header.checksum().cloned().ok_or_else(|| {
+                FedError::Corrupted(format!(
+                    "no checksum in header of '{}'",
+                    &file_strat.file.path_str()
+                ))
+            })?,
+        ),
+        DataLayout::Stream => read_trailer(&mut reader, file_strat, data_start)?,
+    };
+    wrap_io(
+        || "could not read input file",
+        reader.seek(SeekFrom::Start(data_start)),
+    )?;
+    progress.start_read_for_file(file_strat.file);
+    if config.debug() {
+        println!("reading {}", &file_strat.file.path_str());
+    }
+    let is_archive = file_strat.pub_header.options().has(EncOption::Archive);
+    if config.archive_action() == &ArchiveAction::List {
+        list_contents(
+            reader.take(data_len),
+            stretched_key,
+            salt,
+            file_strat,
+            priv_header.as_ref().map(|(_, hdr)| hdr),
+            is_archive,
+            progress,
+        )?;
+        return Ok(report(vec![], ChecksumStatus::NotChecked));
+    }
+    if config.archive_action() == &ArchiveAction::Verify {
+        let is_checksum_ok = verify_data(
+            reader.take(data_len),
+            stretched_key,
+            &expected_checksum,
+            file_strat,
+            priv_header.as_ref().map(|(_, hdr)| hdr),
+            config,
+            progress,
+        )?;
+        return Ok(report(vec![], ChecksumStatus::from_match(is_checksum_ok)));
+    }
+    if is_archive {
+        let data = open_data_reader(
+            reader.take(data_len),
+            stretched_key,
+            salt,
+            file_strat,
+            priv_header.as_ref().map(|(_, hdr)| hdr),
+            progress,
+        )?;
+        progress.start_checksum_for_file(file_strat.file);
+        let out_dir = file_strat
+            .file
+            .out_pth
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        let (actual_checksum, member_pths) = extract_archive(data, out_dir, config, file_strat)?;
+        progress.start_write_for_file(file_strat.file);
+        let is_checksum_ok = validate_checksum_matches(
+            &actual_checksum,
+            &expected_checksum,
+            config.verbosity(),
+            &file_strat.file.path_str(),
+        );
+        if config.delete_input() {
+            delete_input_file(
+                config.delete_input(),
+                file_strat.file,
+                &mut || progress.start_shred_input_for_file(file_strat.file),
+                config.verbosity(),
+            )?;
+        }
+        if !config.quiet() {
+            println!(
+                "successfully extracted {} files from archive '{}'",
+                member_pths.len(),
+                &file_strat.file.path_str(),
+            );
+        }
+        return Ok(report(
+            member_pths,
+            ChecksumStatus::from_match(is_checksum_ok),
+        ));
+    }
+    if let ArchiveAction::Extract(_) = config.archive_action() {
+        return Err(FedError::Usage(format!(
+            "cannot select files to extract from '{}', because it is not an archive",
+            &file_strat.file.path_str()
+        )));
+    }
+    let mut output = open_output_file(config, &out_pth)?;
+    let result = decrypt_data(
+        reader.take(data_len),
+        &mut output,
+        stretched_key,
+        salt,
+        file_strat,
+        priv_header.as_ref().map(|(_, hdr)| hdr),
+        progress,
+    )
+    .and_then(|(checksum, total_len)| {
+        check_size(
+            file_strat,
+            priv_header.as_ref().map(|(_, hdr)| hdr),
+            total_len,
+        )?;
+        Ok((checksum, total_len))
+    });
+    let (actual_checksum, total_len) = match result {
+        Ok(res) => res,
+        Err(err) => {
+            // Do not leave partially decrypted (possibly tampered) data around.
+            drop(output);
+            let _ = fs::remove_file(&out_pth);
+            return Err(err);
+        }
+    };
+    if let Some((_, hdr)) = &priv_header {
+        if config.restore_meta() {
+            restore_file_meta(output.get_ref(), &out_pth, hdr)?;
+        }
+    }
+    drop(output);
+    if config.debug() {
+        println!("decrypted {}", &file_strat.file.file_name());
+    }
+    let is_checksum_ok = validate_checksum_matches(
+        &actual_checksum,
+        &expected_checksum,
+        config.verbosity(),
+        &file_strat.file.path_str(),
+    );
+    if config.delete_input() {
+        delete_input_file(
+            config.delete_input(),
+            file_strat.file,
+            &mut || progress.start_shred_input_for_file(file_strat.file),
+            config.verbosity(),
+        )?;
+    }
+    if !config.quiet() {
+        println!(
+            "successfully decrypted '{}' to '{}' ({} kb)",
+            &file_strat.file.path_str(),
+            &out_pth.to_string_lossy(),
+            total_len / 1024,
+        );
+    }
+    Ok(report(
+        vec![out_pth],
+        ChecksumStatus::from_match(is_checksum_ok),
+    ))
+}
+
+/// Decrypt one or more files and return the new paths. Fails if any of the files failed,
+/// or if any checksum did not match.
 pub fn decrypt(config: &DecryptConfig) -> FedResult<Vec<PathBuf>> {
+    let reports = decrypt_report(config)?;
+    if let Some(err) = failure_error(&reports).or_else(|| checksum_error(&reports)) {
+        return Err(err);
+    }
+    Ok(reports
+        .into_iter()
+        .flat_map(FileReport::into_outputs)
+        .collect())
+}
+
+/// Decrypt one or more files and report the result for each of them. Files that failed, or whose
+/// checksum did not match, are reported as such, instead of failing.
+pub fn decrypt_report(config: &DecryptConfig) -> FedResult<Vec<FileReport>> {
     //TODO @mark: break this up into more functions?
+    let input_files = collect_input_files(config.files(), config.selection(), |pth| {
+        has_enc_extension(pth, config.input_extension())
+    })?;
     let files_info = inspect_files(
-        config.files(),
+        &input_files,
         config.verbosity(),
         config.overwrite(),
         Extension::Strip,
         config.output_dir(),
     )?;
     let files_strats = read_file_strategies(&files_info, config.verbosity())?;
-    let mut progress: Box<dyn Progress> = match config.verbosity() {
+    // Stretch the key once for every salt, so that files can be decrypted concurrently.
+    let mut salt_strats: Vec<&FileHeaderStrategy> = vec![];
+    for file_strat in &files_strats {
+        let salt = file_strat.pub_header.salt();
+        if !salt_strats
+            .iter()
+            .any(|other| other.pub_header.salt() == salt)
+        {
+            salt_strats.push(file_strat);
+        }
+    }
+    let cached_keys = salt_strats
+        .iter()
+        .map(|file_strat| {
+            let cached = cached_stretched_key(
+                config.agent(),
+                file_strat.pub_header.salt(),
+                &file_strat.kdf_cost,
+                &file_strat.strategy.key_hash_algorithms,
+            );
+            (*file_strat, cached)
+        })
+        .collect::<Vec<_>>();
+    // The key might be asked for, which should happen before progress is shown.
+    let raw_key = if cached_keys.iter().all(|(_, cached)| cached.is_some()) {
+        None
+    } else {
+        Some(config.raw_key()?)
+    };
+    // Listing skips most steps, and a progress bar would get in the way of the output.
+    let is_listing = config.archive_action() == &ArchiveAction::List;
+    let progress: Box<dyn Progress + Send> = match config.verbosity() {
         Verbosity::Quiet => Box::new(SilentProgress::new()),
+        Verbosity::Normal if is_listing => Box::new(SilentProgress::new()),
         Verbosity::Normal => Box::new(IndicatifProgress::new_dec_strategy(
             &files_strats,
             config.delete_input(),
@@ -72,142 +788,675 @@ pub fn decrypt(config: &DecryptConfig) -> FedResult<Vec<PathBuf>> {
         )),
         Verbosity::Debug => Box::new(LogProgress::new()),
     };
-    let mut key_cache: HashMap<Salt, StretchKey> = HashMap::new();
-    let mut checksum_failure_count = 0;
-    let mut out_pths = vec![];
-    for file_strat in &files_strats {
-        let mut reader = open_reader(&file_strat.file, config.verbosity())?;
-        reader
-            .seek(SeekFrom::Start(file_strat.pub_header_len as u64))
-            .unwrap();
-        let salt = file_strat.pub_header.salt().clone();
-        let stretched_key = if let Some(sk) = key_cache.get(&salt) {
-            sk.clone()
+    let mut progress = SharedProgress::new(progress);
+    let stretched_keys = run_parallel(config.jobs(), &cached_keys, |(file_strat, cached)| {
+        if let Some(stretched_key) = cached {
+            return Ok(stretched_key.clone());
+        }
+        let mut progress = progress.clone();
+        let raw_key = recover_raw_key(file_strat, raw_key.as_ref().unwrap(), &mut progress)?;
+        stretch_key_with_agent(
+            config.agent(),
+            &raw_key,
+            file_strat.pub_header.salt(),
+            &file_strat.kdf_cost,
+            &file_strat.strategy.key_hash_algorithms,
+            &mut |alg| progress.start_stretch_alg(alg, Some(file_strat.file)),
+        )
+    });
+    // Files whose key could not be recovered fail, but the others can still be decrypted.
+    let key_cache: HashMap<&Salt, FedResult<StretchKey>> = salt_strats
+        .iter()
+        .map(|file_strat| file_strat.pub_header.salt())
+        .zip(stretched_keys)
+        .collect();
+    let results = run_parallel(config.jobs(), &files_strats, |file_strat| {
+        let mut progress = progress.clone();
+        match &key_cache[file_strat.pub_header.salt()] {
+            Ok(stretched_key) => decrypt_one(file_strat, stretched_key, config, &mut progress),
+            Err(err) => Err(err.clone()),
+        }
+    });
+    let reports = results
+        .into_iter()
+        .zip(&files_strats)
+        .map(|(result, file_strat)| {
+            result.unwrap_or_else(|err| {
+                let file = &file_strat.file;
+                FileReport::failed(file.in_path, file.size_b, err)
+            })
+        })
+        .collect::<Vec<_>>();
+    progress.finish();
+    if !config.quiet() {
+        let success_count = reports
+            .iter()
+            .filter(|report| report.error().is_none())
+            .count();
+        if config.archive_action() == &ArchiveAction::Verify {
+            println!("verified {} files", success_count);
         } else {
-            let sk = stretch_key(
-                config.raw_key(),
-                &salt,
-                file_strat.strategy.stretch_count,
-                &file_strat.strategy.key_hash_algorithms,
-                &mut |alg| progress.start_stretch_alg(&alg, Some(&file_strat.file)),
-            );
-            key_cache.insert(salt.clone(), sk.clone());
-            sk
-        };
-        let mut data = Vec::with_capacity(file_strat.file.size_b as usize);
-        read_file(
-            &mut data,
-            &mut reader,
-            &file_strat.file.path_str(),
-            file_strat.file.size_kb(),
-            config.verbosity(),
-            &mut || progress.start_read_for_file(&file_strat.file),
-        )?;
-        let priv_header_len = file_strat
+            println!("decrypted {} files", success_count);
+        }
+    }
+    Ok(reports)
+}
+
+lazy_static! {
+    /// Streams have no file, so they all use this stand-in.
+    static ref STREAM_FILE: FileInfo<'static> = FileInfo::stream();
+}
+
+/// Decrypts data while it is being read from another reader, like stdin. Use it with `io::copy`
+/// or `read_to_end`. The checksum is only known at the end, so reading the last of the data fails
+/// if it does not match. Archives and files from before streaming was supported cannot be read this way.
+pub struct DecryptingReader<R: Read> {
+    data: DecompressReader<DecryptReader<TrailerReader<BufReader<R>>>>,
+    file_strat: FileHeaderStrategy<'static>,
+    priv_header: PrivateHeader,
+    verbosity: Verbosity,
+    progress: Box<dyn Progress>,
+    checksum: Option<ChecksumCalculator>,
+    total_len: usize,
+    end: FedResult<()>,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Read the headers and recover the key. This fails if the key is wrong.
+    pub fn new(reader: R, config: &DecryptConfig) -> FedResult<Self> {
+        let mut reader = BufReader::with_capacity(CHUNK_SIZE, reader);
+        let (pub_header_len, pub_header) = parse_public_header(&mut reader, config.debug())?;
+        let file_strat =
+            FileHeaderStrategy::new(&STREAM_FILE, pub_header, pub_header_len, config.verbosity())?;
+        if !version_has_private_header(file_strat.pub_header.version()) {
+            return Err(FedError::Usage(format!(
+                "data from version {} cannot be decrypted as a stream; decrypt it as a file instead",
+                file_strat.pub_header.version()
+            )));
+        }
+        if file_strat.pub_header.options().has(EncOption::Archive) {
+            return Err(FedError::Usage(
+                "archives cannot be decrypted as a stream; decrypt it as a file instead".to_owned(),
+            ));
+        }
+        let (priv_header_len, priv_header_checksum) = file_strat
             .pub_header
             .private_header()
             .as_ref()
-            .map(|hdr| hdr.0 as usize)
-            .unwrap_or(0);
-        let priv_header = if version_has_options_meta(&file_strat.pub_header.version()) {
-            dbg!(&data[..priv_header_len]); //TODO @mark: TEMPORARY! REMOVE THIS!
-            let index_header = parse_private_header(&mut &data[..priv_header_len])?;
-            Some(index_header.1)
-        } else {
-            None
+            .ok_or_else(|| {
+                FedError::Corrupted(
+                    "could not find the private header of the stream; has it been corrupted?"
+                        .to_owned(),
+                )
+            })?;
+        check_private_header_len(*priv_header_len, "the stream")?;
+        let mut progress: Box<dyn Progress> = match config.verbosity() {
+            Verbosity::Debug => Box::new(LogProgress::new()),
+            _ => Box::new(SilentProgress::new()),
         };
-        let revealed = decrypt_file(
-            data,
-            priv_header_len,
-            &stretched_key,
-            &salt,
-            &file_strat.strategy.symmetric_algorithms,
-            &mut |alg| progress.start_sym_alg_for_file(alg, &file_strat.file),
+        let salt = file_strat.pub_header.salt();
+        let cost = &file_strat.kdf_cost;
+        let algs = &file_strat.strategy.key_hash_algorithms;
+        let key = match cached_stretched_key(config.agent(), salt, cost, algs) {
+            Some(key) => key,
+            None => {
+                let raw_key = recover_raw_key(&file_strat, &config.raw_key()?, progress.as_mut())?;
+                stretch_key_with_agent(config.agent(), &raw_key, salt, cost, algs, &mut |alg| {
+                    progress.start_stretch_alg(alg, None)
+                })?
+            }
+        };
+        let mut secret = vec![0; *priv_header_len as usize];
+        wrap_io(
+            || "could not read encrypted stream",
+            reader.read_exact(&mut secret),
         )?;
-        let big = decompress_file(
-            revealed,
-            &file_strat.strategy.compression_algorithm,
-            &mut |alg| progress.start_compress_alg_for_file(alg, &file_strat.file),
+        let data = decrypt_private_header_data(secret, priv_header_checksum, &file_strat, &key)?;
+        let (_, priv_header) = parse_private_header(&mut data.as_slice())?;
+        let data = open_data_reader(
+            TrailerReader::new(reader, PUB_TRAILER_LEN),
+            &key,
+            salt,
+            &file_strat,
+            Some(&priv_header),
+            progress.as_mut(),
         )?;
-        let actual_checksum = calculate_checksum(&big, &mut || {
-            progress.start_checksum_for_file(&file_strat.file)
-        });
+        // All steps happen at the same time now, so progress moves on when the reading finishes.
+        progress.start_checksum_for_file(file_strat.file);
+        Ok(DecryptingReader {
+            data,
+            file_strat,
+            priv_header,
+            verbosity: config.verbosity(),
+            progress,
+            checksum: Some(ChecksumCalculator::new()),
+            total_len: 0,
+            end: Ok(()),
+        })
+    }
+
+    /// Like `read`, but keeps the original error. The end is only reported if it is valid.
+    fn read_data(&mut self, buf: &mut [u8]) -> FedResult<usize> {
+        let checksum = match &mut self.checksum {
+            Some(checksum) => checksum,
+            None => return self.end.clone().map(|()| 0),
+        };
+        let count = wrap_io(|| "could not decrypt input stream", self.data.read(buf))?;
+        if count > 0 || buf.is_empty() {
+            checksum.update(&buf[..count]);
+            self.total_len += count;
+            return Ok(count);
+        }
+        let actual_checksum = self.checksum.take().unwrap().finish();
+        self.end = self.verify_end(&actual_checksum);
+        self.end.clone().map(|()| 0)
+    }
+
+    /// Check the size and the checksum from the trailer, after all the data has been read.
+    fn verify_end(&mut self, actual_checksum: &Checksum) -> FedResult<()> {
+        check_size(&self.file_strat, Some(&self.priv_header), self.total_len)?;
+        let trailer = wrap_io(
+            || "could not read encrypted stream",
+            self.data.get_mut().get_mut().read_trailer(),
+        )?;
+        let expected_checksum = parse_public_trailer(&trailer)?;
+        self.progress.finish();
         if !validate_checksum_matches(
-            &actual_checksum,
-            file_strat.pub_header.checksum(),
-            config.verbosity(),
-            &file_strat.file.path_str(),
+            actual_checksum,
+            &expected_checksum,
+            self.verbosity,
+            &self.file_strat.file.path_str(),
         ) {
-            checksum_failure_count += 1;
-        }
-        write_output_file(config, &file_strat.file, &big, None, &mut || {
-            progress.start_write_for_file(&file_strat.file)
-        })?;
-        if config.delete_input() {
-            delete_input_file(
-                config.delete_input(),
-                &file_strat.file,
-                &mut || progress.start_shred_input_for_file(&file_strat.file),
-                config.verbosity(),
-            )?;
+            return Err(FedError::ChecksumMismatch(
+                "the checksum of the decrypted stream did not match; it likely does not \
+                contain real data"
+                    .to_owned(),
+            ));
         }
-        if !config.quiet() {
-            println!(
-                "successfully decrypted '{}' to '{}' ({} kb)",
-                &file_strat.file.path_str(),
-                &file_strat.file.out_pth.to_string_lossy(),
-                big.len() / 1024,
-            );
-        }
-        out_pths.push(file_strat.file.out_pth.clone());
+        Ok(())
     }
-    progress.finish();
-    if !config.quiet() {
-        println!("decrypted {} files", files_strats.len());
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_data(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
     }
-    if checksum_failure_count > 0 {
-        return Err(format!(
-            "there were {} files whose checksums did not match; they \
-        likely do not contain real data",
-            checksum_failure_count
-        ));
+}
+
+/// Decrypt data from a stream, like stdin, into another stream. The trailer cannot be found by
+/// seeking, so it is only read at the end. Archives cannot be decrypted this way. If the checksum
+/// does not match, this fails, but the data has already been written by then.
+pub fn decrypt_stream(
+    reader: impl Read,
+    writer: &mut impl Write,
+    config: &DecryptConfig,
+) -> FedResult<()> {
+    let mut reader = DecryptingReader::new(reader, config)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let count = reader.read_data(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        wrap_io(
+            || "Failed to write decrypted output data",
+            writer.write_all(&buffer[..count]),
+        )?;
     }
-    Ok(out_pths)
+    wrap_io(|| "Failed to write decrypted output data", writer.flush())
+}
+
+/// Decrypt data that was encrypted by `encrypt_bytes`, or by `fileenc` from a stream or file.
+pub fn decrypt_bytes(data: &[u8], key: &Key) -> FedResult<Vec<u8>> {
+    let config = DecryptConfig::builder(key.clone())
+        .with_file(STREAM_PATH)
+        .with_verbosity(Verbosity::Quiet)
+        .build()?;
+    let mut plain = Vec::with_capacity(data.len());
+    decrypt_stream(data, &mut plain, &config)?;
+    Ok(plain)
 }
 
 /// The demo used in this blog post:
 /// https://markv.nl/blog/symmetric-encryption-in-rust
 #[cfg(test)]
 mod tests {
+    use ::std::fs;
+    use ::std::io::{Read, Write};
+    #[cfg(unix)]
+    use ::std::os::unix::fs::PermissionsExt;
+    use ::std::path::{Path, PathBuf};
+    #[cfg(unix)]
+    use ::std::thread;
+    #[cfg(unix)]
+    use ::std::time::Duration;
+
     use ::lazy_static::lazy_static;
     use ::tempfile::tempdir;
 
-    use crate::config::typ::{InputAction, OnFileExist};
-    use crate::config::DecryptConfig;
-    use crate::decrypt;
+    use crate::config::typ::{ArchiveAction, InputAction, OnFileExist};
+    use crate::config::{DecryptConfig, EncryptConfig};
+    use crate::files::file_meta::STREAM_PATH;
+    use crate::files::mockfile::generate_test_file_content_for_test;
     use crate::files::scan::TEST_FILE_DIR;
     use crate::header::strategy::Verbosity;
+    use crate::header::{parse_public_header, write_public_header, PublicHeader, PUB_TRAILER_LEN};
+    #[cfg(unix)]
+    use crate::key::agent::{run_agent, AgentClient};
     use crate::key::key::Key;
+    use crate::key::{DeferredKey, KeySource};
+    use crate::orchestrate::decrypt::{
+        decrypt_bytes, decrypt_report, decrypt_stream, DecryptingReader,
+    };
+    use crate::orchestrate::encrypt::{encrypt_bytes, encrypt_stream, EncryptingWriter};
+    use crate::util::option::EncOption;
+    use crate::{decrypt, encrypt};
 
     lazy_static! {
         static ref COMPAT_KEY: Key = Key::new(" LP0y#shbogtwhGjM=*jFFZPmNd&qBO+ ");
     }
 
+    #[test]
+    fn report_per_file() {
+        let dir = tempdir().unwrap();
+        let mut enc_pths = vec![];
+        for (name, key) in &[
+            ("good.txt", "right"),
+            ("other.txt", "wrong"),
+            ("cut.txt", "right"),
+        ] {
+            let pth = dir.path().join(name);
+            fs::write(&pth, name.repeat(100)).unwrap();
+            let enc_conf = EncryptConfig::builder(Key::new(key))
+                .with_file(&pth)
+                .with_option(EncOption::Fast)
+                .with_verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap();
+            enc_pths.extend(encrypt(&enc_conf).unwrap());
+        }
+        let truncated = fs::read(&enc_pths[2]).unwrap();
+        fs::write(&enc_pths[2], &truncated[..truncated.len() - 20]).unwrap();
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(Key::new("right"))
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_overwrite(OnFileExist::Overwrite)
+            .with_output_dir(dec_dir.path())
+            .build()
+            .unwrap();
+        let reports = decrypt_report(&dec_conf).unwrap();
+        assert!(reports[0].error().is_none());
+        assert_eq!(
+            "good.txt".repeat(100),
+            fs::read_to_string(&reports[0].outputs()[0]).unwrap()
+        );
+        assert_eq!("wrong-key", reports[1].error().unwrap().code());
+        assert_eq!("corrupted", reports[2].error().unwrap().code());
+        assert_eq!("other", decrypt(&dec_conf).unwrap_err().code());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn agent_without_key() {
+        let agent_dir = tempdir().unwrap();
+        fs::set_permissions(agent_dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        let socket = agent_dir.path().join("agent.sock");
+        let agent_socket = socket.clone();
+        let agent = thread::spawn(move || run_agent(&agent_socket, Duration::from_secs(60)));
+        let client = AgentClient::new(socket);
+        while client.stretched_key("none").is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("file.txt");
+        fs::write(&pth, "remembered").unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("secret"))
+            .with_file(&pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+
+        // The key cannot be read, so the file can only be decrypted with the agent.
+        let dec_conf = |dec_dir: &Path, key: DeferredKey| {
+            DecryptConfig::builder(key)
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(dec_dir)
+                .with_agent(client.clone())
+                .build()
+                .unwrap()
+        };
+        let no_key = || DeferredKey::new(KeySource::File(dir.path().join("missing")), None);
+        let dec_dir = tempdir().unwrap();
+        assert!(decrypt(&dec_conf(dec_dir.path(), no_key())).is_err());
+        let dec_dir = tempdir().unwrap();
+        decrypt(&dec_conf(dec_dir.path(), Key::new("secret").into())).unwrap();
+        let dec_dir = tempdir().unwrap();
+        let reports = decrypt_report(&dec_conf(dec_dir.path(), no_key())).unwrap();
+        assert_eq!(
+            "remembered",
+            fs::read_to_string(&reports[0].outputs()[0]).unwrap()
+        );
+        client.forget().unwrap();
+        let dec_dir = tempdir().unwrap();
+        assert!(decrypt(&dec_conf(dec_dir.path(), no_key())).is_err());
+        client.stop().unwrap();
+        agent.join().unwrap().unwrap();
+    }
+
     #[test]
     fn fail_invalid_checksum() {
         let mut enc_pth = TEST_FILE_DIR.clone();
-        enc_pth.push("invalid_checksum.txt.enc".to_owned());
+        enc_pth.push("invalid_checksum.txt.enc");
         let out_pth = tempdir().unwrap();
-        let conf = DecryptConfig::new(
-            vec![enc_pth],
-            COMPAT_KEY.clone(),
-            Verbosity::Normal,
-            OnFileExist::Fail,
-            InputAction::Keep,
-            Some(out_pth.path().to_owned()),
-        );
+        let conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(enc_pth)
+            .with_output_dir(out_pth.path().to_owned())
+            .build()
+            .unwrap();
         let result = decrypt(&conf);
         assert!(&result.is_err());
-        assert!(&result.unwrap_err().contains("checksums did not match"));
+        let err = result.unwrap_err();
+        assert_eq!("checksum-mismatch", err.code());
+        assert!(err.message().contains("checksums did not match"));
+    }
+
+    #[test]
+    fn verify_without_writing() {
+        let dir = tempdir().unwrap();
+        let in_pth = dir.path().join("verify.bin");
+        fs::write(&in_pth, generate_test_file_content_for_test(50_000)).unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_pth.clone())
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pth = encrypt(&enc_conf).unwrap().remove(0);
+        let out_pth = tempdir().unwrap();
+        let verify = |enc_pth: PathBuf| {
+            let conf = DecryptConfig::builder(COMPAT_KEY.clone())
+                .with_file(enc_pth)
+                .with_verbosity(Verbosity::Quiet)
+                .with_delete_input(InputAction::Delete)
+                .with_output_dir(out_pth.path().to_owned())
+                .with_archive_action(ArchiveAction::Verify)
+                .build()
+                .unwrap();
+            decrypt(&conf)
+        };
+        assert!(verify(enc_pth.clone()).unwrap().is_empty());
+        assert!(enc_pth.exists());
+        let mut invalid_pth = TEST_FILE_DIR.clone();
+        invalid_pth.push("invalid_checksum.txt.enc");
+        let result = verify(invalid_pth);
+        assert!(result
+            .unwrap_err()
+            .message()
+            .contains("checksums did not match"));
+        assert_eq!(0, fs::read_dir(out_pth.path()).unwrap().count());
+    }
+
+    #[test]
+    fn verify_failures() {
+        let dir = tempdir().unwrap();
+        let mut enc_pths = vec![];
+        for (name, key) in &[("other.txt", "wrong"), ("cut.txt", "right")] {
+            let pth = dir.path().join(name);
+            fs::write(&pth, name.repeat(1000)).unwrap();
+            let enc_conf = EncryptConfig::builder(Key::new(key))
+                .with_file(&pth)
+                .with_option(EncOption::Fast)
+                .with_verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap();
+            enc_pths.extend(encrypt(&enc_conf).unwrap());
+        }
+        let truncated = fs::read(&enc_pths[1]).unwrap();
+        fs::write(&enc_pths[1], &truncated[..truncated.len() - 50]).unwrap();
+
+        let out_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(Key::new("right"))
+            .with_files(enc_pths.clone())
+            .with_verbosity(Verbosity::Quiet)
+            .with_delete_input(InputAction::Delete)
+            .with_output_dir(out_dir.path())
+            .with_archive_action(ArchiveAction::Verify)
+            .build()
+            .unwrap();
+        let reports = decrypt_report(&dec_conf).unwrap();
+        assert_eq!("wrong-key", reports[0].error().unwrap().code());
+        assert_eq!("corrupted", reports[1].error().unwrap().code());
+        assert!(enc_pths.iter().all(|pth| pth.exists()));
+        assert_eq!(0, fs::read_dir(out_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn fail_tampered_data() {
+        let dir = tempdir().unwrap();
+        let in_pth = dir.path().join("tampered.bin");
+        fs::write(&in_pth, generate_test_file_content_for_test(200_000)).unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_pth.clone())
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_delete_input(InputAction::Delete)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        assert!(!in_pth.exists());
+        let mut secret = fs::read(&enc_pths[0]).unwrap();
+        // Change the last byte before the trailer, which is part of the encrypted data.
+        let index = secret.len() - PUB_TRAILER_LEN - 1;
+        secret[index] ^= 1;
+        fs::write(&enc_pths[0], secret).unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let result = decrypt(&dec_conf);
+        let err = result.unwrap_err();
+        assert_eq!("corrupted", err.code());
+        assert!(err.message().contains("authentication failed"));
+        assert!(!in_pth.exists(), "partially decrypted file was not removed");
+    }
+
+    #[test]
+    fn fail_damaged_private_header() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("header.txt");
+        fs::write(&pth, "private".repeat(100)).unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(&pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pth = encrypt(&enc_conf).unwrap().remove(0);
+        let secret = fs::read(&enc_pth).unwrap();
+        let (pub_header_len, _) = parse_public_header(&mut secret.as_slice(), false).unwrap();
+
+        let mut flipped = secret.clone();
+        flipped[pub_header_len + 20] ^= 1;
+        let flipped_pth = dir.path().join("flipped.enc");
+        fs::write(&flipped_pth, flipped).unwrap();
+        let truncated_pth = dir.path().join("truncated.enc");
+        fs::write(&truncated_pth, &secret[..pub_header_len + 20]).unwrap();
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(vec![flipped_pth, truncated_pth])
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path())
+            .build()
+            .unwrap();
+        let reports = decrypt_report(&dec_conf).unwrap();
+        let flipped_err = reports[0].error().unwrap();
+        assert_eq!("wrong-key", flipped_err.code());
+        assert!(flipped_err.message().contains("private header"));
+        assert_eq!("corrupted", reports[1].error().unwrap().code());
+        assert_eq!(0, fs::read_dir(dec_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn stream_roundtrip() {
+        let data = generate_test_file_content_for_test(300_000);
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(PathBuf::from(STREAM_PATH))
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let mut secret = vec![];
+        encrypt_stream(&mut data.as_slice(), &mut secret, &enc_conf).unwrap();
+        let dec_conf = |key: &Key, pth: PathBuf| {
+            DecryptConfig::builder(key.clone())
+                .with_file(pth)
+                .with_verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap()
+        };
+        let mut plain = vec![];
+        decrypt_stream(
+            secret.as_slice(),
+            &mut plain,
+            &dec_conf(&COMPAT_KEY, PathBuf::from(STREAM_PATH)),
+        )
+        .unwrap();
+        assert_eq!(data, plain);
+
+        let wrong = decrypt_stream(
+            secret.as_slice(),
+            &mut vec![],
+            &dec_conf(&Key::new("wrong"), PathBuf::from(STREAM_PATH)),
+        );
+        assert_eq!("wrong-key", wrong.unwrap_err().code());
+        let truncated = &secret[..secret.len() - 100];
+        assert!(decrypt_stream(
+            truncated,
+            &mut vec![],
+            &dec_conf(&COMPAT_KEY, PathBuf::from(STREAM_PATH))
+        )
+        .is_err());
+
+        // A streamed file can also be decrypted as a normal file.
+        let dir = tempdir().unwrap();
+        let enc_pth = dir.path().join("stream.enc");
+        fs::write(&enc_pth, &secret).unwrap();
+        let out_pths = decrypt(&dec_conf(&COMPAT_KEY, enc_pth)).unwrap();
+        assert_eq!(data, fs::read(&out_pths[0]).unwrap());
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let options = vec![EncOption::Fast].into();
+        for size in &[0, 100, 300_000] {
+            let data = generate_test_file_content_for_test(*size);
+            let secret = encrypt_bytes(&data, &COMPAT_KEY, &options).unwrap();
+            assert_eq!(data, decrypt_bytes(&secret, &COMPAT_KEY).unwrap());
+        }
+        let secret = encrypt_bytes(b"secret", &COMPAT_KEY, &options).unwrap();
+        let wrong = decrypt_bytes(&secret, &Key::new("wrong"));
+        assert_eq!("wrong-key", wrong.unwrap_err().code());
+        let padded = encrypt_bytes(b"secret", &COMPAT_KEY, &vec![EncOption::PadSize].into());
+        assert_eq!("usage", padded.unwrap_err().code());
+    }
+
+    #[test]
+    fn bytes_and_adapter_failures() {
+        assert_eq!(
+            "corrupted",
+            decrypt_bytes(&[], &COMPAT_KEY).unwrap_err().code()
+        );
+        let garbage = generate_test_file_content_for_test(1000);
+        assert!(decrypt_bytes(&garbage, &COMPAT_KEY).is_err());
+
+        let options = vec![EncOption::Fast].into();
+        let secret = encrypt_bytes(b"adapters", &COMPAT_KEY, &options).unwrap();
+        let dec_conf = |key: &Key| {
+            DecryptConfig::builder(key.clone())
+                .with_file(STREAM_PATH)
+                .with_verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap()
+        };
+        let wrong = DecryptingReader::new(secret.as_slice(), &dec_conf(&Key::new("wrong")));
+        assert_eq!("wrong-key", wrong.err().unwrap().code());
+        let truncated = &secret[..secret.len() - PUB_TRAILER_LEN - 5];
+        let mut reader = DecryptingReader::new(truncated, &dec_conf(&COMPAT_KEY)).unwrap();
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(STREAM_PATH)
+            .with_option(EncOption::Archive)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let archive = EncryptingWriter::new(vec![], &enc_conf);
+        assert_eq!("usage", archive.err().unwrap().code());
+    }
+
+    #[test]
+    fn huge_private_header_len() {
+        let options = vec![EncOption::Fast].into();
+        let secret = encrypt_bytes(b"huge header", &COMPAT_KEY, &options).unwrap();
+        let (pub_header_len, header) = parse_public_header(&mut secret.as_slice(), false).unwrap();
+        let (_, checksum) = header.private_header().clone().unwrap();
+        let crafted = PublicHeader::new(
+            header.version().clone(),
+            header.salt().clone(),
+            header.options().clone(),
+            (1 << 40, checksum),
+        )
+        .with_kdf_cost(header.kdf_cost().cloned());
+        let mut data = vec![];
+        write_public_header(&mut data, &crafted, false).unwrap();
+        data.extend_from_slice(&secret[pub_header_len..]);
+        assert_eq!(
+            "corrupted",
+            decrypt_bytes(&data, &COMPAT_KEY).unwrap_err().code()
+        );
+    }
+
+    #[test]
+    fn reader_writer_adapters() {
+        let data = generate_test_file_content_for_test(300_000);
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(PathBuf::from(STREAM_PATH))
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let mut encrypter = EncryptingWriter::new(vec![], &enc_conf).unwrap();
+        for chunk in data.chunks(1000) {
+            encrypter.write_all(chunk).unwrap();
+        }
+        let secret = encrypter.finish().unwrap();
+        assert_eq!(data, decrypt_bytes(&secret, &COMPAT_KEY).unwrap());
+
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(PathBuf::from(STREAM_PATH))
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let mut plain = vec![];
+        DecryptingReader::new(secret.as_slice(), &dec_conf)
+            .unwrap()
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(data, plain);
+        // The checksum is in the trailer, so the error only happens at the end.
+        let mut tampered = secret.clone();
+        let last = tampered.len() - 2;
+        tampered[last] ^= 1;
+        let mut reader = DecryptingReader::new(tampered.as_slice(), &dec_conf).unwrap();
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+        assert!(reader.read(&mut [0; 10]).is_err());
     }
 }
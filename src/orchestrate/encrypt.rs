@@ -1,185 +1,994 @@
-use ::std::path::PathBuf;
+use ::std::io;
+use ::std::io::{Read, Seek, SeekFrom, Write};
+use ::std::mem;
+use ::std::path::{Path, PathBuf};
+use ::std::slice;
 
 use crate::config::enc::EncryptConfig;
 use crate::config::typ::{EndecConfig, Extension};
-use crate::files::checksum::calculate_checksum;
-use crate::files::compress::compress_file;
+use crate::files::archive::{member_names, ArchiveReader};
+use crate::files::checksum::{calculate_checksum, ChecksumCalculator};
+use crate::files::compress::{compressed_size, is_compressible, CompressWriter};
 use crate::files::delete::delete_input_file;
-use crate::files::file_meta::{inspect_files, FileInfo};
-use crate::files::reading::{open_reader, read_file};
-use crate::files::write_output::write_output_file;
+use crate::files::file_meta::{inspect_files, FileInfo, STREAM_PATH};
+use crate::files::padding::{data_padding_len, write_padding};
+use crate::files::reading::{open_reader, start_reading_file, CHUNK_SIZE};
+use crate::files::scan::{collect_input_files, InputFile};
+use crate::files::write_output::open_output_file;
 use crate::files::Checksum;
+use crate::header::archive::{write_archive_index, ArchiveIndex};
 use crate::header::private_encode::write_private_header;
 use crate::header::private_header_type::PrivateHeader;
 use crate::header::strategy::get_current_version_strategy;
 use crate::header::strategy::Verbosity;
-use crate::header::{PublicHeader, Strategy};
+use crate::header::{write_public_header, write_public_trailer, PublicHeader, Strategy};
+use crate::key::cost::KdfCost;
 use crate::key::key::StretchKey;
+use crate::key::recipient::{wrap_key, WrappedKey};
+use crate::key::slot::KeySlot;
 use crate::key::stretch::stretch_key;
-use crate::key::Salt;
+use crate::key::{Key, Salt};
+use crate::orchestrate::report::{failure_error, ChecksumStatus, FileReport};
 use crate::progress::indicatif::IndicatifProgress;
 use crate::progress::log::LogProgress;
+use crate::progress::shared::SharedProgress;
 use crate::progress::silent::SilentProgress;
 use crate::progress::Progress;
-use crate::symmetric::encrypt::encrypt_file;
-use crate::util::errors::FedResult;
-use crate::util::option::EncOption;
+use crate::symmetric::aead::{derive_synthetic_iv, SYNTHETIC_IV_LEN};
+use crate::symmetric::encrypt::{encrypt_file, EncryptWriter};
+use crate::util::errors::{wrap_io, FedError, FedResult};
+use crate::util::jobs::run_parallel;
+use crate::util::option::{EncOption, EncOptionSet};
+use crate::util::rounding::round_up_to_power_of_two;
 use crate::util::version::get_current_version;
 
 //TODO @mark: I need to add some random number of bytes to private header, because the attacker knows the size of the cyphertext, so they can deduce private header information
 
-fn encrypt_private_header(
+/// Reading the file changes its access time, so it is not stored when the output should be reproducible.
+fn accessed_ns(file: &FileInfo, config: &EncryptConfig) -> Option<u128> {
+    if config.options().has(EncOption::Deterministic) {
+        return None;
+    }
+    file.accessed_ns
+}
+
+/// Private headers are padded to this length when hiding the size, so that the length of the
+/// filename does not show. Headers with very long filenames are padded to a power of two.
+const PADDED_PRIVATE_HEADER_LEN: u64 = 1024;
+
+/// Decryption rejects private headers longer than this, so that a damaged public header cannot
+/// make it allocate a huge buffer. Even padded headers with long filenames stay far below it.
+pub const MAX_PRIVATE_HEADER_LEN: u64 = 1024 * 1024;
+
+/// The private header for a file, or for a stream if there is no file. Streams have no
+/// metadata, and their size is not known when the header is written.
+fn new_private_header(
+    file: Option<&FileInfo>,
     pepper: &Salt,
+    data_padding_len: u64,
+    config: &EncryptConfig,
+) -> FedResult<PrivateHeader> {
+    if !config.options().has(EncOption::PadSize) {
+        // This padding length has expectation value 128, which is probably enough to obfuscate most filename lengths.
+        let padding_len = pepper.salt[0] as u16;
+        return Ok(private_header_with_padding(
+            file,
+            pepper,
+            padding_len,
+            data_padding_len,
+            config,
+        ));
+    }
+    let unpadded = private_header_with_padding(file, pepper, 0, data_padding_len, config);
+    let mut data = Vec::with_capacity(PADDED_PRIVATE_HEADER_LEN as usize);
+    write_private_header(&mut data, &unpadded, false)?;
+    let unpadded_len = data.len() as u64;
+    let padded_len = if unpadded_len <= PADDED_PRIVATE_HEADER_LEN {
+        PADDED_PRIVATE_HEADER_LEN
+    } else {
+        round_up_to_power_of_two(unpadded_len)
+    };
+    Ok(private_header_with_padding(
+        file,
+        pepper,
+        (padded_len - unpadded_len) as u16,
+        data_padding_len,
+        config,
+    ))
+}
+
+fn private_header_with_padding(
+    file: Option<&FileInfo>,
+    pepper: &Salt,
+    padding_len: u16,
+    data_padding_len: u64,
+    config: &EncryptConfig,
+) -> PrivateHeader {
+    match file {
+        Some(file) if !config.options().has(EncOption::HideMeta) => PrivateHeader::new(
+            Some(file.file_name()),
+            file.permissions,
+            file.created_ns,
+            file.changed_ns,
+            accessed_ns(file, config),
+            Some(file.size_b),
+            pepper.clone(),
+            padding_len,
+            data_padding_len,
+        ),
+        _ => PrivateHeader::new(
+            None,
+            None,
+            None,
+            None,
+            None,
+            file.map(|file| file.size_b),
+            pepper.clone(),
+            padding_len,
+            data_padding_len,
+        ),
+    }
+}
+
+/// Encrypt the private header on its own, so that it can be decrypted before the data.
+/// Returns the encrypted header, and the checksum of the unencrypted header.
+fn encrypt_private_header(
+    priv_header: &PrivateHeader,
+    salt: &Salt,
     key: &StretchKey,
-    file: &FileInfo,
     strategy: &Strategy,
     config: &EncryptConfig,
     start_progress: &mut impl FnMut(),
 ) -> FedResult<(Vec<u8>, Checksum)> {
-    // This padding length has expectation value 128, which is probably enough to obfuscate most filename lengths.
     start_progress();
-    let padding_len = pepper.salt[0] as u16;
-    let priv_header = PrivateHeader::new(
-        file.file_name(),
-        file.permissions,
-        file.created_ns,
-        file.changed_ns,
-        file.accessed_ns,
-        file.size_b,
-        pepper.clone(),
-        padding_len,
-    );
     let mut data = Vec::with_capacity(2048);
-    write_private_header(
-        &mut data,
-        &priv_header,
-        config.options(),
-        config.verbosity().debug(),
-    )?;
-    //TODO @mark: encrypt, maybe compress
+    write_private_header(&mut data, priv_header, config.verbosity().debug())?;
     let checksum = calculate_checksum(&data, &mut || {});
-    let secret = encrypt_file(
-        data,
-        &key,
-        &pepper,
+    // The public salt is used, because the pepper is inside the header and is not known when decrypting.
+    if !config.options().has(EncOption::Deterministic) {
+        let secret = encrypt_file(data, key, salt, &strategy.symmetric_algorithms, &mut |_| {});
+        return Ok((secret, checksum));
+    }
+    // The header contains the data checksum, so its synthetic IV is different for different data.
+    let synthetic_iv = wrap_io(
+        || "could not encrypt private header",
+        derive_synthetic_iv(priv_header.pepper(), "header", &mut data.as_slice()),
+    )?;
+    let mut encrypter = EncryptWriter::new(
+        Vec::with_capacity(data.len() + 64),
+        key,
+        salt,
         &strategy.symmetric_algorithms,
         &mut |_| {},
-    );
+    )
+    .with_synthetic_iv(key, synthetic_iv);
+    wrap_io(
+        || "could not encrypt private header",
+        encrypter.write_all(&data),
+    )?;
+    let secret = wrap_io(|| "could not encrypt private header", encrypter.finish())?;
     Ok((secret, checksum))
 }
 
-/// Encrypt one or more files and return the new paths.
-pub fn encrypt(config: &EncryptConfig) -> FedResult<Vec<PathBuf>> {
-    //TODO @mark: break this up into more functions?
-    if config.options().has(EncOption::HideMeta) {
-        eprintln!("metadata hiding not yet implemented");
-    } //TODO @mark: TEMPORARY! REMOVE THIS!
-    if config.options().has(EncOption::PadSize) {
-        eprintln!("size hiding not yet implemented");
-    } //TODO @mark: TEMPORARY! REMOVE THIS!
-    let version = get_current_version();
-    let strategy = get_current_version_strategy(config.options(), config.debug());
-    let files_info = inspect_files(
-        config.files(),
-        config.verbosity(),
-        config.overwrite(),
-        Extension::Add(config.output_extension()),
-        config.output_dir(),
+/// Read the start of the data, to decide whether it is worth compressing.
+fn read_sample(reader: &mut impl Read) -> FedResult<Vec<u8>> {
+    let mut sample = Vec::with_capacity(CHUNK_SIZE);
+    wrap_io(
+        || "could not read input file",
+        reader.take(CHUNK_SIZE as u64).read_to_end(&mut sample),
+    )?;
+    Ok(sample)
+}
+
+/// The options and strategy for one output file. Compression is skipped if the data does not
+/// compress, which is recorded as an option in the header, so that decryption knows about it.
+fn options_for_data(
+    sample: &[u8],
+    strategy: &Strategy,
+    config: &EncryptConfig,
+) -> FedResult<(EncOptionSet, Strategy)> {
+    let mut options = config.options().clone();
+    if let Some(alg) = &strategy.compression_algorithm {
+        if !is_compressible(sample, alg)? {
+            if config.debug() {
+                println!(
+                    "skipping {} compression, because the data does not compress",
+                    alg
+                );
+            }
+            options.add(EncOption::NoCompression);
+            let strategy = get_current_version_strategy(&options, config.debug());
+            return Ok((options, strategy));
+        }
+    }
+    Ok((options, strategy.clone()))
+}
+
+/// Determine the padding needed to hide the size, by compressing the file once without output.
+/// The reader is moved back to the start afterwards.
+fn determine_data_padding(
+    reader: &mut (impl Read + Seek),
+    strategy: &Strategy,
+    config: &EncryptConfig,
+) -> FedResult<u64> {
+    if !config.options().has(EncOption::PadSize) {
+        return Ok(0);
+    }
+    let compressed_len = compressed_size(reader, &strategy.compression_algorithm)?;
+    wrap_io(
+        || "could not read input file",
+        reader.seek(SeekFrom::Start(0)),
+    )?;
+    Ok(data_padding_len(compressed_len))
+}
+
+/// In deterministic mode, the nonce and key are derived from a synthetic IV instead of being random,
+/// so that the same data gives the same output. The synthetic IV is a keyed hash of all the data,
+/// so the reader has to be reset afterwards.
+fn data_synthetic_iv(
+    reader: &mut impl Read,
+    pepper: &Salt,
+    config: &EncryptConfig,
+) -> FedResult<Option<[u8; SYNTHETIC_IV_LEN]>> {
+    if !config.options().has(EncOption::Deterministic) {
+        return Ok(None);
+    }
+    let synthetic_iv = wrap_io(
+        || "could not read input file",
+        derive_synthetic_iv(pepper, "data", reader),
+    )?;
+    Ok(Some(synthetic_iv))
+}
+
+/// Start compressing and encrypting data into `output`. Any padding is encrypted before the
+/// compressed data, so it can be skipped when decrypting. Use `finish_data` after writing all data.
+#[allow(clippy::too_many_arguments)]
+fn start_data<W: Write>(
+    output: W,
+    key: &StretchKey,
+    salt: &Salt,
+    strategy: &Strategy,
+    file: &FileInfo,
+    data_padding_len: u64,
+    pepper: &Salt,
+    synthetic_iv: Option<[u8; SYNTHETIC_IV_LEN]>,
+    progress: &mut dyn Progress,
+) -> FedResult<CompressWriter<EncryptWriter<W>>> {
+    let mut encrypter = EncryptWriter::new(
+        output,
+        key,
+        salt,
+        &strategy.symmetric_algorithms,
+        &mut |alg| progress.start_sym_alg_for_file(alg, file),
+    );
+    if let Some(synthetic_iv) = synthetic_iv {
+        encrypter = encrypter.with_synthetic_iv(key, synthetic_iv);
+    }
+    write_padding(&mut encrypter, pepper, data_padding_len)?;
+    let compressor = CompressWriter::new(encrypter, &strategy.compression_algorithm, &mut |alg| {
+        progress.start_compress_alg_for_file(alg, file)
+    })?;
+    // All steps happen at the same time now, so progress moves on when the reading finishes.
+    progress.start_checksum_for_file(file);
+    Ok(compressor)
+}
+
+/// Stream the file data through compression and encryption, in chunks, so that
+/// the whole file never has to be in memory. Returns the checksum of the input.
+fn encrypt_data(reader: &mut impl Read, compressor: &mut impl Write) -> FedResult<Checksum> {
+    let mut checksum = ChecksumCalculator::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let count = wrap_io(|| "could not read input file", reader.read(&mut buffer))?;
+        if count == 0 {
+            break;
+        }
+        checksum.update(&buffer[..count]);
+        wrap_io(
+            || "failed to encrypt file data",
+            compressor.write_all(&buffer[..count]),
+        )?;
+    }
+    Ok(checksum.finish())
+}
+
+/// Complete the compressed and encrypted data, and write the trailer with the checksum.
+fn finish_data<W: Write>(
+    compressor: CompressWriter<EncryptWriter<W>>,
+    checksum: &Checksum,
+    file: &FileInfo,
+    config: &EncryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<W> {
+    let encrypter = compressor.finish()?;
+    let mut output = wrap_io(|| "failed to encrypt file data", encrypter.finish())?;
+    progress.start_write_for_file(file);
+    write_public_trailer(&mut output, checksum, config.debug())?;
+    wrap_io(
+        || {
+            format!(
+                "Failed to write encrypted output data for '{}'",
+                &file.out_pth.to_string_lossy()
+            )
+        },
+        output.flush(),
+    )?;
+    Ok(output)
+}
+
+/// The chosen key stretching cost, or the default of the strategy.
+fn kdf_cost<'a>(config: &'a EncryptConfig, strategy: &'a Strategy) -> &'a KdfCost {
+    config.kdf_cost().unwrap_or(&strategy.kdf_cost)
+}
+
+/// The public salt and the private pepper. They are random, unless the caller chose them to
+/// make encryption deterministic. That only works if the data key is not random either.
+fn new_salts(config: &EncryptConfig) -> FedResult<(Salt, Salt)> {
+    if !config.options().has(EncOption::Deterministic) {
+        return Ok((Salt::generate_random()?, Salt::generate_random()?));
+    }
+    let (salt, pepper) = config.fixed_salts().ok_or_else(|| {
+        FedError::Usage("deterministic encryption needs a fixed salt and pepper".to_owned())
+    })?;
+    if config.options().has(EncOption::KeySlots) || config.options().has(EncOption::Recipients) {
+        return Err(FedError::Usage(
+            "deterministic encryption cannot be used with key slots or recipients, \
+            because those use a random data key"
+                .to_owned(),
+        ));
+    }
+    if salt == pepper {
+        return Err(FedError::Usage(
+            "the salt and pepper for deterministic encryption must be different, \
+            because the salt is stored unencrypted"
+                .to_owned(),
+        ));
+    }
+    Ok((salt.clone(), pepper.clone()))
+}
+
+/// The key that the data is encrypted with, and the copies of it that are stored in the header.
+struct DataKey {
+    key: Key,
+    recipients: Vec<WrappedKey>,
+    key_slots: Vec<KeySlot>,
+}
+
+/// With key slots, the data key is random, and the password is only used to encrypt a copy of it.
+/// This is done once, so all files share the data key and the slot.
+fn prepare_data_key(
+    config: &EncryptConfig,
+    strategy: &Strategy,
+    progress: &mut dyn Progress,
+) -> FedResult<DataKey> {
+    let (key, key_slots) = if config.options().has(EncOption::KeySlots) {
+        let key = Key::generate_random();
+        let slot = KeySlot::seal(
+            &key,
+            &config.raw_key()?,
+            strategy,
+            kdf_cost(config, strategy),
+            &mut |alg| progress.start_stretch_alg(alg, None),
+        )?;
+        (key, vec![slot])
+    } else {
+        (config.raw_key()?, vec![])
+    };
+    let recipients = config
+        .recipients()
+        .iter()
+        .map(|recipient| wrap_key(&key, recipient))
+        .collect();
+    Ok(DataKey {
+        key,
+        recipients,
+        key_slots,
+    })
+}
+
+/// The output file, or nowhere for a dry run.
+fn open_encrypted_output(config: &EncryptConfig, file: &FileInfo) -> FedResult<Box<dyn Write>> {
+    if config.dry_run() {
+        Ok(Box::new(io::sink()))
+    } else {
+        Ok(Box::new(open_output_file(config, &file.out_pth)?))
+    }
+}
+
+/// Write the public header, followed by the encrypted private header.
+#[allow(clippy::too_many_arguments)]
+fn write_headers(
+    output: &mut impl Write,
+    file: &FileInfo,
+    priv_header: &PrivateHeader,
+    salt: &Salt,
+    key: &StretchKey,
+    data_key: &DataKey,
+    options: &EncOptionSet,
+    strategy: &Strategy,
+    config: &EncryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<()> {
+    let (priv_header_data, priv_header_checksum) =
+        encrypt_private_header(priv_header, salt, key, strategy, config, &mut || {
+            progress.start_private_header_for_file(file)
+        })?;
+    let priv_header_len = priv_header_data.len();
+    let pub_header = PublicHeader::new(
+        get_current_version(),
+        salt.clone(),
+        options.clone(),
+        (priv_header_len as u64, priv_header_checksum),
+    )
+    .with_recipients(data_key.recipients.clone())
+    .with_key_slots(data_key.key_slots.clone())
+    .with_kdf_cost(Some(kdf_cost(config, strategy).clone()));
+    write_public_header(output, &pub_header, config.debug())?;
+    wrap_io(
+        || {
+            format!(
+                "Failed to write encrypted output data for '{}'",
+                &file.out_pth.to_string_lossy()
+            )
+        },
+        output.write_all(&priv_header_data),
+    )
+}
+
+/// Write the headers, the encrypted data and the trailer. The reader should be at the start of the data.
+#[allow(clippy::too_many_arguments)]
+fn write_encrypted(
+    reader: &mut impl Read,
+    output: impl Write,
+    file: &FileInfo,
+    priv_header: &PrivateHeader,
+    salt: &Salt,
+    key: &StretchKey,
+    data_key: &DataKey,
+    options: &EncOptionSet,
+    strategy: &Strategy,
+    synthetic_iv: Option<[u8; SYNTHETIC_IV_LEN]>,
+    config: &EncryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<()> {
+    let mut output = output;
+    write_headers(
+        &mut output,
+        file,
+        priv_header,
+        salt,
+        key,
+        data_key,
+        options,
+        strategy,
+        config,
+        progress,
+    )?;
+    let mut compressor = start_data(
+        output,
+        key,
+        salt,
+        strategy,
+        file,
+        priv_header.data_padding_len(),
+        priv_header.pepper(),
+        synthetic_iv,
+        progress,
     )?;
-    let mut progress: Box<dyn Progress> = match config.verbosity() {
+    let checksum = encrypt_data(reader, &mut compressor)?;
+    finish_data(compressor, &checksum, file, config, progress)?;
+    Ok(())
+}
+
+fn new_progress(
+    config: &EncryptConfig,
+    strategy: &Strategy,
+    files_info: &[FileInfo],
+    delete_input: bool,
+) -> Box<dyn Progress + Send> {
+    match config.verbosity() {
         Verbosity::Quiet => Box::new(SilentProgress::new()),
         Verbosity::Normal => Box::new(IndicatifProgress::new_enc_strategy(
-            &strategy,
-            &files_info,
-            config.delete_input(),
+            strategy,
+            files_info,
+            delete_input,
             config.verbosity(),
         )),
         Verbosity::Debug => Box::new(LogProgress::new()),
+    }
+}
+
+/// Encrypt a single file and report the output path.
+#[allow(clippy::too_many_arguments)]
+fn encrypt_one(
+    file: &FileInfo,
+    salt: &Salt,
+    pepper: &Salt,
+    stretched_key: &StretchKey,
+    data_key: &DataKey,
+    strategy: &Strategy,
+    config: &EncryptConfig,
+    progress: &mut dyn Progress,
+) -> FedResult<FileReport> {
+    let mut reader = open_reader(file, config.verbosity())?;
+    let sample = read_sample(&mut reader)?;
+    wrap_io(
+        || "could not read input file",
+        reader.seek(SeekFrom::Start(0)),
+    )?;
+    let (options, strategy) = options_for_data(&sample, strategy, config)?;
+    let data_padding_len = determine_data_padding(&mut reader, &strategy, config)?;
+    let synthetic_iv = data_synthetic_iv(&mut reader, pepper, config)?;
+    wrap_io(
+        || "could not read input file",
+        reader.seek(SeekFrom::Start(0)),
+    )?;
+    start_reading_file(
+        &mut reader,
+        &file.path_str(),
+        config.verbosity(),
+        &mut || progress.start_read_for_file(file),
+    )?;
+    let priv_header = new_private_header(Some(file), pepper, data_padding_len, config)?;
+    write_encrypted(
+        &mut reader,
+        open_encrypted_output(config, file)?,
+        file,
+        &priv_header,
+        salt,
+        stretched_key,
+        data_key,
+        &options,
+        &strategy,
+        synthetic_iv,
+        config,
+        progress,
+    )?;
+    if !config.dry_run() {
+        if config.debug() {
+            println!("encrypted {}", &file.file_name());
+        }
+        //TODO @mark: test that file is removed?
+        delete_input_file(
+            config.delete_input(),
+            file,
+            &mut || progress.start_shred_input_for_file(file),
+            config.verbosity(),
+        )?;
+    } else if !config.quiet() {
+        println!(
+            "successfully encrypted '{}' ({} kb); not saving to '{}' because of dry-run",
+            file.path_str(),
+            file.size_kb(),
+            &file.out_pth.to_string_lossy(),
+        );
+    }
+    Ok(FileReport::new(
+        file.in_path,
+        vec![file.out_pth.clone()],
+        file.size_b,
+        ChecksumStatus::Created,
+    ))
+}
+
+/// Encrypt one or more files and return the new paths. Fails if any of the files failed.
+pub fn encrypt(config: &EncryptConfig) -> FedResult<Vec<PathBuf>> {
+    let reports = encrypt_report(config)?;
+    if let Some(err) = failure_error(&reports) {
+        return Err(err);
+    }
+    let mut out_pths = reports
+        .into_iter()
+        .flat_map(FileReport::into_outputs)
+        .collect::<Vec<_>>();
+    // All members of an archive report the same output.
+    out_pths.dedup();
+    Ok(out_pths)
+}
+
+/// Encrypt one or more files and report the result for each of them. Files that failed are
+/// reported with their error, instead of failing, so that the results of other files are not lost.
+pub fn encrypt_report(config: &EncryptConfig) -> FedResult<Vec<FileReport>> {
+    //TODO @mark: break this up into more functions?
+    let strategy = &get_current_version_strategy(config.options(), config.debug());
+    // Files that are already encrypted are skipped when walking directories.
+    let input_files = collect_input_files(config.files(), config.selection(), |pth| {
+        !pth.to_string_lossy().ends_with(config.output_extension())
+    })?;
+    if let Some(archive_pth) = config.archive() {
+        return encrypt_archive(config, strategy, &input_files, archive_pth);
+    }
+    // Hidden metadata includes the filename, so use a random one.
+    let extension = if config.options().has(EncOption::HideMeta) {
+        Extension::Obfuscate(config.output_extension())
+    } else {
+        Extension::Add(config.output_extension())
     };
+    let files_info = inspect_files(
+        &input_files,
+        config.verbosity(),
+        config.overwrite(),
+        extension,
+        config.output_dir(),
+    )?;
+    let mut progress = SharedProgress::new(new_progress(
+        config,
+        strategy,
+        &files_info,
+        config.delete_input(),
+    ));
     // Public and private salt are different from eachother, but the same for all files.
-    let salt = Salt::generate_random()?;
-    let pepper = Salt::generate_random()?;
+    let (salt, pepper) = new_salts(config)?;
+    let data_key = prepare_data_key(config, strategy, &mut progress)?;
     let stretched_key = stretch_key(
-        config.raw_key(),
+        &data_key.key,
         &salt,
-        strategy.stretch_count,
+        kdf_cost(config, strategy),
         &strategy.key_hash_algorithms,
-        &mut |alg| progress.start_stretch_alg(&alg, None),
-    );
-    let mut out_pths = vec![];
-    for file in &files_info {
-        let (priv_header_data, priv_header_checksum) = encrypt_private_header(
+        &mut |alg| progress.start_stretch_alg(alg, None),
+    )?;
+    // Files are independent, so they can be processed concurrently.
+    let results = run_parallel(config.jobs(), &files_info, |file| {
+        let mut progress = progress.clone();
+        encrypt_one(
+            file,
+            &salt,
             &pepper,
             &stretched_key,
+            &data_key,
+            strategy,
+            config,
+            &mut progress,
+        )
+    });
+    let reports = results
+        .into_iter()
+        .zip(&files_info)
+        .map(|(result, file)| {
+            result.unwrap_or_else(|err| FileReport::failed(file.in_path, file.size_b, err))
+        })
+        .collect::<Vec<_>>();
+    progress.finish();
+    if !config.quiet() {
+        let success_count = reports
+            .iter()
+            .filter(|report| report.error().is_none())
+            .count();
+        println!("encrypted {} files", success_count);
+    }
+    Ok(reports)
+}
+
+/// What has been written to an `EncryptingWriter` so far.
+enum EncryptingState<W: Write> {
+    /// The headers depend on the start of the data, so it is kept until there is enough.
+    Buffering(W, Vec<u8>),
+    Encrypting(Box<CompressWriter<EncryptWriter<W>>>, ChecksumCalculator),
+    Failed,
+}
+
+/// Encrypts everything written to it into another writer, like stdout, in the same format as
+/// `encrypt_stream`. Use `finish` after writing all data, to complete the output. The start of
+/// the data is kept in memory to decide about compression.
+pub struct EncryptingWriter<'a, W: Write> {
+    config: &'a EncryptConfig,
+    strategy: Strategy,
+    file: FileInfo<'static>,
+    salt: Salt,
+    pepper: Salt,
+    stretched_key: StretchKey,
+    data_key: DataKey,
+    progress: Box<dyn Progress + Send>,
+    state: EncryptingState<W>,
+}
+
+impl<'a, W: Write> EncryptingWriter<'a, W> {
+    /// Prepare the key. The size is not known in advance, so it is not stored, and it cannot be
+    /// hidden. No other metadata is stored either.
+    pub fn new(writer: W, config: &'a EncryptConfig) -> FedResult<Self> {
+        let options = config.options();
+        if options.has(EncOption::PadSize)
+            || options.has(EncOption::Archive)
+            || options.has(EncOption::Deterministic)
+        {
+            return Err(FedError::Usage(
+                "streams cannot be encrypted with hidden size, deterministically or into an \
+                archive, because that needs all the data up front"
+                    .to_owned(),
+            ));
+        }
+        let strategy = get_current_version_strategy(options, config.debug());
+        let file = FileInfo::stream();
+        let mut progress = new_progress(config, &strategy, slice::from_ref(&file), false);
+        let (salt, pepper) = new_salts(config)?;
+        let data_key = prepare_data_key(config, &strategy, progress.as_mut())?;
+        let stretched_key = stretch_key(
+            &data_key.key,
+            &salt,
+            kdf_cost(config, &strategy),
+            &strategy.key_hash_algorithms,
+            &mut |alg| progress.start_stretch_alg(alg, None),
+        )?;
+        progress.start_read_for_file(&file);
+        Ok(EncryptingWriter {
+            config,
+            strategy,
             file,
+            salt,
+            pepper,
+            stretched_key,
+            data_key,
+            progress,
+            state: EncryptingState::Buffering(writer, Vec::with_capacity(CHUNK_SIZE)),
+        })
+    }
+
+    /// Write the headers and the buffered data, after which data is encrypted as it is written.
+    fn start(&mut self) -> FedResult<()> {
+        let (mut writer, buffered) = match mem::replace(&mut self.state, EncryptingState::Failed) {
+            EncryptingState::Buffering(writer, buffered) => (writer, buffered),
+            state => {
+                self.state = state;
+                return Ok(());
+            }
+        };
+        let sample = &buffered[..buffered.len().min(CHUNK_SIZE)];
+        let (options, strategy) = options_for_data(sample, &self.strategy, self.config)?;
+        let priv_header = new_private_header(None, &self.pepper, 0, self.config)?;
+        write_headers(
+            &mut writer,
+            &self.file,
+            &priv_header,
+            &self.salt,
+            &self.stretched_key,
+            &self.data_key,
+            &options,
             &strategy,
-            config,
-            &mut || progress.start_private_header_for_file(&file),
+            self.config,
+            self.progress.as_mut(),
         )?;
-        let priv_header_len = priv_header_data.len();
-
-        let mut reader = open_reader(&file, config.verbosity())?;
-        let mut data = Vec::with_capacity(file.size_b as usize + priv_header_len + 2048);
-        todo!("private header should be encrypted separately, because it has to be decrypted separately to deal with padding");
-        data.extend(priv_header_data);
-        read_file(
-            &mut data,
-            &mut reader,
-            &file.path_str(),
-            file.size_kb(),
-            config.verbosity(),
-            &mut || progress.start_read_for_file(&file),
+        let mut compressor = start_data(
+            writer,
+            &self.stretched_key,
+            &self.salt,
+            &strategy,
+            &self.file,
+            0,
+            &self.pepper,
+            None,
+            self.progress.as_mut(),
         )?;
-        // Do not include the private header in the checksum (by skipping it).
-        let data_checksum = calculate_checksum(&data[priv_header_len..], &mut || {
-            progress.start_checksum_for_file(&file)
-        });
-        //TODO @mark: why isn't `priv_header_data` written?
-        let small = compress_file(data, &strategy.compression_algorithm, &mut |alg| {
-            progress.start_compress_alg_for_file(&alg, &file)
-        })?;
-        let secret = encrypt_file(
-            small,
-            &stretched_key,
-            &salt,
-            &strategy.symmetric_algorithms,
-            &mut |alg| progress.start_sym_alg_for_file(&alg, &file),
-        );
-        //TODO @mark: private header meta
-        let pub_header = PublicHeader::new(
-            version.clone(),
-            salt.clone(),
-            data_checksum,
-            config.options().clone(),
-            (priv_header_len as u64, priv_header_checksum),
-        );
-        if !config.dry_run() {
-            write_output_file(config, &file, &secret, Some(&pub_header), &mut || {
-                progress.start_write_for_file(&file)
-            })?;
-            //TODO @mark: test that file is removed?
-            delete_input_file(
-                config.delete_input(),
-                file,
-                &mut || progress.start_shred_input_for_file(&file),
-                config.verbosity(),
-            )?;
-        } else if !config.quiet() {
-            progress.start_write_for_file(&file);
-            println!(
-                "successfully encrypted '{}' ({} kb); not saving to '{}' because of dry-run",
-                file.path_str(),
-                secret.len() / 1024,
-                &file.out_pth.to_string_lossy(),
-            );
+        let mut checksum = ChecksumCalculator::new();
+        checksum.update(&buffered);
+        wrap_io(|| "failed to encrypt data", compressor.write_all(&buffered))?;
+        self.state = EncryptingState::Encrypting(Box::new(compressor), checksum);
+        Ok(())
+    }
+
+    /// Like `write`, but keeps the original error.
+    fn write_data(&mut self, data: &[u8]) -> FedResult<()> {
+        match &mut self.state {
+            EncryptingState::Buffering(_, buffered) => {
+                buffered.extend_from_slice(data);
+                if buffered.len() >= CHUNK_SIZE {
+                    self.start()?;
+                }
+                Ok(())
+            }
+            EncryptingState::Encrypting(compressor, checksum) => {
+                checksum.update(data);
+                wrap_io(|| "failed to encrypt data", compressor.write_all(data))
+            }
+            EncryptingState::Failed => Err(FedError::Other(
+                "cannot encrypt more data, because encryption failed earlier".to_owned(),
+            )),
+        }
+    }
+
+    /// Complete the encrypted data and write the trailer, then return the inner writer.
+    pub fn finish(mut self) -> FedResult<W> {
+        self.start()?;
+        match mem::replace(&mut self.state, EncryptingState::Failed) {
+            EncryptingState::Encrypting(compressor, checksum) => {
+                let writer = finish_data(
+                    *compressor,
+                    &checksum.finish(),
+                    &self.file,
+                    self.config,
+                    self.progress.as_mut(),
+                )?;
+                self.progress.finish();
+                Ok(writer)
+            }
+            _ => Err(FedError::Other(
+                "cannot finish encryption, because it failed earlier".to_owned(),
+            )),
+        }
+    }
+}
+
+impl<'a, W: Write> Write for EncryptingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_data(buf)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Compressed and encrypted data is only complete at the end, so use `finish` instead.
+        Ok(())
+    }
+}
+
+/// Encrypt data from a stream, like stdin, into another stream. The size is not known in
+/// advance, so it is not stored, and it cannot be hidden. No other metadata is stored either.
+pub fn encrypt_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    config: &EncryptConfig,
+) -> FedResult<()> {
+    let mut encrypter = EncryptingWriter::new(writer, config)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let count = wrap_io(|| "could not read input file", reader.read(&mut buffer))?;
+        if count == 0 {
+            break;
+        }
+        encrypter.write_data(&buffer[..count])?;
+    }
+    encrypter.finish()?;
+    Ok(())
+}
+
+/// Encrypt data in memory, in the same format that `fileenc` uses for streams. It can be decrypted
+/// with `decrypt_bytes`, or with `filedec` like any other file. Like for streams, the size cannot
+/// be hidden, and the output is not deterministic and not an archive.
+pub fn encrypt_bytes(data: &[u8], key: &Key, options: &EncOptionSet) -> FedResult<Vec<u8>> {
+    let config = EncryptConfig::builder(key.clone())
+        .with_file(STREAM_PATH)
+        .with_options(options.clone())
+        .with_verbosity(Verbosity::Quiet)
+        .build()?;
+    let mut secret = Vec::with_capacity(data.len() + 1024);
+    encrypt_stream(&mut &*data, &mut secret, &config)?;
+    Ok(secret)
+}
+
+/// Pack all the files into a single encrypted archive. Its data is an index, with a private
+/// header for each file, followed by the data of each file. Each file reports the archive path.
+fn encrypt_archive(
+    config: &EncryptConfig,
+    strategy: &Strategy,
+    input_files: &[InputFile],
+    archive_pth: &Path,
+) -> FedResult<Vec<FileReport>> {
+    // The output paths of individual files are not used, so they may exist.
+    let files_info = inspect_files(
+        input_files,
+        config.verbosity(),
+        true,
+        Extension::Add(config.output_extension()),
+        None,
+    )?;
+    let (salt, pepper) = new_salts(config)?;
+    let members = files_info
+        .iter()
+        .zip(member_names(input_files)?)
+        .map(|(file, name)| {
+            if config.options().has(EncOption::HideMeta) {
+                PrivateHeader::new(
+                    Some(name),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some(file.size_b),
+                    pepper.clone(),
+                    0,
+                    0,
+                )
+            } else {
+                PrivateHeader::new(
+                    Some(name),
+                    file.permissions,
+                    file.created_ns,
+                    file.changed_ns,
+                    accessed_ns(file, config),
+                    Some(file.size_b),
+                    pepper.clone(),
+                    0,
+                    0,
+                )
+            }
+        })
+        .collect();
+    let index = ArchiveIndex::new(members);
+    let mut index_data = Vec::with_capacity(256 * files_info.len());
+    write_archive_index(&mut index_data, &index, config.debug())?;
+
+    let out_pth = match config.output_dir() {
+        Some(dir) => dir.join(archive_pth),
+        None => archive_pth.to_owned(),
+    };
+    let archive_info = FileInfo {
+        in_path: &out_pth,
+        size_b: index_data.len() as u64 + index.data_size(),
+        permissions: None,
+        created_ns: None,
+        changed_ns: None,
+        accessed_ns: None,
+        out_pth: out_pth.clone(),
+    };
+    let mut progress = new_progress(config, strategy, slice::from_ref(&archive_info), false);
+    let data_key = prepare_data_key(config, strategy, progress.as_mut())?;
+    let stretched_key = stretch_key(
+        &data_key.key,
+        &salt,
+        kdf_cost(config, strategy),
+        &strategy.key_hash_algorithms,
+        &mut |alg| progress.start_stretch_alg(alg, None),
+    )?;
+    let sample = read_sample(&mut ArchiveReader::new(index_data.clone(), &files_info))?;
+    let (options, strategy) = options_for_data(&sample, strategy, config)?;
+    let data_padding_len = if config.options().has(EncOption::PadSize) {
+        let mut reader = ArchiveReader::new(index_data.clone(), &files_info);
+        data_padding_len(compressed_size(
+            &mut reader,
+            &strategy.compression_algorithm,
+        )?)
+    } else {
+        0
+    };
+    let synthetic_iv = data_synthetic_iv(
+        &mut ArchiveReader::new(index_data.clone(), &files_info),
+        &pepper,
+        config,
+    )?;
+    progress.start_read_for_file(&archive_info);
+    let priv_header = new_private_header(Some(&archive_info), &pepper, data_padding_len, config)?;
+    write_encrypted(
+        &mut ArchiveReader::new(index_data, &files_info),
+        open_encrypted_output(config, &archive_info)?,
+        &archive_info,
+        &priv_header,
+        &salt,
+        &stretched_key,
+        &data_key,
+        &options,
+        &strategy,
+        synthetic_iv,
+        config,
+        progress.as_mut(),
+    )?;
+    if !config.dry_run() {
+        for file in &files_info {
+            delete_input_file(config.delete_input(), file, &mut || {}, config.verbosity())?;
         }
-        out_pths.push(file.out_pth.clone());
     }
     progress.finish();
     if !config.quiet() {
-        println!("encrypted {} files", files_info.len());
+        println!(
+            "encrypted {} files into archive '{}'{}",
+            files_info.len(),
+            out_pth.to_string_lossy(),
+            if config.dry_run() {
+                " (not saved because of dry-run)"
+            } else {
+                ""
+            }
+        );
     }
-    Ok(out_pths)
+    Ok(files_info
+        .iter()
+        .map(|file| {
+            FileReport::new(
+                file.in_path,
+                vec![out_pth.clone()],
+                file.size_b,
+                ChecksumStatus::Created,
+            )
+        })
+        .collect())
 }
 
 /// The demo used in this blog post:
@@ -187,19 +996,29 @@ pub fn encrypt(config: &EncryptConfig) -> FedResult<Vec<PathBuf>> {
 #[cfg(test)]
 mod tests {
     use ::std::fs;
+    use ::std::path::{Path, PathBuf};
 
     use ::lazy_static::lazy_static;
     use tempfile::tempdir;
 
-    use crate::config::enc::RunMode;
-    use crate::config::typ::{InputAction, OnFileExist};
-    use crate::config::EncryptConfig;
-    use crate::encrypt;
-    use crate::files::scan::TEST_FILE_DIR;
+    use crate::config::typ::{ArchiveAction, EndecConfig, Extension, FileSelection, OnFileExist};
+    use crate::config::{DecryptConfig, EncryptConfig};
+    use crate::files::file_meta::inspect_files;
+    use crate::files::read_headers::read_file_strategies;
+    use crate::files::reading::open_reader;
+    use crate::files::scan::{InputFile, TEST_FILE_DIR};
+    use crate::header::parse_public_header;
     use crate::header::strategy::Verbosity;
+    use crate::key::cost::KdfCost;
     use crate::key::key::Key;
-    use crate::util::option::EncOptionSet;
+    use crate::key::random::generate_secure_random_timed;
+    use crate::key::recipient::Identity;
+    use crate::key::stretch::stretch_key;
+    use crate::key::Salt;
+    use crate::orchestrate::decrypt::read_private_header;
+    use crate::util::option::{EncOption, EncOptionSet};
     use crate::util::version::get_current_version;
+    use crate::{decrypt, decrypt_bytes, encrypt, encrypt_bytes, encrypt_stream};
 
     lazy_static! {
         static ref COMPAT_KEY: Key = Key::new(" LP0y#shbogtwhGjM=*jFFZPmNd&qBO+ ");
@@ -222,6 +1041,19 @@ mod tests {
                 postfix: "_fast_hide".to_owned(),
                 options: EncOptionSet::all_for_test(),
             },
+            // Compression choices are supported from version 1.4.0
+            Variation {
+                postfix: "_zstd".to_owned(),
+                options: vec![EncOption::Fast, EncOption::Zstd].into(),
+            },
+            Variation {
+                postfix: "_xz".to_owned(),
+                options: vec![EncOption::Fast, EncOption::Xz].into(),
+            },
+            Variation {
+                postfix: "_uncompressed".to_owned(),
+                options: vec![EncOption::Fast, EncOption::NoCompression].into(),
+            },
         ]
     }
 
@@ -236,24 +1068,16 @@ mod tests {
         };
         assert!(in_pth.exists());
         for variation in variations() {
-            let conf = EncryptConfig::new(
-                vec![in_pth.clone()],
-                COMPAT_KEY.clone(),
+            let conf = EncryptConfig::builder(COMPAT_KEY.clone())
+                .with_file(in_pth.clone())
                 //TODO @mark: try different options
-                variation.options,
-                Verbosity::Debug,
-                OnFileExist::Overwrite,
-                InputAction::Keep,
-                Some(dir.path().to_owned()),
-                ".enc".to_string(),
-                RunMode::IsReal,
-            );
-            let tmp_pth = {
-                let mut p = dir.path().to_owned();
-                p.push("original.png.enc");
-                p
-            };
-            encrypt(&conf).unwrap();
+                .with_options(variation.options)
+                .with_verbosity(Verbosity::Debug)
+                .with_overwrite(OnFileExist::Overwrite)
+                .with_output_dir(dir.path().to_owned())
+                .build()
+                .unwrap();
+            let tmp_pth = encrypt(&conf).unwrap().remove(0);
             assert!(tmp_pth.is_file(), "encrypted file was not created");
             let store_pth = {
                 let mut p = TEST_FILE_DIR.clone();
@@ -276,4 +1100,556 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn round_trip_variations() {
+        let in_pth = {
+            let mut p = TEST_FILE_DIR.clone();
+            p.push("original.png");
+            p
+        };
+        let original = fs::read(&in_pth).unwrap();
+        for variation in variations() {
+            let hide_meta = variation.options.has(EncOption::HideMeta);
+            let enc_dir = tempdir().unwrap();
+            let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+                .with_file(in_pth.clone())
+                .with_options(variation.options)
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(enc_dir.path().to_owned())
+                .build()
+                .unwrap();
+            let enc_pths = encrypt(&enc_conf).unwrap();
+            let dec_dir = tempdir().unwrap();
+            let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(dec_dir.path().to_owned())
+                .build()
+                .unwrap();
+
+            // Check the private header.
+            let input_files = dec_conf
+                .files()
+                .iter()
+                .map(|pth| InputFile::direct(pth.clone()))
+                .collect::<Vec<_>>();
+            let files_info = inspect_files(
+                &input_files,
+                Verbosity::Quiet,
+                false,
+                Extension::Strip,
+                dec_conf.output_dir(),
+            )
+            .unwrap();
+            let file_strat = &read_file_strategies(&files_info, Verbosity::Quiet).unwrap()[0];
+            let key = stretch_key(
+                &COMPAT_KEY,
+                file_strat.pub_header.salt(),
+                &file_strat.kdf_cost,
+                &file_strat.strategy.key_hash_algorithms,
+                &mut |_| {},
+            )
+            .unwrap();
+            let mut reader = open_reader(file_strat.file, Verbosity::Quiet).unwrap();
+            let (_, priv_header) = read_private_header(&mut reader, file_strat, &key, &mut || {})
+                .unwrap()
+                .unwrap();
+            let enc_name = enc_pths[0].file_name().unwrap().to_str().unwrap();
+            if hide_meta {
+                assert!(!enc_name.contains("original"));
+                assert_eq!(None, priv_header.filename());
+                assert_eq!(None, priv_header.permissions());
+                assert_eq!(None, priv_header.changed_ns());
+            } else {
+                assert_eq!("original.png.enc", enc_name);
+                assert_eq!(Some("original.png"), priv_header.filename());
+            }
+            assert_eq!(Some(original.len() as u64), priv_header.size());
+
+            // Check the data.
+            let dec_pths = decrypt(&dec_conf).unwrap();
+            assert_eq!(original, fs::read(&dec_pths[0]).unwrap());
+            let dec_name = dec_pths[0].file_name().unwrap().to_str().unwrap();
+            if hide_meta {
+                assert_eq!(enc_name.trim_end_matches(".enc"), dec_name);
+            } else {
+                assert_eq!("original.png", dec_name);
+                let original_meta = fs::metadata(&in_pth).unwrap();
+                let dec_meta = fs::metadata(&dec_pths[0]).unwrap();
+                assert_eq!(
+                    original_meta.modified().unwrap(),
+                    dec_meta.modified().unwrap()
+                );
+                assert_eq!(original_meta.permissions(), dec_meta.permissions());
+            }
+        }
+    }
+
+    #[test]
+    fn padded_size_buckets() {
+        let dir = tempdir().unwrap();
+        let mut in_pths = vec![];
+        // Random data barely compresses, so these sizes end up in the same bucket.
+        // The names differ in length, which should not show either.
+        for (name, size) in &[("a", 5_000), ("a_much_longer_name.bin", 6_000)] {
+            let mut data = vec![0u8; *size];
+            generate_secure_random_timed(&mut data);
+            let pth = dir.path().join(name);
+            fs::write(&pth, &data).unwrap();
+            in_pths.push(pth);
+        }
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(in_pths.clone())
+            .with_option(EncOption::Fast)
+            .with_option(EncOption::PadSize)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let enc_lens = enc_pths
+            .iter()
+            .map(|pth| fs::metadata(pth).unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(enc_lens[0], enc_lens[1]);
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        for (in_pth, dec_pth) in in_pths.iter().zip(dec_pths.iter()) {
+            assert_eq!(fs::read(in_pth).unwrap(), fs::read(dec_pth).unwrap());
+        }
+    }
+
+    #[test]
+    fn recursive_round_trip() {
+        let in_dir = tempdir().unwrap();
+        fs::create_dir_all(in_dir.path().join("sub/deeper")).unwrap();
+        for name in &[
+            "top.txt",
+            "sub/middle.txt",
+            "sub/deeper/bottom.txt",
+            "sub/skip.log",
+        ] {
+            fs::write(in_dir.path().join(name), name.as_bytes()).unwrap();
+        }
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_dir.path().to_owned())
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .with_selection(FileSelection::new(true, &[], &["*.log".to_owned()]).unwrap())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        assert_eq!(
+            vec![
+                enc_dir.path().join("sub/deeper/bottom.txt.enc"),
+                enc_dir.path().join("sub/middle.txt.enc"),
+                enc_dir.path().join("top.txt.enc"),
+            ],
+            enc_pths
+        );
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(enc_dir.path().to_owned())
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .with_selection(FileSelection::new(true, &[], &[]).unwrap())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(3, dec_pths.len());
+        for name in &["top.txt", "sub/middle.txt", "sub/deeper/bottom.txt"] {
+            let content = fs::read(dec_dir.path().join(name)).unwrap();
+            assert_eq!(name.as_bytes(), content.as_slice());
+        }
+        assert!(!dec_dir.path().join("sub/skip.log").exists());
+    }
+
+    #[test]
+    fn recursive_hide_meta_is_flat() {
+        let in_dir = tempdir().unwrap();
+        fs::create_dir_all(in_dir.path().join("sub/deeper")).unwrap();
+        for name in &["top.txt", "sub/middle.txt", "sub/deeper/bottom.txt"] {
+            fs::write(in_dir.path().join(name), name.as_bytes()).unwrap();
+        }
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_dir.path())
+            .with_options(vec![EncOption::Fast, EncOption::HideMeta].into())
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path())
+            .with_output_extension("secret")
+            .with_selection(FileSelection::new(true, &[], &[]).unwrap())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        assert_eq!(3, enc_pths.len());
+        for pth in &enc_pths {
+            assert_eq!(enc_dir.path(), pth.parent().unwrap());
+        }
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(enc_dir.path())
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path())
+            .with_input_extension("secret")
+            .with_selection(FileSelection::new(true, &[], &[]).unwrap())
+            .build()
+            .unwrap();
+        let mut contents = decrypt(&dec_conf)
+            .unwrap()
+            .iter()
+            .map(|pth| fs::read_to_string(pth).unwrap())
+            .collect::<Vec<_>>();
+        contents.sort();
+        assert_eq!(
+            vec!["sub/deeper/bottom.txt", "sub/middle.txt", "top.txt"],
+            contents
+        );
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let in_dir = tempdir().unwrap();
+        fs::create_dir_all(in_dir.path().join("sub")).unwrap();
+        for name in &["top.txt", "sub/middle.txt", "sub/empty.txt"] {
+            let content = if name.contains("empty") { "" } else { name };
+            fs::write(in_dir.path().join(name), content).unwrap();
+        }
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_dir.path().to_owned())
+            .with_option(EncOption::Fast)
+            .with_option(EncOption::PadSize)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .with_selection(FileSelection::new(true, &[], &[]).unwrap())
+            .with_archive(PathBuf::from("all.enc"))
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        assert_eq!(vec![enc_dir.path().join("all.enc")], enc_pths);
+        assert_eq!(1, fs::read_dir(enc_dir.path()).unwrap().count());
+
+        let decrypt_with = |action: ArchiveAction, out_dir: &Path| {
+            let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(out_dir.to_owned())
+                .with_archive_action(action)
+                .build()
+                .unwrap();
+            decrypt(&dec_conf)
+        };
+
+        let list_dir = tempdir().unwrap();
+        assert!(decrypt_with(ArchiveAction::List, list_dir.path())
+            .unwrap()
+            .is_empty());
+        assert_eq!(0, fs::read_dir(list_dir.path()).unwrap().count());
+
+        let single_dir = tempdir().unwrap();
+        let single_action = ArchiveAction::Extract(vec!["sub/middle.txt".to_owned()]);
+        let dec_pths = decrypt_with(single_action, single_dir.path()).unwrap();
+        assert_eq!(vec![single_dir.path().join("sub/middle.txt")], dec_pths);
+        assert_eq!(
+            b"sub/middle.txt",
+            fs::read(&dec_pths[0]).unwrap().as_slice()
+        );
+        assert!(!single_dir.path().join("top.txt").exists());
+
+        let missing_action = ArchiveAction::Extract(vec!["nope.txt".to_owned()]);
+        let err = decrypt_with(missing_action, single_dir.path()).unwrap_err();
+        assert!(err.message().contains("no file 'nope.txt'"));
+
+        let all_dir = tempdir().unwrap();
+        let dec_pths = decrypt_with(ArchiveAction::ExtractAll, all_dir.path()).unwrap();
+        assert_eq!(3, dec_pths.len());
+        for name in &["top.txt", "sub/middle.txt", "sub/empty.txt"] {
+            assert_eq!(
+                fs::read(in_dir.path().join(name)).unwrap(),
+                fs::read(all_dir.path().join(name)).unwrap()
+            );
+        }
+        let original_meta = fs::metadata(in_dir.path().join("top.txt")).unwrap();
+        let dec_meta = fs::metadata(all_dir.path().join("top.txt")).unwrap();
+        assert_eq!(
+            original_meta.modified().unwrap(),
+            dec_meta.modified().unwrap()
+        );
+    }
+
+    #[test]
+    fn parallel_round_trip() {
+        let in_dir = tempdir().unwrap();
+        let names = (0..6)
+            .map(|nr| format!("file_{}.txt", nr))
+            .collect::<Vec<_>>();
+        for name in &names {
+            fs::write(in_dir.path().join(name), name.repeat(100)).unwrap();
+        }
+        let in_pths = names
+            .iter()
+            .map(|name| in_dir.path().join(name))
+            .collect::<Vec<_>>();
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(in_pths.clone())
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .with_jobs(4)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let expected_enc_pths = names
+            .iter()
+            .map(|name| enc_dir.path().join(format!("{}.enc", name)))
+            .collect::<Vec<_>>();
+        assert_eq!(expected_enc_pths, enc_pths);
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .with_jobs(4)
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(names.len(), dec_pths.len());
+        for (name, dec_pth) in names.iter().zip(&dec_pths) {
+            assert_eq!(&dec_dir.path().join(name), dec_pth);
+            assert_eq!(
+                name.repeat(100).as_bytes(),
+                fs::read(dec_pth).unwrap().as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn skip_incompressible() {
+        let in_dir = tempdir().unwrap();
+        let mut random = vec![0; 100_000];
+        generate_secure_random_timed(&mut random);
+        let text = "compressible text ".repeat(5_000).into_bytes();
+        let in_pths = vec![in_dir.path().join("random"), in_dir.path().join("text")];
+        fs::write(&in_pths[0], &random).unwrap();
+        fs::write(&in_pths[1], &text).unwrap();
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(in_pths)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let input_files = enc_pths
+            .iter()
+            .map(|pth| InputFile::direct(pth.clone()))
+            .collect::<Vec<_>>();
+        let files_info =
+            inspect_files(&input_files, Verbosity::Quiet, true, Extension::Strip, None).unwrap();
+        let file_strats = read_file_strategies(&files_info, Verbosity::Quiet).unwrap();
+        assert!(file_strats[0]
+            .pub_header
+            .options()
+            .has(EncOption::NoCompression));
+        assert_eq!(None, file_strats[0].strategy.compression_algorithm);
+        assert!(!file_strats[1]
+            .pub_header
+            .options()
+            .has(EncOption::NoCompression));
+        assert!(file_strats[1].strategy.compression_algorithm.is_some());
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(random, fs::read(&dec_pths[0]).unwrap());
+        assert_eq!(text, fs::read(&dec_pths[1]).unwrap());
+    }
+
+    #[test]
+    fn skip_incompressible_failures() {
+        // Without decompression, damage has to be found by authentication or the checksum.
+        let mut random = vec![0; 100_000];
+        generate_secure_random_timed(&mut random);
+        let secret = encrypt_bytes(&random, &COMPAT_KEY, &vec![EncOption::Fast].into()).unwrap();
+        let (_, header) = parse_public_header(&mut secret.as_slice(), false).unwrap();
+        assert!(header.options().has(EncOption::NoCompression));
+        assert_eq!(random, decrypt_bytes(&secret, &COMPAT_KEY).unwrap());
+        let wrong = decrypt_bytes(&secret, &Key::new("wrong")).unwrap_err();
+        assert_eq!("wrong-key", wrong.code());
+        let truncated = decrypt_bytes(&secret[..secret.len() / 2], &COMPAT_KEY).unwrap_err();
+        assert_eq!("corrupted", truncated.code());
+        let mut flipped = secret.clone();
+        let index = flipped.len() / 2;
+        flipped[index] ^= 1;
+        assert!(decrypt_bytes(&flipped, &COMPAT_KEY).is_err());
+    }
+
+    #[test]
+    fn deterministic_output() {
+        let in_dir = tempdir().unwrap();
+        let in_pth = in_dir.path().join("text");
+        let content = "the same every time ".repeat(10_000).into_bytes();
+        fs::write(&in_pth, &content).unwrap();
+        let new_config = |out_dir: &Path| {
+            EncryptConfig::builder(COMPAT_KEY.clone())
+                .with_file(in_pth.clone())
+                .with_option(EncOption::Fast)
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(out_dir.to_owned())
+                .with_kdf_cost(KdfCost::interactive().with_argon2(Some(1024), Some(1)))
+                .with_deterministic(Salt::from_seed(b"salt"), Salt::from_seed(b"pepper"))
+                .build()
+                .unwrap()
+        };
+        let enc_dirs = [tempdir().unwrap(), tempdir().unwrap()];
+        let enc_pths = enc_dirs
+            .iter()
+            .map(|dir| encrypt(&new_config(dir.path())).unwrap().remove(0))
+            .collect::<Vec<_>>();
+        let secret = fs::read(&enc_pths[0]).unwrap();
+        assert_eq!(secret, fs::read(&enc_pths[1]).unwrap());
+        let input_files = vec![InputFile::direct(enc_pths[0].clone())];
+        let files_info =
+            inspect_files(&input_files, Verbosity::Quiet, true, Extension::Strip, None).unwrap();
+        let file_strat = &read_file_strategies(&files_info, Verbosity::Quiet).unwrap()[0];
+        assert!(file_strat
+            .pub_header
+            .options()
+            .has(EncOption::Deterministic));
+
+        // Streams cannot be read twice, and would have to be kept in memory completely.
+        let err = encrypt_stream(
+            &mut content.as_slice(),
+            &mut vec![],
+            &new_config(in_dir.path()),
+        )
+        .unwrap_err();
+        assert_eq!("usage", err.code());
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(enc_pths[0].clone())
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(content, fs::read(&dec_pths[0]).unwrap());
+    }
+
+    #[test]
+    fn deterministic_rejects_key_slots() {
+        let in_dir = tempdir().unwrap();
+        let in_pth = in_dir.path().join("text");
+        fs::write(&in_pth, b"slots use a random key").unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_pth)
+            .with_option(EncOption::Fast)
+            .with_option(EncOption::KeySlots)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(in_dir.path().to_owned())
+            .with_deterministic(Salt::from_seed(b"salt"), Salt::from_seed(b"pepper"))
+            .build()
+            .unwrap();
+        let err = encrypt(&enc_conf).unwrap_err();
+        assert_eq!("usage", err.code());
+    }
+
+    #[test]
+    fn custom_kdf_cost() {
+        let in_dir = tempdir().unwrap();
+        let in_pth = in_dir.path().join("text");
+        fs::write(&in_pth, b"stretched differently").unwrap();
+        let cost = KdfCost::interactive().with_argon2(Some(1024), Some(1));
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(COMPAT_KEY.clone())
+            .with_file(in_pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .with_kdf_cost(cost.clone())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let input_files = vec![InputFile::direct(enc_pths[0].clone())];
+        let files_info =
+            inspect_files(&input_files, Verbosity::Quiet, true, Extension::Strip, None).unwrap();
+        let file_strat = &read_file_strategies(&files_info, Verbosity::Quiet).unwrap()[0];
+        assert_eq!(Some(&cost), file_strat.pub_header.kdf_cost());
+        assert_eq!(cost, file_strat.kdf_cost);
+
+        let dec_dir = tempdir().unwrap();
+        let dec_conf = DecryptConfig::builder(COMPAT_KEY.clone())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dec_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(
+            b"stretched differently".to_vec(),
+            fs::read(&dec_pths[0]).unwrap()
+        );
+    }
+
+    #[test]
+    fn recipient_round_trip() {
+        let in_dir = tempdir().unwrap();
+        let in_pth = in_dir.path().join("secret.txt");
+        fs::write(&in_pth, b"for your eyes only").unwrap();
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(Key::generate_random())
+            .with_file(in_pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .with_recipient(alice.recipient())
+            .with_recipient(bob.recipient())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+
+        let decrypt_with = |key: Key| {
+            let dec_dir = tempdir().unwrap();
+            let dec_conf = DecryptConfig::builder(key)
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(dec_dir.path().to_owned())
+                .build()
+                .unwrap();
+            decrypt(&dec_conf).map(|pths| fs::read(&pths[0]).unwrap())
+        };
+        for identity in &[alice, bob] {
+            let key = Key::new(identity.to_file_content().trim());
+            assert_eq!(b"for your eyes only".to_vec(), decrypt_with(key).unwrap());
+        }
+        let eve = Identity::generate();
+        let err = decrypt_with(Key::new(eve.to_file_content().trim())).unwrap_err();
+        assert_eq!("wrong-key", err.code());
+        assert!(err.message().contains("not encrypted for the identity"));
+        let err = decrypt_with(COMPAT_KEY.clone()).unwrap_err();
+        assert!(err.message().contains("identity:"));
+    }
 }
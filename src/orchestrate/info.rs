@@ -0,0 +1,298 @@
+use ::std::collections::HashMap;
+use ::std::convert::TryFrom;
+use ::std::fmt;
+use ::std::path::PathBuf;
+
+use ::serde_json::{json, Value};
+
+use crate::config::typ::Extension;
+use crate::files::file_meta::inspect_files;
+use crate::files::read_headers::{read_file_strategies, FileHeaderStrategy};
+use crate::files::reading::open_reader;
+use crate::files::scan::InputFile;
+use crate::header::private_header_type::PrivateHeader;
+use crate::header::{DataLayout, PublicHeader, Strategy};
+use crate::key::cost::KdfCost;
+use crate::key::key::StretchKey;
+use crate::key::stretch::stretch_key;
+use crate::key::{Key, Salt};
+use crate::orchestrate::decrypt::{read_private_header, recover_raw_key};
+use crate::progress::silent::SilentProgress;
+use crate::{FedResult, Verbosity};
+
+/// What can be learned about an encrypted file from its headers. The private header
+/// is only available if the key was provided.
+#[derive(Debug)]
+pub struct FileDetails {
+    path: PathBuf,
+    size_b: u64,
+    pub_header_len: usize,
+    pub_header: PublicHeader,
+    strategy: Strategy,
+    kdf_cost: KdfCost,
+    priv_header: Option<PrivateHeader>,
+}
+
+fn names<T: fmt::Display>(items: impl IntoIterator<Item = T>) -> Vec<String> {
+    items.into_iter().map(|item| item.to_string()).collect()
+}
+
+fn join<T: fmt::Display>(items: impl IntoIterator<Item = T>) -> String {
+    names(items).join(", ")
+}
+
+fn layout_name(layout: DataLayout) -> &'static str {
+    match layout {
+        DataLayout::Buffered => "buffered",
+        DataLayout::Stream => "stream",
+    }
+}
+
+/// Timestamps in nanoseconds fit in 64 bits until the year 2554.
+fn timestamp(ns: Option<u128>) -> Option<u64> {
+    ns.and_then(|ns| u64::try_from(ns).ok())
+}
+
+impl FileDetails {
+    pub fn to_json(&self) -> Value {
+        let header = &self.pub_header;
+        let strategy = &self.strategy;
+        let priv_header_len = header.private_header().as_ref().map(|(len, _)| len);
+        let priv_header_checksum = header
+            .private_header()
+            .as_ref()
+            .map(|(_, sum)| sum.to_string());
+        let priv_header = self.priv_header.as_ref().map(|hdr| {
+            json!({
+                "filename": hdr.filename(),
+                "permissions": hdr.permissions().map(|perms| format!("{:o}", perms)),
+                "created_ns": timestamp(hdr.created_ns()),
+                "changed_ns": timestamp(hdr.changed_ns()),
+                "accessed_ns": timestamp(hdr.accessed_ns()),
+                "size": hdr.size(),
+                "header_padding": hdr.padding_len(),
+                "data_padding": hdr.data_padding_len(),
+            })
+        });
+        json!({
+            "path": self.path.to_string_lossy(),
+            "size": self.size_b,
+            "version": header.version().to_string(),
+            "options": names(header.options().iter()),
+            "salt": header.salt().as_base64(),
+            "strategy": {
+                "key_hash": names(&strategy.key_hash_algorithms),
+                "stretch_count": self.kdf_cost.rounds,
+                "kdf_cost": self.kdf_cost.to_string(),
+                "ciphers": names(&strategy.symmetric_algorithms),
+                "compression": strategy.compression_algorithm.as_ref().map(|alg| alg.to_string()),
+                "layout": layout_name(strategy.layout),
+            },
+            "public_header_size": self.pub_header_len,
+            "private_header_size": priv_header_len,
+            "private_header_checksum": priv_header_checksum,
+            "data_checksum": header.checksum().map(|sum| sum.to_string()),
+            "recipients": header.recipients().len(),
+            "key_slots": header.key_slots().len(),
+            "private_header": priv_header,
+        })
+    }
+}
+
+impl fmt::Display for FileDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.pub_header;
+        writeln!(f, "{}:", self.path.to_string_lossy())?;
+        writeln!(f, "  version: {}", header.version())?;
+        writeln!(f, "  size: {} bytes", self.size_b)?;
+        if !header.options().is_empty() {
+            writeln!(f, "  options: {}", join(header.options().iter()))?;
+        } else {
+            writeln!(f, "  options: none")?;
+        }
+        writeln!(
+            f,
+            "  key stretching: {} ({})",
+            join(&self.strategy.key_hash_algorithms),
+            self.kdf_cost
+        )?;
+        writeln!(
+            f,
+            "  encryption: {}",
+            join(&self.strategy.symmetric_algorithms)
+        )?;
+        match &self.strategy.compression_algorithm {
+            Some(alg) => writeln!(f, "  compression: {}", alg)?,
+            None => writeln!(f, "  compression: none")?,
+        }
+        writeln!(f, "  layout: {}", layout_name(self.strategy.layout))?;
+        writeln!(f, "  public header: {} bytes", self.pub_header_len)?;
+        match header.private_header() {
+            Some((len, checksum)) => writeln!(f, "  private header: {} bytes ({})", len, checksum)?,
+            None => writeln!(f, "  private header: none")?,
+        }
+        if let Some(checksum) = header.checksum() {
+            writeln!(f, "  data checksum: {}", checksum)?;
+        }
+        if !header.recipients().is_empty() {
+            writeln!(f, "  recipients: {}", header.recipients().len())?;
+        }
+        if !header.key_slots().is_empty() {
+            writeln!(f, "  key slots: {}", header.key_slots().len())?;
+        }
+        if let Some(priv_header) = &self.priv_header {
+            writeln!(
+                f,
+                "  original name: {}",
+                priv_header.filename().unwrap_or("(hidden)")
+            )?;
+            match priv_header.size() {
+                Some(size) => writeln!(f, "  original size: {} bytes", size)?,
+                None => writeln!(f, "  original size: unknown (streamed)")?,
+            }
+            if let Some(perms) = priv_header.permissions() {
+                writeln!(f, "  permissions: {:o}", perms)?;
+            }
+            let times = [
+                ("created", priv_header.created_ns()),
+                ("changed", priv_header.changed_ns()),
+                ("accessed", priv_header.accessed_ns()),
+            ];
+            for (name, ns) in times.iter() {
+                if let Some(ns) = ns {
+                    writeln!(f, "  {}: {} ns since epoch", name, ns)?;
+                }
+            }
+            writeln!(
+                f,
+                "  padding: {} bytes in header, {} bytes in data",
+                priv_header.padding_len(),
+                priv_header.data_padding_len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn decrypt_private_header(
+    file_strat: &FileHeaderStrategy,
+    key: &StretchKey,
+    verbosity: Verbosity,
+) -> FedResult<Option<PrivateHeader>> {
+    let mut reader = open_reader(file_strat.file, verbosity)?;
+    let priv_header = read_private_header(&mut reader, file_strat, key, &mut || {})?;
+    Ok(priv_header.map(|(_, hdr)| hdr))
+}
+
+/// Read the headers of encrypted files, without decrypting the data. The public header
+/// is readable without key; if the key is given, the private header is also decrypted.
+pub fn inspect_headers(
+    files: &[PathBuf],
+    key: Option<&Key>,
+    verbosity: Verbosity,
+) -> FedResult<Vec<FileDetails>> {
+    let input_files = files
+        .iter()
+        .map(|pth| InputFile::direct(pth.clone()))
+        .collect::<Vec<_>>();
+    let files_info = inspect_files(&input_files, verbosity, true, Extension::Strip, None)?;
+    let files_strats = read_file_strategies(&files_info, verbosity)?;
+    let mut key_cache: HashMap<&Salt, StretchKey> = HashMap::new();
+    let mut details = Vec::with_capacity(files_strats.len());
+    for file_strat in &files_strats {
+        let priv_header = match key {
+            Some(key) => {
+                let salt = file_strat.pub_header.salt();
+                if !key_cache.contains_key(salt) {
+                    let raw_key = recover_raw_key(file_strat, key, &mut SilentProgress::new())?;
+                    let stretched_key = stretch_key(
+                        &raw_key,
+                        salt,
+                        &file_strat.kdf_cost,
+                        &file_strat.strategy.key_hash_algorithms,
+                        &mut |_| {},
+                    )?;
+                    key_cache.insert(salt, stretched_key);
+                }
+                decrypt_private_header(file_strat, &key_cache[salt], verbosity)?
+            }
+            None => None,
+        };
+        details.push(FileDetails {
+            path: file_strat.file.in_path.to_owned(),
+            size_b: file_strat.file.size_b,
+            pub_header_len: file_strat.pub_header_len,
+            pub_header: file_strat.pub_header.clone(),
+            strategy: file_strat.strategy.clone(),
+            kdf_cost: file_strat.kdf_cost.clone(),
+            priv_header,
+        });
+    }
+    Ok(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::fs;
+
+    use ::tempfile::tempdir;
+
+    use crate::config::EncryptConfig;
+    use crate::encrypt;
+    use crate::util::option::EncOption;
+
+    use super::*;
+
+    #[test]
+    fn inspect_with_and_without_key() {
+        let dir = tempdir().unwrap();
+        let in_pth = dir.path().join("info.txt");
+        fs::write(&in_pth, b"some data to inspect").unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("secret"))
+            .with_file(in_pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+
+        let public = inspect_headers(&enc_pths, None, Verbosity::Quiet).unwrap();
+        let json = public[0].to_json();
+        assert_eq!(json["options"], json!(["fast"]));
+        assert_eq!(json["strategy"]["ciphers"], json!(["aes256gcm"]));
+        assert!(json["private_header"].is_null());
+        assert!(public[0].to_string().contains("layout: stream"));
+
+        let private =
+            inspect_headers(&enc_pths, Some(&Key::new("secret")), Verbosity::Quiet).unwrap();
+        let json = private[0].to_json();
+        assert_eq!(json["private_header"]["filename"], json!("info.txt"));
+        assert_eq!(json["private_header"]["size"], json!(20));
+        assert!(private[0].to_string().contains("original name: info.txt"));
+
+        assert!(inspect_headers(&enc_pths, Some(&Key::new("wrong")), Verbosity::Quiet).is_err());
+    }
+
+    #[test]
+    fn inspect_invalid_files() {
+        let dir = tempdir().unwrap();
+        let plain_pth = dir.path().join("plain.txt.enc");
+        fs::write(&plain_pth, b"this file was never encrypted").unwrap();
+        let err = inspect_headers(&[plain_pth], None, Verbosity::Quiet).unwrap_err();
+        assert_eq!("corrupted", err.code());
+
+        let in_pth = dir.path().join("cut.txt");
+        fs::write(&in_pth, b"the header of this file is cut off").unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("secret"))
+            .with_file(&in_pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pth = encrypt(&enc_conf).unwrap().remove(0);
+        let secret = fs::read(&enc_pth).unwrap();
+        fs::write(&enc_pth, &secret[..40]).unwrap();
+        let err = inspect_headers(&[enc_pth], None, Verbosity::Quiet).unwrap_err();
+        assert_eq!("corrupted", err.code());
+    }
+}
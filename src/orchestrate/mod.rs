@@ -1,2 +1,6 @@
 pub mod decrypt;
 pub mod encrypt;
+pub mod info;
+pub mod rekey;
+pub mod report;
+pub mod slots;
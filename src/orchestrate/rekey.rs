@@ -0,0 +1,449 @@
+use ::std::collections::HashMap;
+use ::std::fs::File;
+use ::std::io;
+use ::std::io::{Read, Seek, SeekFrom, Write};
+use ::std::path::PathBuf;
+
+use crate::config::typ::Extension;
+use crate::files::file_meta::inspect_files;
+use crate::files::read_headers::{read_file_strategies, FileHeaderStrategy};
+use crate::files::replace_header::{prepare_public_header, prepare_rewrite, PendingRewrite};
+use crate::files::scan::InputFile;
+use crate::header::{write_public_header, PublicHeader};
+use crate::key::key::StretchKey;
+use crate::key::slot::{open_key_slots, KeySlot};
+use crate::key::stretch::stretch_key;
+use crate::key::{Key, KeySource, Salt};
+use crate::orchestrate::decrypt::{open_decrypt_reader, read_private_header_data, read_trailer};
+use crate::symmetric::encrypt::{encrypt_file, EncryptWriter};
+use crate::util::errors::wrap_io;
+use crate::util::option::EncOption;
+use crate::util::version::version_has_private_header;
+use crate::{FedError, FedResult, Verbosity};
+
+/// The old and new stretched keys for the files that were encrypted together (same salt).
+struct Restretched {
+    old_key: StretchKey,
+    salt: Salt,
+    new_key: StretchKey,
+}
+
+fn obtain_key_for(
+    source: &KeySource,
+    second: Option<&KeySource>,
+    purpose: &str,
+    verbosity: Verbosity,
+) -> FedResult<Key> {
+    let asks = |source: &KeySource| matches!(source, KeySource::AskOnce | KeySource::AskTwice);
+    if !verbosity.quiet() && (asks(source) || second.is_some_and(asks)) {
+        println!("{}", purpose);
+    }
+    source.obtain_combined_key(second)
+}
+
+/// What to change for one file. This is determined for all files before any of them is changed.
+enum Change {
+    KeySlots(Vec<KeySlot>),
+    Reencrypt,
+}
+
+/// With key slots, only the slot of the old key is replaced; the data is not touched.
+/// Opening the slot also checks the old key.
+fn new_key_slots(
+    file_strat: &FileHeaderStrategy,
+    old_key: &Key,
+    new_key: &Key,
+    previous: &mut Option<(Vec<KeySlot>, Vec<KeySlot>)>,
+) -> FedResult<Vec<KeySlot>> {
+    let header = &file_strat.pub_header;
+    Ok(match previous {
+        Some((old, new)) if old.as_slice() == header.key_slots() => new.clone(),
+        _ => {
+            let (index, data_key) = open_key_slots(
+                header.key_slots(),
+                old_key,
+                &file_strat.strategy,
+                &file_strat.kdf_cost,
+                &mut |_| {},
+            )
+            .map_err(|err| {
+                err.map_message(|msg| format!("{} ('{}')", msg, file_strat.file.path_str()))
+            })?;
+            let mut key_slots = header.key_slots().to_vec();
+            key_slots[index] = KeySlot::seal(
+                &data_key,
+                new_key,
+                &file_strat.strategy,
+                &file_strat.kdf_cost,
+                &mut |_| {},
+            )?;
+            *previous = Some((header.key_slots().to_vec(), key_slots.clone()));
+            key_slots
+        }
+    })
+}
+
+/// Check that the file can be re-encrypted, and that the old key opens its private header.
+fn check_reencrypt(file_strat: &FileHeaderStrategy, keys: &Restretched) -> FedResult<()> {
+    let file_name = file_strat.file.path_str();
+    if !version_has_private_header(file_strat.pub_header.version()) {
+        return Err(FedError::Usage(format!(
+            "file '{}' is from version {}, which cannot be rekeyed; decrypt and encrypt it again instead",
+            file_name,
+            file_strat.pub_header.version()
+        )));
+    }
+    if file_strat.pub_header.options().has(EncOption::Recipients) {
+        return Err(FedError::Usage(format!(
+            "file '{}' was encrypted for recipients without key slots, so it has no key to change",
+            file_name
+        )));
+    }
+    let mut input = wrap_io(
+        || format!("could not open encrypted file '{}'", file_name),
+        File::open(file_strat.file.in_path),
+    )?;
+    read_private_header_data(&mut input, file_strat, &keys.old_key)?;
+    Ok(())
+}
+
+/// Without key slots, the data is encrypted with the key itself, so it is decrypted and encrypted
+/// again. This happens in chunks while copying, so the plain data is never written anywhere.
+/// The compressed data and any padding are kept as they are.
+fn reencrypt(
+    file_strat: &FileHeaderStrategy,
+    keys: &Restretched,
+    verbose: bool,
+) -> FedResult<PendingRewrite> {
+    let file_name = file_strat.file.path_str();
+    let algorithms = &file_strat.strategy.symmetric_algorithms;
+    prepare_rewrite(file_strat.file.in_path, |input, output| {
+        let (priv_header_len, priv_header_data) =
+            read_private_header_data(input, file_strat, &keys.old_key)?.unwrap();
+        let data_start = file_strat.pub_header_len as u64 + priv_header_len;
+        let (data_len, _) = read_trailer(input, file_strat, data_start)?;
+        let checksum = file_strat
+            .pub_header
+            .private_header()
+            .as_ref()
+            .unwrap()
+            .1
+            .clone();
+        // Re-encryption uses random nonces, so the result is no longer deterministic.
+        let mut options = file_strat.pub_header.options().clone();
+        options.remove(EncOption::Deterministic);
+        let priv_header_data = encrypt_file(
+            priv_header_data,
+            &keys.new_key,
+            &keys.salt,
+            algorithms,
+            &mut |_| {},
+        );
+        let header = PublicHeader::new(
+            file_strat.pub_header.version().clone(),
+            keys.salt.clone(),
+            options,
+            (priv_header_data.len() as u64, checksum),
+        )
+        .with_kdf_cost(file_strat.pub_header.kdf_cost().cloned());
+        write_public_header(output, &header, verbose)?;
+        let err = || format!("could not re-encrypt '{}'", file_name);
+        wrap_io(err, output.write_all(&priv_header_data))?;
+        wrap_io(err, input.seek(SeekFrom::Start(data_start)))?;
+        let mut decrypter = open_decrypt_reader(
+            input.take(data_len),
+            &keys.old_key,
+            file_strat.pub_header.salt(),
+            file_strat,
+            &mut |_| {},
+        );
+        let mut encrypter = EncryptWriter::new(
+            &mut *output,
+            &keys.new_key,
+            &keys.salt,
+            algorithms,
+            &mut |_| {},
+        );
+        wrap_io(err, io::copy(&mut decrypter, &mut encrypter))?;
+        wrap_io(err, encrypter.finish())?;
+        // The trailer contains the checksum of the original file, which did not change.
+        wrap_io(err, input.seek(SeekFrom::Start(data_start + data_len)))?;
+        wrap_io(err, io::copy(input, output))?;
+        Ok(())
+    })
+}
+
+/// Change the key of encrypted files, replacing them in place. Files with key slots only need
+/// a new header. Other files are re-encrypted, without the decrypted data touching the disk.
+/// The old key is checked for all files first, and the files are only replaced when all the
+/// new versions have been written, so that the files do not end up with different keys.
+pub fn rekey(
+    files: &[PathBuf],
+    old_key_source: &KeySource,
+    old_keyfile: Option<&KeySource>,
+    new_key_source: &KeySource,
+    new_keyfile: Option<&KeySource>,
+    verbosity: Verbosity,
+) -> FedResult<()> {
+    let input_files = files
+        .iter()
+        .map(|pth| InputFile::direct(pth.clone()))
+        .collect::<Vec<_>>();
+    let files_info = inspect_files(&input_files, verbosity, true, Extension::Strip, None)?;
+    let files_strats = read_file_strategies(&files_info, verbosity)?;
+    let old_key = obtain_key_for(
+        old_key_source,
+        old_keyfile,
+        "current key of the files",
+        verbosity,
+    )?;
+    let new_key = obtain_key_for(
+        new_key_source,
+        new_keyfile,
+        "new key for the files",
+        verbosity,
+    )?;
+    let mut previous_slots = None;
+    let mut key_cache: HashMap<&Salt, Restretched> = HashMap::new();
+    let mut changes = Vec::with_capacity(files_strats.len());
+    for file_strat in &files_strats {
+        let header = &file_strat.pub_header;
+        if header.options().has(EncOption::KeySlots) {
+            changes.push(Change::KeySlots(new_key_slots(
+                file_strat,
+                &old_key,
+                &new_key,
+                &mut previous_slots,
+            )?));
+        } else {
+            let strategy = &file_strat.strategy;
+            if !key_cache.contains_key(header.salt()) {
+                let salt = Salt::generate_random()?;
+                let stretch = |key: &Key, salt: &Salt| {
+                    stretch_key(
+                        key,
+                        salt,
+                        &file_strat.kdf_cost,
+                        &strategy.key_hash_algorithms,
+                        &mut |_| {},
+                    )
+                };
+                let keys = Restretched {
+                    old_key: stretch(&old_key, header.salt())?,
+                    new_key: stretch(&new_key, &salt)?,
+                    salt,
+                };
+                key_cache.insert(header.salt(), keys);
+            }
+            check_reencrypt(file_strat, &key_cache[header.salt()])?;
+            changes.push(Change::Reencrypt);
+        }
+    }
+    let mut pending = Vec::with_capacity(changes.len());
+    for (file_strat, change) in files_strats.iter().zip(changes) {
+        let header = &file_strat.pub_header;
+        let rewrite = match change {
+            Change::KeySlots(key_slots) => prepare_public_header(
+                file_strat.file.in_path,
+                &header.clone().with_key_slots(key_slots),
+                file_strat.pub_header_len,
+                verbosity.debug(),
+            ),
+            Change::Reencrypt => {
+                reencrypt(file_strat, &key_cache[header.salt()], verbosity.debug())
+            }
+        };
+        match rewrite {
+            Ok(rewrite) => pending.push(rewrite),
+            Err(err) => {
+                pending.into_iter().for_each(PendingRewrite::discard);
+                return Err(err);
+            }
+        }
+    }
+    for (file_strat, rewrite) in files_strats.iter().zip(pending) {
+        rewrite.commit()?;
+        if verbosity.debug() {
+            println!("changed key of {}", file_strat.file.path_str());
+        }
+    }
+    if !verbosity.quiet() {
+        println!("changed key of {} files", files_strats.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::fs;
+
+    use ::tempfile::tempdir;
+
+    use crate::config::{DecryptConfig, EncryptConfig};
+    use crate::{decrypt, encrypt};
+
+    use super::*;
+
+    fn rekey_and_decrypt(options: Vec<EncOption>) {
+        let dir = tempdir().unwrap();
+        let in_pths = vec![dir.path().join("a.txt"), dir.path().join("b.txt")];
+        for pth in &in_pths {
+            fs::write(pth, pth.to_string_lossy().repeat(1000).as_bytes()).unwrap();
+        }
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("old"))
+            .with_files(in_pths.clone())
+            .with_options(options.into())
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let decrypt_with = |key: &str| {
+            let dec_dir = tempdir().unwrap();
+            let dec_conf = DecryptConfig::builder(Key::new(key))
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(dec_dir.path().to_owned())
+                .build()
+                .unwrap();
+            decrypt(&dec_conf).map(|pths| fs::read(&pths[1]).unwrap())
+        };
+
+        let wrong = KeySource::CliArg(Key::new("wrong"));
+        let old = KeySource::CliArg(Key::new("old"));
+        let new = KeySource::CliArg(Key::new("new"));
+        assert!(rekey(&enc_pths, &wrong, None, &new, None, Verbosity::Quiet).is_err());
+        rekey(&enc_pths, &old, None, &new, None, Verbosity::Quiet).unwrap();
+        let expected = fs::read(&in_pths[1]).unwrap();
+        assert_eq!(expected, decrypt_with("new").unwrap());
+        assert!(decrypt_with("old").is_err());
+        assert_eq!(2, fs::read_dir(enc_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn rekey_reencrypt() {
+        rekey_and_decrypt(vec![EncOption::Fast, EncOption::PadSize]);
+    }
+
+    #[test]
+    fn rekey_key_slots() {
+        rekey_and_decrypt(vec![EncOption::Fast, EncOption::KeySlots]);
+    }
+
+    #[test]
+    fn rekey_combined_key() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("a.txt");
+        fs::write(&pth, "combined".repeat(1000).as_bytes()).unwrap();
+        let old_keyfile = dir.path().join("old.key");
+        fs::write(&old_keyfile, b"old key file").unwrap();
+        let new_keyfile = dir.path().join("new.key");
+        fs::write(&new_keyfile, b"new key file").unwrap();
+        let old = KeySource::CliArg(Key::new("old"));
+        let old_second = KeySource::KeyFile(old_keyfile);
+        let new = KeySource::CliArg(Key::new("new"));
+        let new_second = KeySource::KeyFile(new_keyfile);
+        let enc_conf = EncryptConfig::builder(old.obtain_combined_key(Some(&old_second)).unwrap())
+            .with_file(&pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        fs::remove_file(&pth).unwrap();
+
+        let err = rekey(&enc_pths, &old, None, &new, None, Verbosity::Quiet).unwrap_err();
+        assert_eq!("wrong-key", err.code());
+        rekey(
+            &enc_pths,
+            &old,
+            Some(&old_second),
+            &new,
+            Some(&new_second),
+            Verbosity::Quiet,
+        )
+        .unwrap();
+        let dec_conf = DecryptConfig::builder(new.obtain_combined_key(Some(&new_second)).unwrap())
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .build()
+            .unwrap();
+        decrypt(&dec_conf).unwrap();
+        assert_eq!("combined".repeat(1000), fs::read_to_string(&pth).unwrap());
+    }
+
+    #[test]
+    fn rekey_all_or_nothing() {
+        let dir = tempdir().unwrap();
+        let mut enc_pths = vec![];
+        // The second file has a different key, so the old key only opens the first one.
+        for (name, key) in &[("a.txt", "old"), ("b.txt", "other")] {
+            let pth = dir.path().join(name);
+            fs::write(&pth, name.repeat(1000).as_bytes()).unwrap();
+            let enc_conf = EncryptConfig::builder(Key::new(key))
+                .with_file(&pth)
+                .with_option(EncOption::Fast)
+                .with_verbosity(Verbosity::Quiet)
+                .build()
+                .unwrap();
+            enc_pths.extend(encrypt(&enc_conf).unwrap());
+        }
+        let before = enc_pths
+            .iter()
+            .map(|pth| fs::read(pth).unwrap())
+            .collect::<Vec<_>>();
+
+        let old = KeySource::CliArg(Key::new("old"));
+        let new = KeySource::CliArg(Key::new("new"));
+        let err = rekey(&enc_pths, &old, None, &new, None, Verbosity::Quiet).unwrap_err();
+        assert_eq!("wrong-key", err.code());
+        let after = enc_pths
+            .iter()
+            .map(|pth| fs::read(pth).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(before, after);
+        assert_eq!(4, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn rekey_deterministic() {
+        let dir = tempdir().unwrap();
+        let pth = dir.path().join("a.txt");
+        let content = "deterministic".repeat(1000);
+        fs::write(&pth, content.as_bytes()).unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("old"))
+            .with_file(&pth)
+            .with_option(EncOption::Fast)
+            .with_verbosity(Verbosity::Quiet)
+            .with_deterministic(Salt::from_seed(b"salt"), Salt::from_seed(b"pepper"))
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        fs::remove_file(&pth).unwrap();
+
+        let old = KeySource::CliArg(Key::new("old"));
+        let new = KeySource::CliArg(Key::new("new"));
+        rekey(&enc_pths, &old, None, &new, None, Verbosity::Quiet).unwrap();
+        // The data is encrypted again with random nonces, so the option is dropped.
+        let input_files = vec![InputFile::direct(enc_pths[0].clone())];
+        let files_info =
+            inspect_files(&input_files, Verbosity::Quiet, true, Extension::Strip, None).unwrap();
+        let file_strat = &read_file_strategies(&files_info, Verbosity::Quiet).unwrap()[0];
+        assert!(!file_strat
+            .pub_header
+            .options()
+            .has(EncOption::Deterministic));
+        let dec_conf = DecryptConfig::builder(Key::new("new"))
+            .with_files(enc_pths)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(dir.path().to_owned())
+            .build()
+            .unwrap();
+        let dec_pths = decrypt(&dec_conf).unwrap();
+        assert_eq!(
+            content.as_bytes(),
+            fs::read(&dec_pths[0]).unwrap().as_slice()
+        );
+    }
+}
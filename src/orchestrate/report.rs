@@ -0,0 +1,210 @@
+use ::std::fmt;
+use ::std::path::{Path, PathBuf};
+
+use ::serde_json::{json, Value};
+
+use crate::util::jobs::collect_results;
+use crate::util::FedError;
+
+/// What is known about the checksum of the data of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// The checksum was calculated and stored while encrypting.
+    Created,
+    Match,
+    Mismatch,
+    /// The data was not decrypted, like when listing archive contents.
+    NotChecked,
+}
+
+impl ChecksumStatus {
+    pub fn from_match(is_match: bool) -> Self {
+        if is_match {
+            ChecksumStatus::Match
+        } else {
+            ChecksumStatus::Mismatch
+        }
+    }
+}
+
+impl fmt::Display for ChecksumStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChecksumStatus::Created => "created",
+            ChecksumStatus::Match => "match",
+            ChecksumStatus::Mismatch => "mismatch",
+            ChecksumStatus::NotChecked => "not-checked",
+        })
+    }
+}
+
+/// The result of encrypting or decrypting one input file. There can be several outputs
+/// when extracting an archive, or none when only listing or verifying, or if it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    input: PathBuf,
+    outputs: Vec<PathBuf>,
+    size_b: u64,
+    checksum: ChecksumStatus,
+    error: Option<FedError>,
+}
+
+impl FileReport {
+    pub fn new(input: &Path, outputs: Vec<PathBuf>, size_b: u64, checksum: ChecksumStatus) -> Self {
+        FileReport {
+            input: input.to_owned(),
+            outputs,
+            size_b,
+            checksum,
+            error: None,
+        }
+    }
+
+    /// The report of a file that could not be encrypted or decrypted, while others could.
+    pub fn failed(input: &Path, size_b: u64, error: FedError) -> Self {
+        FileReport {
+            input: input.to_owned(),
+            outputs: vec![],
+            size_b,
+            checksum: ChecksumStatus::NotChecked,
+            error: Some(error),
+        }
+    }
+
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    pub fn outputs(&self) -> &[PathBuf] {
+        &self.outputs
+    }
+
+    /// Size of the input file in bytes.
+    pub fn size_b(&self) -> u64 {
+        self.size_b
+    }
+
+    pub fn checksum(&self) -> ChecksumStatus {
+        self.checksum
+    }
+
+    pub fn error(&self) -> Option<&FedError> {
+        self.error.as_ref()
+    }
+
+    pub fn into_outputs(self) -> Vec<PathBuf> {
+        self.outputs
+    }
+
+    pub fn to_json(&self) -> Value {
+        match &self.error {
+            Some(err) => json!({
+                "input": self.input.to_string_lossy(),
+                "status": "error",
+                "error": {
+                    "code": err.code(),
+                    "message": err.message(),
+                },
+            }),
+            None => json!({
+                "input": self.input.to_string_lossy(),
+                "status": "ok",
+                "outputs": self.outputs.iter().map(|pth| pth.to_string_lossy()).collect::<Vec<_>>(),
+                "size": self.size_b,
+                "checksum": self.checksum.to_string(),
+            }),
+        }
+    }
+}
+
+/// All the file results of a run, and the error that stopped it, if any.
+pub fn reports_to_json(reports: &[FileReport], error: Option<&FedError>) -> Value {
+    let files = reports.iter().map(FileReport::to_json).collect::<Vec<_>>();
+    match error {
+        Some(err) => json!({
+            "files": files,
+            "error": {
+                "code": err.code(),
+                "message": err.message(),
+            },
+        }),
+        None => json!({ "files": files }),
+    }
+}
+
+/// Reports are returned even if some of the files failed; this combines their errors, if any.
+pub fn failure_error(reports: &[FileReport]) -> Option<FedError> {
+    let errors = reports
+        .iter()
+        .filter_map(|report| report.error.clone())
+        .map(Err::<(), _>)
+        .collect::<Vec<_>>();
+    collect_results(errors).err()
+}
+
+/// Decrypting returns the reports even if checksums did not match; this turns that into an error.
+pub fn checksum_error(reports: &[FileReport]) -> Option<FedError> {
+    let failure_count = reports
+        .iter()
+        .filter(|report| report.checksum == ChecksumStatus::Mismatch)
+        .count();
+    if failure_count == 0 {
+        return None;
+    }
+    Some(FedError::ChecksumMismatch(format!(
+        "there were {} files whose checksums did not match; they \
+        likely do not contain real data",
+        failure_count
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_with_error() {
+        let reports = vec![
+            FileReport::new(
+                Path::new("a.txt.enc"),
+                vec![PathBuf::from("a.txt")],
+                100,
+                ChecksumStatus::Match,
+            ),
+            FileReport::new(Path::new("b.txt.enc"), vec![], 50, ChecksumStatus::Mismatch),
+        ];
+        let err = checksum_error(&reports).unwrap();
+        assert_eq!(7, err.exit_code());
+        let json = reports_to_json(&reports, Some(&err));
+        assert_eq!(json["files"][0]["outputs"], json!(["a.txt"]));
+        assert_eq!(json["files"][1]["checksum"], json!("mismatch"));
+        assert_eq!(json["error"]["code"], json!("checksum-mismatch"));
+        assert!(checksum_error(&reports[..1]).is_none());
+        assert!(reports_to_json(&reports[..1], None).get("error").is_none());
+    }
+
+    #[test]
+    fn json_per_file() {
+        let reports = vec![
+            FileReport::new(
+                Path::new("a.txt.enc"),
+                vec![PathBuf::from("a.txt")],
+                100,
+                ChecksumStatus::Match,
+            ),
+            FileReport::failed(
+                Path::new("b.txt.enc"),
+                50,
+                FedError::WrongKey("wrong key".to_owned()),
+            ),
+        ];
+        let err = failure_error(&reports).unwrap();
+        assert_eq!("wrong-key", err.code());
+        assert!(failure_error(&reports[..1]).is_none());
+        let json = reports_to_json(&reports, Some(&err));
+        assert_eq!(json["files"][0]["status"], json!("ok"));
+        assert_eq!(json["files"][0]["outputs"], json!(["a.txt"]));
+        assert_eq!(json["files"][1]["status"], json!("error"));
+        assert_eq!(json["files"][1]["error"]["code"], json!("wrong-key"));
+    }
+}
@@ -0,0 +1,163 @@
+use ::std::path::{Path, PathBuf};
+
+use crate::files::replace_header::{read_public_header, replace_public_header};
+use crate::header::{get_version_strategy, PublicHeader, Strategy};
+use crate::key::cost::KdfCost;
+use crate::key::slot::{open_key_slots, KeySlot};
+use crate::key::Key;
+use crate::util::option::EncOption;
+use crate::{FedError, FedResult, Verbosity};
+
+/// Change the key slots of each file, leaving the encrypted data untouched. Files that were
+/// encrypted together have the same slots, so the result is reused instead of stretching again.
+fn update_key_slots(
+    files: &[PathBuf],
+    verbosity: Verbosity,
+    mut change: impl FnMut(&Path, &PublicHeader, &Strategy, &KdfCost) -> FedResult<Vec<KeySlot>>,
+) -> FedResult<()> {
+    let mut previous: Option<(Vec<KeySlot>, Vec<KeySlot>)> = None;
+    for pth in files {
+        let (header_len, header) = read_public_header(pth, verbosity.debug())?;
+        if !header.options().has(EncOption::KeySlots) {
+            return Err(FedError::Usage(format!(
+                "file '{}' does not have key slots; encrypt it with --key-slots to be able to change keys",
+                pth.to_string_lossy()
+            )));
+        }
+        let key_slots = match &previous {
+            Some((old, new)) if old.as_slice() == header.key_slots() => new.clone(),
+            _ => {
+                let strategy =
+                    get_version_strategy(header.version(), header.options(), verbosity.debug())?;
+                let kdf_cost = header.kdf_cost().unwrap_or(&strategy.kdf_cost);
+                let new = change(pth, &header, &strategy, kdf_cost)?;
+                previous = Some((header.key_slots().to_vec(), new.clone()));
+                new
+            }
+        };
+        let header = header.with_key_slots(key_slots);
+        replace_public_header(pth, &header, header_len, verbosity.debug())?;
+        if verbosity.debug() {
+            println!("updated key slots of {}", pth.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+/// Add a key to files that have key slots, using one of their current keys.
+pub fn add_key(
+    files: &[PathBuf],
+    current_key: &Key,
+    new_key: &Key,
+    verbosity: Verbosity,
+) -> FedResult<()> {
+    update_key_slots(files, verbosity, |pth, header, strategy, cost| {
+        let (_, data_key) =
+            open_key_slots(header.key_slots(), current_key, strategy, cost, &mut |_| {}).map_err(
+                |err| err.map_message(|msg| format!("{} ('{}')", msg, pth.to_string_lossy())),
+            )?;
+        let mut key_slots = header.key_slots().to_vec();
+        key_slots.push(KeySlot::seal(
+            &data_key,
+            new_key,
+            strategy,
+            cost,
+            &mut |_| {},
+        )?);
+        Ok(key_slots)
+    })?;
+    if !verbosity.quiet() {
+        println!("added key to {} files", files.len());
+    }
+    Ok(())
+}
+
+/// Remove a key from files that have key slots. The last key cannot be removed,
+/// unless the file can also be decrypted by recipients.
+pub fn remove_key(files: &[PathBuf], key: &Key, verbosity: Verbosity) -> FedResult<()> {
+    update_key_slots(files, verbosity, |pth, header, strategy, cost| {
+        let (index, _) = open_key_slots(header.key_slots(), key, strategy, cost, &mut |_| {})
+            .map_err(|err| {
+                err.map_message(|msg| format!("{} ('{}')", msg, pth.to_string_lossy()))
+            })?;
+        if header.key_slots().len() == 1 && header.recipients().is_empty() {
+            return Err(FedError::Usage(format!(
+                "cannot remove the only key of file '{}', because it could not be decrypted anymore",
+                pth.to_string_lossy()
+            )));
+        }
+        let mut key_slots = header.key_slots().to_vec();
+        key_slots.remove(index);
+        Ok(key_slots)
+    })?;
+    if !verbosity.quiet() {
+        println!("removed key from {} files", files.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::fs;
+
+    use ::tempfile::tempdir;
+
+    use crate::config::{DecryptConfig, EncryptConfig};
+    use crate::util::option::EncOption;
+    use crate::{decrypt, encrypt};
+
+    use super::*;
+
+    #[test]
+    fn add_and_remove_keys() {
+        let dir = tempdir().unwrap();
+        let in_pths = vec![dir.path().join("a.txt"), dir.path().join("b.txt")];
+        for pth in &in_pths {
+            fs::write(pth, pth.to_string_lossy().as_bytes()).unwrap();
+        }
+        let enc_dir = tempdir().unwrap();
+        let enc_conf = EncryptConfig::builder(Key::new("first"))
+            .with_files(in_pths.clone())
+            .with_option(EncOption::Fast)
+            .with_option(EncOption::KeySlots)
+            .with_verbosity(Verbosity::Quiet)
+            .with_output_dir(enc_dir.path().to_owned())
+            .build()
+            .unwrap();
+        let enc_pths = encrypt(&enc_conf).unwrap();
+        let decrypt_with = |key: &str| {
+            let dec_dir = tempdir().unwrap();
+            let dec_conf = DecryptConfig::builder(Key::new(key))
+                .with_files(enc_pths.clone())
+                .with_verbosity(Verbosity::Quiet)
+                .with_output_dir(dec_dir.path().to_owned())
+                .build()
+                .unwrap();
+            decrypt(&dec_conf).map(|pths| fs::read(&pths[1]).unwrap())
+        };
+        let expected = in_pths[1].to_string_lossy().as_bytes().to_vec();
+        assert_eq!(expected, decrypt_with("first").unwrap());
+
+        assert!(add_key(
+            &enc_pths,
+            &Key::new("wrong"),
+            &Key::new("x"),
+            Verbosity::Quiet
+        )
+        .is_err());
+        add_key(
+            &enc_pths,
+            &Key::new("first"),
+            &Key::new("second"),
+            Verbosity::Quiet,
+        )
+        .unwrap();
+        assert_eq!(expected, decrypt_with("first").unwrap());
+        assert_eq!(expected, decrypt_with("second").unwrap());
+
+        remove_key(&enc_pths, &Key::new("first"), Verbosity::Quiet).unwrap();
+        assert!(decrypt_with("first").is_err());
+        assert_eq!(expected, decrypt_with("second").unwrap());
+        assert!(remove_key(&enc_pths, &Key::new("second"), Verbosity::Quiet).is_err());
+    }
+}
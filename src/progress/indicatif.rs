@@ -1,6 +1,7 @@
 use ::std::collections::HashMap;
 use ::std::mem;
 use ::std::path::PathBuf;
+use ::std::time::Duration;
 #[cfg(feature = "dev-mode")]
 use ::std::time::Instant;
 
@@ -63,7 +64,7 @@ impl ProgressData {
         }
 
         // Set the message of the task that is starting.
-        self.bar.set_message(&task.text);
+        self.bar.set_message(task.text.clone());
         // Increment the progress bar based on the task that was just completed.
         self.bar.inc(self.current.size);
         // Swap the completed task and the starting one, so that a new task becomes
@@ -73,7 +74,7 @@ impl ProgressData {
 }
 
 impl<'a> FileStrategy for (&'a FileInfo<'a>, &'a Strategy) {
-    fn file(&self) -> &FileInfo {
+    fn file(&self) -> &FileInfo<'_> {
         self.0
     }
 
@@ -110,13 +111,14 @@ impl IndicatifProgress {
                 let weight = match alg {
                     KeyHashAlg::BCrypt => 50,
                     KeyHashAlg::Argon2i => 27,
+                    KeyHashAlg::Argon2id => 27,
                     KeyHashAlg::Sha512 => 33,
                 };
                 todo.insert(
                     typ,
                     TaskInfo {
                         text: format!("{} key stretch", &alg),
-                        size: (file_strat.strategy().stretch_count + 1) * weight,
+                        size: (file_strat.strategy().kdf_cost.rounds + 1) * weight,
                     },
                 );
             }
@@ -158,9 +160,11 @@ impl IndicatifProgress {
                     size: 1,
                 },
             );
-            for alg in &file_strat.strategy().compression_algorithm {
+            if let Some(alg) = &file_strat.strategy().compression_algorithm {
                 let size_factor = match alg {
                     CompressionAlg::Brotli => 100,
+                    CompressionAlg::Zstd => 400,
+                    CompressionAlg::Xz => 25,
                 };
                 todo.insert(
                     TaskType::Compress(alg.clone(), file_strat.file().in_path.to_owned()),
@@ -174,6 +178,7 @@ impl IndicatifProgress {
                 let size_factor = match alg {
                     SymmetricEncryptionAlg::Aes256 => 35,
                     SymmetricEncryptionAlg::Twofish => 3,
+                    SymmetricEncryptionAlg::Aes256Gcm => 30,
                 };
                 todo.insert(
                     TaskType::Symmetric(alg.clone(), file_strat.file().in_path.to_owned()),
@@ -191,16 +196,17 @@ impl IndicatifProgress {
                 },
             );
         }
-        let total_size = todo.iter().map(|task| task.1.size).sum();
+        let total_size = todo.iter().map(|task| task.1.size).sum::<u64>();
         let progress_bar = {
-            let pb = ProgressBar::with_draw_target(total_size, ProgressDrawTarget::stderr());
+            let pb = ProgressBar::with_draw_target(Some(total_size), ProgressDrawTarget::stderr());
             pb.set_style(
                 ProgressStyle::default_bar()
                     .template("[{elapsed:>3}] [{wide_bar:}] {percent:>3}% {msg:<40!}")
+                    .unwrap()
                     .progress_chars("=> "),
             );
             pb.tick();
-            pb.enable_steady_tick(50);
+            pb.enable_steady_tick(Duration::from_millis(50));
             pb
         };
         IndicatifProgress {
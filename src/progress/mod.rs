@@ -1,5 +1,6 @@
 pub mod indicatif;
 pub mod log;
+pub mod shared;
 pub mod silent;
 pub mod typ;
 
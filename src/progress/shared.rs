@@ -0,0 +1,65 @@
+use ::std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::files::file_meta::FileInfo;
+use crate::header::{CompressionAlg, KeyHashAlg, SymmetricEncryptionAlg};
+use crate::progress::Progress;
+
+/// Progress that can be reported from multiple threads. Each thread uses its own clone,
+/// and the updates are forwarded to the wrapped progress one at a time.
+#[derive(Clone)]
+pub struct SharedProgress {
+    inner: Arc<Mutex<Box<dyn Progress + Send>>>,
+}
+
+impl SharedProgress {
+    pub fn new(progress: Box<dyn Progress + Send>) -> Self {
+        SharedProgress {
+            inner: Arc::new(Mutex::new(progress)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Progress + Send>> {
+        // Progress is only informational, so keep going if another thread panicked.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Progress for SharedProgress {
+    fn start_stretch_alg(&mut self, alg: &KeyHashAlg, file: Option<&FileInfo>) {
+        self.lock().start_stretch_alg(alg, file)
+    }
+
+    fn start_read_for_file(&mut self, file: &FileInfo) {
+        self.lock().start_read_for_file(file)
+    }
+
+    fn start_private_header_for_file(&mut self, file: &FileInfo) {
+        self.lock().start_private_header_for_file(file)
+    }
+
+    fn start_compress_alg_for_file(&mut self, alg: &CompressionAlg, file: &FileInfo) {
+        self.lock().start_compress_alg_for_file(alg, file)
+    }
+
+    fn start_sym_alg_for_file(&mut self, alg: &SymmetricEncryptionAlg, file: &FileInfo) {
+        self.lock().start_sym_alg_for_file(alg, file)
+    }
+
+    fn start_checksum_for_file(&mut self, file: &FileInfo) {
+        self.lock().start_checksum_for_file(file)
+    }
+
+    fn start_write_for_file(&mut self, file: &FileInfo) {
+        self.lock().start_write_for_file(file)
+    }
+
+    fn start_shred_input_for_file(&mut self, file: &FileInfo) {
+        self.lock().start_shred_input_for_file(file)
+    }
+
+    fn finish(&mut self) {
+        self.lock().finish()
+    }
+}
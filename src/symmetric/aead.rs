@@ -0,0 +1,393 @@
+use ::std::convert::TryInto;
+use ::std::io;
+use ::std::io::Read;
+
+use ::aes_gcm::aead::{Aead, KeyInit};
+use ::aes_gcm::{Aes256Gcm, Nonce};
+use ::ring::{hkdf, hmac};
+
+use crate::key::key::StretchKey;
+use crate::key::random::generate_secure_random_timed;
+use crate::key::Salt;
+use crate::util::{FedError, FedResult};
+
+/// Plaintext size of each authenticated chunk. This is part of the file format, so do not change it.
+pub const AEAD_CHUNK_SIZE: usize = 64 * 1024;
+/// Random prefix of the nonces, stored at the start of the encrypted data.
+pub const NONCE_PREFIX_LEN: usize = 7;
+/// Authentication tag added to every chunk.
+pub const TAG_LEN: usize = 16;
+/// In deterministic mode, the synthetic IV is stored at the start instead of the nonce prefix.
+pub const SYNTHETIC_IV_LEN: usize = 32;
+
+const SEALED_CHUNK_SIZE: usize = AEAD_CHUNK_SIZE + TAG_LEN;
+
+/// The nonce is the random prefix, followed by the chunk number and a flag for the last chunk.
+/// Because of this, reordering, dropping or appending chunks makes authentication fail.
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, is_last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = is_last as u8;
+    nonce
+}
+
+/// Synthetic IV for deterministic encryption, which depends only on the pepper and the plaintext.
+/// The `label` separates different kinds of data encrypted with the same key.
+pub fn derive_synthetic_iv(
+    pepper: &Salt,
+    label: &str,
+    reader: &mut impl Read,
+) -> io::Result<[u8; SYNTHETIC_IV_LEN]> {
+    let mut context = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA256, &pepper.salt));
+    context.update(label.as_bytes());
+    let mut buffer = vec![0; AEAD_CHUNK_SIZE];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        context.update(&buffer[..count]);
+    }
+    Ok(context.sign().as_ref().try_into().unwrap())
+}
+
+/// The key is the same for everything encrypted with the same salt, and a nonce prefix is too
+/// short to never collide between different plaintexts. So in deterministic mode, each plaintext
+/// is encrypted with its own key, derived from the synthetic IV.
+fn synthetic_iv_key(key: &StretchKey, synthetic_iv: &[u8; SYNTHETIC_IV_LEN]) -> StretchKey {
+    debug_assert!(key.len() >= 32);
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, synthetic_iv).extract(key.unsecure_slice(32));
+    let mut subkey = [0u8; 32];
+    prk.expand(&[b"file_endec deterministic"], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut subkey))
+        .expect("key derivation failed");
+    StretchKey::new(&subkey)
+}
+
+fn new_cipher(key: &StretchKey) -> Aes256Gcm {
+    debug_assert!(key.len() >= 32);
+    Aes256Gcm::new_from_slice(key.unsecure_slice(32)).unwrap()
+}
+
+/// Encrypts data in chunks of `AEAD_CHUNK_SIZE`, each with its own authentication tag.
+pub struct AeadEncryptor {
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    synthetic_iv: Option<[u8; SYNTHETIC_IV_LEN]>,
+    counter: u32,
+    has_started: bool,
+}
+
+impl AeadEncryptor {
+    pub fn new(key: &StretchKey) -> Self {
+        // The key is shared by all files encrypted together, so the nonce must be random per file.
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        generate_secure_random_timed(&mut nonce_prefix);
+        AeadEncryptor {
+            cipher: new_cipher(key),
+            nonce_prefix,
+            synthetic_iv: None,
+            counter: 0,
+            has_started: false,
+        }
+    }
+
+    /// Encrypt deterministically, with a key derived from `key` and the synthetic IV, which is
+    /// stored instead of a random nonce prefix. Decrypt using `AeadDecryptor::with_synthetic_iv`.
+    pub fn with_synthetic_iv(
+        mut self,
+        key: &StretchKey,
+        synthetic_iv: [u8; SYNTHETIC_IV_LEN],
+    ) -> Self {
+        debug_assert!(!self.has_started);
+        self.cipher = new_cipher(&synthetic_iv_key(key, &synthetic_iv));
+        self.nonce_prefix = synthetic_iv[..NONCE_PREFIX_LEN].try_into().unwrap();
+        self.synthetic_iv = Some(synthetic_iv);
+        self
+    }
+
+    fn seal(&mut self, chunk: &[u8], is_last: bool) -> Vec<u8> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, is_last);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .expect("too many chunks for authenticated encryption");
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), chunk)
+            .expect("authenticated encryption failed")
+    }
+
+    /// Encrypt all complete chunks in `pending`, removing them. The last chunk is kept
+    /// until `is_last`, because it is authenticated as being the last one.
+    pub fn encrypt_available(&mut self, pending: &mut Vec<u8>, is_last: bool) -> Vec<u8> {
+        let mut output = vec![];
+        if !self.has_started {
+            match &self.synthetic_iv {
+                Some(synthetic_iv) => output.extend_from_slice(synthetic_iv),
+                None => output.extend_from_slice(&self.nonce_prefix),
+            }
+            self.has_started = true;
+        }
+        let mut start = 0;
+        while pending.len() - start > AEAD_CHUNK_SIZE {
+            let sealed = self.seal(&pending[start..start + AEAD_CHUNK_SIZE], false);
+            output.extend_from_slice(&sealed);
+            start += AEAD_CHUNK_SIZE;
+        }
+        pending.drain(..start);
+        if is_last {
+            let sealed = self.seal(pending, true);
+            output.extend_from_slice(&sealed);
+            pending.clear();
+        }
+        output
+    }
+}
+
+/// Decrypts and authenticates the chunks created by `AeadEncryptor`.
+pub struct AeadDecryptor {
+    key: StretchKey,
+    is_synthetic_iv: bool,
+    // The cipher and nonce prefix, once the start of the data has been read.
+    state: Option<(Aes256Gcm, [u8; NONCE_PREFIX_LEN])>,
+    counter: u32,
+}
+
+impl AeadDecryptor {
+    pub fn new(key: &StretchKey) -> Self {
+        AeadDecryptor {
+            key: key.clone(),
+            is_synthetic_iv: false,
+            state: None,
+            counter: 0,
+        }
+    }
+
+    /// Expect a synthetic IV at the start, as written by `AeadEncryptor::with_synthetic_iv`.
+    pub fn with_synthetic_iv(mut self) -> Self {
+        self.is_synthetic_iv = true;
+        self
+    }
+
+    /// Set up the cipher from the nonce prefix or synthetic IV at the start of the data.
+    fn start(&self, start: &[u8]) -> (Aes256Gcm, [u8; NONCE_PREFIX_LEN]) {
+        let nonce_prefix = start[..NONCE_PREFIX_LEN].try_into().unwrap();
+        if self.is_synthetic_iv {
+            let key = synthetic_iv_key(&self.key, start.try_into().unwrap());
+            (new_cipher(&key), nonce_prefix)
+        } else {
+            (new_cipher(&self.key), nonce_prefix)
+        }
+    }
+
+    fn open(&mut self, chunk: &[u8], is_last: bool) -> FedResult<Vec<u8>> {
+        let (cipher, prefix) = self.state.as_ref().unwrap();
+        let nonce = chunk_nonce(prefix, self.counter, is_last);
+        self.counter = self.counter.checked_add(1).ok_or_else(|| {
+            FedError::Corrupted(
+                "Decryption algorithm failed: too many chunks in encrypted data".to_owned(),
+            )
+        })?;
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| {
+                FedError::Corrupted(
+                    "Decryption algorithm failed: authentication failed, the data may have \
+                    been tampered with, truncated or the key is wrong"
+                        .to_owned(),
+                )
+            })
+    }
+
+    /// Decrypt and authenticate all complete chunks in `pending`, removing them.
+    pub fn decrypt_available(
+        &mut self,
+        pending: &mut Vec<u8>,
+        is_last: bool,
+    ) -> FedResult<Vec<u8>> {
+        let mut start = 0;
+        if self.state.is_none() {
+            let start_len = if self.is_synthetic_iv {
+                SYNTHETIC_IV_LEN
+            } else {
+                NONCE_PREFIX_LEN
+            };
+            if pending.len() < start_len {
+                if is_last {
+                    return Err(FedError::Corrupted(
+                        "Decryption algorithm failed: encrypted data is too short".to_owned(),
+                    ));
+                }
+                return Ok(vec![]);
+            }
+            self.state = Some(self.start(&pending[..start_len]));
+            start = start_len;
+        }
+        let mut output = vec![];
+        // A full chunk is only known not to be the last one when more data follows it.
+        while pending.len() - start > SEALED_CHUNK_SIZE {
+            let plain = self.open(&pending[start..start + SEALED_CHUNK_SIZE], false)?;
+            output.extend_from_slice(&plain);
+            start += SEALED_CHUNK_SIZE;
+        }
+        pending.drain(..start);
+        if is_last {
+            let plain = self.open(pending, true)?;
+            output.extend_from_slice(&plain);
+            pending.clear();
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::files::mockfile::generate_test_file_content_for_test;
+
+    use super::*;
+
+    fn encrypt(input: &[u8], key: &StretchKey) -> Vec<u8> {
+        let mut encryptor = AeadEncryptor::new(key);
+        let mut pending = input.to_vec();
+        encryptor.encrypt_available(&mut pending, true)
+    }
+
+    fn decrypt(input: &[u8], key: &StretchKey) -> FedResult<Vec<u8>> {
+        let mut decryptor = AeadDecryptor::new(key);
+        let mut pending = input.to_vec();
+        decryptor.decrypt_available(&mut pending, true)
+    }
+
+    #[test]
+    fn back_and_forth() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        for size in &[
+            0,
+            1,
+            AEAD_CHUNK_SIZE,
+            AEAD_CHUNK_SIZE + 1,
+            3 * AEAD_CHUNK_SIZE,
+        ] {
+            let input = generate_test_file_content_for_test(*size);
+            let secret = encrypt(&input, &key);
+            let chunk_count =
+                size / AEAD_CHUNK_SIZE + if size % AEAD_CHUNK_SIZE == 0 { 0 } else { 1 };
+            assert_eq!(
+                NONCE_PREFIX_LEN + size + chunk_count.max(1) * TAG_LEN,
+                secret.len()
+            );
+            assert_eq!(input, decrypt(&secret, &key).unwrap());
+        }
+    }
+
+    #[test]
+    fn random_nonce() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let input = generate_test_file_content_for_test(100);
+        assert_ne!(encrypt(&input, &key), encrypt(&input, &key));
+    }
+
+    fn seal_synthetic(
+        input: &[u8],
+        key: &StretchKey,
+        synthetic_iv: [u8; SYNTHETIC_IV_LEN],
+    ) -> Vec<u8> {
+        let mut pending = input.to_vec();
+        AeadEncryptor::new(key)
+            .with_synthetic_iv(key, synthetic_iv)
+            .encrypt_available(&mut pending, true)
+    }
+
+    #[test]
+    fn synthetic_iv() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let pepper = Salt::fixed_for_test(123_456);
+        let input = generate_test_file_content_for_test(AEAD_CHUNK_SIZE + 100);
+        let derive =
+            |label: &str, data: &[u8]| derive_synthetic_iv(&pepper, label, &mut &data[..]).unwrap();
+        assert_eq!(derive("data", &input), derive("data", &input));
+        assert_ne!(derive("data", &input), derive("header", &input));
+        assert_ne!(derive("data", &input), derive("data", &input[1..]));
+        let secret = seal_synthetic(&input, &key, derive("data", &input));
+        assert_eq!(secret, seal_synthetic(&input, &key, derive("data", &input)));
+        assert_eq!(SYNTHETIC_IV_LEN + input.len() + 2 * TAG_LEN, secret.len());
+        let mut pending = secret.clone();
+        let plain = AeadDecryptor::new(&key)
+            .with_synthetic_iv()
+            .decrypt_available(&mut pending, true)
+            .unwrap();
+        assert_eq!(input, plain);
+        assert!(decrypt(&secret, &key).is_err());
+    }
+
+    #[test]
+    fn synthetic_iv_separate_keys() {
+        // Even if the nonce prefixes collide, different synthetic IVs must not reuse the keystream.
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let first_iv = [1u8; SYNTHETIC_IV_LEN];
+        let mut second_iv = first_iv;
+        second_iv[SYNTHETIC_IV_LEN - 1] = 2;
+        let first_input = vec![0u8; 100];
+        let second_input = vec![0xffu8; 100];
+        let first = seal_synthetic(&first_input, &key, first_iv);
+        let second = seal_synthetic(&second_input, &key, second_iv);
+        let keystream_xor = first[SYNTHETIC_IV_LEN..SYNTHETIC_IV_LEN + 100]
+            .iter()
+            .zip(&second[SYNTHETIC_IV_LEN..])
+            .map(|(first, second)| first ^ second)
+            .collect::<Vec<_>>();
+        assert_ne!(vec![0xffu8; 100], keystream_xor);
+        let mut tampered = second.clone();
+        tampered[..SYNTHETIC_IV_LEN].copy_from_slice(&first_iv);
+        let mut pending = tampered;
+        assert!(AeadDecryptor::new(&key)
+            .with_synthetic_iv()
+            .decrypt_available(&mut pending, true)
+            .is_err());
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let input = generate_test_file_content_for_test(2 * AEAD_CHUNK_SIZE + 100);
+        let mut secret = encrypt(&input, &key);
+        secret[AEAD_CHUNK_SIZE + 1000] ^= 1;
+        assert!(decrypt(&secret, &key).is_err());
+    }
+
+    #[test]
+    fn detect_wrong_key() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let input = generate_test_file_content_for_test(100);
+        let secret = encrypt(&input, &key);
+        let other_key = StretchKey::mock_stretch(b"s3cr3t?");
+        assert!(decrypt(&secret, &other_key).is_err());
+    }
+
+    #[test]
+    fn detect_truncation() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let input = generate_test_file_content_for_test(2 * AEAD_CHUNK_SIZE + 100);
+        let secret = encrypt(&input, &key);
+        // Dropping the last chunk leaves only complete chunks, which are not marked as last.
+        let truncated = &secret[..NONCE_PREFIX_LEN + 2 * SEALED_CHUNK_SIZE];
+        assert!(decrypt(truncated, &key).is_err());
+        assert!(decrypt(&secret[..NONCE_PREFIX_LEN - 1], &key).is_err());
+    }
+
+    #[test]
+    fn detect_reordering() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let input = generate_test_file_content_for_test(3 * AEAD_CHUNK_SIZE + 100);
+        let secret = encrypt(&input, &key);
+        let first = NONCE_PREFIX_LEN..NONCE_PREFIX_LEN + SEALED_CHUNK_SIZE;
+        let second = first.end..first.end + SEALED_CHUNK_SIZE;
+        let mut swapped = secret[..NONCE_PREFIX_LEN].to_vec();
+        swapped.extend_from_slice(&secret[second.clone()]);
+        swapped.extend_from_slice(&secret[first]);
+        swapped.extend_from_slice(&secret[second.end..]);
+        assert_eq!(secret.len(), swapped.len());
+        assert!(decrypt(&swapped, &key).is_err());
+    }
+}
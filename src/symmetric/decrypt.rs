@@ -1,8 +1,17 @@
+use ::std::io;
+use ::std::io::Read;
+use ::std::mem;
+
+use ::cbc::cipher::block_padding::Iso7816;
+use ::cbc::cipher::generic_array::GenericArray;
+use ::cbc::cipher::{BlockDecryptMut, KeyIvInit};
+
 use crate::header::SymmetricEncryptionAlg;
 use crate::key::key::StretchKey;
 use crate::key::Salt;
-use crate::symmetric::{Aes256Cbc, TwofishCbc};
-use crate::util::FedResult;
+use crate::symmetric::aead::AeadDecryptor;
+use crate::symmetric::{Aes256CbcDec, TwofishCbcDec, BLOCK_SIZE};
+use crate::util::{FedError, FedResult};
 
 pub fn decrypt_file(
     mut data: Vec<u8>,
@@ -20,6 +29,9 @@ pub fn decrypt_file(
             SymmetricEncryptionAlg::Twofish => {
                 decrypt_twofish(&data[data_start_index..], key, salt)?
             }
+            SymmetricEncryptionAlg::Aes256Gcm => {
+                decrypt_aes256_gcm(&data[data_start_index..], key)?
+            }
         };
         data_start_index = 0;
     }
@@ -29,29 +41,220 @@ pub fn decrypt_file(
 pub fn decrypt_aes256(data: &[u8], key: &StretchKey, salt: &Salt) -> FedResult<Vec<u8>> {
     debug_assert!(key.len() >= 32);
     debug_assert!(salt.salt.len() >= 16);
-    let cipher = Aes256Cbc::new_var(&key.unsecure_slice(32), &salt.salt[..16]).unwrap();
+    let cipher = Aes256CbcDec::new_from_slices(key.unsecure_slice(32), &salt.salt[..16]).unwrap();
     //TODO @mark: make this avoid allocation by using decrypt instead of decrypt_vec.
-    match cipher.decrypt_vec(data) {
+    match cipher.decrypt_padded_vec_mut::<Iso7816>(data) {
         Ok(plain) => Ok(plain),
-        Err(err) => Err(format!("Decryption algorithm failed: {}", err)),
+        Err(err) => Err(FedError::Corrupted(format!(
+            "Decryption algorithm failed: {}",
+            err
+        ))),
     }
 }
 
 pub fn decrypt_twofish(data: &[u8], key: &StretchKey, salt: &Salt) -> FedResult<Vec<u8>> {
     debug_assert!(key.len() >= 16);
     debug_assert!(salt.salt.len() >= 16);
-    let cipher = TwofishCbc::new_var(&key.unsecure_slice(16), &salt.salt[..16]).unwrap();
+    let cipher = TwofishCbcDec::new_from_slices(key.unsecure_slice(16), &salt.salt[..16]).unwrap();
     //TODO @mark: make this avoid allocation by using decrypt instead of decrypt_vec.
-    match cipher.decrypt_vec(data) {
+    match cipher.decrypt_padded_vec_mut::<Iso7816>(data) {
         Ok(plain) => Ok(plain),
-        Err(err) => Err(format!("Decryption algorithm failed: {}", err)),
+        Err(err) => Err(FedError::Corrupted(format!(
+            "Decryption algorithm failed: {}",
+            err
+        ))),
+    }
+}
+
+pub fn decrypt_aes256_gcm(data: &[u8], key: &StretchKey) -> FedResult<Vec<u8>> {
+    let mut pending = data.to_vec();
+    AeadDecryptor::new(key).decrypt_available(&mut pending, true)
+}
+
+enum DecryptLayer {
+    Aes256(Aes256CbcDec),
+    Twofish(TwofishCbcDec),
+    Aes256Gcm(AeadDecryptor),
+}
+
+impl DecryptLayer {
+    fn new(alg: &SymmetricEncryptionAlg, key: &StretchKey, salt: &Salt) -> Self {
+        debug_assert!(key.len() >= 32);
+        debug_assert!(salt.salt.len() >= 16);
+        match alg {
+            SymmetricEncryptionAlg::Aes256 => DecryptLayer::Aes256(
+                Aes256CbcDec::new_from_slices(key.unsecure_slice(32), &salt.salt[..16]).unwrap(),
+            ),
+            SymmetricEncryptionAlg::Twofish => DecryptLayer::Twofish(
+                TwofishCbcDec::new_from_slices(key.unsecure_slice(16), &salt.salt[..16]).unwrap(),
+            ),
+            SymmetricEncryptionAlg::Aes256Gcm => DecryptLayer::Aes256Gcm(AeadDecryptor::new(key)),
+        }
+    }
+
+    fn with_synthetic_iv(self) -> Self {
+        match self {
+            DecryptLayer::Aes256Gcm(decryptor) => {
+                DecryptLayer::Aes256Gcm(decryptor.with_synthetic_iv())
+            }
+            other => other,
+        }
+    }
+
+    /// Decrypt as much of `pending` as possible, leaving what cannot be decrypted yet.
+    fn decrypt_available(&mut self, pending: &mut Vec<u8>, is_last: bool) -> FedResult<Vec<u8>> {
+        match self {
+            DecryptLayer::Aes256(cipher) => decrypt_blocks_available(cipher, pending, is_last),
+            DecryptLayer::Twofish(cipher) => decrypt_blocks_available(cipher, pending, is_last),
+            DecryptLayer::Aes256Gcm(decryptor) => decryptor.decrypt_available(pending, is_last),
+        }
+    }
+}
+
+/// Decrypt the available blocks in `pending`, removing the padding if `is_last`.
+fn decrypt_blocks_available(
+    cipher: &mut impl BlockDecryptMut,
+    pending: &mut Vec<u8>,
+    is_last: bool,
+) -> FedResult<Vec<u8>> {
+    if is_last {
+        if pending.is_empty() || !pending.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(FedError::Corrupted(
+                "Decryption algorithm failed: encrypted data has incorrect length".to_owned(),
+            ));
+        }
+        let mut data = pending.split_off(0);
+        for block in data.chunks_exact_mut(BLOCK_SIZE) {
+            cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        strip_padding(&mut data)?;
+        return Ok(data);
+    }
+    // The last block may contain padding, which can only be removed at the end.
+    let keep_len = match pending.len() % BLOCK_SIZE {
+        0 => BLOCK_SIZE.min(pending.len()),
+        rem => rem,
+    };
+    let keep = pending.split_off(pending.len() - keep_len);
+    let mut data = mem::replace(pending, keep);
+    for block in data.chunks_exact_mut(BLOCK_SIZE) {
+        cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+    Ok(data)
+}
+
+/// Remove Iso7816 padding, which is 0x80 followed by zeros, from the end of the last block.
+fn strip_padding(data: &mut Vec<u8>) -> FedResult<()> {
+    let marker_index = match data.iter().rposition(|byte| *byte != 0) {
+        Some(index) => index,
+        None => {
+            return Err(FedError::Corrupted(
+                "Decryption algorithm failed: padding not found".to_owned(),
+            ))
+        }
+    };
+    if data[marker_index] != 0x80 || data.len() - marker_index > BLOCK_SIZE {
+        return Err(FedError::Corrupted(
+            "Decryption algorithm failed: invalid padding".to_owned(),
+        ));
+    }
+    data.truncate(marker_index);
+    Ok(())
+}
+
+/// Streaming version of `decrypt_file`, which reads encrypted data from `reader` and
+/// only keeps a few blocks or chunks in memory at a time.
+pub struct DecryptReader<R: Read> {
+    reader: R,
+    // Each algorithm (in reverse order) with data that has not been decrypted yet.
+    layers: Vec<(DecryptLayer, Vec<u8>)>,
+    buffer: Vec<u8>,
+    output: Vec<u8>,
+    output_index: usize,
+    is_done: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(
+        reader: R,
+        key: &StretchKey,
+        salt: &Salt,
+        encrypt_algs: &[SymmetricEncryptionAlg],
+        start_progress: &mut impl FnMut(&SymmetricEncryptionAlg),
+    ) -> Self {
+        assert!(!encrypt_algs.is_empty());
+        let layers = encrypt_algs
+            .iter()
+            .rev()
+            .map(|alg| {
+                start_progress(alg);
+                (
+                    DecryptLayer::new(alg, key, salt),
+                    Vec::with_capacity(BLOCK_SIZE),
+                )
+            })
+            .collect();
+        DecryptReader {
+            reader,
+            layers,
+            buffer: vec![0; 64 * 1024],
+            output: vec![],
+            output_index: 0,
+            is_done: false,
+        }
+    }
+
+    /// Expect a synthetic IV for authenticated encryption, for data written in deterministic mode.
+    pub fn with_synthetic_iv(mut self) -> Self {
+        self.layers = self
+            .layers
+            .into_iter()
+            .map(|(layer, pending)| (layer.with_synthetic_iv(), pending))
+            .collect();
+        self
+    }
+
+    /// The inner reader, e.g. to read what follows the encrypted data.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Decrypt the available blocks, passing the output of each algorithm to the next one.
+    fn decrypt_layers(&mut self, data: &[u8], is_last: bool) -> FedResult<Vec<u8>> {
+        let mut data = data.to_vec();
+        for (layer, pending) in &mut self.layers {
+            pending.extend_from_slice(&data);
+            data = layer.decrypt_available(pending, is_last)?;
+        }
+        Ok(data)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_index >= self.output.len() && !self.is_done {
+            let count = self.reader.read(&mut self.buffer)?;
+            let data = self.buffer[..count].to_vec();
+            self.is_done = count == 0;
+            self.output = self
+                .decrypt_layers(&data, self.is_done)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            self.output_index = 0;
+        }
+        let count = buf.len().min(self.output.len() - self.output_index);
+        buf[..count].copy_from_slice(&self.output[self.output_index..self.output_index + count]);
+        self.output_index += count;
+        Ok(count)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use ::std::io::Write;
+
     use crate::files::mockfile::generate_test_file_content_for_test;
-    use crate::symmetric::encrypt::{encrypt_aes256, encrypt_twofish};
+    use crate::symmetric::aead::AEAD_CHUNK_SIZE;
+    use crate::symmetric::encrypt::{encrypt_aes256, encrypt_file, encrypt_twofish, EncryptWriter};
 
     use super::*;
 
@@ -142,4 +345,74 @@ mod tests {
         let actual = decrypt_twofish(&input, &key, &salt).unwrap();
         assert_eq!(plain, actual);
     }
+
+    #[test]
+    fn stream_same_as_buffered() {
+        let key = StretchKey::mock_stretch(b"1_s3cr3t_p@55w0rd!!");
+        let salt = Salt::fixed_for_test(123_456_789_123_456_789);
+        let algs = vec![
+            SymmetricEncryptionAlg::Aes256,
+            SymmetricEncryptionAlg::Twofish,
+        ];
+        for size in &[0, 15, 16, 17, 250_000] {
+            let plain = generate_test_file_content_for_test(*size);
+            let input = encrypt_file(plain.clone(), &key, &salt, &algs, &mut |_| {});
+            let mut reader = DecryptReader::new(input.as_slice(), &key, &salt, &algs, &mut |_| {});
+            let mut actual = vec![];
+            reader.read_to_end(&mut actual).unwrap();
+            assert_eq!(plain, actual);
+        }
+    }
+
+    #[test]
+    fn stream_truncated() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let salt = Salt::fixed_for_test(123_456_789);
+        let algs = vec![SymmetricEncryptionAlg::Aes256];
+        let plain = generate_test_file_content_for_test(1000);
+        let input = encrypt_file(plain, &key, &salt, &algs, &mut |_| {});
+        let truncated = &input[..input.len() - 5];
+        let mut reader = DecryptReader::new(truncated, &key, &salt, &algs, &mut |_| {});
+        let mut actual = vec![];
+        assert!(reader.read_to_end(&mut actual).is_err());
+    }
+
+    #[test]
+    fn stream_authenticated() {
+        let key = StretchKey::mock_stretch(b"1_s3cr3t_p@55w0rd!!");
+        let salt = Salt::fixed_for_test(123_456_789_123_456_789);
+        let algs = vec![
+            SymmetricEncryptionAlg::Twofish,
+            SymmetricEncryptionAlg::Aes256Gcm,
+        ];
+        for size in &[0, 17, AEAD_CHUNK_SIZE, 250_000] {
+            let plain = generate_test_file_content_for_test(*size);
+            let mut writer = EncryptWriter::new(vec![], &key, &salt, &algs, &mut |_| {});
+            for chunk in plain.chunks(1_111) {
+                writer.write_all(chunk).unwrap();
+            }
+            let input = writer.finish().unwrap();
+            assert_eq!(
+                plain,
+                decrypt_file(input.clone(), 0, &key, &salt, &algs, &mut |_| {}).unwrap()
+            );
+            let mut reader = DecryptReader::new(input.as_slice(), &key, &salt, &algs, &mut |_| {});
+            let mut actual = vec![];
+            reader.read_to_end(&mut actual).unwrap();
+            assert_eq!(plain, actual);
+        }
+    }
+
+    #[test]
+    fn stream_authenticated_tampered() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let salt = Salt::fixed_for_test(123_456_789);
+        let algs = vec![SymmetricEncryptionAlg::Aes256Gcm];
+        let plain = generate_test_file_content_for_test(250_000);
+        let mut input = encrypt_file(plain, &key, &salt, &algs, &mut |_| {});
+        input[100_000] ^= 1;
+        let mut reader = DecryptReader::new(input.as_slice(), &key, &salt, &algs, &mut |_| {});
+        let mut actual = vec![];
+        assert!(reader.read_to_end(&mut actual).is_err());
+    }
 }
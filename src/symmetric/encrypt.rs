@@ -1,7 +1,16 @@
+use ::std::io;
+use ::std::io::Write;
+use ::std::mem;
+
+use ::cbc::cipher::block_padding::Iso7816;
+use ::cbc::cipher::generic_array::GenericArray;
+use ::cbc::cipher::{BlockEncryptMut, KeyIvInit};
+
 use crate::header::SymmetricEncryptionAlg;
 use crate::key::key::StretchKey;
 use crate::key::Salt;
-use crate::symmetric::{Aes256Cbc, TwofishCbc};
+use crate::symmetric::aead::{AeadEncryptor, SYNTHETIC_IV_LEN};
+use crate::symmetric::{Aes256CbcEnc, TwofishCbcEnc, BLOCK_SIZE};
 
 pub fn encrypt_file(
     mut data: Vec<u8>,
@@ -16,6 +25,7 @@ pub fn encrypt_file(
         data = match encrypt_alg {
             SymmetricEncryptionAlg::Aes256 => encrypt_aes256(&data, key, salt),
             SymmetricEncryptionAlg::Twofish => encrypt_twofish(&data, key, salt),
+            SymmetricEncryptionAlg::Aes256Gcm => encrypt_aes256_gcm(&data, key),
         }
     }
     data
@@ -24,15 +34,158 @@ pub fn encrypt_file(
 pub fn encrypt_aes256(data: &[u8], key: &StretchKey, salt: &Salt) -> Vec<u8> {
     debug_assert!(key.len() >= 32);
     debug_assert!(salt.salt.len() >= 16);
-    let cipher = Aes256Cbc::new_var(key.unsecure_slice(32), &salt.salt[..16]).unwrap();
-    cipher.encrypt_vec(data)
+    let cipher = Aes256CbcEnc::new_from_slices(key.unsecure_slice(32), &salt.salt[..16]).unwrap();
+    cipher.encrypt_padded_vec_mut::<Iso7816>(data)
 }
 
 pub fn encrypt_twofish(data: &[u8], key: &StretchKey, salt: &Salt) -> Vec<u8> {
     debug_assert!(key.len() >= 16);
     debug_assert!(salt.salt.len() >= 16);
-    let cipher = TwofishCbc::new_var(key.unsecure_slice(16), &salt.salt[..16]).unwrap();
-    cipher.encrypt_vec(data)
+    let cipher = TwofishCbcEnc::new_from_slices(key.unsecure_slice(16), &salt.salt[..16]).unwrap();
+    cipher.encrypt_padded_vec_mut::<Iso7816>(data)
+}
+
+pub fn encrypt_aes256_gcm(data: &[u8], key: &StretchKey) -> Vec<u8> {
+    let mut pending = data.to_vec();
+    AeadEncryptor::new(key).encrypt_available(&mut pending, true)
+}
+
+enum EncryptLayer {
+    Aes256(Aes256CbcEnc),
+    Twofish(TwofishCbcEnc),
+    Aes256Gcm(AeadEncryptor),
+}
+
+impl EncryptLayer {
+    fn new(alg: &SymmetricEncryptionAlg, key: &StretchKey, salt: &Salt) -> Self {
+        debug_assert!(key.len() >= 32);
+        debug_assert!(salt.salt.len() >= 16);
+        match alg {
+            SymmetricEncryptionAlg::Aes256 => EncryptLayer::Aes256(
+                Aes256CbcEnc::new_from_slices(key.unsecure_slice(32), &salt.salt[..16]).unwrap(),
+            ),
+            SymmetricEncryptionAlg::Twofish => EncryptLayer::Twofish(
+                TwofishCbcEnc::new_from_slices(key.unsecure_slice(16), &salt.salt[..16]).unwrap(),
+            ),
+            SymmetricEncryptionAlg::Aes256Gcm => EncryptLayer::Aes256Gcm(AeadEncryptor::new(key)),
+        }
+    }
+
+    fn with_synthetic_iv(self, key: &StretchKey, synthetic_iv: [u8; SYNTHETIC_IV_LEN]) -> Self {
+        match self {
+            EncryptLayer::Aes256Gcm(encryptor) => {
+                EncryptLayer::Aes256Gcm(encryptor.with_synthetic_iv(key, synthetic_iv))
+            }
+            other => other,
+        }
+    }
+
+    /// Encrypt as much of `pending` as possible, leaving what cannot be encrypted yet.
+    fn encrypt_available(&mut self, pending: &mut Vec<u8>, is_last: bool) -> Vec<u8> {
+        match self {
+            EncryptLayer::Aes256(cipher) => encrypt_blocks_available(cipher, pending, is_last),
+            EncryptLayer::Twofish(cipher) => encrypt_blocks_available(cipher, pending, is_last),
+            EncryptLayer::Aes256Gcm(encryptor) => encryptor.encrypt_available(pending, is_last),
+        }
+    }
+}
+
+/// Encrypt all complete blocks in `pending`, padding the last block if `is_last`.
+fn encrypt_blocks_available(
+    cipher: &mut impl BlockEncryptMut,
+    pending: &mut Vec<u8>,
+    is_last: bool,
+) -> Vec<u8> {
+    if is_last {
+        // Iso7816 padding: 0x80 followed by zeros, always at least one byte.
+        pending.push(0x80);
+        while !pending.len().is_multiple_of(BLOCK_SIZE) {
+            pending.push(0);
+        }
+    }
+    let incomplete = pending.split_off(pending.len() - pending.len() % BLOCK_SIZE);
+    let mut data = mem::replace(pending, incomplete);
+    for block in data.chunks_exact_mut(BLOCK_SIZE) {
+        cipher.encrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+    data
+}
+
+/// Streaming version of `encrypt_file`, which produces the same format but only keeps
+/// a few blocks or chunks in memory. Use `finish` after writing all data, to write the padding.
+pub struct EncryptWriter<W: Write> {
+    writer: W,
+    // Each algorithm with the data that does not yet make a complete block or chunk.
+    layers: Vec<(EncryptLayer, Vec<u8>)>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(
+        writer: W,
+        key: &StretchKey,
+        salt: &Salt,
+        encrypt_algs: &[SymmetricEncryptionAlg],
+        start_progress: &mut impl FnMut(&SymmetricEncryptionAlg),
+    ) -> Self {
+        assert!(!encrypt_algs.is_empty());
+        let layers = encrypt_algs
+            .iter()
+            .map(|alg| {
+                start_progress(alg);
+                (
+                    EncryptLayer::new(alg, key, salt),
+                    Vec::with_capacity(BLOCK_SIZE),
+                )
+            })
+            .collect();
+        EncryptWriter { writer, layers }
+    }
+
+    /// Use a synthetic IV for authenticated encryption, instead of a random nonce, so that the
+    /// output is deterministic. It must be derived from the data, e.g. with `derive_synthetic_iv`.
+    pub fn with_synthetic_iv(
+        mut self,
+        key: &StretchKey,
+        synthetic_iv: [u8; SYNTHETIC_IV_LEN],
+    ) -> Self {
+        self.layers = self
+            .layers
+            .into_iter()
+            .map(|(layer, pending)| (layer.with_synthetic_iv(key, synthetic_iv), pending))
+            .collect();
+        self
+    }
+
+    /// Encrypt all complete blocks, passing the output of each algorithm to the next one.
+    fn encrypt_layers(&mut self, data: &[u8], is_last: bool) -> Vec<u8> {
+        let mut data = data.to_vec();
+        for (layer, pending) in &mut self.layers {
+            pending.extend_from_slice(&data);
+            data = layer.encrypt_available(pending, is_last);
+        }
+        data
+    }
+
+    /// Write the final padded block(s) and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data = self.encrypt_layers(&[], true);
+        self.writer.write_all(&data)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let data = self.encrypt_layers(buf, false);
+        self.writer.write_all(&data)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Incomplete blocks cannot be flushed until the end.
+        self.writer.flush()
+    }
 }
 
 #[cfg(test)]
@@ -130,4 +283,35 @@ mod tests {
         assert_eq!(expected_start, &actual[..8]);
         assert_eq!(expected_end, &actual[actual.len() - 8..]);
     }
+
+    #[test]
+    fn stream_same_as_buffered() {
+        let key = StretchKey::mock_stretch(b"1_s3cr3t_p@55w0rd!!");
+        let salt = Salt::fixed_for_test(123_456_789_123_456_789);
+        let algs = vec![
+            SymmetricEncryptionAlg::Aes256,
+            SymmetricEncryptionAlg::Twofish,
+        ];
+        let input = generate_test_file_content_for_test(50_000);
+        let expected = encrypt_file(input.clone(), &key, &salt, &algs, &mut |_| {});
+        let mut writer = EncryptWriter::new(vec![], &key, &salt, &algs, &mut |_| {});
+        for chunk in input.chunks(1_111) {
+            writer.write_all(chunk).unwrap();
+        }
+        let actual = writer.finish().unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn stream_empty() {
+        let key = StretchKey::mock_stretch(b"s3cr3t!");
+        let salt = Salt::fixed_for_test(111_555_999);
+        let algs = vec![SymmetricEncryptionAlg::Aes256];
+        let writer = EncryptWriter::new(vec![], &key, &salt, &algs, &mut |_| {});
+        let actual = writer.finish().unwrap();
+        let expected: Vec<u8> = vec![
+            239, 171, 247, 22, 166, 83, 232, 115, 142, 205, 233, 249, 184, 2, 254, 29,
+        ];
+        assert_eq!(expected, actual);
+    }
 }
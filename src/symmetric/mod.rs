@@ -1,8 +1,15 @@
 use ::aes::Aes256;
 use ::twofish::Twofish;
 
-type Aes256Cbc = Cbc<Aes256, Iso7816>;
-type TwofishCbc = Cbc<Twofish, Iso7816>;
+type Aes256CbcEnc = ::cbc::Encryptor<Aes256>;
+type Aes256CbcDec = ::cbc::Decryptor<Aes256>;
+type TwofishCbcEnc = ::cbc::Encryptor<Twofish>;
+type TwofishCbcDec = ::cbc::Decryptor<Twofish>;
+
+/// Both Aes256 and Twofish use 128-bit blocks.
+const BLOCK_SIZE: usize = 16;
+
+pub mod aead;
 
 pub mod encrypt;
 
@@ -4,7 +4,7 @@ use std::convert::TryInto;
 use ::data_encoding::BASE64URL_NOPAD;
 use ::number2name::BASE64URL;
 
-use crate::util::errors::FedResult;
+use crate::util::errors::{FedError, FedResult};
 
 pub fn u64_to_small_str(value: u64) -> String {
     BASE64URL.encode_u64(value)
@@ -32,15 +32,20 @@ pub fn base64str_to_u64(base64_str: &str) -> FedResult<u64> {
     let bytes = match BASE64URL_NOPAD.decode(base64_str.as_bytes()) {
         Ok(bytes) => bytes,
         Err(_err) => {
-            return Err(
+            return Err(FedError::Corrupted(
                 "did not find valid base64 encoded integer (expecting url base characters)"
                     .to_owned(),
-            )
+            ))
         }
     };
     Ok(u64::from_le_bytes(match bytes.as_slice().try_into() {
         Ok(nr) => nr,
-        Err(_) => return Err(format!("could not decode '{}' to a number", base64_str)),
+        Err(_) => {
+            return Err(FedError::Corrupted(format!(
+                "could not decode '{}' to a number",
+                base64_str
+            )))
+        }
     }))
 }
 
@@ -51,9 +56,9 @@ pub fn u8s_to_base64str(value: &[u8]) -> String {
 pub fn base64str_to_u8s(base64_str: &str) -> FedResult<Vec<u8>> {
     match BASE64URL_NOPAD.decode(base64_str.as_bytes()) {
         Ok(bytes) => Ok(bytes),
-        Err(_err) => {
-            Err("did not find valid base64 content (expecting url base characters)".to_owned())
-        }
+        Err(_err) => Err(FedError::Corrupted(
+            "did not find valid base64 content (expecting url base characters)".to_owned(),
+        )),
     }
 }
 
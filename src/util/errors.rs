@@ -1,13 +1,106 @@
 use ::std::fmt;
 use ::std::io;
 
-pub type FedResult<T> = Result<T, String>;
+pub type FedResult<T> = Result<T, FedError>;
+
+/// Everything that can go wrong, so that callers can distinguish e.g. a wrong key from a
+/// missing file. The codes and exit codes are stable; the messages are meant for humans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FedError {
+    /// Invalid arguments, options or key.
+    Usage(String),
+    /// An input file does not exist, or is not a file.
+    NotFound(String),
+    /// An output file already exists, and overwriting was not enabled.
+    OutputExists(String),
+    /// The key does not decrypt the file.
+    WrongKey(String),
+    /// The file is not an encrypted file, or it has been damaged or tampered with.
+    Corrupted(String),
+    /// The data was decrypted, but its checksum did not match the original.
+    ChecksumMismatch(String),
+    /// Reading or writing failed.
+    Io(String),
+    /// Anything that does not fit the other kinds.
+    Other(String),
+}
+
+impl FedError {
+    /// Short identifier of the kind of error, for use in machine-readable output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FedError::Usage(_) => "usage",
+            FedError::NotFound(_) => "not-found",
+            FedError::OutputExists(_) => "output-exists",
+            FedError::WrongKey(_) => "wrong-key",
+            FedError::Corrupted(_) => "corrupted",
+            FedError::ChecksumMismatch(_) => "checksum-mismatch",
+            FedError::Io(_) => "io",
+            FedError::Other(_) => "other",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FedError::Other(_) => 1,
+            FedError::Usage(_) => 2,
+            FedError::NotFound(_) => 3,
+            FedError::OutputExists(_) => 4,
+            FedError::WrongKey(_) => 5,
+            FedError::Corrupted(_) => 6,
+            FedError::ChecksumMismatch(_) => 7,
+            FedError::Io(_) => 8,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            FedError::Usage(msg)
+            | FedError::NotFound(msg)
+            | FedError::OutputExists(msg)
+            | FedError::WrongKey(msg)
+            | FedError::Corrupted(msg)
+            | FedError::ChecksumMismatch(msg)
+            | FedError::Io(msg)
+            | FedError::Other(msg) => msg,
+        }
+    }
+
+    /// The same kind of error, with extra information in the message.
+    pub fn map_message(self, change: impl FnOnce(String) -> String) -> Self {
+        match self {
+            FedError::Usage(msg) => FedError::Usage(change(msg)),
+            FedError::NotFound(msg) => FedError::NotFound(change(msg)),
+            FedError::OutputExists(msg) => FedError::OutputExists(change(msg)),
+            FedError::WrongKey(msg) => FedError::WrongKey(change(msg)),
+            FedError::Corrupted(msg) => FedError::Corrupted(change(msg)),
+            FedError::ChecksumMismatch(msg) => FedError::ChecksumMismatch(change(msg)),
+            FedError::Io(msg) => FedError::Io(change(msg)),
+            FedError::Other(msg) => FedError::Other(change(msg)),
+        }
+    }
+}
+
+impl fmt::Display for FedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
 
 /// Change IO error into FedResult error.
 pub fn wrap_io<T, S: AsRef<str>>(base_msg: impl FnOnce() -> S, res: io::Result<T>) -> FedResult<T> {
     match res {
         Ok(val) => FedResult::Ok(val),
-        Err(val) => FedResult::Err(format!("{}: {}", base_msg().as_ref(), val)),
+        Err(val) => {
+            let msg = format!("{}: {}", base_msg().as_ref(), val);
+            FedResult::Err(match val.kind() {
+                io::ErrorKind::NotFound => FedError::NotFound(msg),
+                io::ErrorKind::AlreadyExists => FedError::OutputExists(msg),
+                // Decryption failures while streaming are reported as invalid data.
+                io::ErrorKind::InvalidData => FedError::Corrupted(msg),
+                _ => FedError::Io(msg),
+            })
+        }
     }
 }
 
@@ -20,3 +113,20 @@ pub fn add_err(msg: impl Into<String>, verbose: bool, err: impl fmt::Debug) -> S
     }
     msg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_kinds() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "gone");
+        let err = wrap_io(|| "could not open", Err::<(), _>(missing)).unwrap_err();
+        assert_eq!(FedError::NotFound("could not open: gone".to_owned()), err);
+        assert_eq!(3, err.exit_code());
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        let err = wrap_io(|| "could not open", Err::<(), _>(denied)).unwrap_err();
+        assert_eq!("io", err.code());
+        assert_eq!("could not open: denied", err.to_string());
+    }
+}
@@ -0,0 +1,143 @@
+use ::std::sync::atomic::{AtomicUsize, Ordering};
+use ::std::sync::Mutex;
+use ::std::thread;
+
+use crate::util::{FedError, FedResult};
+
+/// The number of jobs to use, where 0 means one job per CPU core.
+pub fn resolve_job_count(jobs: usize) -> usize {
+    if jobs > 0 {
+        return jobs;
+    }
+    thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+}
+
+/// Run the task for every item, using up to `jobs` threads. Items are started in order,
+/// and the results are in the same order as the items, regardless of which finishes first.
+pub fn run_parallel<T: Sync, R: Send>(
+    jobs: usize,
+    items: &[T],
+    task: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(task).collect();
+    }
+    let next_index = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| loop {
+                let index = next_index.fetch_add(1, Ordering::SeqCst);
+                if index >= items.len() {
+                    break;
+                }
+                let result = task(&items[index]);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("a task did not finish"))
+        .collect()
+}
+
+/// Combine the results of all items. Errors are reported in the order of the items,
+/// so that the message does not depend on the order in which they were processed.
+pub fn collect_results<R>(results: Vec<FedResult<R>>) -> FedResult<Vec<R>> {
+    let total = results.len();
+    let mut values = Vec::with_capacity(total);
+    let mut errors = vec![];
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(err) => errors.push(err),
+        }
+    }
+    match errors.len() {
+        0 => Ok(values),
+        1 => Err(errors.remove(0)),
+        count => {
+            let msg = format!(
+                "{} of {} files failed:\n{}",
+                count,
+                total,
+                errors
+                    .iter()
+                    .map(|err| format!("- {}", err))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+            // Keep the kind of error if all files failed for the same reason.
+            let first = errors.remove(0);
+            if errors.iter().all(|err| err.code() == first.code()) {
+                Err(first.map_message(|_| msg))
+            } else {
+                Err(FedError::Other(msg))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn results_in_order() {
+        let items = (0..20u64).collect::<Vec<_>>();
+        let results = run_parallel(4, &items, |item| {
+            // Make early items finish last.
+            thread::sleep(Duration::from_millis(20 - item));
+            item * 2
+        });
+        assert_eq!(
+            items.iter().map(|item| item * 2).collect::<Vec<_>>(),
+            results
+        );
+    }
+
+    #[test]
+    fn sequential() {
+        let results = run_parallel(1, &["a", "b"], |item| item.to_uppercase());
+        assert_eq!(vec!["A", "B"], results);
+    }
+
+    #[test]
+    fn job_count() {
+        assert_eq!(3, resolve_job_count(3));
+        assert!(resolve_job_count(0) >= 1);
+    }
+
+    #[test]
+    fn errors_in_order() {
+        let results: Vec<FedResult<u8>> = vec![
+            Ok(1),
+            Err(FedError::NotFound("first".to_owned())),
+            Ok(2),
+            Err(FedError::NotFound("second".to_owned())),
+        ];
+        assert_eq!(
+            Err(FedError::NotFound(
+                "2 of 4 files failed:\n- first\n- second".to_owned()
+            )),
+            collect_results(results)
+        );
+        assert_eq!(
+            Err(FedError::Other("only".to_owned())),
+            collect_results(vec![Ok(1), Err(FedError::Other("only".to_owned()))])
+        );
+        let mixed: Vec<FedResult<u8>> = vec![
+            Err(FedError::NotFound("gone".to_owned())),
+            Err(FedError::WrongKey("wrong".to_owned())),
+        ];
+        assert_eq!("other", collect_results(mixed).unwrap_err().code());
+        assert_eq!(Ok(vec![1, 2]), collect_results(vec![Ok(1), Ok(2)]));
+    }
+}
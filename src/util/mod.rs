@@ -1,8 +1,10 @@
+pub use errors::FedError;
 pub use errors::FedResult;
 
 #[allow(clippy::module_inception)]
 pub mod base;
 pub mod errors;
+pub mod jobs;
 pub mod option;
 pub mod pth;
 pub mod rounding;
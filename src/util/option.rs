@@ -34,10 +34,22 @@ impl EncOptionSet {
         vec![EncOption::Fast, EncOption::HideMeta, EncOption::PadSize].into()
     }
 
+    pub fn add(&mut self, option: EncOption) {
+        self.options.insert(option);
+    }
+
+    pub fn remove(&mut self, option: EncOption) {
+        self.options.remove(&option);
+    }
+
     pub fn len(&self) -> usize {
         self.options.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
     pub fn has(&self, option: EncOption) -> bool {
         self.options.contains(&option)
     }
@@ -48,11 +60,25 @@ impl EncOptionSet {
 }
 
 /// Encryption modifiers to use. Each should be used at most once.
-#[derive(Debug, Clone, PartialEq, Eq, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum EncOption {
     Fast,
     HideMeta,
     PadSize,
+    /// Multiple files are packed into a single container.
+    Archive,
+    /// The key is encrypted for public keys, instead of being a password.
+    Recipients,
+    /// The data key is random, and stored in the header encrypted with one or more passwords.
+    KeySlots,
+    /// Compress with zstd instead of brotli.
+    Zstd,
+    /// Compress with xz instead of brotli.
+    Xz,
+    /// Do not compress the data.
+    NoCompression,
+    /// The salt and pepper were chosen by the caller, so the same input gives the same output.
+    Deterministic,
 }
 
 impl EncOption {
@@ -61,13 +87,26 @@ impl EncOption {
             EncOption::Fast => 1,
             EncOption::HideMeta => 2,
             EncOption::PadSize => 3,
+            EncOption::Archive => 4,
+            EncOption::Recipients => 5,
+            EncOption::KeySlots => 6,
+            EncOption::Zstd => 7,
+            EncOption::Xz => 8,
+            EncOption::NoCompression => 9,
+            EncOption::Deterministic => 10,
         }
     }
 }
 
+impl Ord for EncOption {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ordinal().cmp(&other.ordinal())
+    }
+}
+
 impl PartialOrd for EncOption {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.ordinal().cmp(&other.ordinal()))
+        Some(self.cmp(other))
     }
 }
 
@@ -81,6 +120,13 @@ impl fmt::Display for EncOption {
                 EncOption::Fast => "fast",
                 EncOption::HideMeta => "hide-meta",
                 EncOption::PadSize => "pad-size",
+                EncOption::Archive => "archive",
+                EncOption::Recipients => "recipients",
+                EncOption::KeySlots => "key-slots",
+                EncOption::Zstd => "zstd",
+                EncOption::Xz => "xz",
+                EncOption::NoCompression => "no-compression",
+                EncOption::Deterministic => "deterministic",
             }
         )
     }
@@ -90,12 +136,19 @@ impl FromStr for EncOption {
     type Err = ();
 
     fn from_str(txt: &str) -> Result<Self, Self::Err> {
-        return Ok(match txt.to_ascii_lowercase().as_str() {
+        Ok(match txt.to_ascii_lowercase().as_str() {
             "fast" => EncOption::Fast,
             "hide-meta" => EncOption::HideMeta,
             "pad-size" => EncOption::PadSize,
+            "archive" => EncOption::Archive,
+            "recipients" => EncOption::Recipients,
+            "key-slots" => EncOption::KeySlots,
+            "zstd" => EncOption::Zstd,
+            "xz" => EncOption::Xz,
+            "no-compression" => EncOption::NoCompression,
+            "deterministic" => EncOption::Deterministic,
             _ => return Err(()),
-        });
+        })
     }
 }
 
@@ -173,5 +226,33 @@ mod tests {
             assert_eq!(EncOption::PadSize.to_string(), repr);
             assert_eq!(EncOption::from_str(repr), Ok(EncOption::PadSize));
         }
+
+        #[test]
+        fn variant_archive() {
+            let repr = "archive";
+            assert_eq!(EncOption::Archive.to_string(), repr);
+            assert_eq!(EncOption::from_str(repr), Ok(EncOption::Archive));
+        }
+
+        #[test]
+        fn variant_recipients() {
+            let repr = "recipients";
+            assert_eq!(EncOption::Recipients.to_string(), repr);
+            assert_eq!(EncOption::from_str(repr), Ok(EncOption::Recipients));
+        }
+
+        #[test]
+        fn variant_key_slots() {
+            let repr = "key-slots";
+            assert_eq!(EncOption::KeySlots.to_string(), repr);
+            assert_eq!(EncOption::from_str(repr), Ok(EncOption::KeySlots));
+        }
+
+        #[test]
+        fn variant_deterministic() {
+            let repr = "deterministic";
+            assert_eq!(EncOption::Deterministic.to_string(), repr);
+            assert_eq!(EncOption::from_str(repr), Ok(EncOption::Deterministic));
+        }
     }
 }
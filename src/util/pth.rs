@@ -1,7 +1,16 @@
+use ::std::ffi::{OsStr, OsString};
 use ::std::path::Path;
 use ::std::path::PathBuf;
 
 use crate::config::typ::Extension;
+use crate::key::random::generate_secure_random_timed;
+use crate::util::base::u8s_to_base64str;
+
+fn random_name() -> OsString {
+    let mut random = [0u8; 15];
+    generate_secure_random_timed(&mut random);
+    OsString::from(u8s_to_base64str(&random))
+}
 
 pub fn determine_output_path(
     input_path: &Path,
@@ -15,6 +24,11 @@ pub fn determine_output_path(
             new_name.push(ext);
             new_name
         }
+        Extension::Obfuscate(ext) => {
+            let mut new_name = random_name();
+            new_name.push(ext);
+            new_name
+        }
         Extension::Strip => {
             let mut new_name = input_path.file_stem().unwrap().to_os_string();
             let original_name = input_path.file_name().unwrap().to_os_string();
@@ -29,6 +43,21 @@ pub fn determine_output_path(
             new_name
         }
     };
+    in_output_dir(input_path, name, output_dir)
+}
+
+/// Use the original filename, in the same directory, if it is known and valid.
+pub fn determine_restored_output_path(out_pth: &Path, original_name: Option<&str>) -> PathBuf {
+    match original_name {
+        // Do not trust the name to not contain directories, even though it is authenticated.
+        Some(name) if Path::new(name).file_name() == Some(OsStr::new(name)) => {
+            out_pth.with_file_name(name)
+        }
+        _ => out_pth.to_owned(),
+    }
+}
+
+fn in_output_dir(input_path: &Path, name: OsString, output_dir: Option<&Path>) -> PathBuf {
     match output_dir {
         Some(p) => {
             let mut p = p.to_owned();
@@ -165,4 +194,48 @@ mod tests {
         let out_pth = determine_output_path(&PathBuf::from("name"), Extension::Strip, None);
         assert_eq!(out_pth, PathBuf::from("name~"));
     }
+
+    #[test]
+    fn obfuscate_name() {
+        let out_pth = determine_output_path(
+            &PathBuf::from("/alpha/beta/gamma.txt"),
+            Extension::Obfuscate(".enc"),
+            None,
+        );
+        let name = out_pth.file_name().unwrap().to_str().unwrap();
+        assert_eq!(out_pth.parent().unwrap(), Path::new("/alpha/beta"));
+        assert!(!name.contains("gamma"));
+        assert!(name.ends_with(".enc"));
+        assert_eq!(name.len(), 24);
+        let other_pth = determine_output_path(
+            &PathBuf::from("/alpha/beta/gamma.txt"),
+            Extension::Obfuscate(".enc"),
+            None,
+        );
+        assert_ne!(out_pth, other_pth);
+    }
+
+    #[test]
+    fn restore_original_name() {
+        let out_pth = determine_restored_output_path(
+            &PathBuf::from("/output/dec/Yx3_random"),
+            Some("gamma.txt"),
+        );
+        assert_eq!(out_pth, PathBuf::from("/output/dec/gamma.txt"));
+    }
+
+    #[test]
+    fn restore_without_original_name() {
+        let out_pth = determine_restored_output_path(&PathBuf::from("/alpha/beta/gamma.txt"), None);
+        assert_eq!(out_pth, PathBuf::from("/alpha/beta/gamma.txt"));
+    }
+
+    #[test]
+    fn restore_name_with_directory() {
+        for name in &["../gamma.txt", "/etc/gamma.txt", "..", ""] {
+            let out_pth =
+                determine_restored_output_path(&PathBuf::from("/alpha/beta/delta"), Some(name));
+            assert_eq!(out_pth, PathBuf::from("/alpha/beta/delta"));
+        }
+    }
 }
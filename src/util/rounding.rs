@@ -1,5 +1,3 @@
-use ::std::mem::size_of;
-
 /// Round up to the nearest power of two, e.g. 16, 32, 64, 128...
 pub fn round_up_to_power_of_two(value: u64) -> u64 {
     debug_assert!(value < 2u64.pow(63), "not implemented for numbers > 2**63");
@@ -7,8 +5,8 @@ pub fn round_up_to_power_of_two(value: u64) -> u64 {
         return 0;
     }
     // The power of two, rounded up, is the number of bits needed to store the number.
-    let maximum_bits = (size_of::<u64>() * 8) as u32;
-    let bits_used = (value - 1).leading_zeros() as u32;
+    let maximum_bits = u64::BITS;
+    let bits_used = (value - 1).leading_zeros();
     let power = maximum_bits - bits_used;
     // The answer then is just 2^power.
     2u64.pow(power)
@@ -4,6 +4,12 @@ use ::semver::Version;
 lazy_static! {
     static ref CURRENT_VERSION: Version = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
     static ref OPTIONS_INTORDUCED_IN_VERSION: Version = Version::parse("1.1.0").unwrap();
+    static ref STREAM_INTRODUCED_IN_VERSION: Version = Version::parse("1.2.0").unwrap();
+    static ref AEAD_INTRODUCED_IN_VERSION: Version = Version::parse("1.3.0").unwrap();
+    static ref PRIVATE_HEADER_INTRODUCED_IN_VERSION: Version = Version::parse("1.3.0").unwrap();
+    static ref COMPRESSION_CHOICE_INTRODUCED_IN_VERSION: Version = Version::parse("1.4.0").unwrap();
+    static ref KDF_COST_INTRODUCED_IN_VERSION: Version = Version::parse("1.5.0").unwrap();
+    static ref ARGON2ID_INTRODUCED_IN_VERSION: Version = Version::parse("1.6.0").unwrap();
 }
 
 pub fn get_current_version() -> Version {
@@ -18,6 +24,39 @@ pub fn version_has_options_meta(version: &Version) -> bool {
     version >= &*OPTIONS_INTORDUCED_IN_VERSION
 }
 
+/// Whether the data can be processed as a stream, which means the data checksum is
+/// stored in a trailer after the data, instead of in the header.
+pub fn version_has_stream_layout(version: &Version) -> bool {
+    version >= &*STREAM_INTRODUCED_IN_VERSION
+}
+
+/// Whether the data is encrypted with authenticated encryption, so that tampering
+/// makes decryption fail instead of only producing a checksum warning.
+pub fn version_has_authenticated_encryption(version: &Version) -> bool {
+    version >= &*AEAD_INTRODUCED_IN_VERSION
+}
+
+/// Whether the encrypted private header is stored after the public header. Before this version,
+/// the public header did contain the private header size and checksum, but the header was not stored.
+pub fn version_has_private_header(version: &Version) -> bool {
+    version >= &*PRIVATE_HEADER_INTRODUCED_IN_VERSION
+}
+
+/// Whether the compression algorithm can be chosen using options, instead of always being brotli.
+pub fn version_has_compression_choice(version: &Version) -> bool {
+    version >= &*COMPRESSION_CHOICE_INTRODUCED_IN_VERSION
+}
+
+/// Whether the key stretching cost is stored in the header, instead of following from the strategy.
+pub fn version_has_kdf_cost(version: &Version) -> bool {
+    version >= &*KDF_COST_INTRODUCED_IN_VERSION
+}
+
+/// Whether the key is stretched with argon2id, instead of argon2i.
+pub fn version_has_argon2id(version: &Version) -> bool {
+    version >= &*ARGON2ID_INTRODUCED_IN_VERSION
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;